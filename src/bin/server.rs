@@ -1,12 +1,13 @@
 //! nexus-server - Background daemon managing channels and PTYs
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::Parser;
 use nexus::config::Config;
-use nexus::server::ServerListener;
+use nexus::server::{daemon, systemd, SessionHost};
+use std::os::unix::net::UnixListener;
 use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::signal;
-use tokio::sync::mpsc;
 
 #[derive(Parser)]
 #[command(name = "nexus-server")]
@@ -24,10 +25,42 @@ struct Cli {
     /// Run in foreground (don't daemonize)
     #[arg(short, long)]
     foreground: bool,
+
+    /// Install systemd user units that start sessions on demand, then exit
+    #[arg(long)]
+    install_service: bool,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // Load configuration
+    let config = Config::load().unwrap_or_default();
+
+    if cli.install_service {
+        return install_service(&config);
+    }
+
+    // Determine socket path
+    let socket_path = cli
+        .socket
+        .clone()
+        .unwrap_or_else(|| config.socket_path(&cli.session));
+
+    // Serialize startup with any other server for this session, before
+    // daemonizing so the daemon inherits the lock. systemd-activated servers
+    // already have their socket and need neither.
+    let activated = systemd::activated_listener()?;
+    let startup_lock = match activated {
+        Some(_) => None,
+        None => Some(daemon::StartupLock::acquire(&daemon::lock_path(
+            &socket_path,
+        ))?),
+    };
+    if !cli.foreground && activated.is_none() {
+        daemon::daemonize()?;
+    }
+
     // Initialize logging
     tracing_subscriber::fmt()
         .with_env_filter(
@@ -36,44 +69,70 @@ async fn main() -> Result<()> {
         )
         .init();
 
-    let cli = Cli::parse();
+    let pid_file = daemon::PidFile::acquire(daemon::pid_path(&socket_path))?;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    if let Err(e) = runtime.block_on(serve(
+        cli.session,
+        socket_path,
+        config,
+        activated,
+        startup_lock,
+        pid_file,
+    )) {
+        tracing::error!("Server error: {}", e);
+        eprintln!("Error: {}", e);
+        return Err(e);
+    }
 
-    // Load configuration
-    let config = Config::load().unwrap_or_default();
+    tracing::info!("Server shutdown complete");
 
-    // Determine socket path
-    let socket_path = cli
-        .socket
-        .unwrap_or_else(|| config.socket_path(&cli.session));
+    Ok(())
+}
 
-    tracing::info!("Starting nexus server for session: {}", cli.session);
+async fn serve(
+    session: String,
+    socket_path: PathBuf,
+    config: Config,
+    activated: Option<UnixListener>,
+    startup_lock: Option<daemon::StartupLock>,
+    pid_file: daemon::PidFile,
+) -> Result<()> {
+    tracing::info!("Starting nexus server for session: {}", session);
     tracing::info!("Socket path: {:?}", socket_path);
 
-    // Create server listener
-    let server = ServerListener::new(cli.session.clone(), socket_path.clone());
+    // Clients may ask this process to host more sessions alongside this one
+    let host = Arc::new(
+        SessionHost::new(config)
+            .with_scripts_dir(Config::scripts_dir())
+            .with_plugins_dir(Config::plugins_dir()),
+    );
+    let mut server = host.listener(session.clone(), socket_path.clone());
+
+    let is_activated = activated.is_some();
+    if let Some(listener) = activated {
+        tracing::info!("Using socket from systemd socket activation");
+        server = server.with_activated_listener(listener);
+    }
+    if let Some(lock) = startup_lock {
+        server = server.with_startup_lock(lock);
+    }
 
     // Check if server is already running
-    if server.socket_exists() {
-        // Try to verify if it's a stale socket
-        match tokio::net::UnixStream::connect(&socket_path).await {
-            Ok(_) => {
-                eprintln!(
-                    "Error: Server already running for session '{}' at {:?}",
-                    cli.session, socket_path
-                );
-                std::process::exit(1);
-            }
-            Err(_) => {
-                // Socket exists but can't connect - it's stale, server will clean it up
-            }
-        }
+    if !is_activated
+        && server.socket_exists()
+        && tokio::net::UnixStream::connect(&socket_path).await.is_ok()
+    {
+        // A socket nobody answers on is stale; the server cleans it up
+        return Err(anyhow!(
+            "Server already running for session '{}' at {:?}",
+            session,
+            socket_path
+        ));
     }
 
-    // Create shutdown channel
-    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
-
     // Spawn signal handlers
-    let shutdown_tx_clone = shutdown_tx.clone();
+    let signal_host = Arc::clone(&host);
     tokio::spawn(async move {
         let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())
             .expect("Failed to install SIGTERM handler");
@@ -89,17 +148,27 @@ async fn main() -> Result<()> {
             }
         }
 
-        let _ = shutdown_tx_clone.send(()).await;
+        signal_host.shutdown_all();
     });
 
-    // Run server
-    if let Err(e) = server.run(shutdown_rx).await {
-        tracing::error!("Server error: {}", e);
-        eprintln!("Error: {}", e);
-        std::process::exit(1);
-    }
-
-    tracing::info!("Server shutdown complete");
+    // Run until every hosted session has shut down
+    host.spawn(server, Some(pid_file));
+    host.wait().await;
+    Ok(())
+}
 
+/// Write the systemd user units and explain how to enable them
+fn install_service(config: &Config) -> Result<()> {
+    let server = std::env::current_exe()?;
+    let unit_dir = systemd::user_unit_dir();
+    for path in systemd::install_user_service(&unit_dir, &server, &config.runtime_dir())? {
+        println!("Wrote {}", path.display());
+    }
+    println!();
+    println!("Enable a session's socket with:");
+    println!("  systemctl --user daemon-reload");
+    println!("  systemctl --user enable --now nexus-server@default.socket");
+    println!("To keep sessions running after logout:");
+    println!("  loginctl enable-linger $USER");
     Ok(())
 }
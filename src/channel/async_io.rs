@@ -0,0 +1,63 @@
+//! Nonblocking PTY and pipe I/O driven by the tokio reactor, so channels
+//! don't each hold threads from the blocking pool
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+
+/// A file descriptor read and written asynchronously
+pub(super) struct AsyncFile(AsyncFd<File>);
+
+impl AsyncFile {
+    /// Take over `fd`, switching it to nonblocking mode. The mode belongs to
+    /// the open file, so descriptors duplicated from it are affected too.
+    pub(super) fn new(fd: OwnedFd) -> io::Result<Self> {
+        set_nonblocking(&fd)?;
+        // SAFETY: the `File` owns the descriptor for as long as it's registered
+        Ok(Self(unsafe { AsyncFd::register(File::from(fd)) }?))
+    }
+
+    /// Read some bytes; 0 means EOF, including a PTY whose other side closed
+    /// (reported as `EIO`)
+    pub(super) async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.0.readable().await?;
+            match guard.try_io(|inner| inner.get_ref().read(buf)) {
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::EIO) => return Ok(0),
+                Ok(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// Write all of `data`, waiting whenever the other side is full
+    pub(super) async fn write_all(&self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let mut guard = self.0.writable().await?;
+            match guard.try_io(|inner| inner.get_ref().write(data)) {
+                Ok(Ok(0)) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(Ok(n)) => data = &data[n..],
+                Ok(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => continue,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn set_nonblocking(fd: &OwnedFd) -> io::Result<()> {
+    let raw = fd.as_raw_fd();
+    // SAFETY: `raw` is a valid descriptor owned by `fd` for the duration
+    let flags = unsafe { libc::fcntl(raw, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: as above
+    if unsafe { libc::fcntl(raw, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
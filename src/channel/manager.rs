@@ -1,16 +1,60 @@
 //! Channel manager - orchestrates multiple channels
 
-use super::{ChannelConfig, ChannelState, PtyChannel};
+use super::schedule::ScheduleState;
+use super::{process, ChannelConfig, ChannelState, Foreground, OutputStream, PtyChannel};
 use anyhow::{anyhow, Result};
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Instant;
 use tokio::sync::mpsc;
 
+/// Fold each channel's foreground, as just looked up, into `known` (the
+/// command names last reported), naming processes with `name_of`. Returns
+/// the channels whose command changed; channels that couldn't be checked
+/// keep what was known, and ones no longer looked up are forgotten.
+fn update_foreground(
+    known: &mut HashMap<String, String>,
+    lookups: impl IntoIterator<Item = (String, Foreground)>,
+    name_of: impl Fn(u32) -> Option<String>,
+) -> Vec<(String, Option<String>)> {
+    let mut changed = Vec::new();
+    let mut seen = Vec::new();
+    for (name, foreground) in lookups {
+        let current = match foreground {
+            Foreground::Unknown => {
+                seen.push(name);
+                continue;
+            }
+            Foreground::Own => None,
+            Foreground::Process(pid) => name_of(pid),
+        };
+        if known.get(&name) != current.as_ref() {
+            match &current {
+                Some(command) => known.insert(name.clone(), command.clone()),
+                None => known.remove(&name),
+            };
+            changed.push((name.clone(), current));
+        }
+        seen.push(name);
+    }
+    known.retain(|name, _| seen.contains(name));
+    changed
+}
+
 /// Lightweight channel listing item used by the server when returning channel info.
 #[derive(Debug, Clone)]
 pub struct ChannelListItem {
     pub name: String,
     pub running: bool,
+    /// Spawned but not yet ready (no output so far)
+    pub starting: bool,
     pub is_active: bool,
+    /// Container label (`docker:web`) for container channels
+    pub container: Option<String>,
+    /// When the process was spawned, in milliseconds since the epoch
+    pub started_at: i64,
+    /// Command in the foreground, if it isn't the channel's own process
+    pub foreground: Option<String>,
 }
 
 /// Detailed channel status snapshot
@@ -24,13 +68,36 @@ pub struct ChannelStatusItem {
     pub command: String,
     pub output_lines: usize,
     pub is_active: bool,
+    /// CPU use of the process and its descendants, in percent of one core,
+    /// since the last status request (or since it started)
+    pub cpu_percent: Option<f32>,
+    /// Resident memory of the process and its descendants
+    pub rss_bytes: Option<u64>,
+    /// Schedule and recent runs of a scheduled channel
+    pub schedule: Option<ScheduleState>,
+}
+
+/// A channel's resource use at the last sample
+#[derive(Debug, Clone, Copy)]
+struct UsageSample {
+    /// CPU time its processes had used, in clock ticks
+    ticks: u64,
+    at: Instant,
+    /// CPU use since the sample before
+    cpu_percent: f32,
+    rss_bytes: u64,
 }
 
 /// Event emitted by channels
 #[derive(Debug, Clone)]
 pub enum ChannelManagerEvent {
     /// Output received from a channel
-    Output { channel_name: String, data: Vec<u8> },
+    Output {
+        channel_name: String,
+        data: Vec<u8>,
+        /// Stream the chunk was read from (always stdout for PTY channels)
+        stream: OutputStream,
+    },
     /// Channel state changed
     StateChanged {
         channel_name: String,
@@ -43,14 +110,31 @@ pub struct ChannelManager {
     /// All channels by name
     channels: HashMap<String, PtyChannel>,
 
+    /// Configuration each channel was created with (used for restarts)
+    configs: HashMap<String, ChannelConfig>,
+
     /// Currently active channel (receives input by default)
     active_channel: Option<String>,
 
+    /// Size channels were last resized to; new channels start at it
+    size: Option<(u16, u16)>,
+
     /// Channels the client is subscribed to
     subscribed_channels: Vec<String>,
 
     /// Event sender for notifying about channel events
     event_sender: mpsc::Sender<ChannelManagerEvent>,
+
+    /// Resource use of each running channel at the last sample, to report
+    /// in status and measure CPU use since
+    usage: HashMap<String, UsageSample>,
+
+    /// Name of the command in the foreground of each channel running one,
+    /// as last looked up
+    foreground: HashMap<String, String>,
+
+    /// Schedule and run history of each scheduled channel
+    schedules: HashMap<String, ScheduleState>,
 }
 
 impl ChannelManager {
@@ -58,9 +142,14 @@ impl ChannelManager {
     pub fn new(event_sender: mpsc::Sender<ChannelManagerEvent>) -> Self {
         Self {
             channels: HashMap::new(),
+            configs: HashMap::new(),
             active_channel: None,
+            size: None,
             subscribed_channels: Vec::new(),
             event_sender,
+            usage: HashMap::new(),
+            foreground: HashMap::new(),
+            schedules: HashMap::new(),
         }
     }
 
@@ -74,7 +163,15 @@ impl ChannelManager {
 
         // Spawn with notifier - output events go directly to event_sender
         let channel =
-            PtyChannel::spawn_with_notifier(config, Some(self.event_sender.clone())).await?;
+            PtyChannel::spawn_with_notifier(self.sized(&config), Some(self.event_sender.clone()))
+                .await?;
+        if let Some(schedule) = &config.schedule {
+            self.schedules.insert(
+                channel_name.clone(),
+                ScheduleState::new(schedule.clone(), chrono::Utc::now()),
+            );
+        }
+        self.configs.insert(channel_name.clone(), config);
 
         // If this is the first channel, make it active and subscribed
         let is_first = self.channels.is_empty();
@@ -83,20 +180,139 @@ impl ChannelManager {
 
         if is_first {
             self.active_channel = Some(channel_name.clone());
-            self.subscribed_channels.push(channel_name.clone());
+            self.subscribed_channels.push(channel_name);
         }
 
-        let _ = self
-            .event_sender
-            .send(ChannelManagerEvent::StateChanged {
-                channel_name,
-                state: ChannelState::Running,
+        Ok(())
+    }
+
+    /// Whether an exited channel should be restarted according to its policy
+    pub fn should_restart(&self, name: &str) -> bool {
+        match (self.configs.get(name), self.channels.get(name)) {
+            (Some(config), Some(channel)) => match channel.state() {
+                ChannelState::Exited(code) => config.restart.should_restart(code),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Running channels that have outlived their timeout, with the timeout
+    /// in seconds
+    pub fn timed_out(&self) -> Vec<(String, u64)> {
+        let now = chrono::Utc::now();
+        self.channels
+            .values()
+            .filter(|c| c.state().is_alive())
+            .filter_map(|c| {
+                let timeout = self.configs.get(c.name())?.timeout_secs?;
+                let ran = (now - c.started_at()).num_seconds();
+                (ran >= timeout as i64).then(|| (c.name().to_string(), timeout))
             })
-            .await;
+            .collect()
+    }
+
+    /// Respawn an exited channel using the configuration it was created with
+    pub async fn restart_channel(&mut self, name: &str) -> Result<()> {
+        let config = self
+            .configs
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Channel '{}' not found", name))?;
+
+        if self
+            .channels
+            .get(name)
+            .map(|c| c.state().is_alive())
+            .unwrap_or(false)
+        {
+            return Err(anyhow!("Channel '{}' is still running", name));
+        }
+
+        let channel =
+            PtyChannel::spawn_with_notifier(self.sized(&config), Some(self.event_sender.clone()))
+                .await?;
+        self.channels.insert(name.to_string(), channel);
+        if let Some(schedule) = self.schedules.get_mut(name) {
+            schedule.started(chrono::Utc::now());
+        }
 
         Ok(())
     }
 
+    /// Whether a channel runs on a schedule
+    pub fn is_scheduled(&self, name: &str) -> bool {
+        self.schedules.contains_key(name)
+    }
+
+    /// Stop running a channel on its schedule
+    pub fn cancel_schedule(&mut self, name: &str) -> bool {
+        self.schedules.remove(name).is_some()
+    }
+
+    /// Scheduled channels due to run again now. A channel whose last run is
+    /// still going skips this one.
+    pub fn due_runs(&mut self) -> Vec<String> {
+        let now = chrono::Utc::now();
+        let mut due = Vec::new();
+        for (name, schedule) in self.schedules.iter_mut() {
+            if !schedule.take_due(now) {
+                continue;
+            }
+            if self
+                .channels
+                .get(name)
+                .is_some_and(|c| c.state().is_alive())
+            {
+                tracing::info!("Skipping scheduled run of '{}': still running", name);
+            } else {
+                due.push(name.clone());
+            }
+        }
+        due
+    }
+
+    /// Note how a scheduled channel's run ended
+    pub fn record_exit(&mut self, name: &str, exit_code: Option<i32>) {
+        if let Some(schedule) = self.schedules.get_mut(name) {
+            schedule.finished(chrono::Utc::now(), exit_code);
+        }
+    }
+
+    /// Whether a finished channel should be discarded: it was created as
+    /// ephemeral, and didn't fail while asked to be kept on failure
+    pub fn is_disposable(&self, name: &str) -> bool {
+        match (self.configs.get(name), self.channels.get(name)) {
+            (Some(config), Some(channel)) if config.ephemeral => {
+                !config.keep_on_failure
+                    || !matches!(channel.state(), ChannelState::Exited(code) if code != Some(0))
+            }
+            _ => false,
+        }
+    }
+
+    /// Forget a channel that is no longer running.
+    ///
+    /// Returns whether it was removed; running channels must be killed first.
+    pub fn remove_channel(&mut self, name: &str) -> bool {
+        if self
+            .channels
+            .get(name)
+            .is_none_or(|channel| channel.state().is_alive())
+        {
+            return false;
+        }
+
+        self.channels.remove(name);
+        self.configs.remove(name);
+        self.schedules.remove(name);
+        self.subscribed_channels.retain(|c| c != name);
+        if self.active_channel.as_deref() == Some(name) {
+            self.active_channel = self.channels.keys().next().cloned();
+        }
+        true
+    }
+
     /// Kill a channel
     pub async fn kill_channel(&mut self, name: &str) -> Result<()> {
         let channel = self
@@ -194,12 +410,21 @@ impl ChannelManager {
             .map(|c| ChannelListItem {
                 name: c.name().to_string(),
                 running: c.state().is_alive(),
+                starting: c.state() == ChannelState::Starting,
                 is_active: active.as_deref() == Some(c.name()),
+                container: self
+                    .configs
+                    .get(c.name())
+                    .and_then(|config| config.container.as_ref())
+                    .map(|target| target.label()),
+                started_at: c.started_at().timestamp_millis(),
+                foreground: self.foreground.get(c.name()).cloned(),
             })
             .collect()
     }
 
-    /// List channel status details
+    /// List channel status details, with the resource use of running
+    /// channels as last sampled
     pub fn list_channel_status(&self) -> Vec<ChannelStatusItem> {
         let active = self.active_channel().map(|name| name.to_string());
         self.channels
@@ -211,6 +436,7 @@ impl ChannelManager {
                     ChannelState::Exited(code) => (false, code),
                     ChannelState::Killed => (false, None),
                 };
+                let usage = self.usage.get(c.name()).filter(|_| running);
                 ChannelStatusItem {
                     name: c.name().to_string(),
                     running,
@@ -220,16 +446,205 @@ impl ChannelManager {
                     command: c.command().to_string(),
                     output_lines: 0,
                     is_active: active.as_deref() == Some(c.name()),
+                    cpu_percent: usage.map(|usage| usage.cpu_percent),
+                    rss_bytes: usage.map(|usage| usage.rss_bytes),
+                    schedule: self.schedules.get(c.name()).cloned(),
                 }
             })
             .collect()
     }
 
-    /// Resize all channels
+    /// Whether any channel is running, so has resource use to sample
+    pub fn has_running_channels(&self) -> bool {
+        self.channels.values().any(|c| c.state().is_alive())
+    }
+
+    /// Record the resource use of each running channel from `processes`, a
+    /// snapshot of [`process::all_processes`]
+    pub fn sample_usage(&mut self, processes: &[process::ProcStat]) {
+        let ticks_per_sec = process::clock_ticks() as f64;
+        let now = Instant::now();
+        self.usage
+            .retain(|name, _| self.channels.contains_key(name));
+        for c in self.channels.values() {
+            let usage = c
+                .pid()
+                .filter(|_| c.state().is_alive())
+                .and_then(|pid| process::usage(pid, processes));
+            let Some(usage) = usage else {
+                self.usage.remove(c.name());
+                continue;
+            };
+            let (ticks, secs) = match self.usage.get(c.name()) {
+                // Fewer ticks than before: the channel was restarted
+                Some(last) if usage.cpu_ticks >= last.ticks => {
+                    (usage.cpu_ticks - last.ticks, (now - last.at).as_secs_f64())
+                }
+                _ => (
+                    usage.cpu_ticks,
+                    (chrono::Utc::now() - c.started_at()).as_seconds_f64(),
+                ),
+            };
+            let cpu_percent = if secs > 0.0 {
+                (ticks as f64 / ticks_per_sec / secs * 100.0) as f32
+            } else {
+                0.0
+            };
+            self.usage.insert(
+                c.name().to_string(),
+                UsageSample {
+                    ticks: usage.cpu_ticks,
+                    at: now,
+                    cpu_percent,
+                    rss_bytes: usage.rss_bytes,
+                },
+            );
+        }
+    }
+
+    /// Current working directory of a channel's process.
+    ///
+    /// Follows `cd` in the channel where `/proc` is available, otherwise
+    /// falls back to the directory the channel was started in.
+    pub fn current_dir(&self, name: &str) -> Option<PathBuf> {
+        let channel = self.channels.get(name)?;
+        channel
+            .pid()
+            .and_then(|pid| std::fs::read_link(format!("/proc/{}/cwd", pid)).ok())
+            .or_else(|| Some(channel.working_dir().clone()))
+    }
+
+    /// Name of the command in a channel's foreground, if it's running one
+    /// other than its own process
+    pub fn foreground(&self, name: &str) -> Option<&str> {
+        self.foreground.get(name).map(String::as_str)
+    }
+
+    /// Send `signal` to the process group in a channel's foreground,
+    /// returning the name of the command it stopped
+    pub fn signal_foreground(&self, name: &str, signal: libc::c_int) -> Result<String> {
+        let channel = self
+            .channels
+            .get(name)
+            .ok_or_else(|| anyhow!("Channel '{}' not found", name))?;
+        let Foreground::Process(leader) = channel.foreground() else {
+            return Err(anyhow!(
+                "Nothing is running in the foreground of '{}'",
+                name
+            ));
+        };
+        process::signal_group(leader, signal)?;
+        Ok(process::name(leader).unwrap_or_else(|| leader.to_string()))
+    }
+
+    /// Look up the command in the foreground of every channel, returning
+    /// the channels where it changed
+    pub fn refresh_foreground(&mut self) -> Vec<(String, Option<String>)> {
+        let lookups = self
+            .channels
+            .values()
+            .map(|channel| (channel.name().to_string(), channel.foreground()));
+        update_foreground(&mut self.foreground, lookups, process::name)
+    }
+
+    /// Processes running in a channel, its own first, in tree order with
+    /// their depth below it; `None` unless the channel is running
+    pub fn process_tree(&self, name: &str) -> Option<Vec<(usize, process::ProcStat)>> {
+        let channel = self.channels.get(name)?;
+        let pid = channel.pid().filter(|_| channel.state().is_alive())?;
+        let processes = process::all_processes();
+        Some(
+            process::tree(pid, &processes)
+                .into_iter()
+                .map(|(depth, process)| (depth, process.clone()))
+                .collect(),
+        )
+    }
+
+    /// `config` to spawn with, starting at the current size unless it sets
+    /// its own
+    fn sized(&self, config: &ChannelConfig) -> ChannelConfig {
+        let mut config = config.clone();
+        config.size = config.size.or(self.size);
+        config
+    }
+
+    /// Size channels were last resized to, if any
+    pub fn size(&self) -> Option<(u16, u16)> {
+        self.size
+    }
+
+    /// Resize all channels, except those locked at a fixed size
     pub async fn resize_all(&mut self, cols: u16, rows: u16) -> Result<()> {
-        for channel in self.channels.values_mut() {
+        self.size = Some((cols, rows));
+        for (name, channel) in self.channels.iter_mut() {
+            if self
+                .configs
+                .get(name)
+                .is_some_and(|config| config.fixed_size)
+            {
+                continue;
+            }
             channel.resize(cols, rows).await?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookups(entries: &[(&str, Foreground)]) -> Vec<(String, Foreground)> {
+        entries
+            .iter()
+            .map(|(name, foreground)| (name.to_string(), *foreground))
+            .collect()
+    }
+
+    #[test]
+    fn foreground_changes_are_reported_once() {
+        let name_of = |pid: u32| Some(format!("cmd{}", pid));
+        let mut known = HashMap::new();
+
+        let changed = update_foreground(
+            &mut known,
+            lookups(&[("vim", Foreground::Process(1)), ("sh", Foreground::Own)]),
+            name_of,
+        );
+        assert_eq!(changed, vec![("vim".to_string(), Some("cmd1".to_string()))]);
+        let changed = update_foreground(
+            &mut known,
+            lookups(&[("vim", Foreground::Process(1)), ("sh", Foreground::Own)]),
+            name_of,
+        );
+        assert!(changed.is_empty());
+
+        // A busy PTY leaves the command as it was, with nothing to report
+        let changed = update_foreground(
+            &mut known,
+            lookups(&[("vim", Foreground::Unknown), ("sh", Foreground::Own)]),
+            name_of,
+        );
+        assert!(changed.is_empty());
+        assert_eq!(known.get("vim").map(String::as_str), Some("cmd1"));
+
+        let changed = update_foreground(
+            &mut known,
+            lookups(&[("vim", Foreground::Own), ("sh", Foreground::Process(2))]),
+            name_of,
+        );
+        assert_eq!(
+            changed,
+            vec![
+                ("vim".to_string(), None),
+                ("sh".to_string(), Some("cmd2".to_string())),
+            ]
+        );
+
+        // Removed channels are dropped without an event
+        let changed = update_foreground(&mut known, lookups(&[("vim", Foreground::Own)]), name_of);
+        assert!(changed.is_empty());
+        assert!(known.is_empty());
+    }
+}
@@ -1,14 +1,20 @@
 //! Channel management - PTY spawning, I/O handling, lifecycle
 
+mod async_io;
 mod manager;
+pub mod process;
 mod pty_handler;
+pub mod schedule;
+mod utf8;
 
 pub use manager::ChannelListItem;
 pub use manager::ChannelManager;
 pub use manager::ChannelManagerEvent;
 pub use manager::ChannelStatusItem;
+pub use pty_handler::Foreground;
 pub use pty_handler::PtyChannel;
 
+use schedule::Schedule;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -29,6 +35,39 @@ pub struct ChannelConfig {
 
     /// Initial terminal size
     pub size: Option<(u16, u16)>,
+
+    /// Keep `size` instead of following the attached clients' terminals
+    #[serde(default)]
+    pub fixed_size: bool,
+
+    /// What to do when the channel process exits
+    #[serde(default)]
+    pub restart: RestartPolicy,
+
+    /// Run the command inside a running container instead of on the host
+    #[serde(default)]
+    pub container: Option<ContainerTarget>,
+
+    /// Whether the process gets a PTY or plain pipes
+    #[serde(default)]
+    pub mode: ChannelMode,
+
+    /// Remove the channel once its process exits or is killed
+    #[serde(default)]
+    pub ephemeral: bool,
+
+    /// Keep an ephemeral channel around when its process exits with a
+    /// failure status, so its output can still be read
+    #[serde(default)]
+    pub keep_on_failure: bool,
+
+    /// Kill the process once it has run this many seconds
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+
+    /// Run the command again on this schedule once it has exited
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
 }
 
 impl ChannelConfig {
@@ -39,6 +78,14 @@ impl ChannelConfig {
             working_dir: None,
             env: None,
             size: None,
+            fixed_size: false,
+            restart: RestartPolicy::Never,
+            container: None,
+            mode: ChannelMode::Pty,
+            ephemeral: false,
+            keep_on_failure: false,
+            timeout_secs: None,
+            schedule: None,
         }
     }
 
@@ -51,6 +98,166 @@ impl ChannelConfig {
         self.working_dir = Some(dir.into());
         self
     }
+
+    pub fn with_restart(mut self, policy: RestartPolicy) -> Self {
+        self.restart = policy;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: ChannelMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Lock the terminal at `cols` x `rows`, ignoring client resizes
+    pub fn with_fixed_size(mut self, cols: u16, rows: u16) -> Self {
+        self.size = Some((cols, rows));
+        self.fixed_size = true;
+        self
+    }
+
+    pub fn with_ephemeral(mut self, ephemeral: bool) -> Self {
+        self.ephemeral = ephemeral;
+        self
+    }
+
+    pub fn with_timeout(mut self, secs: u64) -> Self {
+        self.timeout_secs = Some(secs);
+        self
+    }
+
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
+    pub fn with_container(mut self, target: ContainerTarget) -> Self {
+        self.container = Some(target);
+        self
+    }
+
+    pub fn with_env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env
+            .get_or_insert_with(Vec::new)
+            .push((key.into(), value.into()));
+        self
+    }
+}
+
+/// Restart behaviour when a channel process exits on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Leave the channel stopped
+    #[default]
+    Never,
+    /// Restart only when the process exits with a non-zero code
+    OnFailure,
+    /// Always restart
+    Always,
+}
+
+impl RestartPolicy {
+    /// Whether a process that exited with `exit_code` should be restarted
+    pub fn should_restart(&self, exit_code: Option<i32>) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => exit_code != Some(0),
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+/// How a channel's process is attached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChannelMode {
+    /// Interactive terminal; stdout and stderr arrive interleaved
+    #[default]
+    Pty,
+    /// Plain pipes, so stdout and stderr can be told apart
+    Pipe,
+}
+
+/// Which output stream a chunk came from (PTY output is always `Stdout`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    #[default]
+    Stdout,
+    Stderr,
+}
+
+/// Container engine used for container channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerRuntime {
+    Docker,
+    Podman,
+}
+
+impl ContainerRuntime {
+    /// CLI program that drives this runtime
+    pub fn program(&self) -> &'static str {
+        match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+        }
+    }
+}
+
+/// A running container to spawn a channel inside (via `<runtime> exec -it`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerTarget {
+    pub runtime: ContainerRuntime,
+    pub container: String,
+}
+
+impl ContainerTarget {
+    pub fn new(runtime: ContainerRuntime, container: impl Into<String>) -> Self {
+        Self {
+            runtime,
+            container: container.into(),
+        }
+    }
+
+    /// Short label shown in listings and the status bar, e.g. `docker:web`
+    pub fn label(&self) -> String {
+        format!("{}:{}", self.runtime.program(), self.container)
+    }
+
+    /// Arguments for `<runtime>` that run `command` (or `sh`) in the container.
+    ///
+    /// With `tty`, the exec'd process gets a TTY from the runtime, which
+    /// follows our PTY's size, so resizes propagate without extra plumbing.
+    pub fn exec_args(
+        &self,
+        command: Option<&str>,
+        working_dir: Option<&std::path::Path>,
+        env: &[(String, String)],
+        tty: bool,
+    ) -> Vec<String> {
+        let mut args = vec!["exec".to_string()];
+        if tty {
+            args.extend(["-it", "-e", "TERM=xterm-256color"].map(String::from));
+        } else {
+            args.push("-i".to_string());
+        }
+        if let Some(dir) = working_dir {
+            args.push("-w".to_string());
+            args.push(dir.display().to_string());
+        }
+        for (key, value) in env {
+            args.push("-e".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+        args.push(self.container.clone());
+        match command {
+            Some(command) => args.extend(["sh".to_string(), "-c".to_string(), command.to_string()]),
+            None => args.push("sh".to_string()),
+        }
+        args
+    }
 }
 
 /// Channel state
@@ -0,0 +1,206 @@
+//! A channel's processes - the one it spawned and everything under it - as
+//! seen through `/proc`; elsewhere nothing is known about them
+
+use std::collections::HashMap;
+
+/// What `/proc/<pid>/stat` says about a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcStat {
+    pub pid: u32,
+    pub ppid: u32,
+    /// Executable name, at most 15 bytes
+    pub name: String,
+    /// State letter: `R` running, `S` sleeping, `T` stopped, `Z` zombie...
+    pub state: char,
+    /// User plus system CPU time, in clock ticks
+    pub cpu_ticks: u64,
+    /// Resident set size, in pages
+    pub rss_pages: u64,
+}
+
+/// Parse a `/proc/<pid>/stat` line. The name is in parentheses and may hold
+/// spaces or parentheses itself, so fields are counted from the last `)`.
+pub fn parse_stat(text: &str) -> Option<ProcStat> {
+    let open = text.find('(')?;
+    let close = text.rfind(')')?;
+    let pid = text[..open].trim().parse().ok()?;
+    let name = text.get(open + 1..close)?.to_string();
+    // Fields from the state (field 3) on
+    let fields: Vec<&str> = text[close + 1..].split_whitespace().collect();
+    let field = |n: usize| -> Option<u64> { fields.get(n - 3)?.parse().ok() };
+    Some(ProcStat {
+        pid,
+        ppid: field(4)? as u32,
+        name,
+        state: fields.first()?.chars().next()?,
+        cpu_ticks: field(14)? + field(15)?,
+        rss_pages: field(24)?,
+    })
+}
+
+fn read_stat(pid: u32) -> Option<ProcStat> {
+    parse_stat(&std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?)
+}
+
+/// Executable name of a process
+pub fn name(pid: u32) -> Option<String> {
+    read_stat(pid).map(|stat| stat.name)
+}
+
+/// Full command line of a process; empty for kernel threads and zombies
+pub fn command_line(pid: u32) -> Option<String> {
+    let raw = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args: Vec<String> = raw
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    (!args.is_empty()).then(|| args.join(" "))
+}
+
+/// Send `signal` to every process in the group led by `pgid`
+pub fn signal_group(pgid: u32, signal: libc::c_int) -> std::io::Result<()> {
+    let pgid =
+        libc::pid_t::try_from(pgid).map_err(|_| std::io::Error::from_raw_os_error(libc::ESRCH))?;
+    // SAFETY: killpg only sends a signal
+    if unsafe { libc::killpg(pgid, signal) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Every process on the system
+pub fn all_processes() -> Vec<ProcStat> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .filter_map(read_stat)
+        .collect()
+}
+
+/// `root` and its descendants among `processes` in tree order (each
+/// process followed by its children, oldest first), with their depth
+/// below `root`
+pub fn tree(root: u32, processes: &[ProcStat]) -> Vec<(usize, &ProcStat)> {
+    let mut children: HashMap<u32, Vec<&ProcStat>> = HashMap::new();
+    for process in processes {
+        children.entry(process.ppid).or_default().push(process);
+    }
+    let mut found = Vec::new();
+    let mut stack: Vec<(usize, &ProcStat)> = processes
+        .iter()
+        .filter(|p| p.pid == root)
+        .map(|p| (0, p))
+        .collect();
+    while let Some((depth, process)) = stack.pop() {
+        found.push((depth, process));
+        if let Some(kids) = children.get(&process.pid) {
+            // Pushed newest first so the oldest comes off next
+            let mut kids = kids.clone();
+            kids.sort_by_key(|p| std::cmp::Reverse(p.pid));
+            stack.extend(kids.into_iter().map(|p| (depth + 1, p)));
+        }
+    }
+    found
+}
+
+/// CPU time and memory of a process tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    /// CPU time used so far, in clock ticks
+    pub cpu_ticks: u64,
+    /// Resident memory, in bytes
+    pub rss_bytes: u64,
+}
+
+/// Usage of `root` and its descendants among `processes`
+pub fn usage(root: u32, processes: &[ProcStat]) -> Option<Usage> {
+    let tree = tree(root, processes);
+    if tree.is_empty() {
+        return None;
+    }
+    Some(Usage {
+        cpu_ticks: tree.iter().map(|(_, p)| p.cpu_ticks).sum(),
+        rss_bytes: tree.iter().map(|(_, p)| p.rss_pages).sum::<u64>() * page_size(),
+    })
+}
+
+/// Clock ticks a second, the unit of CPU times in `/proc`
+pub fn clock_ticks() -> u64 {
+    // SAFETY: sysconf only reads a system constant
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
+
+fn page_size() -> u64 {
+    // SAFETY: sysconf only reads a system constant
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as u64,
+        _ => 4096,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(pid: u32, ppid: u32) -> ProcStat {
+        ProcStat {
+            pid,
+            ppid,
+            name: format!("p{}", pid),
+            state: 'S',
+            cpu_ticks: 10,
+            rss_pages: 1,
+        }
+    }
+
+    #[test]
+    fn parses_names_with_spaces_and_parens() {
+        let line = "4242 (tmux: (server)) S 1 4242 4242 0 -1 4194560 \
+                    2095 0 0 0 150 25 0 0 20 0 1 0 8122 23658496 1290 \
+                    18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 3 0 0";
+        assert_eq!(
+            parse_stat(line),
+            Some(ProcStat {
+                pid: 4242,
+                ppid: 1,
+                name: "tmux: (server)".to_string(),
+                state: 'S',
+                cpu_ticks: 175,
+                rss_pages: 1290,
+            })
+        );
+        assert_eq!(parse_stat("12 (sh"), None);
+    }
+
+    #[test]
+    fn tree_holds_descendants_in_tree_order() {
+        let processes = [
+            stat(1, 0),
+            stat(10, 1),
+            stat(13, 10),
+            stat(11, 10),
+            stat(12, 11),
+            stat(20, 1),
+        ];
+        let found: Vec<(usize, u32)> = tree(10, &processes)
+            .iter()
+            .map(|(depth, p)| (*depth, p.pid))
+            .collect();
+        assert_eq!(found, vec![(0, 10), (1, 11), (2, 12), (1, 13)]);
+        assert!(tree(99, &processes).is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reads_own_usage() {
+        let own = usage(std::process::id(), &all_processes()).unwrap();
+        assert!(own.rss_bytes > 0);
+    }
+}
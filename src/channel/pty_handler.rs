@@ -1,18 +1,50 @@
 //! PTY handling - spawn and manage pseudo-terminal processes
 
-use super::{manager::ChannelManagerEvent, ChannelConfig, ChannelState};
-use anyhow::Result;
-use portable_pty::{native_pty_system, ChildKiller, CommandBuilder, MasterPty, PtySize};
+use super::{
+    async_io::AsyncFile, manager::ChannelManagerEvent, utf8::Utf8Chunker, ChannelConfig,
+    ChannelMode, ChannelState, OutputStream,
+};
+use anyhow::{anyhow, Result};
+use portable_pty::{
+    native_pty_system, Child, ChildKiller, CommandBuilder, ExitStatus, MasterPty, PtySize,
+};
 use std::{
-    io::{Read, Write},
+    os::fd::{BorrowedFd, OwnedFd},
     path::PathBuf,
+    process::{Command, Stdio},
     sync::{Arc, RwLock},
+    time::Duration,
 };
 use tokio::{
+    signal::unix::{signal, SignalKind},
     sync::{mpsc, Mutex},
     task,
 };
 
+/// How long an exit report waits for the output readers to reach EOF
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Input is written to the channel in chunks of at most this many bytes
+const INPUT_CHUNK_BYTES: usize = 4096;
+
+/// Input chunks queued for a channel before further input is refused (4 MiB)
+const INPUT_QUEUE_CHUNKS: usize = 1024;
+
+/// How long a channel stays Starting without output before it counts as
+/// ready anyway, so quiet processes don't look stuck
+const STARTUP_READY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// What is in the foreground of a channel's terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Foreground {
+    /// The terminal couldn't be checked just now
+    Unknown,
+    /// The channel's own process (or it has no terminal)
+    Own,
+    /// Another process group, by its leader's pid
+    Process(u32),
+}
+
 // Fields are deliberately kept for future server-side status reporting; suppress dead_code lint until wired.
 #[allow(dead_code)]
 /// A single PTY channel
@@ -32,17 +64,21 @@ pub struct PtyChannel {
     /// Process ID (when running)
     pid: Option<u32>,
 
-    /// Master PTY handle (for resize)
-    master: Arc<Mutex<Box<dyn MasterPty + Send>>>,
+    /// Master PTY handle (for resize); `None` for pipe-mode channels
+    master: Option<Arc<Mutex<Box<dyn MasterPty + Send>>>>,
 
-    /// Writer to the PTY master
-    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    /// Input queued for the task writing to the PTY master (or the
+    /// process's stdin)
+    input_tx: mpsc::Sender<Vec<u8>>,
 
     /// Child process killer handle
     killer: Option<Box<dyn ChildKiller + Send + Sync>>,
 
     /// Output stream receiver
     output_rx: Option<mpsc::Receiver<Vec<u8>>>,
+
+    /// When the process was spawned
+    started_at: chrono::DateTime<chrono::Utc>,
 }
 
 impl PtyChannel {
@@ -64,148 +100,202 @@ impl PtyChannel {
         config: ChannelConfig,
         event_notifier: Option<mpsc::Sender<ChannelManagerEvent>>,
     ) -> Result<Self> {
-        let working_dir = config
-            .working_dir
-            .clone()
-            .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/")));
-
-        let command_str = config
-            .command
-            .clone()
-            .unwrap_or_else(|| std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string()));
-
-        let pty_system = native_pty_system();
-        let pair = pty_system.openpty(Self::pty_size_from_config(&config))?;
-
-        // Parse command string into executable and arguments
-        // Use shell to handle complex commands with pipes, redirects, etc.
-        let (executable, args) = if command_str.contains(' ')
-            || command_str.contains('|')
-            || command_str.contains('>')
-            || command_str.contains('<')
-            || command_str.contains('&')
-            || command_str.contains(';')
-        {
-            // Complex command - use shell to execute
-            let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
-            (shell, vec!["-c".to_string(), command_str.clone()])
-        } else {
-            // Simple command - execute directly
-            (command_str.clone(), vec![])
-        };
-
-        let mut cmd = CommandBuilder::new(&executable);
-        for arg in &args {
-            cmd.arg(arg);
-        }
-        cmd.cwd(&working_dir);
-        let command = command_str;
-
-        // Set TERM for proper terminal emulation
-        cmd.env("TERM", "xterm-256color");
+        let host_dir = || std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+        let working_dir = config.working_dir.clone().unwrap_or_else(host_dir);
+
+        let tty = config.mode == ChannelMode::Pty;
+        let (mut cmd, command) = match &config.container {
+            Some(target) => {
+                // The process runs in the container, so env and working dir go
+                // to `exec` rather than the host-side CLI
+                let env = config.env.clone().unwrap_or_default();
+                let mut cmd = CommandBuilder::new(target.runtime.program());
+                for arg in target.exec_args(
+                    config.command.as_deref(),
+                    config.working_dir.as_deref(),
+                    &env,
+                    tty,
+                ) {
+                    cmd.arg(arg);
+                }
+                let command = config.command.clone().unwrap_or_else(|| "sh".to_string());
+                (cmd, command)
+            }
+            None => {
+                let command_str = config.command.clone().unwrap_or_else(|| {
+                    std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+                });
+
+                // Parse command string into executable and arguments
+                // Use shell to handle complex commands with pipes, redirects, etc.
+                let (executable, args) = if command_str.contains(' ')
+                    || command_str.contains('|')
+                    || command_str.contains('>')
+                    || command_str.contains('<')
+                    || command_str.contains('&')
+                    || command_str.contains(';')
+                {
+                    // Complex command - use shell to execute
+                    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+                    (shell, vec!["-c".to_string(), command_str.clone()])
+                } else {
+                    // Simple command - execute directly
+                    (command_str.clone(), vec![])
+                };
+
+                let mut cmd = CommandBuilder::new(&executable);
+                for arg in &args {
+                    cmd.arg(arg);
+                }
 
-        if let Some(env) = &config.env {
-            for (key, value) in env {
-                cmd.env(key, value);
+                if let Some(env) = &config.env {
+                    for (key, value) in env {
+                        cmd.env(key, value);
+                    }
+                }
+                (cmd, command_str)
             }
+        };
+        // A container channel's directory is inside the container; the
+        // runtime CLI itself runs wherever the server does
+        match config.container {
+            Some(_) => cmd.cwd(host_dir()),
+            None => cmd.cwd(&working_dir),
         }
 
-        let mut child = pair.slave.spawn_command(cmd)?;
+        let (mut child, readers, writer, master) = match config.mode {
+            ChannelMode::Pty => {
+                // Set TERM for proper terminal emulation
+                cmd.env("TERM", "xterm-256color");
+
+                let pty_system = native_pty_system();
+                let pair = pty_system.openpty(Self::pty_size_from_config(&config))?;
+                let child = pair.slave.spawn_command(cmd)?;
+
+                // Separate descriptors for reading and writing the master,
+                // taken before it's wrapped in a Mutex
+                let fd = pair
+                    .master
+                    .as_raw_fd()
+                    .ok_or_else(|| anyhow!("PTY master has no file descriptor"))?;
+                // SAFETY: the master owns `fd` and outlives these calls
+                let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+                let readers = vec![(OutputStream::Stdout, fd.try_clone_to_owned()?)];
+                (child, readers, fd.try_clone_to_owned()?, Some(pair.master))
+            }
+            ChannelMode::Pipe => {
+                let mut child = Self::spawn_piped(&cmd)?;
+                let stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin pipe"))?;
+                let stdout = child
+                    .stdout
+                    .take()
+                    .ok_or_else(|| anyhow!("No stdout pipe"))?;
+                let stderr = child
+                    .stderr
+                    .take()
+                    .ok_or_else(|| anyhow!("No stderr pipe"))?;
+                let child: Box<dyn Child + Send + Sync> = Box::new(child);
+                let readers = vec![
+                    (OutputStream::Stdout, OwnedFd::from(stdout)),
+                    (OutputStream::Stderr, OwnedFd::from(stderr)),
+                ];
+                (child, readers, OwnedFd::from(stdin), None)
+            }
+        };
         let pid = child.process_id();
         let killer = Some(child.clone_killer());
-        let state = Arc::new(RwLock::new(ChannelState::Running));
-
-        // Take reader and writer before wrapping master in Mutex to avoid potential deadlock
-        let mut reader = pair.master.try_clone_reader()?;
-        let writer = pair.master.take_writer()?;
-        let master = Arc::new(Mutex::new(pair.master));
-        let writer = Arc::new(Mutex::new(writer));
+        let state = Arc::new(RwLock::new(ChannelState::Starting));
+        let master = master.map(|master| Arc::new(Mutex::new(master)));
+        let writer = AsyncFile::new(writer)?;
+        let (input_tx, input_rx) = mpsc::channel(INPUT_QUEUE_CHUNKS);
+        task::spawn(Self::pump_input(writer, input_rx, config.name.clone()));
 
         let (output_tx, output_rx) = mpsc::channel(64);
-        let output_log_name = config.name.clone();
-        let output_event_name = output_log_name.clone();
         let wait_log_name = config.name.clone();
         let wait_event_name = wait_log_name.clone();
         let state_for_wait = Arc::clone(&state);
-        let notifier_for_output = event_notifier.clone();
-
-        // Async output reader (runs in blocking thread)
-        task::spawn_blocking(move || {
-            let mut buf = [0u8; 4096];
-            loop {
-                match reader.read(&mut buf) {
-                    Ok(0) => {
-                        tracing::debug!("PTY EOF for channel '{}'", output_log_name);
-                        break;
+
+        // Async output readers, one per stream. Each holds a `drained`
+        // sender until it hits EOF.
+        // Starting is reported before any reader can report Running
+        if let Some(notifier) = &event_notifier {
+            let _ = notifier
+                .send(ChannelManagerEvent::StateChanged {
+                    channel_name: config.name.clone(),
+                    state: ChannelState::Starting,
+                })
+                .await;
+        }
+        let (drained_tx, mut drained_rx) = mpsc::channel::<()>(1);
+        for (stream, reader) in readers {
+            let reader = AsyncFile::new(reader)?;
+            let name = config.name.clone();
+            let notifier = event_notifier.clone();
+            let output_tx = output_tx.clone();
+            let drained_tx = drained_tx.clone();
+            let state = Arc::clone(&state);
+            task::spawn(async move {
+                Self::pump_output(reader, stream, &name, &state, notifier.as_ref(), &output_tx)
+                    .await;
+                drop(drained_tx);
+            });
+        }
+        drop(drained_tx);
+
+        // Ready without output once the startup timeout passes
+        let name = config.name.clone();
+        let notifier = event_notifier.clone();
+        let state_for_ready = Arc::clone(&state);
+        task::spawn(async move {
+            tokio::time::sleep(STARTUP_READY_TIMEOUT).await;
+            Self::mark_ready(&state_for_ready, &name, notifier.as_ref()).await;
+        });
+
+        // Track child exit
+        task::spawn(async move {
+            match Self::wait_for_exit(&mut child).await {
+                Ok(status) => {
+                    // Report the exit after the remaining output, unless something
+                    // else (e.g. a background job) keeps the streams open
+                    let _ = tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, drained_rx.recv()).await;
+
+                    let code = Some(status.exit_code() as i32);
+                    if let Ok(mut guard) = state_for_wait.write() {
+                        // Keep Killed so an explicit kill isn't mistaken for a normal exit
+                        if *guard != ChannelState::Killed {
+                            *guard = ChannelState::Exited(code);
+                        }
                     }
-                    Ok(n) => {
-                        let chunk = buf[..n].to_vec();
-
-                        // Send via notifier if available, otherwise via output_tx
-                        // This avoids duplicate sends when ChannelManager is listening
-                        if let Some(notifier) = &notifier_for_output {
-                            if notifier
-                                .blocking_send(ChannelManagerEvent::Output {
-                                    channel_name: output_event_name.clone(),
-                                    data: chunk,
-                                })
-                                .is_err()
-                            {
-                                tracing::debug!(
-                                    "Event notifier closed for channel '{}'",
-                                    output_log_name
-                                );
-                                break;
-                            }
-                        } else if output_tx.blocking_send(chunk).is_err() {
+                    tracing::info!("Channel '{}' exited with code {:?}", wait_log_name, code);
+                    if let Some(notifier) = event_notifier {
+                        if notifier
+                            .send(ChannelManagerEvent::StateChanged {
+                                channel_name: wait_event_name.clone(),
+                                state: ChannelState::Exited(code),
+                            })
+                            .await
+                            .is_err()
+                        {
                             tracing::debug!(
-                                "Output channel closed for channel '{}'",
-                                output_log_name
+                                "Event notifier closed when reporting exit for '{}'",
+                                wait_log_name
                             );
-                            break;
                         }
                     }
-                    Err(err) => {
-                        tracing::warn!("Read error on channel '{}': {}", output_log_name, err);
-                        break;
-                    }
                 }
-            }
-        });
-
-        // Track child exit without blocking the async runtime
-        task::spawn_blocking(move || match child.wait() {
-            Ok(status) => {
-                let code = Some(status.exit_code() as i32);
-                if let Ok(mut guard) = state_for_wait.write() {
-                    *guard = ChannelState::Exited(code);
-                }
-                tracing::info!("Channel '{}' exited with code {:?}", wait_log_name, code);
-                if let Some(notifier) = event_notifier {
-                    if notifier
-                        .blocking_send(ChannelManagerEvent::StateChanged {
-                            channel_name: wait_event_name.clone(),
-                            state: ChannelState::Exited(code),
-                        })
-                        .is_err()
-                    {
-                        tracing::debug!(
-                            "Event notifier closed when reporting exit for '{}'",
-                            wait_log_name
-                        );
+                Err(err) => {
+                    tracing::warn!("Failed waiting on child '{}': {}", wait_log_name, err);
+                    if let Ok(mut guard) = state_for_wait.write() {
+                        *guard = ChannelState::Exited(None);
+                    }
+                    if let Some(notifier) = event_notifier {
+                        let _ = notifier
+                            .send(ChannelManagerEvent::StateChanged {
+                                channel_name: wait_event_name,
+                                state: ChannelState::Exited(None),
+                            })
+                            .await;
                     }
-                }
-            }
-            Err(err) => {
-                tracing::warn!("Failed waiting on child '{}': {}", wait_log_name, err);
-                if let Ok(mut guard) = state_for_wait.write() {
-                    *guard = ChannelState::Exited(None);
-                }
-                if let Some(notifier) = event_notifier {
-                    let _ = notifier.blocking_send(ChannelManagerEvent::StateChanged {
-                        channel_name: wait_event_name,
-                        state: ChannelState::Exited(None),
-                    });
                 }
             }
         });
@@ -225,12 +315,123 @@ impl PtyChannel {
             command,
             pid,
             master,
-            writer,
+            input_tx,
             killer,
             output_rx: Some(output_rx),
+            started_at: chrono::Utc::now(),
         })
     }
 
+    /// Spawn `cmd` with piped stdio instead of a PTY
+    fn spawn_piped(cmd: &CommandBuilder) -> Result<std::process::Child> {
+        let argv = cmd.get_argv();
+        let program = argv.first().ok_or_else(|| anyhow!("Empty command"))?;
+        let mut command = Command::new(program);
+        command
+            .args(&argv[1..])
+            .envs(cmd.iter_extra_env_as_str())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(dir) = cmd.get_cwd() {
+            command.current_dir(dir);
+        }
+        Ok(command.spawn()?)
+    }
+
+    /// Wait for the child to exit, checking whenever a child process changes
+    /// state rather than blocking a thread in `wait()`
+    async fn wait_for_exit(
+        child: &mut Box<dyn Child + Send + Sync>,
+    ) -> std::io::Result<ExitStatus> {
+        let mut sigchld = signal(SignalKind::child())?;
+        loop {
+            // Checked after subscribing, so an exit before then isn't missed
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+            sigchld.recv().await;
+        }
+    }
+
+    /// Forward chunks read from one output stream until EOF, never splitting
+    /// a UTF-8 character between chunks.
+    ///
+    /// Output goes via the notifier if available, otherwise via `output_tx`.
+    /// This avoids duplicate sends when ChannelManager is listening.
+    async fn pump_output(
+        reader: AsyncFile,
+        stream: OutputStream,
+        name: &str,
+        state: &RwLock<ChannelState>,
+        notifier: Option<&mpsc::Sender<ChannelManagerEvent>>,
+        output_tx: &mpsc::Sender<Vec<u8>>,
+    ) {
+        let mut buf = [0u8; 4096];
+        let mut chunker = Utf8Chunker::default();
+        loop {
+            let (chunk, done) = match reader.read(&mut buf).await {
+                Ok(0) => {
+                    tracing::debug!("EOF on {:?} for channel '{}'", stream, name);
+                    (chunker.finish(), true)
+                }
+                Ok(n) => (chunker.push(&buf[..n]), false),
+                Err(err) => {
+                    tracing::warn!("Read error on channel '{}': {}", name, err);
+                    (chunker.finish(), true)
+                }
+            };
+            if !chunk.is_empty() {
+                // The first output marks the channel ready
+                Self::mark_ready(state, name, notifier).await;
+                if let Some(notifier) = notifier {
+                    if notifier
+                        .send(ChannelManagerEvent::Output {
+                            channel_name: name.to_string(),
+                            data: chunk,
+                            stream,
+                        })
+                        .await
+                        .is_err()
+                    {
+                        tracing::debug!("Event notifier closed for channel '{}'", name);
+                        break;
+                    }
+                } else if output_tx.send(chunk).await.is_err() {
+                    tracing::debug!("Output channel closed for channel '{}'", name);
+                    break;
+                }
+            }
+            if done {
+                break;
+            }
+        }
+    }
+
+    /// Move a Starting channel to Running, reporting the change. Does
+    /// nothing once it has started (or already ended).
+    async fn mark_ready(
+        state: &RwLock<ChannelState>,
+        name: &str,
+        notifier: Option<&mpsc::Sender<ChannelManagerEvent>>,
+    ) {
+        let ready = match state.write() {
+            Ok(mut guard) if *guard == ChannelState::Starting => {
+                *guard = ChannelState::Running;
+                true
+            }
+            _ => false,
+        };
+        if let (true, Some(notifier)) = (ready, notifier) {
+            let _ = notifier
+                .send(ChannelManagerEvent::StateChanged {
+                    channel_name: name.to_string(),
+                    state: ChannelState::Running,
+                })
+                .await;
+        }
+    }
+
     fn pty_size_from_config(config: &ChannelConfig) -> PtySize {
         if let Some((cols, rows)) = config.size {
             PtySize {
@@ -244,29 +445,67 @@ impl PtyChannel {
         }
     }
 
-    /// Write data to the PTY
+    /// Queue data to be written to the PTY.
+    ///
+    /// Returns once the data is queued, so a process that stops reading its
+    /// input can't stall the caller. Input that doesn't fit in the queue is
+    /// refused as a whole rather than written in part.
     pub async fn write(&mut self, data: &[u8]) -> Result<()> {
         if !self.state().is_alive() {
             anyhow::bail!("Channel '{}' is not running", self.name);
         }
 
-        let writer = Arc::clone(&self.writer);
-        let data = data.to_vec();
+        let chunks = data.len().div_ceil(INPUT_CHUNK_BYTES);
+        if chunks > self.input_tx.capacity() {
+            anyhow::bail!(
+                "Channel '{}' is not keeping up with its input; {} bytes were not sent",
+                self.name,
+                data.len()
+            );
+        }
+        for chunk in data.chunks(INPUT_CHUNK_BYTES) {
+            self.input_tx
+                .try_send(chunk.to_vec())
+                .map_err(|_| anyhow!("Channel '{}' is not accepting input", self.name))?;
+        }
+        Ok(())
+    }
 
-        task::spawn_blocking(move || -> Result<()> {
-            let mut guard = writer.blocking_lock();
-            guard.write_all(&data)?;
-            guard.flush()?;
-            Ok(())
-        })
-        .await??;
+    /// Write queued input as the PTY becomes writable, until the channel is
+    /// dropped or the process stops accepting input
+    async fn pump_input(writer: AsyncFile, mut input_rx: mpsc::Receiver<Vec<u8>>, name: String) {
+        while let Some(chunk) = input_rx.recv().await {
+            if let Err(err) = writer.write_all(&chunk).await {
+                tracing::warn!("Write error on channel '{}': {}", name, err);
+                break;
+            }
+        }
+    }
 
-        Ok(())
+    /// What is in the PTY's foreground: the channel's own process, or
+    /// another process group - a command running in a shell, say
+    pub fn foreground(&self) -> Foreground {
+        let Some(master) = &self.master else {
+            return Foreground::Own;
+        };
+        // Busy writing or resizing; try again next time
+        let Ok(master) = master.try_lock() else {
+            return Foreground::Unknown;
+        };
+        match master
+            .process_group_leader()
+            .and_then(|leader| u32::try_from(leader).ok())
+        {
+            Some(leader) if Some(leader) != self.pid => Foreground::Process(leader),
+            _ => Foreground::Own,
+        }
     }
 
-    /// Resize the PTY
+    /// Resize the PTY (a no-op for pipe-mode channels)
     pub async fn resize(&mut self, cols: u16, rows: u16) -> Result<()> {
-        let master = Arc::clone(&self.master);
+        let Some(master) = self.master.as_ref().map(Arc::clone) else {
+            return Ok(());
+        };
         task::spawn_blocking(move || -> Result<()> {
             let guard = master.blocking_lock();
             guard.resize(PtySize {
@@ -285,7 +524,10 @@ impl PtyChannel {
 
     /// Kill the channel process
     pub async fn kill(&mut self) -> Result<()> {
-        if let Some(mut killer) = self.killer.take() {
+        // An exited process has nothing left to signal; the channel is still
+        // marked killed so it can be dismissed
+        let killer = self.killer.take().filter(|_| self.state().is_alive());
+        if let Some(mut killer) = killer {
             task::spawn_blocking(move || killer.kill())
                 .await?
                 .map_err(anyhow::Error::from)?;
@@ -319,6 +561,11 @@ impl PtyChannel {
         self.pid
     }
 
+    /// When the process was spawned
+    pub fn started_at(&self) -> chrono::DateTime<chrono::Utc> {
+        self.started_at
+    }
+
     /// Get working directory path
     pub fn working_dir(&self) -> &PathBuf {
         &self.working_dir
@@ -0,0 +1,341 @@
+//! Scheduled channels: a command re-run every so often (`--every 1h`) or on
+//! a cron expression (`--cron 0 3 * * *`), with a short history of its runs
+
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::VecDeque;
+
+/// Runs remembered per scheduled channel
+pub const RUN_HISTORY: usize = 10;
+
+/// When a scheduled channel's command runs again
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Schedule {
+    /// This many seconds after the previous run was due
+    Every(#[serde(with = "crate::config::duration::secs")] u64),
+    /// Whenever the (local) time matches a cron expression
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// First time after `after` the command is due
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Every(secs) => {
+                after.checked_add_signed(Duration::try_seconds(i64::try_from(*secs).ok()?)?)
+            }
+            Schedule::Cron(cron) => cron.next_after(after),
+        }
+    }
+
+    /// Short description: "every 1h", "cron 0 3 * * *"
+    pub fn describe(&self) -> String {
+        match self {
+            Schedule::Every(secs) => format!("every {}", crate::config::duration::format(*secs)),
+            Schedule::Cron(cron) => format!("cron {}", cron.source),
+        }
+    }
+}
+
+/// A five-field cron expression: minute, hour, day of month, month and day
+/// of week, each `*`, a number, a range `a-b` or a list of them, optionally
+/// stepped with `/n`. `@hourly`, `@daily`, `@weekly` and `@monthly` stand in
+/// for the usual expressions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Day of month or week left as `*`; when both are restricted, a day
+    /// matching either one is due
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let source = expr.split_whitespace().collect::<Vec<_>>().join(" ");
+        let expanded = match source.as_str() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split(' ').collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "Invalid cron expression '{}': expected 5 fields (minute hour day month weekday)",
+                source
+            ));
+        };
+        // Sunday is 0 or 7
+        let weekdays = parse_field(weekday, 0, 7)?;
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays: (weekdays | weekdays >> 7) & 0x7f,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+            source,
+        })
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days & 1 << date.day() != 0;
+        let weekday = self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// First minute after `after` that matches, in local time
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = after.with_timezone(&Local).naive_local();
+        let mut time = start.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        // Every day of a leap cycle has been tried by then
+        let limit = start + Duration::days(366 * 4 + 1);
+        while time <= limit {
+            let date = time.date();
+            if self.months & 1 << date.month() == 0 {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(date) {
+                time = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & 1 << time.hour() == 0 {
+                time = next_hour(time)?;
+            } else if self.minutes & 1 << time.minute() == 0 {
+                time += Duration::minutes(1);
+            } else if let Some(local) = Local.from_local_datetime(&time).earliest() {
+                return Some(local.with_timezone(&Utc));
+            } else {
+                // Skipped by a daylight saving change
+                time += Duration::minutes(1);
+            }
+        }
+        None
+    }
+}
+
+fn next_hour(time: NaiveDateTime) -> Option<NaiveDateTime> {
+    (time + Duration::hours(1)).with_minute(0)
+}
+
+/// Bit set of the values a cron field allows
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("Invalid cron field '{}' (allowed {}-{})", field, min, max);
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (
+                    a.parse().map_err(|_| invalid())?,
+                    b.parse().map_err(|_| invalid())?,
+                ),
+                // `5/15` runs from 5 to the end of the range
+                None => {
+                    let start = range.parse().map_err(|_| invalid())?;
+                    (start, if part.contains('/') { max } else { start })
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl Serialize for Cron {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cron {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Cron::parse(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+/// One run of a scheduled channel's command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub started_at: DateTime<Utc>,
+    /// When it exited, or `None` while it's running
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Exit status; `None` if it was killed or is still running
+    pub exit_code: Option<i32>,
+}
+
+/// A scheduled channel's next due time and recent runs
+#[derive(Debug, Clone)]
+pub struct ScheduleState {
+    pub schedule: Schedule,
+    pub next_run: Option<DateTime<Utc>>,
+    /// Latest runs, oldest first
+    pub runs: VecDeque<Run>,
+}
+
+impl ScheduleState {
+    /// State for a channel whose first run starts at `now`
+    pub fn new(schedule: Schedule, now: DateTime<Utc>) -> Self {
+        let mut state = Self {
+            next_run: schedule.next_after(now),
+            schedule,
+            runs: VecDeque::new(),
+        };
+        state.started(now);
+        state
+    }
+
+    /// Whether a run is due at `now`. Moves the due time on either way, so
+    /// a run still going when the next is due skips that one.
+    pub fn take_due(&mut self, now: DateTime<Utc>) -> bool {
+        let Some(due) = self.next_run.filter(|due| *due <= now) else {
+            return false;
+        };
+        self.next_run = match &self.schedule {
+            // Kept on the same grid however late this one is
+            Schedule::Every(secs) => {
+                // Only ever zero if built by hand; deserializing rejects it
+                let secs = i64::try_from(*secs).unwrap_or(i64::MAX).max(1);
+                let periods = (now - due).num_seconds() / secs + 1;
+                periods
+                    .checked_mul(secs)
+                    .and_then(Duration::try_seconds)
+                    .and_then(|wait| due.checked_add_signed(wait))
+            }
+            Schedule::Cron(cron) => cron.next_after(now),
+        };
+        true
+    }
+
+    pub fn started(&mut self, at: DateTime<Utc>) {
+        if self.runs.len() == RUN_HISTORY {
+            self.runs.pop_front();
+        }
+        self.runs.push_back(Run {
+            started_at: at,
+            finished_at: None,
+            exit_code: None,
+        });
+    }
+
+    pub fn finished(&mut self, at: DateTime<Utc>, exit_code: Option<i32>) {
+        if let Some(run) = self.runs.back_mut().filter(|run| run.finished_at.is_none()) {
+            run.finished_at = Some(at);
+            run.exit_code = exit_code;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(text: &str) -> DateTime<Utc> {
+        let naive = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn next(expr: &str, after: &str) -> String {
+        Cron::parse(expr)
+            .unwrap()
+            .next_after(local(after))
+            .unwrap()
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    }
+
+    #[test]
+    fn cron_finds_the_next_matching_minute() {
+        assert_eq!(next("0 3 * * *", "2024-05-10 02:59"), "2024-05-10 03:00");
+        assert_eq!(next("0 3 * * *", "2024-05-10 03:00"), "2024-05-11 03:00");
+        assert_eq!(next("*/15 * * * *", "2024-05-10 10:16"), "2024-05-10 10:30");
+        assert_eq!(next("30 9 * * 1-5", "2024-05-10 10:00"), "2024-05-13 09:30");
+        assert_eq!(next("0 0 29 2 *", "2024-03-01 00:00"), "2028-02-29 00:00");
+        assert_eq!(next("@monthly", "2024-12-15 12:00"), "2025-01-01 00:00");
+        // Restricted day of month and weekday: either one will do
+        assert_eq!(next("0 12 1 * 0", "2024-05-10 00:00"), "2024-05-12 12:00");
+        // Sunday as 7
+        assert_eq!(next("0 12 * * 7", "2024-05-10 00:00"), "2024-05-12 12:00");
+    }
+
+    #[test]
+    fn rejects_bad_cron_expressions() {
+        for expr in [
+            "0 3 * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "x * * * *",
+        ] {
+            assert!(Cron::parse(expr).is_err(), "{}", expr);
+        }
+        assert_eq!(
+            Schedule::Cron(Cron::parse("0  3 * * *").unwrap()).describe(),
+            "cron 0 3 * * *"
+        );
+    }
+
+    #[test]
+    fn overdue_runs_are_skipped_not_queued() {
+        let start = local("2024-05-10 10:00");
+        let mut state = ScheduleState::new(Schedule::Every(600), start);
+        assert!(!state.take_due(start + Duration::minutes(9)));
+        // Two runs' worth late: one run, then back on the 10-minute grid
+        assert!(state.take_due(start + Duration::minutes(25)));
+        assert_eq!(state.next_run, Some(start + Duration::minutes(30)));
+        assert!(!state.take_due(start + Duration::minutes(26)));
+
+        state.finished(start + Duration::minutes(1), Some(0));
+        for _ in 0..RUN_HISTORY {
+            state.started(start);
+        }
+        assert_eq!(state.runs.len(), RUN_HISTORY);
+        assert!(state.runs.iter().all(|run| run.finished_at.is_none()));
+    }
+
+    #[test]
+    fn zero_or_huge_intervals_never_panic() {
+        let start = local("2024-05-10 10:00");
+        let mut state = ScheduleState::new(Schedule::Every(0), start);
+        assert!(state.take_due(start + Duration::seconds(5)));
+
+        let mut state = ScheduleState::new(Schedule::Every(u64::MAX), start);
+        assert_eq!(state.next_run, None);
+        state.next_run = Some(start);
+        assert!(state.take_due(start));
+        assert_eq!(state.next_run, None);
+    }
+}
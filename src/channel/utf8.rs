@@ -0,0 +1,71 @@
+//! Keeping multi-byte UTF-8 sequences whole across output chunks
+
+/// Holds back a trailing partial UTF-8 sequence from each read until the
+/// rest of it arrives, so no chunk ends mid-character. Bytes that can't
+/// start a valid sequence pass straight through, so binary output isn't
+/// delayed.
+#[derive(Debug, Default)]
+pub(super) struct Utf8Chunker {
+    pending: Vec<u8>,
+}
+
+impl Utf8Chunker {
+    /// Take a chunk as read, returning what can be sent now
+    pub(super) fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut chunk = std::mem::take(&mut self.pending);
+        chunk.extend_from_slice(data);
+        let keep = incomplete_suffix_len(&chunk);
+        self.pending = chunk.split_off(chunk.len() - keep);
+        chunk
+    }
+
+    /// Whatever is still held back, e.g. at EOF
+    pub(super) fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// Length of a multi-byte sequence cut short at the end of `data`, or 0
+fn incomplete_suffix_len(data: &[u8]) -> usize {
+    for len in 1..=data.len().min(3) {
+        let byte = data[data.len() - len];
+        if byte & 0b1100_0000 == 0b1000_0000 {
+            // Continuation byte; the lead byte is further back
+            continue;
+        }
+        let needed = match byte {
+            0b1100_0000..=0b1101_1111 => 2,
+            0b1110_0000..=0b1110_1111 => 3,
+            0b1111_0000..=0b1111_0111 => 4,
+            _ => return 0,
+        };
+        return if needed > len { len } else { 0 };
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_back_split_characters() {
+        let text = "naïve → ✓ 🦀".as_bytes();
+        for split in 0..=text.len() {
+            let mut chunker = Utf8Chunker::default();
+            let first = chunker.push(&text[..split]);
+            let second = chunker.push(&text[split..]);
+            assert!(std::str::from_utf8(&first).is_ok(), "split at {}", split);
+            assert_eq!([first, second].concat(), text);
+            assert!(chunker.finish().is_empty());
+        }
+    }
+
+    #[test]
+    fn passes_binary_through() {
+        let mut chunker = Utf8Chunker::default();
+        assert_eq!(chunker.push(b"\xff\x80\x80"), b"\xff\x80\x80");
+        assert_eq!(chunker.push(b"ok\xe2"), b"ok");
+        assert_eq!(chunker.finish(), b"\xe2");
+    }
+}
@@ -0,0 +1,362 @@
+//! ANSI output styling - SGR sequences in channel output turned into
+//! ratatui styles, with colors reduced to what the host terminal supports
+
+use crate::config::ColorDepth;
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+use regex::Regex;
+use std::sync::LazyLock;
+
+static ANSI_ESCAPE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\x1b\[[0-9;:?]*[a-zA-Z~]|\x1b\][^\x07]*(?:\x07|\x1b\\)|\x1b[a-zA-Z]").unwrap()
+});
+
+pub fn strip_ansi_codes(s: &str) -> String {
+    ANSI_ESCAPE_RE.replace_all(s, "").to_string()
+}
+
+/// Strip escape sequences, then make any stray control characters left over
+/// (a lone ESC, C1 controls, shift-out from binary junk) visible instead of
+/// letting them reach the terminal
+pub fn sanitize_output(s: &str) -> String {
+    visible_controls(&strip_ansi_codes(s))
+}
+
+/// Replace control characters other than tab with visible stand-ins
+fn visible_controls(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\t' => c,
+            '\x00'..='\x1f' => char::from_u32(0x2400 + c as u32).unwrap_or('\u{fffd}'),
+            '\x7f' => '\u{2421}',
+            '\u{80}'..='\u{9f}' => '\u{fffd}',
+            _ => c,
+        })
+        .collect()
+}
+
+/// Colors the host terminal can show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSupport {
+    /// 24-bit RGB
+    TrueColor,
+    /// The xterm 256-color palette
+    Ansi256,
+    /// The 16 basic colors
+    Ansi16,
+}
+
+impl ColorSupport {
+    /// Support chosen in config, detecting it from `COLORTERM` and `TERM`
+    /// for `auto`
+    pub fn from_config(depth: ColorDepth) -> Self {
+        match depth {
+            ColorDepth::Auto => Self::detect(
+                std::env::var("COLORTERM").ok().as_deref(),
+                std::env::var("TERM").ok().as_deref(),
+            ),
+            ColorDepth::Truecolor => ColorSupport::TrueColor,
+            ColorDepth::Ansi256 => ColorSupport::Ansi256,
+            ColorDepth::Ansi16 => ColorSupport::Ansi16,
+        }
+    }
+
+    fn detect(colorterm: Option<&str>, term: Option<&str>) -> Self {
+        if matches!(colorterm, Some("truecolor" | "24bit")) {
+            ColorSupport::TrueColor
+        } else if term.is_some_and(|term| term.contains("256color")) {
+            ColorSupport::Ansi256
+        } else {
+            ColorSupport::Ansi16
+        }
+    }
+
+    /// `color` as the nearest one the terminal can show
+    pub fn adapt(self, color: Color) -> Color {
+        match (self, color) {
+            (ColorSupport::TrueColor, _) => color,
+            (ColorSupport::Ansi256, Color::Rgb(r, g, b)) => Color::Indexed(rgb_to_256(r, g, b)),
+            (ColorSupport::Ansi16, Color::Rgb(r, g, b)) => nearest_basic((r, g, b)),
+            (ColorSupport::Ansi16, Color::Indexed(n)) => nearest_basic(indexed_rgb(n)),
+            _ => color,
+        }
+    }
+}
+
+/// Parse a line of output into spans styled by its SGR sequences, on top of
+/// `base`. Other escape sequences are dropped and control characters made
+/// visible, as with [`sanitize_output`].
+pub fn styled_line(s: &str, base: Style, colors: ColorSupport) -> Line<'static> {
+    let mut spans = Vec::new();
+    let mut style = Style::default();
+    let mut last = 0;
+    for escape in ANSI_ESCAPE_RE.find_iter(s) {
+        if escape.start() > last {
+            spans.push(Span::styled(
+                visible_controls(&s[last..escape.start()]),
+                style,
+            ));
+        }
+        last = escape.end();
+        if let Some(params) = escape
+            .as_str()
+            .strip_prefix("\x1b[")
+            .and_then(|rest| rest.strip_suffix('m'))
+        {
+            style = apply_sgr(style, params, colors);
+        }
+    }
+    if last < s.len() {
+        spans.push(Span::styled(visible_controls(&s[last..]), style));
+    }
+    Line::from(spans).style(base)
+}
+
+/// Apply an SGR parameter list (the part between `ESC [` and `m`) to `style`
+fn apply_sgr(mut style: Style, params: &str, colors: ColorSupport) -> Style {
+    // Colon-separated subparameters (`38:5:208`) read like semicolons
+    let codes: Vec<Option<u16>> = params
+        .split([';', ':'])
+        .map(|code| {
+            if code.is_empty() {
+                Some(0)
+            } else {
+                code.parse().ok()
+            }
+        })
+        .collect();
+    let mut codes = codes.into_iter();
+    while let Some(code) = codes.next() {
+        let Some(code) = code else { continue };
+        style = match code {
+            0 => Style::default(),
+            1 => style.add_modifier(Modifier::BOLD),
+            2 => style.add_modifier(Modifier::DIM),
+            3 => style.add_modifier(Modifier::ITALIC),
+            4 => style.add_modifier(Modifier::UNDERLINED),
+            5 => style.add_modifier(Modifier::SLOW_BLINK),
+            7 => style.add_modifier(Modifier::REVERSED),
+            9 => style.add_modifier(Modifier::CROSSED_OUT),
+            22 => style.remove_modifier(Modifier::BOLD | Modifier::DIM),
+            23 => style.remove_modifier(Modifier::ITALIC),
+            24 => style.remove_modifier(Modifier::UNDERLINED),
+            25 => style.remove_modifier(Modifier::SLOW_BLINK),
+            27 => style.remove_modifier(Modifier::REVERSED),
+            29 => style.remove_modifier(Modifier::CROSSED_OUT),
+            30..=37 => style.fg(basic_color(code - 30)),
+            39 => style.fg(Color::Reset),
+            40..=47 => style.bg(basic_color(code - 40)),
+            49 => style.bg(Color::Reset),
+            90..=97 => style.fg(basic_color(code - 90 + 8)),
+            100..=107 => style.bg(basic_color(code - 100 + 8)),
+            38 | 48 => {
+                let Some(color) = extended_color(&mut codes) else {
+                    break;
+                };
+                let color = colors.adapt(color);
+                if code == 38 {
+                    style.fg(color)
+                } else {
+                    style.bg(color)
+                }
+            }
+            _ => style,
+        };
+    }
+    style
+}
+
+/// The color after a 38 or 48: `5;n` for the 256-color palette or
+/// `2;r;g;b` for RGB
+fn extended_color(codes: &mut impl Iterator<Item = Option<u16>>) -> Option<Color> {
+    let mut next = || codes.next().flatten().and_then(|n| u8::try_from(n).ok());
+    match next()? {
+        5 => Some(Color::Indexed(next()?)),
+        2 => Some(Color::Rgb(next()?, next()?, next()?)),
+        _ => None,
+    }
+}
+
+/// One of the 16 basic colors by SGR index (0-7 normal, 8-15 bright)
+fn basic_color(index: u16) -> Color {
+    const COLORS: [Color; 16] = [
+        Color::Black,
+        Color::Red,
+        Color::Green,
+        Color::Yellow,
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+        Color::Gray,
+        Color::DarkGray,
+        Color::LightRed,
+        Color::LightGreen,
+        Color::LightYellow,
+        Color::LightBlue,
+        Color::LightMagenta,
+        Color::LightCyan,
+        Color::White,
+    ];
+    COLORS[index as usize % 16]
+}
+
+/// xterm's default RGB values for the 16 basic colors
+const BASIC_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// Channel levels of the 6x6x6 color cube
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// RGB of a 256-color palette entry
+fn indexed_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => BASIC_RGB[n as usize],
+        16..=231 => {
+            let n = n - 16;
+            (
+                CUBE_LEVELS[(n / 36) as usize],
+                CUBE_LEVELS[(n / 6 % 6) as usize],
+                CUBE_LEVELS[(n % 6) as usize],
+            )
+        }
+        232..=255 => {
+            let level = 8 + (n - 232) * 10;
+            (level, level, level)
+        }
+    }
+}
+
+/// Closest 256-color palette entry to an RGB color, from the cube or the
+/// gray ramp
+fn rgb_to_256(r: u8, g: u8, b: u8) -> u8 {
+    let level = |c: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&i| CUBE_LEVELS[i].abs_diff(c))
+            .unwrap_or(0) as u8
+    };
+    let cube = 16 + 36 * level(r) + 6 * level(g) + level(b);
+
+    let average = ((r as u16 + g as u16 + b as u16) / 3) as u8;
+    let gray = 232 + (average.saturating_sub(3) / 10).min(23);
+
+    if distance((r, g, b), indexed_rgb(gray)) < distance((r, g, b), indexed_rgb(cube)) {
+        gray
+    } else {
+        cube
+    }
+}
+
+/// Closest of the 16 basic colors
+fn nearest_basic(rgb: (u8, u8, u8)) -> Color {
+    let index = (0..BASIC_RGB.len())
+        .min_by_key(|&i| distance(rgb, BASIC_RGB[i]))
+        .unwrap_or(0);
+    basic_color(index as u16)
+}
+
+/// Squared distance between two RGB colors
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn styles(line: &Line) -> Vec<(String, Style)> {
+        line.spans
+            .iter()
+            .map(|span| (span.content.to_string(), span.style))
+            .collect()
+    }
+
+    #[test]
+    fn parses_basic_256_and_rgb_colors() {
+        let line = styled_line(
+            "\x1b[1;31mred\x1b[0m plain \x1b[38;5;208mamber\x1b[48;2;1;2;3mon rgb\x1b[39;49m",
+            Style::default(),
+            ColorSupport::TrueColor,
+        );
+
+        assert_eq!(
+            styles(&line),
+            vec![
+                (
+                    "red".to_string(),
+                    Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+                ),
+                (" plain ".to_string(), Style::default()),
+                (
+                    "amber".to_string(),
+                    Style::default().fg(Color::Indexed(208))
+                ),
+                (
+                    "on rgb".to_string(),
+                    Style::default()
+                        .fg(Color::Indexed(208))
+                        .bg(Color::Rgb(1, 2, 3))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_other_escapes_and_shows_controls() {
+        let line = styled_line("\x1b[2Ka\x07b", Style::default(), ColorSupport::TrueColor);
+        assert_eq!(line.spans.len(), 1);
+        assert_eq!(line.spans[0].content, "a\u{2407}b");
+    }
+
+    #[test]
+    fn reduces_colors_to_terminal_support() {
+        assert_eq!(
+            ColorSupport::Ansi256.adapt(Color::Rgb(255, 135, 0)),
+            Color::Indexed(208)
+        );
+        assert_eq!(
+            ColorSupport::Ansi256.adapt(Color::Rgb(128, 128, 128)),
+            Color::Indexed(244)
+        );
+        assert_eq!(
+            ColorSupport::Ansi16.adapt(Color::Rgb(250, 10, 10)),
+            Color::LightRed
+        );
+        assert_eq!(ColorSupport::Ansi16.adapt(Color::Indexed(21)), Color::Blue);
+        assert_eq!(ColorSupport::Ansi16.adapt(Color::Cyan), Color::Cyan);
+    }
+
+    #[test]
+    fn detects_support_from_environment() {
+        assert_eq!(
+            ColorSupport::detect(Some("truecolor"), Some("xterm")),
+            ColorSupport::TrueColor
+        );
+        assert_eq!(
+            ColorSupport::detect(None, Some("xterm-256color")),
+            ColorSupport::Ansi256
+        );
+        assert_eq!(
+            ColorSupport::detect(None, Some("vt100")),
+            ColorSupport::Ansi16
+        );
+    }
+}
@@ -0,0 +1,669 @@
+//! Programmatic client API for driving a nexus session from other tools
+//!
+//! ```no_run
+//! use nexus::client::{ClientEvent, NexusClient};
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let mut client = NexusClient::connect_session("dev").await?;
+//! let mut events = client.take_events().expect("events already taken");
+//!
+//! client.create_channel("build", Some("cargo build")).await?;
+//! client.subscribe(&["build"]).await?;
+//!
+//! while let Some(event) = events.next().await {
+//!     if let ClientEvent::Output { data, .. } = event {
+//!         print!("{}", String::from_utf8_lossy(&data));
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requests are tagged with an id that the server echoes in its `Ack` or
+//! `Error`, but other replies aren't, so the client keeps one request in
+//! flight at a time; methods may still be called concurrently from several
+//! tasks.
+
+use crate::channel::OutputStream;
+use crate::config::Config;
+use crate::protocol::{
+    deserialize, serialize, Capability, ChannelEvent, ChannelInfo, ChannelStatus, ClientInfo,
+    ClientMessage, CreateChannelRequest, NotificationKind, NotificationPrefs, ServerMessage,
+    SessionInfo, CAPABILITIES, PROTOCOL_VERSION,
+};
+use crate::server::connection::{read_message, write_message};
+use crate::server::tls::ClientTls;
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+/// How long to wait for the server to answer a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Output and notifications pushed by the server
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// Output from a subscribed channel
+    Output {
+        channel: String,
+        data: Bytes,
+        timestamp: i64,
+        stream: OutputStream,
+        /// Position in the channel's output; see [`NexusClient::resume`]
+        seq: u64,
+    },
+    /// Channel lifecycle or subscription change
+    Channel(ChannelEvent),
+    /// Another client added a command to the shared history
+    HistoryAppended { channel: String, entry: String },
+    /// The session was renamed
+    SessionRenamed { name: String },
+    /// Another client attached
+    ClientJoined(ClientInfo),
+    /// Another client detached or disconnected
+    ClientLeft(ClientInfo),
+    /// A note sent to every client with `:wall`, this one's included
+    Wall { from: String, message: String },
+    /// Channel activity asked for with [`NexusClient::set_notifications`]
+    Notification {
+        channel: String,
+        kind: NotificationKind,
+        message: String,
+    },
+    /// The session's pinned channels changed
+    PinnedChanged(Vec<String>),
+}
+
+/// Stream of [`ClientEvent`]s, ending when the server disconnects
+pub struct EventStream {
+    receiver: mpsc::UnboundedReceiver<ClientEvent>,
+}
+
+impl EventStream {
+    /// Wait for the next event; `None` once the connection is closed
+    pub async fn next(&mut self) -> Option<ClientEvent> {
+        self.receiver.recv().await
+    }
+
+    /// Next event if one is already queued
+    pub fn try_next(&mut self) -> Option<ClientEvent> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Connection to a nexus server
+pub struct NexusClient {
+    session_id: Uuid,
+    resume_token: Option<String>,
+    capabilities: Vec<Capability>,
+    next_request_id: AtomicU64,
+    writer: Mutex<Box<dyn AsyncWrite + Unpin + Send>>,
+    replies: Mutex<mpsc::UnboundedReceiver<ServerMessage>>,
+    events: Option<EventStream>,
+    reader: JoinHandle<()>,
+}
+
+impl NexusClient {
+    /// Connect to the server for a named session using the configured socket path
+    pub async fn connect_session(name: &str) -> Result<Self> {
+        let config = Config::load()?;
+        Self::connect(&config.socket_path(name)).await
+    }
+
+    /// Connect to a server socket and complete the handshake
+    pub async fn connect(socket_path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(socket_path)
+            .await
+            .with_context(|| format!("Failed to connect to {}", socket_path.display()))?;
+        Self::from_stream(stream).await
+    }
+
+    /// Complete the handshake on an already connected socket
+    pub async fn from_stream(stream: UnixStream) -> Result<Self> {
+        let (reader, writer) = stream.into_split();
+        Self::handshake(reader, Box::new(writer), None).await
+    }
+
+    /// Connect to a server's TCP listener, authenticating with its token
+    pub async fn connect_tcp(addr: &str, token: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .with_context(|| format!("Failed to connect to {}", addr))?;
+        let (reader, writer) = stream.into_split();
+        Self::handshake(reader, Box::new(writer), Some(token.to_string())).await
+    }
+
+    /// Connect to a server's TCP listener over TLS, authenticating with its token
+    pub async fn connect_tls(addr: &str, token: &str, tls: &ClientTls) -> Result<Self> {
+        let stream = tls.connect(addr).await?;
+        let (reader, writer) = tokio::io::split(stream);
+        Self::handshake(reader, Box::new(writer), Some(token.to_string())).await
+    }
+
+    async fn handshake<R>(
+        mut reader: R,
+        mut writer: Box<dyn AsyncWrite + Unpin + Send>,
+        token: Option<String>,
+    ) -> Result<Self>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        // Hello goes first: TCP servers wait for it before sending Welcome
+        let hello = ClientMessage::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            token,
+            identity: None,
+            capabilities: CAPABILITIES.to_vec(),
+            observe: false,
+            name: None,
+        };
+        write_message(&mut writer, &serialize(&hello)?).await?;
+
+        let (session_id, resume_token, capabilities) =
+            match timeout(REQUEST_TIMEOUT, read_message(&mut reader)).await {
+                Ok(Ok(Some(bytes))) => match deserialize(&bytes)? {
+                    ServerMessage::Welcome {
+                        session_id,
+                        resume_token,
+                        capabilities,
+                        ..
+                    } => (session_id, resume_token, capabilities),
+                    ServerMessage::Error { message, .. } => bail!("{}", message),
+                    other => bail!("Expected Welcome from server, got {:?}", other),
+                },
+                Ok(Ok(None)) => bail!("Server closed the connection"),
+                Ok(Err(e)) => return Err(e),
+                Err(_) => bail!("Timed out waiting for server"),
+            };
+
+        let (reply_tx, reply_rx) = mpsc::unbounded_channel();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let reader = tokio::spawn(route_messages(reader, reply_tx, event_tx));
+
+        let client = Self {
+            session_id,
+            resume_token,
+            capabilities,
+            next_request_id: AtomicU64::new(1),
+            writer: Mutex::new(writer),
+            replies: Mutex::new(reply_rx),
+            events: Some(EventStream { receiver: event_rx }),
+            reader,
+        };
+        wait_for_reply(&mut *client.replies.lock().await, None, |msg| {
+            is_ack(msg, "Hello")
+        })
+        .await?;
+        Ok(client)
+    }
+
+    /// ID of the connected session
+    pub fn session_id(&self) -> Uuid {
+        self.session_id
+    }
+
+    /// Token for picking this connection back up with [`NexusClient::resume`]
+    /// once it's gone; `None` from servers that don't support resuming
+    pub fn resume_token(&self) -> Option<&str> {
+        self.resume_token.as_deref()
+    }
+
+    /// Optional features the server advertised in its Welcome
+    pub fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+
+    /// Whether the server advertised a capability
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Take the stream of output and events.
+    ///
+    /// Events are buffered from the moment the client connects, so take the
+    /// stream before subscribing to avoid missing replayed output. Returns
+    /// `None` if it was already taken.
+    pub fn take_events(&mut self) -> Option<EventStream> {
+        self.events.take()
+    }
+
+    /// Create a channel, running `command` or the default shell
+    pub async fn create_channel(&self, name: &str, command: Option<&str>) -> Result<()> {
+        self.create_channel_in(name, command, None).await
+    }
+
+    /// Create a channel with an explicit working directory
+    pub async fn create_channel_in(
+        &self,
+        name: &str,
+        command: Option<&str>,
+        working_dir: Option<&str>,
+    ) -> Result<()> {
+        let msg = ClientMessage::CreateChannel(CreateChannelRequest {
+            name: name.to_string(),
+            command: command.map(str::to_string),
+            working_dir: working_dir.map(str::to_string),
+            ..Default::default()
+        });
+        self.create_channel_with(msg).await
+    }
+
+    /// Create a channel from a full `CreateChannel` request, for options the
+    /// shorthand methods don't cover
+    pub async fn create_channel_with(&self, request: ClientMessage) -> Result<()> {
+        self.request(request, |msg| is_ack(msg, "CreateChannel"))
+            .await
+            .map(|_| ())
+    }
+
+    /// Rename the session; clients connect under the new name afterwards
+    pub async fn rename_session(&self, name: &str) -> Result<()> {
+        let msg = ClientMessage::RenameSession {
+            name: name.to_string(),
+        };
+        self.request(msg, |msg| is_ack(msg, "RenameSession"))
+            .await
+            .map(|_| ())
+    }
+
+    /// Ask the server to host another session in its process
+    pub async fn start_session(&self, name: &str) -> Result<()> {
+        let msg = ClientMessage::StartSession {
+            name: name.to_string(),
+        };
+        self.request(msg, |msg| is_ack(msg, "StartSession"))
+            .await
+            .map(|_| ())
+    }
+
+    /// Kill a channel
+    pub async fn kill_channel(&self, name: &str) -> Result<()> {
+        let msg = ClientMessage::KillChannel {
+            name: name.to_string(),
+        };
+        self.request(msg, |msg| is_ack(msg, "KillChannel"))
+            .await
+            .map(|_| ())
+    }
+
+    /// Make a channel the session's active channel
+    pub async fn switch_channel(&self, name: &str) -> Result<()> {
+        let msg = ClientMessage::SwitchChannel {
+            name: name.to_string(),
+        };
+        self.request(msg, |msg| is_ack(msg, "SwitchChannel"))
+            .await
+            .map(|_| ())
+    }
+
+    /// Write input to a channel.
+    ///
+    /// The server only answers input on failure, so errors surface as a
+    /// logged stale reply rather than from this call.
+    pub async fn send_input(&self, channel: &str, data: impl AsRef<[u8]>) -> Result<()> {
+        self.send(ClientMessage::InputTo {
+            channel: channel.to_string(),
+            data: data.as_ref().to_vec(),
+        })
+        .await
+    }
+
+    /// Subscribe to channel output (`"*"` for every channel), returning the
+    /// full subscription list. Buffered output is replayed as events.
+    pub async fn subscribe(&self, channels: &[&str]) -> Result<Vec<String>> {
+        let msg = ClientMessage::Subscribe {
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+        };
+        self.request_subscriptions(msg).await
+    }
+
+    /// Unsubscribe from channel output, returning the remaining subscriptions
+    pub async fn unsubscribe(&self, channels: &[&str]) -> Result<Vec<String>> {
+        let msg = ClientMessage::Unsubscribe {
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+        };
+        self.request_subscriptions(msg).await
+    }
+
+    /// List the session's channels
+    pub async fn list_channels(&self) -> Result<Vec<ChannelInfo>> {
+        match self
+            .request(ClientMessage::ListChannels, |msg| {
+                matches!(msg, ServerMessage::ChannelList { .. })
+            })
+            .await?
+        {
+            ServerMessage::ChannelList { channels } => Ok(channels),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Detailed status of one channel, or every channel when `None`
+    pub async fn status(&self, channel: Option<&str>) -> Result<Vec<ChannelStatus>> {
+        let msg = ClientMessage::GetStatus {
+            channel: channel.map(str::to_string),
+        };
+        match self
+            .request(msg, |msg| matches!(msg, ServerMessage::Status { .. }))
+            .await?
+        {
+            ServerMessage::Status { channels } => Ok(channels),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The session's metadata and how long it has been running
+    pub async fn session_info(&self) -> Result<(SessionInfo, Duration)> {
+        match self
+            .request(ClientMessage::GetSessionInfo, |msg| {
+                matches!(msg, ServerMessage::SessionInfo { .. })
+            })
+            .await?
+        {
+            ServerMessage::SessionInfo { info, uptime_secs } => {
+                Ok((info, Duration::from_secs(uptime_secs)))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// The clients attached to the session, longest attached first
+    pub async fn clients(&self) -> Result<Vec<ClientInfo>> {
+        match self
+            .request(ClientMessage::ListClients, |msg| {
+                matches!(msg, ServerMessage::ClientList { .. })
+            })
+            .await?
+        {
+            ServerMessage::ClientList { clients } => Ok(clients),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Show a note to every client attached to the session
+    pub async fn wall(&self, message: &str) -> Result<()> {
+        let msg = ClientMessage::Wall {
+            message: message.to_string(),
+        };
+        self.request(msg, |msg| is_ack(msg, "Wall"))
+            .await
+            .map(|_| ())
+    }
+
+    /// Choose which channel activity arrives as [`ClientEvent::Notification`]s
+    pub async fn set_notifications(&self, prefs: NotificationPrefs) -> Result<()> {
+        if !self.supports(Capability::Notifications) {
+            bail!("Server doesn't support notifications");
+        }
+        self.request(ClientMessage::SetNotifications { prefs }, |msg| {
+            is_ack(msg, "SetNotifications")
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Pin a channel to the front of every client's channel list, or unpin it
+    pub async fn set_pinned(&self, channel: &str, pinned: bool) -> Result<()> {
+        if !self.supports(Capability::Pins) {
+            bail!("Server doesn't support pinned channels");
+        }
+        let msg = ClientMessage::SetPinned {
+            channel: channel.to_string(),
+            pinned,
+        };
+        self.request(msg, |msg| is_ack(msg, "SetPinned"))
+            .await
+            .map(|_| ())
+    }
+
+    /// Check the server is answering, returning the round-trip time
+    pub async fn ping(&self) -> Result<Duration> {
+        if !self.supports(Capability::Heartbeat) {
+            bail!("Server doesn't support heartbeats");
+        }
+        let nonce = rand_nonce();
+        let started = std::time::Instant::now();
+        self.request(
+            ClientMessage::Ping { nonce },
+            |msg| matches!(msg, ServerMessage::Pong { nonce: n } if *n == nonce),
+        )
+        .await?;
+        Ok(started.elapsed())
+    }
+
+    /// Run a command registered by a server-side script, returning its message
+    pub async fn run_script_command(&self, name: &str, args: &[&str]) -> Result<Option<String>> {
+        let msg = ClientMessage::ScriptCommand {
+            name: name.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        };
+        match self
+            .request(msg, |msg| matches!(msg, ServerMessage::ScriptResult { .. }))
+            .await?
+        {
+            ServerMessage::ScriptResult { message, .. } => Ok(message),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Take over the subscriptions of an earlier connection to the same
+    /// session, given its [`resume_token`](NexusClient::resume_token).
+    ///
+    /// Only output after the sequence numbers in `last_seen` (by channel) is
+    /// replayed. Returns false if the server no longer knows the token, in
+    /// which case nothing changed.
+    pub async fn resume(&self, token: &str, last_seen: HashMap<String, u64>) -> Result<bool> {
+        if !self.supports(Capability::Resume) {
+            bail!("Server doesn't support resuming");
+        }
+        let msg = ClientMessage::Resume {
+            token: token.to_string(),
+            last_seen,
+        };
+        match self
+            .request(msg, |msg| matches!(msg, ServerMessage::Resumed { .. }))
+            .await?
+        {
+            ServerMessage::Resumed { restored } => Ok(restored),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Detach from the session, leaving the server and its channels running
+    pub async fn detach(self) -> Result<()> {
+        self.request(ClientMessage::Detach, |msg| is_ack(msg, "Detach"))
+            .await
+            .map(|_| ())
+    }
+
+    /// Send a raw message without waiting for a reply
+    pub async fn send(&self, msg: ClientMessage) -> Result<()> {
+        let bytes = serialize(&msg)?;
+        let mut writer = self.writer.lock().await;
+        write_message(&mut *writer, &bytes).await
+    }
+
+    async fn request_subscriptions(&self, msg: ClientMessage) -> Result<Vec<String>> {
+        match self
+            .request(msg, |msg| {
+                matches!(
+                    msg,
+                    ServerMessage::Event(ChannelEvent::SubscriptionChanged { .. })
+                )
+            })
+            .await?
+        {
+            ServerMessage::Event(ChannelEvent::SubscriptionChanged { subscribed }) => {
+                Ok(subscribed)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Send `msg` tagged with a fresh request id and wait for the first reply
+    /// accepted by `is_reply`, skipping unrelated replies
+    async fn request(
+        &self,
+        msg: ClientMessage,
+        is_reply: impl Fn(&ServerMessage) -> bool,
+    ) -> Result<ServerMessage> {
+        let mut replies = self.replies.lock().await;
+
+        // Replies nobody waited for, e.g. errors from earlier input
+        while let Ok(stale) = replies.try_recv() {
+            tracing::debug!("Discarding stale reply: {:?}", stale);
+        }
+
+        // Servers without request ids get the bare message
+        if !self.supports(Capability::RequestIds) {
+            self.send(msg).await?;
+            return wait_for_reply(&mut replies, None, is_reply).await;
+        }
+        let id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let request = ClientMessage::Request {
+            id,
+            message: Box::new(msg),
+        };
+        self.send(request).await?;
+        wait_for_reply(&mut replies, Some(id), is_reply).await
+    }
+}
+
+impl Drop for NexusClient {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Wait for the first reply accepted by `is_reply`; an `Error` reply fails.
+/// Acks and errors tagged for a request other than `id` are skipped.
+async fn wait_for_reply(
+    replies: &mut mpsc::UnboundedReceiver<ServerMessage>,
+    id: Option<u64>,
+    is_reply: impl Fn(&ServerMessage) -> bool,
+) -> Result<ServerMessage> {
+    timeout(REQUEST_TIMEOUT, async {
+        loop {
+            match replies.recv().await {
+                Some(reply) if answers_other_request(&reply, id) => continue,
+                Some(ServerMessage::Error { message, .. }) => bail!("{}", message),
+                Some(reply) if is_reply(&reply) => return Ok(reply),
+                Some(_) => continue,
+                None => bail!("Server closed the connection"),
+            }
+        }
+    })
+    .await
+    .map_err(|_| anyhow!("Timed out waiting for server"))?
+}
+
+/// Nonce for a ping, so a late pong to an earlier one isn't mistaken for it
+fn rand_nonce() -> u64 {
+    Uuid::new_v4().as_u64_pair().0
+}
+
+fn answers_other_request(msg: &ServerMessage, id: Option<u64>) -> bool {
+    matches!(
+        msg,
+        ServerMessage::Ack { request_id: Some(other), .. }
+            | ServerMessage::Error { request_id: Some(other), .. }
+            if Some(*other) != id
+    )
+}
+
+fn is_ack(msg: &ServerMessage, command: &str) -> bool {
+    matches!(msg, ServerMessage::Ack { for_command, .. } if for_command == command)
+}
+
+/// Split incoming messages into replies and pushed events
+async fn route_messages(
+    mut reader: impl AsyncRead + Unpin,
+    replies: mpsc::UnboundedSender<ServerMessage>,
+    events: mpsc::UnboundedSender<ClientEvent>,
+) {
+    loop {
+        let bytes = match read_message(&mut reader).await {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::warn!("Failed to read from server: {}", e);
+                break;
+            }
+        };
+        let msg: ServerMessage = match deserialize(&bytes) {
+            Ok(msg) => msg,
+            Err(e) => {
+                tracing::warn!("Failed to decode server message: {}", e);
+                continue;
+            }
+        };
+
+        // Sending fails only when the other side was dropped, which is fine
+        match msg {
+            ServerMessage::Output {
+                channel,
+                data,
+                timestamp,
+                stream,
+                seq,
+            } => {
+                let _ = events.send(ClientEvent::Output {
+                    channel,
+                    data,
+                    timestamp,
+                    stream,
+                    seq,
+                });
+            }
+            ServerMessage::HistoryAppended { channel, entry } => {
+                let _ = events.send(ClientEvent::HistoryAppended { channel, entry });
+            }
+            ServerMessage::SessionRenamed { name } => {
+                let _ = events.send(ClientEvent::SessionRenamed { name });
+            }
+            ServerMessage::ClientJoined { client } => {
+                let _ = events.send(ClientEvent::ClientJoined(client));
+            }
+            ServerMessage::ClientLeft { client } => {
+                let _ = events.send(ClientEvent::ClientLeft(client));
+            }
+            ServerMessage::Wall { from, message } => {
+                let _ = events.send(ClientEvent::Wall { from, message });
+            }
+            ServerMessage::Notification {
+                channel,
+                kind,
+                message,
+            } => {
+                let _ = events.send(ClientEvent::Notification {
+                    channel,
+                    kind,
+                    message,
+                });
+            }
+            ServerMessage::PinnedChanged { channels } => {
+                let _ = events.send(ClientEvent::PinnedChanged(channels));
+            }
+            // Subscription changes answer (un)subscribe requests and are events too
+            ServerMessage::Event(event @ ChannelEvent::SubscriptionChanged { .. }) => {
+                let _ = replies.send(ServerMessage::Event(event.clone()));
+                let _ = events.send(ClientEvent::Channel(event));
+            }
+            ServerMessage::Event(event) => {
+                let _ = events.send(ClientEvent::Channel(event));
+            }
+            reply => {
+                let _ = replies.send(reply);
+            }
+        }
+    }
+}
@@ -1,16 +1,58 @@
+use crate::channel::OutputStream;
+use crate::client::ansi::strip_ansi_codes;
+use crate::client::ansi::ColorSupport;
+use crate::client::clipboard::Clipboard;
+use crate::client::diff::DIFF_LINES;
+use crate::client::executables::ExecutableCache;
+use crate::client::folds;
+use crate::client::hexdump::RawOutput;
+use crate::client::keybindings::Keymap;
+use crate::client::layout::{grid, PaneLayout, Split};
+use crate::client::macros::{Playback, Recording};
+use crate::client::marks::Mark;
+use crate::client::palette::Palette;
+use crate::client::search::Search;
+use crate::client::selection::{ScreenText, Selection};
+use crate::client::sidebar::Sidebar;
+use crate::client::theme::Theme;
+use crate::client::timestamps;
+use crate::client::AttachOptions;
+use crate::config::{Config, TimestampMode};
+use crate::protocol::{Capability, ClientMessage};
 use chrono::{DateTime, Local};
-use ratatui::style::Color;
-use std::collections::{HashMap, HashSet};
+use ratatui::{
+    layout::{Position, Rect},
+    style::Color,
+};
+use regex::{Regex, RegexSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 // actually we should define it here or in a types module. Let's redefine it here and update mod.rs to use this one.
 
 pub struct ChannelInfo {
     pub name: String,
     pub running: bool,
+    /// Spawned but no output yet; shown with a spinner
+    pub starting: bool,
     pub has_new_output: bool,
     pub exit_code: Option<i32>,
+    /// Container label (`docker:web`) when the channel runs in a container
+    pub container: Option<String>,
+    /// When its process started, if the server said
+    pub started_at: Option<DateTime<Local>>,
+    /// Command running in the foreground, if it isn't the channel's own
+    /// process
+    pub foreground: Option<String>,
 }
 
 impl ChannelInfo {
+    /// Whether the channel is still starting up
+    pub fn is_starting(&self) -> bool {
+        self.running && self.starting
+    }
+
     pub fn status_indicator(&self) -> &'static str {
         if !self.running {
             if let Some(code) = self.exit_code {
@@ -33,12 +75,59 @@ impl ChannelInfo {
 pub struct BufferedLine {
     pub content: String,
     pub timestamp: DateTime<Local>,
+    pub stream: OutputStream,
+    /// Matched `appearance.fold_pattern`, so starts a foldable section
+    pub section_start: bool,
+    /// The section this line starts is collapsed
+    pub folded: bool,
+    /// Output chunk that completed the line; 0 for lines nexus wrote itself
+    pub seq: u64,
+}
+
+/// How many lines to drop from the front of a buffer, given its lines'
+/// lengths oldest first and their total, to keep to both caps; and the
+/// bytes that frees. The newest line is always kept.
+fn excess_lines(
+    lengths: impl ExactSizeIterator<Item = usize>,
+    bytes: usize,
+    max_lines: usize,
+    max_bytes: usize,
+) -> (usize, usize) {
+    let len = lengths.len();
+    let (mut excess, mut freed) = (0, 0);
+    for length in lengths {
+        let over = len - excess > max_lines || bytes - freed > max_bytes;
+        if !over || len - excess == 1 {
+            break;
+        }
+        excess += 1;
+        freed += length;
+    }
+    (excess, freed)
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum ViewMode {
     ActiveChannel,
     AllChannels,
+    /// Active channel's raw output as a hexdump
+    Hex,
+    /// Every subscribed channel tiled into equal panes
+    Grid,
+    /// Two channels side by side, differing lines highlighted (`:diff`)
+    Diff,
+}
+
+impl ViewMode {
+    /// Mode the view toggle switches to
+    pub fn toggled(self) -> Self {
+        match self {
+            ViewMode::ActiveChannel => ViewMode::AllChannels,
+            ViewMode::AllChannels | ViewMode::Hex | ViewMode::Grid | ViewMode::Diff => {
+                ViewMode::ActiveChannel
+            }
+        }
+    }
 }
 
 /// Input line editor with cursor position tracking
@@ -59,10 +148,6 @@ impl LineEditor {
         &self.buffer
     }
 
-    pub fn cursor_position(&self) -> usize {
-        self.cursor
-    }
-
     pub fn is_empty(&self) -> bool {
         self.buffer.is_empty()
     }
@@ -75,14 +160,31 @@ impl LineEditor {
         self.cursor += c.len_utf8();
     }
 
+    /// Start of the grapheme cluster before the cursor
+    fn previous_boundary(&self) -> usize {
+        self.buffer[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    /// End of the grapheme cluster after the cursor
+    fn next_boundary(&self) -> usize {
+        self.buffer[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map_or(self.buffer.len(), |g| self.cursor + g.len())
+    }
+
+    /// Display column of the cursor, counting wide characters as two
+    pub fn cursor_column(&self) -> usize {
+        self.buffer[..self.cursor].width()
+    }
+
     pub fn backspace(&mut self) -> bool {
         if self.cursor > 0 {
-            let prev_cursor = self.buffer[..self.cursor]
-                .char_indices()
-                .last()
-                .map(|(i, _)| i)
-                .unwrap_or(0);
-            self.buffer.remove(prev_cursor);
+            let prev_cursor = self.previous_boundary();
+            self.buffer.drain(prev_cursor..self.cursor);
             self.cursor = prev_cursor;
             true
         } else {
@@ -92,7 +194,8 @@ impl LineEditor {
 
     pub fn delete(&mut self) -> bool {
         if self.cursor < self.buffer.len() {
-            self.buffer.remove(self.cursor);
+            let next = self.next_boundary();
+            self.buffer.drain(self.cursor..next);
             true
         } else {
             false
@@ -101,11 +204,7 @@ impl LineEditor {
 
     pub fn move_left(&mut self) -> bool {
         if self.cursor > 0 {
-            self.cursor = self.buffer[..self.cursor]
-                .char_indices()
-                .last()
-                .map(|(i, _)| i)
-                .unwrap_or(0);
+            self.cursor = self.previous_boundary();
             true
         } else {
             false
@@ -114,11 +213,7 @@ impl LineEditor {
 
     pub fn move_right(&mut self) -> bool {
         if self.cursor < self.buffer.len() {
-            self.cursor = self.buffer[self.cursor..]
-                .char_indices()
-                .nth(1)
-                .map(|(i, _)| self.cursor + i)
-                .unwrap_or(self.buffer.len());
+            self.cursor = self.next_boundary();
             true
         } else {
             false
@@ -183,6 +278,15 @@ impl LineEditor {
         self.cursor = 0;
     }
 
+    /// Insert text at the cursor. Line breaks become spaces, as input is a
+    /// single line.
+    pub fn insert_str(&mut self, text: &str) {
+        let text = text.trim_end_matches(['\r', '\n']).replace("\r\n", " ");
+        for c in text.chars() {
+            self.insert(if c == '\n' || c == '\r' { ' ' } else { c });
+        }
+    }
+
     pub fn set(&mut self, content: &str) {
         self.buffer = content.to_string();
         self.cursor = self.buffer.len();
@@ -195,6 +299,100 @@ impl LineEditor {
     }
 }
 
+/// First delay before reconnecting to a server that went away
+const RECONNECT_INITIAL_DELAY: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Longest delay between reconnection attempts
+const RECONNECT_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Reconnection state while the server connection is down
+#[derive(Debug, Clone)]
+pub struct Reconnect {
+    /// Failed attempts so far
+    pub attempts: u32,
+    /// When to try next
+    pub retry_at: tokio::time::Instant,
+}
+
+impl Reconnect {
+    pub fn new() -> Self {
+        Self {
+            attempts: 0,
+            retry_at: tokio::time::Instant::now() + reconnect_delay(0),
+        }
+    }
+
+    /// Record a failed attempt and back off exponentially
+    pub fn failed(&mut self) {
+        self.attempts += 1;
+        self.retry_at = tokio::time::Instant::now() + reconnect_delay(self.attempts);
+    }
+}
+
+/// How long to wait before the next reconnection attempt after `attempts`
+/// failed ones: doubling from the initial delay up to the maximum
+fn reconnect_delay(attempts: u32) -> std::time::Duration {
+    RECONNECT_INITIAL_DELAY
+        .saturating_mul(1 << attempts.min(8))
+        .min(RECONNECT_MAX_DELAY)
+}
+
+/// How long a ping may go unanswered before the server counts as unresponsive
+const PING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Ping awaiting the server's pong
+#[derive(Debug, Clone, Copy)]
+pub struct PendingPing {
+    pub nonce: u64,
+    pub sent_at: std::time::Instant,
+}
+
+/// This machine's hostname, without the domain
+fn local_hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    let name = String::from_utf8_lossy(&buf[..len]);
+    let name = name.split('.').next().unwrap_or_default();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Temporary channel shown in a floating window (`:popup`)
+#[derive(Debug, Clone)]
+pub struct Popup {
+    pub channel: String,
+    /// Command shown in the window title
+    pub title: String,
+    /// Channel that was active before the popup, restored when it closes
+    pub previous: Option<String>,
+}
+
+/// One-shot command running in a scratch channel (`:run`)
+#[derive(Debug, Clone)]
+pub struct ScratchRun {
+    pub command: String,
+    /// Channel that was active when it started, restored once it's closed
+    pub previous: Option<String>,
+}
+
+/// Text shown in a floating window until dismissed (`:ps`)
+#[derive(Debug, Clone)]
+pub struct InfoPopup {
+    pub title: String,
+    pub lines: Vec<String>,
+    /// First line in view
+    pub scroll: usize,
+}
+
+impl InfoPopup {
+    pub fn scroll_by(&mut self, lines: isize) {
+        let max = self.lines.len().saturating_sub(1);
+        self.scroll = self.scroll.saturating_add_signed(lines).min(max);
+    }
+}
+
 pub struct App {
     pub channels: Vec<ChannelInfo>,
     pub active_channel: Option<String>,
@@ -205,11 +403,112 @@ pub struct App {
     pub scroll_offsets: HashMap<String, usize>,
     pub view_mode: ViewMode,
     pub show_timestamps: bool,
+    pub timestamp_mode: TimestampMode,
     pub show_welcome: bool,
     pub show_channel_numbers: bool,
     pub max_buffer_lines: usize,
+    /// Most bytes of output kept per buffer, from `general.history_bytes`
+    pub max_buffer_bytes: usize,
     pub channel_colors: HashMap<String, Color>,
     pub completions: Option<Vec<String>>,
+    pub config: Config,
+    pub keymap: Keymap,
+    /// Colors from `[theme]`, reduced to what the terminal can show
+    pub theme: Theme,
+    /// Colors the terminal can show
+    pub color_support: ColorSupport,
+    /// Prefix key was pressed; the next key is looked up in the prefix table
+    pub prefix_pending: bool,
+    /// Open command palette, if any
+    pub palette: Option<Palette>,
+    /// Executables on $PATH, for command completion
+    pub executables: ExecutableCache,
+    /// Macro currently being recorded
+    pub recording: Option<Recording>,
+    /// Macro currently being played back
+    pub playback: Option<Playback>,
+    /// Set while the connection to the server is down
+    pub disconnected: Option<Reconnect>,
+    /// Session the server said we're connected to
+    pub session_id: Option<uuid::Uuid>,
+    /// Token from the server's Welcome, for resuming after a reconnect
+    pub resume_token: Option<String>,
+    /// Sequence number of the latest output chunk received per channel
+    pub output_seqs: HashMap<String, u64>,
+    /// Heartbeat sent to the server and not answered yet
+    pub pending_ping: Option<PendingPing>,
+    /// Optional features the server advertised in its Welcome
+    pub server_capabilities: Vec<Capability>,
+    /// How this client attached (`--observe`, `--name`)
+    pub attach: AttachOptions,
+    /// Recent raw output per channel, for the hex view
+    pub raw_output: HashMap<String, RawOutput>,
+    /// Channels already pointed at `:view hex` for emitting binary output
+    pub binary_hinted: HashSet<String>,
+    /// Split panes showing several channels at once (`:split`, `:vsplit`)
+    pub panes: PaneLayout,
+    /// Focused channel temporarily fills the output area of a split or grid
+    pub zoomed: bool,
+    /// Floating popup channel, if one is open
+    pub popup: Option<Popup>,
+    /// `:run` channels, closed once their command succeeds
+    pub scratch_runs: HashMap<String, ScratchRun>,
+    /// Floating text window, if one is open; it takes keys until closed
+    pub info: Option<InfoPopup>,
+    /// Channel list sidebar, when shown
+    pub sidebar: Option<Sidebar>,
+    /// Channels pinned to the front of the status bar, in pin order
+    pub pinned: Vec<String>,
+    /// Name of the attached session, once the server has reported it
+    pub session_name: Option<String>,
+    /// Clients attached to the session, kept current by presence notices
+    pub client_count: Option<usize>,
+    /// CPU percent and resident bytes of each channel's processes, as of
+    /// the last status the server sent
+    pub usage: HashMap<String, (Option<f32>, Option<u64>)>,
+    /// Status requests sent for the usage segment and not answered yet;
+    /// their answers update `usage` without being printed
+    pub pending_usage_polls: usize,
+    /// This machine's hostname, for the status bar
+    pub hostname: Option<String>,
+    /// `:info` was asked for; session info fetched for the status bar is
+    /// otherwise not printed
+    pub info_requested: bool,
+    /// Compiled `appearance.fold_pattern`; lines it matches start sections
+    pub fold_pattern: Option<Regex>,
+    /// Lines dropped from the front of each channel buffer, so line numbers
+    /// stay put as it is trimmed
+    pub trimmed: HashMap<String, usize>,
+    /// Bytes of output held in each channel buffer
+    pub buffer_bytes: HashMap<String, usize>,
+    /// Scrollback marks per channel, by name
+    pub marks: HashMap<String, BTreeMap<String, Mark>>,
+    /// Compiled `appearance.error_patterns`, for jumping between errors
+    pub error_patterns: RegexSet,
+    /// Where output was last drawn, for mouse hit-testing
+    pub output_area: Rect,
+    /// Where each channel's tab was last drawn in the status bar
+    pub status_bar_channel_rects: Vec<(String, Rect)>,
+    /// Text being selected with the mouse
+    pub selection: Option<Selection>,
+    /// Output as drawn while a selection is made
+    pub screen_text: ScreenText,
+    /// System clipboard, for copying selections and `:copy`/`:paste`
+    pub clipboard: Clipboard,
+    /// Search of the output in view, while one is active
+    pub search: Option<Search>,
+    /// Lines dropped from the front of the interleaved buffer
+    pub interleaved_trimmed: usize,
+    /// Bytes of output held in the interleaved buffer
+    pub interleaved_bytes: usize,
+    /// Lines of the interleaved view below the bottom of the window
+    pub interleaved_scroll: usize,
+    /// Channels compared by the diff view, left and right
+    pub diff: Option<(String, String)>,
+    /// Rows of the diff view below the bottom of the window
+    pub diff_scroll: usize,
+    /// Something shown has changed since the last draw
+    pub dirty: bool,
 }
 
 impl App {
@@ -224,34 +523,257 @@ impl App {
             scroll_offsets: HashMap::new(),
             view_mode: ViewMode::ActiveChannel,
             show_timestamps: false,
+            timestamp_mode: TimestampMode::Clock,
             show_welcome: true,
             show_channel_numbers: true,
             max_buffer_lines: 10000,
+            max_buffer_bytes: 8 * 1024 * 1024,
             channel_colors: HashMap::new(),
             completions: None,
+            config: Config::default(),
+            keymap: Keymap::from_config(&Default::default()).0,
+            theme: Theme::default(),
+            color_support: ColorSupport::TrueColor,
+            prefix_pending: false,
+            palette: None,
+            executables: ExecutableCache::new(),
+            recording: None,
+            playback: None,
+            disconnected: None,
+            session_id: None,
+            resume_token: None,
+            output_seqs: HashMap::new(),
+            pending_ping: None,
+            server_capabilities: Vec::new(),
+            attach: AttachOptions::default(),
+            raw_output: HashMap::new(),
+            binary_hinted: HashSet::new(),
+            panes: PaneLayout::default(),
+            zoomed: false,
+            popup: None,
+            scratch_runs: HashMap::new(),
+            info: None,
+            sidebar: None,
+            pinned: Vec::new(),
+            session_name: None,
+            client_count: None,
+            usage: HashMap::new(),
+            pending_usage_polls: 0,
+            hostname: local_hostname(),
+            info_requested: false,
+            fold_pattern: None,
+            trimmed: HashMap::new(),
+            buffer_bytes: HashMap::new(),
+            marks: HashMap::new(),
+            error_patterns: RegexSet::empty(),
+            output_area: Rect::default(),
+            status_bar_channel_rects: Vec::new(),
+            selection: None,
+            screen_text: ScreenText::default(),
+            clipboard: Clipboard::default(),
+            search: None,
+            interleaved_trimmed: 0,
+            interleaved_bytes: 0,
+            interleaved_scroll: 0,
+            diff: None,
+            diff_scroll: 0,
+            dirty: true,
+        }
+    }
+
+    /// Whether the server advertised a capability
+    pub fn server_supports(&self, capability: Capability) -> bool {
+        self.server_capabilities.contains(&capability)
+    }
+
+    /// Whether the server has stopped answering pings
+    pub fn server_unresponsive(&self) -> bool {
+        self.pending_ping
+            .is_some_and(|ping| ping.sent_at.elapsed() >= PING_TIMEOUT)
+    }
+
+    /// Request for the notifications configured under `[notifications] push`,
+    /// if the server can push them
+    pub fn notification_request(&self) -> Option<ClientMessage> {
+        self.server_capabilities
+            .contains(&Capability::Notifications)
+            .then(|| ClientMessage::SetNotifications {
+                prefs: self.config.notifications.push_prefs(),
+            })
+    }
+
+    /// Adopt a loaded config and apply its runtime-adjustable settings.
+    ///
+    /// Invalid keybindings are skipped and reported as system messages.
+    pub fn apply_config(&mut self, config: Config) {
+        // Timestamps toggled with `:ts` stay as they are unless the config
+        // itself changes them
+        if config.appearance.show_timestamps != self.config.appearance.show_timestamps {
+            self.show_timestamps = config.appearance.show_timestamps;
+        }
+        if config.appearance.timestamp_mode != self.config.appearance.timestamp_mode {
+            self.timestamp_mode = config.appearance.timestamp_mode;
+        }
+        self.show_channel_numbers = config.appearance.show_channel_numbers;
+        self.max_buffer_lines = config.general.history_limit;
+        self.max_buffer_bytes = config.general.history_bytes;
+
+        let (keymap, errors) = Keymap::from_config(&config.keybindings);
+        self.keymap = keymap;
+        for error in errors {
+            self.add_output("SYSTEM".to_string(), error);
+        }
+
+        self.clipboard.set_mode(config.general.clipboard);
+
+        self.color_support = ColorSupport::from_config(config.appearance.color_depth);
+        let (mut theme, errors) = Theme::from_config(&config.theme);
+        theme.adapt(self.color_support);
+        if theme != self.theme {
+            self.channel_colors.clear();
+        }
+        self.theme = theme;
+        for error in errors {
+            self.add_output("SYSTEM".to_string(), error);
+        }
+
+        self.fold_pattern = match config.appearance.fold_pattern.as_str() {
+            "" => None,
+            pattern => match Regex::new(pattern) {
+                Ok(re) => Some(re),
+                Err(e) => {
+                    self.add_output(
+                        "SYSTEM".to_string(),
+                        format!("Invalid appearance.fold_pattern: {}", e),
+                    );
+                    None
+                }
+            },
+        };
+
+        self.error_patterns = match RegexSet::new(&config.appearance.error_patterns) {
+            Ok(set) => set,
+            Err(e) => {
+                self.add_output(
+                    "SYSTEM".to_string(),
+                    format!("Invalid appearance.error_patterns: {}", e),
+                );
+                RegexSet::empty()
+            }
+        };
+
+        self.config = config;
+    }
+
+    /// Clear all output buffers
+    pub fn clear_output(&mut self) {
+        self.channel_buffers.clear();
+        self.interleaved_buffer.clear();
+        self.scroll_offsets.clear();
+        self.raw_output.clear();
+        self.trimmed.clear();
+        self.buffer_bytes.clear();
+        self.marks.clear();
+        self.interleaved_trimmed = 0;
+        self.interleaved_bytes = 0;
+        self.interleaved_scroll = 0;
+        if let Some(search) = &mut self.search {
+            search.matches.clear();
+            search.current = None;
         }
     }
 
     pub fn add_output(&mut self, channel: String, text: String) {
+        self.add_stream_output(channel, text, OutputStream::Stdout, 0, Local::now());
+    }
+
+    /// Add a line of output that came from a specific stream, completed by
+    /// output chunk `seq`, stamped with when the server saw it start
+    pub fn add_stream_output(
+        &mut self,
+        channel: String,
+        text: String,
+        stream: OutputStream,
+        seq: u64,
+        timestamp: DateTime<Local>,
+    ) {
         self.show_welcome = false;
 
+        let mut text = text;
+        if text.len() > self.max_buffer_bytes {
+            let mut end = self.max_buffer_bytes;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+        }
+        let section_start = self
+            .fold_pattern
+            .as_ref()
+            .is_some_and(|re| re.is_match(&strip_ansi_codes(&text)));
         let buffered_line = BufferedLine {
             content: text,
-            timestamp: Local::now(),
+            timestamp,
+            stream,
+            section_start,
+            folded: false,
+            seq,
         };
 
+        let size = buffered_line.content.len();
         let buffer = self.channel_buffers.entry(channel.clone()).or_default();
         buffer.push(buffered_line.clone());
-        if buffer.len() > self.max_buffer_lines {
-            let excess = buffer.len() - self.max_buffer_lines;
+        let bytes = self.buffer_bytes.entry(channel.clone()).or_default();
+        *bytes += size;
+        let (excess, freed) = excess_lines(
+            buffer.iter().map(|line| line.content.len()),
+            *bytes,
+            self.max_buffer_lines,
+            self.max_buffer_bytes,
+        );
+        if excess > 0 {
             buffer.drain(0..excess);
+            *bytes -= freed;
+            *self.trimmed.entry(channel.clone()).or_default() += excess;
+        }
+
+        if let Some(search) = self
+            .search
+            .as_mut()
+            .filter(|s| s.channel.as_ref() == Some(&channel))
+        {
+            let trimmed = self.trimmed.get(&channel).copied().unwrap_or(0);
+            search.trim(trimmed);
+            search.push(trimmed + buffer.len() - 1, &channel, &buffered_line.content);
         }
 
         self.interleaved_buffer
             .push((channel.clone(), buffered_line));
-        if self.interleaved_buffer.len() > self.max_buffer_lines {
-            let excess = self.interleaved_buffer.len() - self.max_buffer_lines;
+        self.interleaved_bytes += size;
+        // Scrolled back, the interleaved view keeps the same lines in view
+        if self.interleaved_scroll > 0 {
+            self.scroll_interleaved_up(1);
+        }
+        let (excess, freed) = excess_lines(
+            self.interleaved_buffer
+                .iter()
+                .map(|(_, line)| line.content.len()),
+            self.interleaved_bytes,
+            self.max_buffer_lines,
+            self.max_buffer_bytes,
+        );
+        if excess > 0 {
             self.interleaved_buffer.drain(0..excess);
+            self.interleaved_bytes -= freed;
+            self.interleaved_trimmed += excess;
+        }
+
+        if let Some(search) = self.search.as_mut().filter(|s| s.channel.is_none()) {
+            search.trim(self.interleaved_trimmed);
+            if let Some((_, line)) = self.interleaved_buffer.last() {
+                let number = self.interleaved_trimmed + self.interleaved_buffer.len() - 1;
+                search.push(number, &channel, &line.content);
+            }
         }
 
         // Auto-scroll to bottom if not scrolled up
@@ -267,27 +789,148 @@ impl App {
             .unwrap_or(false)
     }
 
+    /// Timestamp shown before a line of `channel`'s output, per `:ts`
+    pub fn timestamp_label(
+        &self,
+        channel: &str,
+        timestamp: DateTime<Local>,
+        now: DateTime<Local>,
+    ) -> String {
+        let started = self
+            .channels
+            .iter()
+            .find(|c| c.name == channel)
+            .and_then(|c| c.started_at);
+        timestamps::label(self.timestamp_mode, timestamp, started, now)
+    }
+
+    /// Whether the view is scrolled back from the latest output
+    pub fn view_scrolled(&self) -> bool {
+        match self.view_mode {
+            ViewMode::AllChannels => self.interleaved_scroll > 0,
+            ViewMode::Diff => self.diff_scroll > 0,
+            _ => self.is_scrolled(self.active_channel.as_deref()),
+        }
+    }
+
+    /// Scroll the interleaved view back, keeping at least its first line
+    /// in view
+    pub fn scroll_interleaved_up(&mut self, lines: usize) {
+        let max = self.interleaved_buffer.len().saturating_sub(1);
+        self.interleaved_scroll = self.interleaved_scroll.saturating_add(lines).min(max);
+    }
+
+    pub fn scroll_interleaved_down(&mut self, lines: usize) {
+        self.interleaved_scroll = self.interleaved_scroll.saturating_sub(lines);
+    }
+
     pub fn scroll_up(&mut self, lines: usize) {
-        let _target = match self.view_mode {
-            ViewMode::ActiveChannel => self.active_channel.as_deref(),
-            ViewMode::AllChannels => Some("__interleaved__"), // Use a special key or handle logic differently
+        if self.view_mode == ViewMode::AllChannels {
+            self.scroll_interleaved_up(lines);
+            return;
+        }
+        if let (ViewMode::Diff, Some((left, right))) = (self.view_mode, &self.diff) {
+            // A diff has at most a row per line of either side
+            let rows: usize = [left, right]
+                .iter()
+                .map(|ch| self.channel_buffers.get(*ch).map_or(0, |b| b.len()))
+                .map(|len| len.min(DIFF_LINES))
+                .sum();
+            self.diff_scroll = (self.diff_scroll + lines).min(rows.saturating_sub(1));
+            return;
+        }
+        if let Some(ch) = self.active_channel.clone() {
+            self.scroll_channel_up(&ch, lines);
+        }
+    }
+
+    /// Scroll one channel back, keeping at least its first line in view
+    pub fn scroll_channel_up(&mut self, ch: &str, lines: usize) {
+        let buffer_len = if self.view_mode == ViewMode::Hex {
+            self.raw_output.get(ch).map(|r| r.rows()).unwrap_or(0)
+        } else {
+            self.channel_buffers
+                .get(ch)
+                .map(|b| folds::visible_lines(b).len())
+                .unwrap_or(0)
         };
+        let offset = self.scroll_offsets.entry(ch.to_string()).or_insert(0);
+        *offset = offset
+            .saturating_add(lines)
+            .min(buffer_len.saturating_sub(1));
+    }
+
+    pub fn scroll_channel_down(&mut self, ch: &str, lines: usize) {
+        let offset = self.scroll_offsets.entry(ch.to_string()).or_insert(0);
+        *offset = offset.saturating_sub(lines);
+    }
 
-        // For now, only scroll active channel
-        if let Some(ch) = self.active_channel.as_deref() {
-            let buffer_len = self.channel_buffers.get(ch).map(|b| b.len()).unwrap_or(0);
-            // approximate visible rows - exact value available in draw, but logic needs it here.
-            // We can store viewport height in App or just clamp to buffer len.
-            // Clamping to buffer len is safe.
-            let offset = self.scroll_offsets.entry(ch.to_string()).or_insert(0);
-            *offset = (*offset + lines).min(buffer_len.saturating_sub(1));
+    /// Channel shown in the split or grid pane covering a cell
+    pub fn pane_channel_at(&self, column: u16, row: u16) -> Option<String> {
+        if !self.is_tiled() || self.zoomed || self.popup.is_some() {
+            return None;
+        }
+        let cell = Position::new(column, row);
+        if self.view_mode == ViewMode::Grid {
+            let channels = self.grid_channels();
+            return grid(self.output_area, channels.len())
+                .into_iter()
+                .zip(channels)
+                .find(|(area, _)| area.contains(cell))
+                .map(|(_, channel)| channel);
+        }
+        self.panes
+            .panes(self.output_area)
+            .into_iter()
+            .find(|(area, _, _)| area.contains(cell))
+            .map(
+                |(_, channel, focused)| match (self.view_channel(), focused) {
+                    (Some(active), true) => active.to_string(),
+                    _ => channel.to_string(),
+                },
+            )
+    }
+
+    /// Scroll for a mouse wheel notch at a cell: the palette selection
+    /// while it is open, the pane under the pointer when the output is
+    /// tiled, and otherwise whatever the view shows
+    pub fn scroll_wheel(&mut self, column: u16, row: u16, up: bool) {
+        let lines = self.config.appearance.scroll_lines;
+        if let Some(info) = self.info.as_mut() {
+            info.scroll_by(if up {
+                -(lines as isize)
+            } else {
+                lines as isize
+            });
+            return;
+        }
+        if let Some(palette) = self.palette.as_mut() {
+            if up {
+                palette.select_prev();
+            } else {
+                palette.select_next();
+            }
+            return;
+        }
+        match self.pane_channel_at(column, row) {
+            Some(channel) if up => self.scroll_channel_up(&channel, lines),
+            Some(channel) => self.scroll_channel_down(&channel, lines),
+            None if up => self.scroll_up(lines),
+            None => self.scroll_down(lines),
         }
     }
 
     pub fn scroll_down(&mut self, lines: usize) {
-        if let Some(ch) = self.active_channel.as_deref() {
-            let offset = self.scroll_offsets.entry(ch.to_string()).or_insert(0);
-            *offset = offset.saturating_sub(lines);
+        if self.view_mode == ViewMode::AllChannels {
+            self.scroll_interleaved_down(lines);
+            return;
+        }
+        if self.view_mode == ViewMode::Diff {
+            self.diff_scroll = self.diff_scroll.saturating_sub(lines);
+            return;
+        }
+        if let Some(ch) = self.active_channel.clone() {
+            self.scroll_channel_down(&ch, lines);
         }
     }
 
@@ -297,20 +940,344 @@ impl App {
         }
     }
 
+    /// Go back to the latest output of whatever the view shows
+    pub fn scroll_view_to_bottom(&mut self) {
+        match self.view_mode {
+            ViewMode::AllChannels => self.interleaved_scroll = 0,
+            ViewMode::Diff => self.diff_scroll = 0,
+            _ => {
+                let active = self.active_channel.clone();
+                self.scroll_to_bottom(active.as_deref());
+            }
+        }
+    }
+
+    /// Fold or unfold the section at the bottom of the active channel's
+    /// view. Folding leaves the section's first line at the bottom;
+    /// unfolding brings its last line there. Returns false when the bottom
+    /// line isn't in a section.
+    pub fn toggle_fold(&mut self) -> bool {
+        let Some(ch) = self.active_channel.clone() else {
+            return false;
+        };
+        let Some(bottom) = self.bottom_line(&ch) else {
+            return false;
+        };
+        let buffer = self.channel_buffers.entry(ch.clone()).or_default();
+        let Some(start) = folds::section_of(buffer, bottom) else {
+            return false;
+        };
+
+        buffer[start].folded = !buffer[start].folded;
+        let target = if buffer[start].folded {
+            start
+        } else {
+            folds::section_end(buffer, start)
+        };
+        let visible = folds::visible_lines(buffer);
+        if let Some(pos) = visible.iter().position(|v| v.index == target) {
+            self.scroll_offsets.insert(ch, visible.len() - 1 - pos);
+        }
+        true
+    }
+
+    /// Fold or unfold every section of the active channel
+    pub fn set_all_folds(&mut self, folded: bool) {
+        let Some(ch) = self.active_channel.clone() else {
+            return;
+        };
+        if let Some(buffer) = self.channel_buffers.get_mut(&ch) {
+            folds::set_all(buffer, folded);
+        }
+        self.scroll_to_bottom(Some(&ch));
+    }
+
+    /// Index into a channel's buffer of the line at the bottom of its view
+    fn bottom_line(&self, channel: &str) -> Option<usize> {
+        let buffer = self.channel_buffers.get(channel)?;
+        let offset = self.scroll_offsets.get(channel).copied().unwrap_or(0);
+        let visible = folds::visible_lines(buffer);
+        let bottom = visible.len().checked_sub(offset + 1)?;
+        Some(visible[bottom].index)
+    }
+
+    /// Mark the line at the bottom of the active channel's view as `name`,
+    /// replacing any mark of that name
+    pub fn set_mark(&mut self, name: &str) -> Result<Mark, String> {
+        let channel = self.active_channel.clone().ok_or("No active channel")?;
+        let index = self
+            .bottom_line(&channel)
+            .ok_or_else(|| format!("#{} has no output to mark", channel))?;
+        let mark = Mark {
+            line: Some(self.trimmed.get(&channel).copied().unwrap_or(0) + index),
+            seq: self.channel_buffers[&channel][index].seq,
+        };
+        self.marks
+            .entry(channel)
+            .or_default()
+            .insert(name.to_string(), mark);
+        Ok(mark)
+    }
+
+    /// Scroll the active channel so the line marked `name` is at the bottom
+    /// of the view, unfolding its section if it is folded away
+    pub fn jump_to_mark(&mut self, name: &str) -> Result<(), String> {
+        let channel = self.active_channel.clone().ok_or("No active channel")?;
+        let mark = self
+            .marks
+            .get(&channel)
+            .and_then(|marks| marks.get(name))
+            .copied()
+            .ok_or_else(|| format!("No mark '{}' in #{}", name, channel))?;
+        let trimmed = self.trimmed.get(&channel).copied().unwrap_or(0);
+        let index = self
+            .channel_buffers
+            .get(&channel)
+            .and_then(|buffer| mark.resolve(buffer, trimmed))
+            .ok_or_else(|| format!("Mark '{}' has scrolled out of the buffer", name))?;
+        self.scroll_to_line(channel, index);
+        Ok(())
+    }
+
+    /// Scroll the active channel to the nearest error-looking line above the
+    /// bottom of its view, or below it when `forward`. Returns false when
+    /// there is none.
+    pub fn jump_to_error(&mut self, forward: bool) -> bool {
+        let Some(channel) = self.active_channel.clone() else {
+            return false;
+        };
+        let (Some(bottom), Some(buffer)) = (
+            self.bottom_line(&channel),
+            self.channel_buffers.get(&channel),
+        ) else {
+            return false;
+        };
+        let is_error = |i: &usize| {
+            self.error_patterns
+                .is_match(&strip_ansi_codes(&buffer[*i].content))
+        };
+        let found = if forward {
+            (bottom + 1..buffer.len()).find(is_error)
+        } else {
+            (0..bottom).rev().find(is_error)
+        };
+        match found {
+            Some(index) => {
+                self.scroll_to_line(channel, index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Shortest time between redraws, from `appearance.max_fps`
+    pub fn frame_interval(&self) -> Duration {
+        match self.config.appearance.max_fps {
+            0 => Duration::ZERO,
+            fps => Duration::from_secs(1) / fps,
+        }
+    }
+
+    /// What a search covers: the active channel, or every channel in the
+    /// interleaved view
+    fn search_scope(&self) -> Option<Option<String>> {
+        match self.view_mode {
+            ViewMode::AllChannels => Some(None),
+            _ => self.active_channel.clone().map(Some),
+        }
+    }
+
+    /// Search the output in view for `query` and scroll to the latest match
+    /// in view or above it
+    pub fn start_search(&mut self, query: &str) -> Result<(), String> {
+        let scope = self.search_scope().ok_or("No active channel")?;
+        self.search = Some(Search::new(query, scope.as_deref()).map_err(|e| e.to_string())?);
+        self.rescan_search(scope);
+        self.step_search(false);
+        Ok(())
+    }
+
+    /// Find the search's matches again, in a channel or across channels
+    fn rescan_search(&mut self, scope: Option<String>) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        match &scope {
+            Some(channel) => {
+                let trimmed = self.trimmed.get(channel).copied().unwrap_or(0);
+                let lines = self.channel_buffers.get(channel).into_iter().flatten();
+                search.scan(lines.map(|line| (channel.as_str(), line)), trimmed);
+            }
+            None => {
+                let lines = self.interleaved_buffer.iter();
+                search.scan(
+                    lines.map(|(channel, line)| (channel.as_str(), line)),
+                    self.interleaved_trimmed,
+                );
+            }
+        }
+        search.channel = scope;
+    }
+
+    /// Scroll to the next (later) or previous search match. A search of
+    /// other output is carried over to what is in view first.
+    pub fn step_search(&mut self, forward: bool) {
+        let Some(scope) = self.search_scope() else {
+            return;
+        };
+        if self.search.as_ref().is_some_and(|s| s.channel != scope) {
+            self.rescan_search(scope.clone());
+        }
+
+        let (trimmed, bottom) = match &scope {
+            Some(channel) => {
+                let trimmed = self.trimmed.get(channel).copied().unwrap_or(0);
+                let bottom = self.bottom_line(channel).map_or(0, |i| trimmed + i);
+                (trimmed, bottom)
+            }
+            None => {
+                let end = self.interleaved_trimmed + self.interleaved_buffer.len();
+                (
+                    self.interleaved_trimmed,
+                    end.saturating_sub(self.interleaved_scroll + 1),
+                )
+            }
+        };
+        let Some(line) = self.search.as_mut().and_then(|s| s.step(forward, bottom)) else {
+            return;
+        };
+        match scope {
+            Some(channel) => self.scroll_to_line(channel, line - trimmed),
+            None => {
+                self.interleaved_scroll = self.interleaved_buffer.len() - 1 - (line - trimmed);
+            }
+        }
+    }
+
+    /// Drop the search, and go back to the latest output of the
+    /// interleaved view
+    pub fn end_search(&mut self) {
+        self.search = None;
+        self.interleaved_scroll = 0;
+    }
+
+    /// Put line `index` of a channel's buffer at the bottom of its view,
+    /// unfolding its section if it is folded away
+    fn scroll_to_line(&mut self, channel: String, index: usize) {
+        let Some(buffer) = self.channel_buffers.get_mut(&channel) else {
+            return;
+        };
+        let mut visible = folds::visible_lines(buffer);
+        if !visible.iter().any(|v| v.index == index) {
+            if let Some(start) = folds::section_of(buffer, index) {
+                buffer[start].folded = false;
+            }
+            visible = folds::visible_lines(buffer);
+        }
+        if let Some(pos) = visible.iter().position(|v| v.index == index) {
+            self.scroll_offsets.insert(channel, visible.len() - 1 - pos);
+        }
+    }
+
+    /// Channel whose status bar tab covers a cell
+    pub fn channel_tab_at(&self, column: u16, row: u16) -> Option<&str> {
+        self.status_bar_channel_rects
+            .iter()
+            .find(|(_, rect)| rect.contains(Position::new(column, row)))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Start selecting at a cell if it is in the output area
+    pub fn start_selection(&mut self, column: u16, row: u16) {
+        self.selection = self
+            .output_area
+            .contains(Position::new(column, row))
+            .then(|| Selection::new(column, row));
+    }
+
+    /// Drag the selection out to a cell, stopping at the output area's edge
+    pub fn extend_selection(&mut self, column: u16, row: u16) {
+        let cursor = self.screen_text.clamp(column, row);
+        if let Some(selection) = &mut self.selection {
+            selection.cursor = cursor;
+        }
+    }
+
+    /// End the selection, returning the selected text unless nothing was
+    /// dragged over
+    pub fn finish_selection(&mut self) -> Option<String> {
+        let selection = self.selection.take()?;
+        let screen = std::mem::take(&mut self.screen_text);
+        if selection.anchor == selection.cursor {
+            return None;
+        }
+        Some(screen.selected(&selection)).filter(|text| !text.is_empty())
+    }
+
+    /// Text of the last `count` lines of the active channel up to the
+    /// bottom of its view, without color codes. Folded-away lines are left
+    /// out, as on screen.
+    pub fn lines_in_view(&self, count: usize) -> Option<String> {
+        let channel = self.active_channel.as_deref()?;
+        let buffer = self.channel_buffers.get(channel)?;
+        let offset = self.scroll_offsets.get(channel).copied().unwrap_or(0);
+        let visible = folds::visible_lines(buffer);
+        let end = visible.len().checked_sub(offset)?;
+        let lines: Vec<String> = visible[end.saturating_sub(count)..end]
+            .iter()
+            .map(|v| strip_ansi_codes(&buffer[v.index].content))
+            .collect();
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    /// Output of the most recent command in the active channel that printed
+    /// any, without color codes
+    pub fn last_command_output(&self) -> Option<String> {
+        let channel = self.active_channel.as_deref()?;
+        let output = folds::last_output(self.channel_buffers.get(channel)?)?;
+        let lines: Vec<String> = output
+            .iter()
+            .map(|line| strip_ansi_codes(&line.content))
+            .collect();
+        Some(lines.join("\n"))
+    }
+
+    /// Whether `:mark`s are shared with the session
+    pub fn shares_marks(&self) -> bool {
+        self.config.general.share_marks && self.server_supports(Capability::Marks)
+    }
+
+    /// Adopt a mark shared by a client (possibly this one), or drop it when
+    /// `seq` is `None`. A mark already set here at the same chunk keeps its
+    /// exact line.
+    pub fn shared_mark(&mut self, channel: String, name: String, seq: Option<u64>) {
+        let marks = self.marks.entry(channel).or_default();
+        match seq {
+            Some(seq) => {
+                if marks.get(&name).is_none_or(|mark| mark.seq != seq) {
+                    marks.insert(name, Mark { line: None, seq });
+                }
+            }
+            None => {
+                marks.remove(&name);
+            }
+        }
+    }
+
     pub fn get_channel_color(&mut self, channel: &str) -> Color {
+        if !self.config.appearance.channel_colors {
+            return Color::Reset;
+        }
         if let Some(c) = self.channel_colors.get(channel) {
             return *c;
         }
+        if let Some(color) = self.theme.channel_color(channel) {
+            self.channel_colors.insert(channel.to_string(), color);
+            return color;
+        }
 
-        // Simple color rotation
-        let colors = [
-            Color::Blue,
-            Color::Magenta,
-            Color::Cyan,
-            Color::Yellow,
-            Color::Green,
-            Color::Red,
-        ];
+        // Rotate through the theme's palette
+        let colors = &self.theme.channel_palette;
 
         let used: HashSet<_> = self.channel_colors.values().copied().collect();
         let color = *colors
@@ -322,6 +1289,160 @@ impl App {
         color
     }
 
+    /// Subscribed channels, in status bar order, for the grid view
+    pub fn grid_channels(&self) -> Vec<String> {
+        self.channels
+            .iter()
+            .filter(|c| self.subscriptions.contains(&c.name))
+            .map(|c| c.name.clone())
+            .collect()
+    }
+
+    /// Split the output area, showing `channel` in a new focused pane
+    pub fn split_pane(&mut self, split: Split, channel: &str) {
+        let active = self.active_channel.clone().unwrap_or_default();
+        self.panes.split(split, &active, channel);
+        self.active_channel = Some(channel.to_string());
+        self.zoomed = false;
+    }
+
+    /// Replace the pinned channels and move them to the front of the list
+    pub fn set_pinned(&mut self, pinned: Vec<String>) {
+        self.pinned = pinned;
+        self.order_channels();
+    }
+
+    pub fn is_pinned(&self, channel: &str) -> bool {
+        self.pinned.iter().any(|name| name == channel)
+    }
+
+    /// Pinned channels first, in pin order; the rest keep their order
+    pub fn order_channels(&mut self) {
+        let pinned = &self.pinned;
+        self.channels.sort_by_key(|c| {
+            pinned
+                .iter()
+                .position(|name| name == &c.name)
+                .unwrap_or(usize::MAX)
+        });
+    }
+
+    /// Show the sidebar and give it the keyboard, or hide it if it already
+    /// has the keyboard
+    pub fn toggle_sidebar(&mut self) {
+        let active = self
+            .active_channel
+            .as_ref()
+            .and_then(|active| self.channels.iter().position(|c| &c.name == active))
+            .unwrap_or(0);
+        self.sidebar = match &self.sidebar {
+            Some(sidebar) if sidebar.focused => None,
+            _ => Some(Sidebar::focused_on(active)),
+        };
+    }
+
+    /// Channel the main view shows: the active one, or the one under an
+    /// open popup
+    pub fn view_channel(&self) -> Option<&str> {
+        match &self.popup {
+            Some(popup) => popup.previous.as_deref(),
+            None => self.active_channel.as_deref(),
+        }
+    }
+
+    /// Open a popup for `channel`, which becomes active while it's shown
+    pub fn open_popup(&mut self, channel: String, title: String) {
+        self.popup = Some(Popup {
+            channel: channel.clone(),
+            title,
+            previous: self.active_channel.replace(channel),
+        });
+    }
+
+    /// Close the popup and forget its channel, returning the channel to
+    /// switch back to
+    pub fn close_popup(&mut self) -> Option<String> {
+        let popup = self.popup.take()?;
+        self.forget_channel(&popup.channel);
+        self.active_channel = popup
+            .previous
+            .or_else(|| self.channels.first().map(|c| c.name.clone()));
+        self.active_channel.clone()
+    }
+
+    /// First `run-N` name not taken by a channel
+    pub fn next_run_name(&self) -> String {
+        (1..)
+            .map(|n| format!("run-{}", n))
+            .find(|name| {
+                !self.scratch_runs.contains_key(name)
+                    && !self.channels.iter().any(|c| &c.name == name)
+            })
+            .expect("some run name is free")
+    }
+
+    /// Track a `:run` channel, which becomes active
+    pub fn start_run(&mut self, channel: String, command: String) {
+        let previous = self.active_channel.replace(channel.clone());
+        self.scratch_runs
+            .insert(channel, ScratchRun { command, previous });
+    }
+
+    /// Stop tracking a `:run` channel and forget it. Returns the channel to
+    /// switch back to if it was the active one.
+    pub fn discard_run(&mut self, channel: &str) -> Option<String> {
+        let run = self.scratch_runs.remove(channel)?;
+        self.forget_channel(channel);
+        if self.active_channel.as_deref() != Some(channel) {
+            return None;
+        }
+        self.active_channel = run
+            .previous
+            .filter(|previous| self.channels.iter().any(|c| &c.name == previous))
+            .or_else(|| self.channels.first().map(|c| c.name.clone()));
+        self.active_channel.clone()
+    }
+
+    /// Drop a channel the server has discarded, with everything kept for it
+    fn forget_channel(&mut self, channel: &str) {
+        self.channels.retain(|c| c.name != channel);
+        self.channel_buffers.remove(channel);
+        self.buffer_bytes.remove(channel);
+        self.scroll_offsets.remove(channel);
+        self.subscriptions.retain(|name| name != channel);
+    }
+
+    /// Whether the output area is divided into panes (splits or the grid)
+    pub fn is_tiled(&self) -> bool {
+        match self.view_mode {
+            ViewMode::ActiveChannel => self.panes.is_split(),
+            ViewMode::Grid => true,
+            ViewMode::AllChannels | ViewMode::Hex | ViewMode::Diff => false,
+        }
+    }
+
+    /// Maximize the focused channel, or restore the panes. Returns whether
+    /// it's now zoomed; there's nothing to zoom without panes.
+    pub fn toggle_zoom(&mut self) -> bool {
+        self.zoomed = !self.zoomed && self.is_tiled();
+        self.zoomed
+    }
+
+    /// Move focus between split panes, making the focused pane's channel active
+    pub fn focus_pane(&mut self, steps: isize) {
+        let active = self.active_channel.clone().unwrap_or_default();
+        if let Some(channel) = self.panes.focus_by(steps, &active) {
+            self.active_channel = Some(channel);
+        }
+    }
+
+    /// Close the focused pane, making the newly focused pane's channel active
+    pub fn close_pane(&mut self) {
+        if let Some(channel) = self.panes.close() {
+            self.active_channel = Some(channel);
+        }
+    }
+
     pub fn next_channel(&mut self) {
         if self.channels.is_empty() {
             return;
@@ -354,3 +1475,206 @@ impl App {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_editor_moves_by_grapheme_cluster() {
+        let mut editor = LineEditor::new();
+        // "e" plus a combining accent, and a ZWJ emoji sequence
+        editor.insert_str("ae\u{301}👩\u{200d}🔬z");
+        assert_eq!(editor.cursor_column(), 5);
+
+        assert!(editor.move_left());
+        assert!(editor.move_left());
+        assert_eq!(editor.cursor_column(), 2);
+        assert!(editor.backspace());
+        assert_eq!(editor.content(), "a👩\u{200d}🔬z");
+        assert!(editor.delete());
+        assert_eq!(editor.content(), "az");
+        assert!(editor.move_right());
+        assert_eq!(editor.cursor, 2);
+    }
+
+    #[test]
+    fn scratch_runs_take_free_names_and_restore_the_previous_channel() {
+        let mut app = App::new();
+        for name in ["api", "run-1"] {
+            app.add_output(name.to_string(), "output".to_string());
+            app.channels.push(ChannelInfo {
+                name: name.to_string(),
+                running: true,
+                starting: false,
+                has_new_output: false,
+                exit_code: None,
+                container: None,
+                started_at: None,
+                foreground: None,
+            });
+        }
+        app.active_channel = Some("api".to_string());
+        assert_eq!(app.next_run_name(), "run-2");
+
+        app.start_run("run-2".to_string(), "make".to_string());
+        assert_eq!(app.next_run_name(), "run-3");
+        assert_eq!(app.active_channel.as_deref(), Some("run-2"));
+        assert_eq!(app.discard_run("run-2"), Some("api".to_string()));
+        assert_eq!(app.active_channel.as_deref(), Some("api"));
+
+        // Not in view when it finished: nothing to switch back to
+        app.start_run("run-2".to_string(), "make".to_string());
+        app.active_channel = Some("run-1".to_string());
+        assert_eq!(app.discard_run("run-2"), None);
+        assert_eq!(app.discard_run("run-1"), None);
+        assert!(app.channel_buffers.contains_key("run-1"));
+    }
+
+    #[test]
+    fn popups_take_over_the_active_channel_until_closed() {
+        let mut app = App::new();
+        for name in ["api", "logs"] {
+            app.channels.push(ChannelInfo {
+                name: name.to_string(),
+                running: true,
+                starting: false,
+                has_new_output: false,
+                exit_code: None,
+                container: None,
+                started_at: None,
+                foreground: None,
+            });
+        }
+        app.active_channel = Some("logs".to_string());
+        assert_eq!(app.close_popup(), None);
+
+        app.open_popup("popup-1".to_string(), "htop".to_string());
+        app.add_output("popup-1".to_string(), "load".to_string());
+        app.subscriptions.push("popup-1".to_string());
+        assert_eq!(app.active_channel.as_deref(), Some("popup-1"));
+        // The channel under the popup stays in the main view
+        assert_eq!(app.view_channel(), Some("logs"));
+        assert_eq!(app.popup.as_ref().unwrap().title, "htop");
+
+        assert_eq!(app.close_popup(), Some("logs".to_string()));
+        assert!(app.popup.is_none());
+        assert_eq!(app.view_channel(), Some("logs"));
+        assert!(!app.channel_buffers.contains_key("popup-1"));
+        assert!(app.subscriptions.is_empty());
+
+        // Opened with nothing active: back to the first channel
+        app.active_channel = None;
+        app.open_popup("popup-2".to_string(), "shell".to_string());
+        assert_eq!(app.view_channel(), None);
+        assert_eq!(app.close_popup(), Some("api".to_string()));
+    }
+
+    #[test]
+    fn applying_config_keeps_toggled_timestamps() {
+        let mut app = App::new();
+        let mut config = Config::default();
+        config.appearance.show_timestamps = true;
+        app.apply_config(config.clone());
+        assert!(app.show_timestamps);
+
+        // Turned off with :ts, then another setting changes
+        app.show_timestamps = false;
+        config.appearance.line_wrap = !config.appearance.line_wrap;
+        app.apply_config(config.clone());
+        assert!(!app.show_timestamps);
+
+        config.appearance.show_timestamps = false;
+        app.apply_config(config.clone());
+        config.appearance.show_timestamps = true;
+        app.apply_config(config);
+        assert!(app.show_timestamps);
+    }
+
+    #[test]
+    fn interleaved_view_scrolls_and_holds_its_place() {
+        let mut app = App::new();
+        app.view_mode = ViewMode::AllChannels;
+        for i in 0..5 {
+            app.add_output("api".to_string(), format!("line {}", i));
+        }
+        assert!(!app.view_scrolled());
+        app.scroll_up(10);
+        assert_eq!(app.interleaved_scroll, 4);
+        app.scroll_down(2);
+        assert_eq!(app.interleaved_scroll, 2);
+        assert!(app.view_scrolled());
+
+        app.add_output("db".to_string(), "new".to_string());
+        assert_eq!(app.interleaved_scroll, 3);
+        app.scroll_view_to_bottom();
+        assert!(!app.view_scrolled());
+    }
+
+    #[test]
+    fn wheel_scrolls_the_pane_under_the_pointer() {
+        let mut app = App::new();
+        for channel in ["api", "db"] {
+            for i in 0..20 {
+                app.add_output(channel.to_string(), format!("line {}", i));
+            }
+        }
+        app.active_channel = Some("api".to_string());
+        app.output_area = Rect::new(0, 0, 80, 20);
+        app.split_pane(Split::Stacked, "db");
+        app.active_channel = Some("db".to_string());
+
+        app.scroll_wheel(5, 2, true);
+        assert_eq!(app.scroll_offsets.get("api"), Some(&3));
+        assert!(!app.is_scrolled(Some("db")));
+        app.scroll_wheel(5, 15, true);
+        assert_eq!(app.scroll_offsets.get("db"), Some(&3));
+    }
+
+    #[test]
+    fn buffers_keep_to_line_and_byte_caps() {
+        let lengths = [10, 10, 10, 10];
+        let excess =
+            |max_lines, max_bytes| excess_lines(lengths.into_iter(), 40, max_lines, max_bytes);
+        assert_eq!(excess(10, 100), (0, 0));
+        assert_eq!(excess(3, 100), (1, 10));
+        assert_eq!(excess(10, 25), (2, 20));
+        // A line over the byte cap on its own is still kept
+        assert_eq!(excess(10, 5), (3, 30));
+    }
+
+    #[test]
+    fn long_lines_are_cut_to_the_byte_cap() {
+        let mut app = App::new();
+        app.max_buffer_bytes = 8;
+        app.add_output("api".to_string(), "héllo wörld".to_string());
+        app.add_output("api".to_string(), "second".to_string());
+        let buffer = &app.channel_buffers["api"];
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer[0].content, "second");
+        assert_eq!(app.buffer_bytes["api"], 6);
+        assert_eq!(app.trimmed["api"], 1);
+
+        app.add_output("api".to_string(), "héllo wörld".to_string());
+        assert_eq!(app.channel_buffers["api"][0].content, "héllo w");
+    }
+
+    #[test]
+    fn reconnect_delay_doubles_up_to_the_cap() {
+        let delays: Vec<_> = (0..6).map(reconnect_delay).collect();
+        assert_eq!(delays[0], RECONNECT_INITIAL_DELAY);
+        assert!(delays.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert_eq!(delays[1], RECONNECT_INITIAL_DELAY * 2);
+        assert_eq!(delays[3], RECONNECT_INITIAL_DELAY * 8);
+        assert_eq!(reconnect_delay(5), RECONNECT_MAX_DELAY);
+        assert_eq!(reconnect_delay(u32::MAX), RECONNECT_MAX_DELAY);
+
+        let mut reconnect = Reconnect::new();
+        reconnect.failed();
+        reconnect.failed();
+        assert_eq!(reconnect.attempts, 2);
+        let wait = reconnect.retry_at - tokio::time::Instant::now();
+        assert!(wait <= RECONNECT_INITIAL_DELAY * 4, "waiting {:?}", wait);
+        assert!(wait > RECONNECT_INITIAL_DELAY * 2, "waiting {:?}", wait);
+    }
+}
@@ -0,0 +1,488 @@
+//! Non-interactive subcommands for scripts and CI (`nexus run`, `nexus send`, ...)
+
+use crate::channel::{ChannelMode, OutputStream};
+use crate::client::api::{ClientEvent, EventStream, NexusClient};
+use crate::client::{connect_or_spawn, shutdown_server};
+use crate::config::Config;
+use crate::protocol::{ChannelEvent, ClientMessage, CreateChannelRequest};
+use crate::server::daemon;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Exit status reported when the command was killed or died from a signal
+const ABNORMAL_EXIT: i32 = 1;
+
+/// Exit status after Ctrl-C, following the shell convention of 128 + SIGINT
+const INTERRUPTED_EXIT: i32 = 130;
+
+/// Session names paired with one of their runtime files
+pub(crate) type SessionFiles = Vec<(String, PathBuf)>;
+
+/// One entry of `nexus list --json`
+#[derive(Debug, Serialize)]
+struct SessionSummary {
+    name: String,
+    socket: PathBuf,
+    /// False when the socket is left over from a server that's gone
+    running: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    channels: usize,
+    running_channels: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active_channel: Option<String>,
+}
+
+/// Lines printed by `nexus tail` when `-n` isn't given, as with tail(1)
+const DEFAULT_TAIL_LINES: usize = 10;
+
+/// Run `command` in a temporary channel of `session`, copying its stdout and
+/// stderr to ours, and return its exit code.
+///
+/// The session's server is started if needed. The channel uses pipe mode so
+/// the two streams stay separate, and the server removes it once it exits.
+pub async fn run(session: &str, command: &[String]) -> Result<i32> {
+    let mut client = NexusClient::from_stream(connect_or_spawn(session).await?).await?;
+    let mut events = client
+        .take_events()
+        .ok_or_else(|| anyhow!("Event stream unavailable"))?;
+
+    let name = format!("run-{}", &Uuid::new_v4().simple().to_string()[..8]);
+    client
+        .create_channel_with(ClientMessage::CreateChannel(CreateChannelRequest {
+            name: name.clone(),
+            command: Some(shell_join(command)),
+            working_dir: std::env::current_dir()
+                .ok()
+                .map(|dir| dir.to_string_lossy().to_string()),
+            mode: Some(ChannelMode::Pipe),
+            ephemeral: true,
+            ..Default::default()
+        }))
+        .await?;
+
+    let code = loop {
+        let event = tokio::select! {
+            event = events.next() => event,
+            _ = tokio::signal::ctrl_c() => {
+                let _ = client.kill_channel(&name).await;
+                break INTERRUPTED_EXIT;
+            }
+        };
+        match event {
+            Some(ClientEvent::Output {
+                channel,
+                data,
+                stream,
+                ..
+            }) if channel == name => match stream {
+                OutputStream::Stdout => {
+                    let mut stdout = std::io::stdout().lock();
+                    stdout.write_all(&data)?;
+                    stdout.flush()?;
+                }
+                OutputStream::Stderr => std::io::stderr().write_all(&data)?,
+            },
+            Some(ClientEvent::Channel(ChannelEvent::Exited {
+                name: exited,
+                exit_code,
+            })) if exited == name => break exit_code.unwrap_or(ABNORMAL_EXIT),
+            Some(ClientEvent::Channel(ChannelEvent::Killed { name: killed })) if killed == name => {
+                eprintln!("nexus: channel '{}' was killed", name);
+                break ABNORMAL_EXIT;
+            }
+            Some(_) => {}
+            None => return Err(anyhow!("Server closed the connection")),
+        }
+    };
+
+    client.detach().await?;
+    Ok(code)
+}
+
+/// Type `text` into a channel of a running session (the active channel when
+/// `channel` is `None`), followed by Enter unless `newline` is false.
+pub async fn send(session: &str, channel: Option<&str>, text: &str, newline: bool) -> Result<()> {
+    let client = connect_existing(session).await?;
+
+    // Input errors aren't reported back reliably, so check the target first
+    let channels = client.list_channels().await?;
+    let target = match channel {
+        Some(name) => channels
+            .iter()
+            .find(|info| info.name == name)
+            .ok_or_else(|| anyhow!("Channel '{}' not found in session '{}'", name, session))?,
+        None => channels
+            .iter()
+            .find(|info| info.is_active)
+            .ok_or_else(|| anyhow!("Session '{}' has no active channel", session))?,
+    };
+    if !target.running {
+        return Err(anyhow!("Channel '{}' is not running", target.name));
+    }
+
+    let mut data = text.as_bytes().to_vec();
+    if newline {
+        data.push(b'\n');
+    }
+    client.send_input(&target.name, data).await?;
+    client.detach().await
+}
+
+/// Print the last `lines` lines of a channel's buffered output, then keep
+/// printing new output until the channel exits when `follow` is set.
+pub async fn tail(session: &str, channel: &str, follow: bool, lines: Option<usize>) -> Result<()> {
+    let mut client = connect_existing(session).await?;
+    let mut events = client
+        .take_events()
+        .ok_or_else(|| anyhow!("Event stream unavailable"))?;
+
+    let channels = client.list_channels().await?;
+    let running = channels
+        .iter()
+        .find(|info| info.name == channel)
+        .ok_or_else(|| anyhow!("Channel '{}' not found in session '{}'", channel, session))?
+        .running;
+
+    // New connections are subscribed to the active channel, whose replay may
+    // already be in flight; drop it so the replay below is the only copy
+    client.unsubscribe(&[channel]).await?;
+    next_subscription_change(&mut events).await?;
+
+    // The server replays buffered output before confirming the subscription
+    client.subscribe(&[channel]).await?;
+    let mut buffered = Vec::new();
+    loop {
+        match events.next().await {
+            Some(ClientEvent::Output {
+                channel: from,
+                data,
+                ..
+            }) if from == channel => buffered.extend_from_slice(&data),
+            Some(ClientEvent::Channel(ChannelEvent::SubscriptionChanged { .. })) => break,
+            Some(_) => {}
+            None => return Err(anyhow!("Server closed the connection")),
+        }
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(last_lines(&buffered, lines.unwrap_or(DEFAULT_TAIL_LINES)))?;
+    stdout.flush()?;
+    drop(stdout);
+
+    // A channel that has already exited won't produce more output
+    if follow && running {
+        loop {
+            let event = tokio::select! {
+                event = events.next() => event,
+                _ = tokio::signal::ctrl_c() => break,
+            };
+            match event {
+                Some(ClientEvent::Output {
+                    channel: from,
+                    data,
+                    ..
+                }) if from == channel => {
+                    let mut stdout = std::io::stdout().lock();
+                    stdout.write_all(&data)?;
+                    stdout.flush()?;
+                }
+                Some(ClientEvent::Channel(
+                    ChannelEvent::Exited { name, .. } | ChannelEvent::Killed { name },
+                )) if name == channel => break,
+                Some(_) => {}
+                None => break,
+            }
+        }
+    }
+
+    client.detach().await
+}
+
+/// Print every session in the runtime dir as JSON, asking each server for
+/// its live state.
+pub async fn list_json() -> Result<()> {
+    let runtime_dir = Config::load()?.runtime_dir();
+    let mut sessions = Vec::new();
+    for (name, socket) in session_sockets(&runtime_dir) {
+        sessions.push(summarize_session(name, socket).await);
+    }
+    println!("{}", serde_json::to_string_pretty(&sessions)?);
+    Ok(())
+}
+
+/// Shut down the named session's server, or every session's when `session`
+/// is `None`, killing their channels and removing their sockets.
+pub async fn kill_server(session: Option<&str>) -> Result<()> {
+    let runtime_dir = Config::load()?.runtime_dir();
+    let (targets, orphans): (SessionFiles, SessionFiles) = match session {
+        Some(name) => {
+            let socket = runtime_dir.join(format!("{}.sock", name));
+            let pid_file = daemon::pid_path(&socket);
+            if socket.exists() {
+                (vec![(name.to_string(), socket)], Vec::new())
+            } else if pid_file.exists() {
+                (Vec::new(), vec![(name.to_string(), pid_file)])
+            } else {
+                return Err(anyhow!("Session '{}' not found", name));
+            }
+        }
+        None => (
+            session_sockets(&runtime_dir),
+            orphaned_pid_files(&runtime_dir),
+        ),
+    };
+    if targets.is_empty() && orphans.is_empty() {
+        println!("No sessions found.");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for (name, socket) in targets {
+        match shutdown_server(&socket).await {
+            Ok(()) => println!("Session '{}' killed.", name),
+            Err(e) => {
+                eprintln!("nexus: failed to kill session '{}': {:#}", name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    // Servers whose socket was deleted can only be reached by signal
+    for (name, pid_file) in orphans {
+        match daemon::read_pid(&pid_file) {
+            Some(pid) if daemon::is_server_running(pid) => match daemon::terminate(pid) {
+                Ok(()) => println!("Orphaned server for '{}' (pid {}) stopped.", name, pid),
+                Err(e) => {
+                    eprintln!("nexus: failed to stop pid {}: {:#}", pid, e);
+                    failed += 1;
+                }
+            },
+            _ => {
+                std::fs::remove_file(&pid_file)?;
+                println!("Removed stale PID file for '{}'.", name);
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} session(s) could not be killed", failed));
+    }
+    Ok(())
+}
+
+/// PID files in `runtime_dir` with no socket beside them: orphaned servers,
+/// or leftovers from servers that died without cleaning up
+fn orphaned_pid_files(runtime_dir: &Path) -> SessionFiles {
+    let mut orphans: SessionFiles = std::fs::read_dir(runtime_dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "pid"))
+                .filter(|path| !path.with_extension("sock").exists())
+                .filter_map(|path| Some((path.file_stem()?.to_string_lossy().to_string(), path)))
+                .collect()
+        })
+        .unwrap_or_default();
+    orphans.sort();
+    orphans
+}
+
+/// Rename a running session; attached clients stay connected
+pub async fn rename_session(old: &str, new: &str) -> Result<()> {
+    let client = connect_existing(old).await?;
+    client.rename_session(new).await?;
+    client.detach().await?;
+    println!("Session '{}' renamed to '{}'.", old, new);
+    Ok(())
+}
+
+/// Sessions with a socket in `runtime_dir`, sorted by name
+pub(crate) fn session_sockets(runtime_dir: &Path) -> SessionFiles {
+    let mut sockets: SessionFiles = std::fs::read_dir(runtime_dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "sock"))
+                .filter_map(|path| Some((path.file_stem()?.to_string_lossy().to_string(), path)))
+                .collect()
+        })
+        .unwrap_or_default();
+    sockets.sort();
+    sockets
+}
+
+async fn summarize_session(name: String, socket: PathBuf) -> SessionSummary {
+    let mut summary = SessionSummary {
+        name,
+        socket,
+        running: false,
+        session_id: None,
+        pid: None,
+        channels: 0,
+        running_channels: 0,
+        active_channel: None,
+    };
+    let Ok(client) = NexusClient::connect(&summary.socket).await else {
+        return summary;
+    };
+    summary.running = true;
+    summary.session_id = Some(client.session_id());
+    summary.pid = daemon::read_pid(&daemon::pid_path(&summary.socket));
+    if let Ok(channels) = client.list_channels().await {
+        summary.channels = channels.len();
+        summary.running_channels = channels.iter().filter(|info| info.running).count();
+        summary.active_channel = channels
+            .into_iter()
+            .find(|info| info.is_active)
+            .map(|info| info.name);
+    }
+    let _ = client.detach().await;
+    summary
+}
+
+/// Block until a channel's process exits and return its exit code.
+///
+/// Returns immediately for a channel that has already exited. Ctrl-C stops
+/// waiting without touching the channel.
+pub async fn wait(session: &str, channel: &str) -> Result<i32> {
+    let mut client = connect_existing(session).await?;
+    let mut events = client
+        .take_events()
+        .ok_or_else(|| anyhow!("Event stream unavailable"))?;
+
+    // Lifecycle events are queued from connect, so an exit racing this
+    // status check still shows up below
+    let status = client
+        .status(Some(channel))
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("Channel '{}' not found in session '{}'", channel, session))?;
+    if !status.running {
+        client.detach().await?;
+        return Ok(status.exit_code.unwrap_or(ABNORMAL_EXIT));
+    }
+
+    let code = loop {
+        let event = tokio::select! {
+            event = events.next() => event,
+            _ = tokio::signal::ctrl_c() => break INTERRUPTED_EXIT,
+        };
+        match event {
+            Some(ClientEvent::Channel(ChannelEvent::Exited { name, exit_code }))
+                if name == channel =>
+            {
+                break exit_code.unwrap_or(ABNORMAL_EXIT)
+            }
+            Some(ClientEvent::Channel(ChannelEvent::Killed { name })) if name == channel => {
+                eprintln!("nexus: channel '{}' was killed", channel);
+                break ABNORMAL_EXIT;
+            }
+            Some(_) => {}
+            None => return Err(anyhow!("Server closed the connection")),
+        }
+    };
+
+    client.detach().await?;
+    Ok(code)
+}
+
+/// Skip events up to and including the next subscription change
+async fn next_subscription_change(events: &mut EventStream) -> Result<()> {
+    loop {
+        match events.next().await {
+            Some(ClientEvent::Channel(ChannelEvent::SubscriptionChanged { .. })) => return Ok(()),
+            Some(_) => {}
+            None => return Err(anyhow!("Server closed the connection")),
+        }
+    }
+}
+
+/// The tail of `data` holding its last `count` lines
+fn last_lines(data: &[u8], count: usize) -> &[u8] {
+    if count == 0 {
+        return &[];
+    }
+    // A trailing newline ends the last line rather than starting a new one
+    let body = data.strip_suffix(b"\n").unwrap_or(data);
+    let start = body
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, &byte)| byte == b'\n')
+        .nth(count - 1)
+        .map_or(0, |(pos, _)| pos + 1);
+    &data[start..]
+}
+
+/// Connect to a session that must already be running
+async fn connect_existing(session: &str) -> Result<NexusClient> {
+    let socket_path = Config::load()?.socket_path(session);
+    if !socket_path.exists() {
+        return Err(anyhow!("Session '{}' not found", session));
+    }
+    NexusClient::connect(&socket_path).await
+}
+
+/// Join arguments into a shell command line, quoting where needed
+fn shell_join(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c));
+    if is_plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_arguments_for_the_shell() {
+        let args: Vec<String> = ["sh", "-c", "echo it's here", ""]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(shell_join(&args), r"sh -c 'echo it'\''s here' ''");
+    }
+
+    #[test]
+    fn finds_session_sockets_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["work.sock", "api.sock", "work.http-token"] {
+            std::fs::write(dir.path().join(file), "").unwrap();
+        }
+        let names: Vec<String> = session_sockets(dir.path())
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["api", "work"]);
+    }
+
+    #[test]
+    fn keeps_only_the_last_lines() {
+        assert_eq!(last_lines(b"a\nb\nc\n", 2), b"b\nc\n");
+        assert_eq!(last_lines(b"a\nb\nc", 2), b"b\nc");
+        assert_eq!(last_lines(b"a\nb\n", 5), b"a\nb\n");
+        assert_eq!(last_lines(b"a\nb\n", 0), b"");
+    }
+}
@@ -0,0 +1,118 @@
+//! System clipboard access: the native clipboard, or the terminal's through
+//! OSC 52 (which works over SSH, but can only be written)
+
+use crate::config::ClipboardMode;
+use std::io::{self, Write};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Escape sequence asking the terminal to put `text` on the clipboard
+pub fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+/// Write `text` to the clipboard of the terminal nexus runs in
+fn copy_osc52(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    stdout.write_all(osc52(text).as_bytes())?;
+    stdout.flush()
+}
+
+/// Whether the client runs in an SSH session, where the native clipboard
+/// is the remote machine's rather than the user's
+fn over_ssh() -> bool {
+    std::env::var_os("SSH_TTY").is_some() || std::env::var_os("SSH_CONNECTION").is_some()
+}
+
+/// The clipboard as configured by `general.clipboard`
+#[derive(Default)]
+pub struct Clipboard {
+    mode: ClipboardMode,
+    /// Opened on first use and kept, since on X11 copied text is only
+    /// served while it is open
+    native: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    pub fn set_mode(&mut self, mode: ClipboardMode) {
+        self.mode = mode;
+    }
+
+    fn native(&mut self) -> Result<&mut arboard::Clipboard, String> {
+        if self.native.is_none() {
+            let clipboard =
+                arboard::Clipboard::new().map_err(|e| format!("No system clipboard: {}", e))?;
+            self.native = Some(clipboard);
+        }
+        Ok(self.native.as_mut().expect("opened above"))
+    }
+
+    fn uses_native(&self) -> bool {
+        match self.mode {
+            ClipboardMode::Auto => !over_ssh(),
+            ClipboardMode::Native => true,
+            ClipboardMode::Osc52 => false,
+        }
+    }
+
+    /// Copy `text`, falling back to OSC 52 when `auto` finds no native
+    /// clipboard
+    pub fn copy(&mut self, text: &str) -> Result<(), String> {
+        if self.uses_native() {
+            let native = self
+                .native()
+                .and_then(|c| c.set_text(text).map_err(|e| e.to_string()));
+            match native {
+                Ok(()) => return Ok(()),
+                Err(e) if self.mode == ClipboardMode::Native => return Err(e),
+                Err(_) => {}
+            }
+        }
+        copy_osc52(text).map_err(|e| e.to_string())
+    }
+
+    /// Text on the native clipboard
+    pub fn paste(&mut self) -> Result<String, String> {
+        if self.mode == ClipboardMode::Osc52 {
+            return Err(
+                "the OSC 52 clipboard can't be read; set general.clipboard to \"auto\"".into(),
+            );
+        }
+        self.native()?.get_text().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_base64_with_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64("héllo".as_bytes()), "aMOpbGxv");
+        assert_eq!(osc52("hi"), "\x1b]52;c;aGk=\x07");
+    }
+}
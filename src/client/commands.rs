@@ -1,7 +1,14 @@
 //! Command handling for client control commands (prefixed with `:`)
 
+use crate::channel::schedule::{Cron, Schedule};
+use crate::channel::{ChannelMode, ContainerRuntime, ContainerTarget};
+use crate::client::ansi::strip_ansi_codes;
 use crate::client::app::{App, ViewMode};
-use crate::protocol::ClientMessage;
+use crate::client::layout::Split;
+use crate::client::macros::{parse_play_args, Playback, Recording};
+use crate::client::sidebar::Sidebar;
+use crate::config::{duration, Config, TimestampMode};
+use crate::protocol::{Capability, ClientMessage, CreateChannelRequest, StopSignal};
 use anyhow::Result;
 use tokio::sync::mpsc::Sender;
 
@@ -10,6 +17,157 @@ pub enum CommandResult {
     Exit,
 }
 
+/// Modes `:ts` switches timestamps to, by name
+const TIMESTAMP_MODES: [(&str, TimestampMode); 3] = [
+    ("clock", TimestampMode::Clock),
+    ("relative", TimestampMode::Relative),
+    ("elapsed", TimestampMode::Elapsed),
+];
+
+/// Arguments accepted by `:new`
+#[derive(Debug, Default, PartialEq)]
+pub struct NewChannelArgs {
+    pub name: String,
+    pub command: Option<String>,
+    pub env: Vec<(String, String)>,
+    pub template: Option<String>,
+    pub container: Option<ContainerTarget>,
+    pub mode: Option<ChannelMode>,
+    pub size: Option<(u16, u16)>,
+    pub timeout_secs: Option<u64>,
+    pub schedule: Option<Schedule>,
+}
+
+/// Parse `:new <name> [--template NAME] [--env KEY=VAL]... [--docker|--podman CONTAINER] [--pipe] [--size COLSxROWS] [--timeout DURATION] [--every DURATION|--cron EXPR] [command]`.
+///
+/// Options must come before the command; everything after the first
+/// non-option argument is treated as the command line.
+pub fn parse_new_args(args: &[String]) -> Result<NewChannelArgs, String> {
+    let mut iter = args.iter();
+    let name = iter
+        .next()
+        .ok_or_else(|| {
+            "Usage: :new <name> [--template NAME] [--env KEY=VAL]... [--docker|--podman CONTAINER] [--pipe] [--size COLSxROWS] [--timeout DURATION] [--every DURATION|--cron EXPR] [command]"
+                .to_string()
+        })?
+        .clone();
+
+    let mut parsed = NewChannelArgs {
+        name,
+        ..Default::default()
+    };
+
+    let mut rest: Vec<String> = Vec::new();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--env" | "-e" => {
+                let pair = iter
+                    .next()
+                    .ok_or_else(|| format!("{} requires a KEY=VAL argument", arg))?;
+                parsed.env.push(parse_env_pair(pair)?);
+            }
+            "--template" | "-t" => {
+                let template = iter
+                    .next()
+                    .ok_or_else(|| format!("{} requires a template name", arg))?;
+                parsed.template = Some(template.clone());
+            }
+            "--pipe" => parsed.mode = Some(ChannelMode::Pipe),
+            "--size" => {
+                let size = iter
+                    .next()
+                    .ok_or_else(|| format!("{} requires a COLSxROWS argument", arg))?;
+                parsed.size = Some(parse_size(size)?);
+            }
+            "--timeout" => {
+                let timeout = iter
+                    .next()
+                    .ok_or_else(|| format!("{} requires a duration such as 30m", arg))?;
+                parsed.timeout_secs = Some(duration::parse(timeout)?);
+            }
+            "--every" => {
+                let every = iter
+                    .next()
+                    .ok_or_else(|| format!("{} requires a duration such as 1h", arg))?;
+                parsed.schedule = Some(Schedule::Every(duration::parse(every)?));
+            }
+            "--cron" => {
+                // Five fields, or one `@daily`-style shortcut
+                let first = iter
+                    .next()
+                    .ok_or_else(|| format!("{} requires a cron expression", arg))?;
+                let mut expr = vec![first.as_str()];
+                if !first.starts_with('@') {
+                    expr.extend(iter.by_ref().take(4).map(String::as_str));
+                }
+                parsed.schedule = Some(Schedule::Cron(Cron::parse(&expr.join(" "))?));
+            }
+            "--docker" | "--podman" => {
+                let container = iter
+                    .next()
+                    .ok_or_else(|| format!("{} requires a container name", arg))?;
+                let runtime = if arg == "--docker" {
+                    ContainerRuntime::Docker
+                } else {
+                    ContainerRuntime::Podman
+                };
+                parsed.container = Some(ContainerTarget::new(runtime, container.clone()));
+            }
+            _ => {
+                if let Some(pair) = arg.strip_prefix("--env=") {
+                    parsed.env.push(parse_env_pair(pair)?);
+                } else {
+                    rest.push(arg.clone());
+                    rest.extend(iter.by_ref().cloned());
+                }
+            }
+        }
+    }
+
+    if !rest.is_empty() {
+        parsed.command = Some(rest.join(" "));
+    }
+    Ok(parsed)
+}
+
+fn parse_size(size: &str) -> Result<(u16, u16), String> {
+    size.split_once('x')
+        .and_then(|(cols, rows)| Some((cols.parse().ok()?, rows.parse().ok()?)))
+        .filter(|&(cols, rows)| cols > 0 && rows > 0)
+        .ok_or_else(|| format!("Invalid size '{}', expected COLSxROWS", size))
+}
+
+fn parse_env_pair(pair: &str) -> Result<(String, String), String> {
+    match pair.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!(
+            "Invalid environment variable '{}', expected KEY=VAL",
+            pair
+        )),
+    }
+}
+
+/// Reload config.toml, apply it locally and ask the server to pick it up too
+pub async fn reload_config(app: &mut App, msg_tx: &Sender<ClientMessage>) -> Result<()> {
+    match Config::load() {
+        Ok(config) => {
+            app.apply_config(config);
+            msg_tx.send(ClientMessage::ReloadConfig).await?;
+            if let Some(request) = app.notification_request() {
+                msg_tx.send(request).await?;
+            }
+            app.add_output("SYSTEM".to_string(), "Configuration reloaded".to_string());
+        }
+        Err(e) => {
+            app.add_output(
+                "SYSTEM".to_string(),
+                format!("Failed to reload configuration: {}", e),
+            );
+        }
+    }
+    Ok(())
+}
+
 /// Handle a parsed control command and return whether to continue or exit.
 #[allow(clippy::too_many_arguments)]
 pub async fn handle_control_command(
@@ -21,25 +179,33 @@ pub async fn handle_control_command(
 ) -> Result<CommandResult> {
     match command {
         "new" => {
-            if args.is_empty() {
+            let parsed = match parse_new_args(&args) {
+                Ok(parsed) => parsed,
+                Err(message) => {
+                    app.add_output("SYSTEM".to_string(), message);
+                    return Ok(CommandResult::Continue);
+                }
+            };
+            if parsed.schedule.is_some() && !app.server_supports(Capability::Schedules) {
                 app.add_output(
                     "SYSTEM".to_string(),
-                    "Usage: :new <name> [command]".to_string(),
+                    "The server doesn't support scheduled channels".to_string(),
                 );
                 return Ok(CommandResult::Continue);
             }
-            let name = args[0].clone();
-            let command = if args.len() > 1 {
-                Some(args[1..].join(" "))
-            } else {
-                None
-            };
             msg_tx
-                .send(ClientMessage::CreateChannel {
-                    name,
-                    command,
-                    working_dir: None,
-                })
+                .send(ClientMessage::CreateChannel(CreateChannelRequest {
+                    name: parsed.name,
+                    command: parsed.command,
+                    env: parsed.env,
+                    template: parsed.template,
+                    container: parsed.container,
+                    mode: parsed.mode,
+                    size: parsed.size,
+                    timeout_secs: parsed.timeout_secs,
+                    schedule: parsed.schedule,
+                    ..Default::default()
+                }))
                 .await?;
         }
         "kill" => {
@@ -56,6 +222,24 @@ pub async fn handle_control_command(
         "list" => {
             msg_tx.send(ClientMessage::ListChannels).await?;
         }
+        "info" => {
+            app.info_requested = true;
+            msg_tx.send(ClientMessage::GetSessionInfo).await?;
+        }
+        "clients" => {
+            msg_tx.send(ClientMessage::ListClients).await?;
+        }
+        "wall" => {
+            if args.is_empty() {
+                app.add_output("SYSTEM".to_string(), "Usage: :wall <message>".to_string());
+                return Ok(CommandResult::Continue);
+            }
+            msg_tx
+                .send(ClientMessage::Wall {
+                    message: args.join(" "),
+                })
+                .await?;
+        }
         "status" => {
             let target = args.first().cloned();
             msg_tx
@@ -121,44 +305,357 @@ pub async fn handle_control_command(
                 ),
             );
         }
-        "clear" => {
-            // Clear buffers
-            app.channel_buffers.clear();
-            app.interleaved_buffer.clear();
-            app.scroll_offsets.clear();
-        }
+        "clear" => app.clear_output(),
         "view" => {
             // Toggle or set view mode
             if args.is_empty() {
-                app.view_mode = match app.view_mode {
-                    ViewMode::ActiveChannel => ViewMode::AllChannels,
-                    ViewMode::AllChannels => ViewMode::ActiveChannel,
-                };
+                app.view_mode = app.view_mode.toggled();
                 let mode_name = match app.view_mode {
                     ViewMode::ActiveChannel => "channel (clean output)",
                     ViewMode::AllChannels => "all (interleaved with prefixes)",
+                    ViewMode::Hex => "hex (raw output as a hexdump)",
+                    ViewMode::Grid => "grid (every subscribed channel tiled)",
+                    ViewMode::Diff => "diff (two channels side by side)",
                 };
                 app.add_output("SYSTEM".to_string(), format!("View mode: {}", mode_name));
             } else {
                 match args[0].as_str() {
                     "channel" | "active" => app.view_mode = ViewMode::ActiveChannel,
                     "all" | "interleaved" => app.view_mode = ViewMode::AllChannels,
+                    "hex" => app.view_mode = ViewMode::Hex,
+                    "grid" => app.view_mode = ViewMode::Grid,
                     _ => {
                         app.add_output(
                             "SYSTEM".to_string(),
-                            "Usage: :view [channel|all]".to_string(),
+                            "Usage: :view [channel|all|hex|grid]".to_string(),
                         );
                         return Ok(CommandResult::Continue);
                     }
                 }
             }
         }
-        "timestamps" | "ts" => {
-            app.show_timestamps = !app.show_timestamps;
-            let status = if app.show_timestamps {
-                "enabled"
+        "split" | "vsplit" => {
+            let split = if command == "split" {
+                Split::Stacked
+            } else {
+                Split::SideBySide
+            };
+            if args.len() != 1 {
+                app.add_output(
+                    "SYSTEM".to_string(),
+                    format!("Usage: :{} <channel>", command),
+                );
+                return Ok(CommandResult::Continue);
+            }
+            let channel = args[0].trim_start_matches('#');
+            if app.active_channel.is_none() {
+                app.add_output(
+                    "SYSTEM".to_string(),
+                    "No active channel to split".to_string(),
+                );
+                return Ok(CommandResult::Continue);
+            }
+            if !app.channels.iter().any(|c| c.name == channel) {
+                app.add_output("SYSTEM".to_string(), format!("No channel '{}'", channel));
+                return Ok(CommandResult::Continue);
+            }
+            app.split_pane(split, channel);
+            msg_tx
+                .send(ClientMessage::SwitchChannel {
+                    name: channel.to_string(),
+                })
+                .await?;
+        }
+        "diff" => {
+            if args.is_empty() && app.view_mode == ViewMode::Diff {
+                app.view_mode = ViewMode::ActiveChannel;
+                return Ok(CommandResult::Continue);
+            }
+            if args.len() != 2 {
+                app.add_output(
+                    "SYSTEM".to_string(),
+                    "Usage: :diff <channel> <channel>".to_string(),
+                );
+                return Ok(CommandResult::Continue);
+            }
+            let left = args[0].trim_start_matches('#').to_string();
+            let right = args[1].trim_start_matches('#').to_string();
+            if let Some(missing) = [&left, &right]
+                .into_iter()
+                .find(|ch| !app.channels.iter().any(|c| &c.name == *ch))
+            {
+                app.add_output("SYSTEM".to_string(), format!("No channel '{}'", missing));
+                return Ok(CommandResult::Continue);
+            }
+            // Only subscribed channels' output reaches the client
+            let unsubscribed: Vec<String> = [&left, &right]
+                .into_iter()
+                .filter(|ch| !app.subscriptions.contains(ch))
+                .cloned()
+                .collect();
+            if !unsubscribed.is_empty() {
+                msg_tx
+                    .send(ClientMessage::Subscribe {
+                        channels: unsubscribed,
+                    })
+                    .await?;
+            }
+            app.diff = Some((left, right));
+            app.diff_scroll = 0;
+            app.view_mode = ViewMode::Diff;
+        }
+        "close" => {
+            if !app.panes.is_split() {
+                app.add_output("SYSTEM".to_string(), "No split to close".to_string());
+                return Ok(CommandResult::Continue);
+            }
+            app.close_pane();
+            if let Some(name) = app.active_channel.clone() {
+                msg_tx.send(ClientMessage::SwitchChannel { name }).await?;
+            }
+        }
+        "only" => app.panes.close_others(),
+        "popup" => {
+            if app.popup.is_some() {
+                app.add_output("SYSTEM".to_string(), "A popup is already open".to_string());
+                return Ok(CommandResult::Continue);
+            }
+            let command = (!args.is_empty()).then(|| args.join(" "));
+            let name = format!("popup-{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+            msg_tx
+                .send(ClientMessage::CreateChannel(CreateChannelRequest {
+                    name: name.clone(),
+                    command: command.clone(),
+                    ephemeral: true,
+                    ..Default::default()
+                }))
+                .await?;
+            msg_tx
+                .send(ClientMessage::SwitchChannel { name: name.clone() })
+                .await?;
+            app.open_popup(name, command.unwrap_or_else(|| "shell".to_string()));
+        }
+        "run" => {
+            if args.is_empty() {
+                app.add_output("SYSTEM".to_string(), "Usage: :run <command>".to_string());
+                return Ok(CommandResult::Continue);
+            }
+            let command = args.join(" ");
+            let name = app.next_run_name();
+            msg_tx
+                .send(ClientMessage::CreateChannel(CreateChannelRequest {
+                    name: name.clone(),
+                    command: Some(command.clone()),
+                    ephemeral: true,
+                    keep_on_failure: true,
+                    ..Default::default()
+                }))
+                .await?;
+            msg_tx
+                .send(ClientMessage::SwitchChannel { name: name.clone() })
+                .await?;
+            app.start_run(name, command);
+        }
+        "ps" => {
+            if !app.server_supports(Capability::Processes) {
+                app.add_output(
+                    "SYSTEM".to_string(),
+                    "The server doesn't support listing processes".to_string(),
+                );
+                return Ok(CommandResult::Continue);
+            }
+            let channel = match args.first() {
+                Some(name) => name.trim_start_matches('#').to_string(),
+                None => match &app.active_channel {
+                    Some(name) => name.clone(),
+                    None => {
+                        app.add_output("SYSTEM".to_string(), "Usage: :ps [channel]".to_string());
+                        return Ok(CommandResult::Continue);
+                    }
+                },
+            };
+            msg_tx.send(ClientMessage::GetProcesses { channel }).await?;
+        }
+        "stop" => {
+            let mut signal = StopSignal::Interrupt;
+            let mut channel = None;
+            for arg in &args {
+                match arg.as_str() {
+                    "--term" => signal = StopSignal::Terminate,
+                    "--kill" => signal = StopSignal::Kill,
+                    name if !name.starts_with("--") => {
+                        channel = Some(name.trim_start_matches('#').to_string())
+                    }
+                    _ => {
+                        app.add_output(
+                            "SYSTEM".to_string(),
+                            "Usage: :stop [channel] [--term|--kill]".to_string(),
+                        );
+                        return Ok(CommandResult::Continue);
+                    }
+                }
+            }
+            stop_foreground(app, msg_tx, channel, signal).await?;
+        }
+        "pin" | "unpin" => {
+            let pinned = command == "pin";
+            if !app.server_capabilities.contains(&Capability::Pins) {
+                app.add_output(
+                    "SYSTEM".to_string(),
+                    "The server doesn't support pinned channels".to_string(),
+                );
+                return Ok(CommandResult::Continue);
+            }
+            let channel = match args.first() {
+                Some(name) => name.trim_start_matches('#').to_string(),
+                None => match &app.active_channel {
+                    Some(name) => name.clone(),
+                    None => {
+                        app.add_output(
+                            "SYSTEM".to_string(),
+                            format!("Usage: :{} [channel]", command),
+                        );
+                        return Ok(CommandResult::Continue);
+                    }
+                },
+            };
+            msg_tx
+                .send(ClientMessage::SetPinned { channel, pinned })
+                .await?;
+        }
+        "sidebar" => {
+            app.sidebar = match app.sidebar {
+                Some(_) => None,
+                None => Some(Sidebar::default()),
+            };
+        }
+        "zoom" => {
+            if app.is_tiled() {
+                app.toggle_zoom();
             } else {
-                "disabled"
+                app.add_output(
+                    "SYSTEM".to_string(),
+                    "Nothing to zoom: use :split or :view grid first".to_string(),
+                );
+            }
+        }
+        "fold" => match args.first().map(String::as_str) {
+            None => {
+                if !app.toggle_fold() {
+                    app.add_output(
+                        "SYSTEM".to_string(),
+                        "No output section here to fold".to_string(),
+                    );
+                }
+            }
+            Some("all") => app.set_all_folds(true),
+            Some("none") => app.set_all_folds(false),
+            Some(other) => app.add_output(
+                "SYSTEM".to_string(),
+                format!("Usage: :fold [all|none] (got '{}')", other),
+            ),
+        },
+        "mark" => match args.first() {
+            None => list_marks(app),
+            Some(name) => match app.set_mark(name) {
+                Ok(mark) => {
+                    if app.shares_marks() && mark.seq != 0 {
+                        msg_tx
+                            .send(ClientMessage::SetMark {
+                                channel: app.active_channel.clone().unwrap_or_default(),
+                                name: name.clone(),
+                                seq: Some(mark.seq),
+                            })
+                            .await?;
+                    }
+                }
+                Err(e) => app.add_output("SYSTEM".to_string(), e),
+            },
+        },
+        "unmark" => {
+            let (Some(name), Some(channel)) = (args.first(), app.active_channel.clone()) else {
+                app.add_output("SYSTEM".to_string(), "Usage: :unmark <name>".to_string());
+                return Ok(CommandResult::Continue);
+            };
+            let removed = app
+                .marks
+                .get_mut(&channel)
+                .and_then(|marks| marks.remove(name));
+            if removed.is_none() {
+                app.add_output(
+                    "SYSTEM".to_string(),
+                    format!("No mark '{}' in #{}", name, channel),
+                );
+            } else if app.shares_marks() {
+                msg_tx
+                    .send(ClientMessage::SetMark {
+                        channel,
+                        name: name.clone(),
+                        seq: None,
+                    })
+                    .await?;
+            }
+        }
+        "jump" => match args.first() {
+            Some(name) => {
+                if let Err(e) = app.jump_to_mark(name) {
+                    app.add_output("SYSTEM".to_string(), e);
+                }
+            }
+            None => app.add_output("SYSTEM".to_string(), "Usage: :jump <mark>".to_string()),
+        },
+        "search" | "find" => {
+            if args.is_empty() {
+                app.end_search();
+            } else if let Err(e) = app.start_search(&args.join(" ")) {
+                app.add_output("SYSTEM".to_string(), format!("Invalid search: {}", e));
+            }
+        }
+        "copy" => {
+            let text = match args.first().map(String::as_str) {
+                None => app.lines_in_view(app.output_area.height as usize),
+                Some("last") => app.last_command_output(),
+                Some(n) => match n.parse::<usize>() {
+                    Ok(n) if n > 0 => app.lines_in_view(n),
+                    _ => {
+                        app.add_output(
+                            "SYSTEM".to_string(),
+                            "Usage: :copy [lines|last]".to_string(),
+                        );
+                        return Ok(CommandResult::Continue);
+                    }
+                },
+            };
+            copy_to_clipboard(app, text);
+        }
+        "paste" => match app.clipboard.paste() {
+            Ok(text) => app.line_editor.insert_str(&text),
+            Err(e) => app.add_output("SYSTEM".to_string(), format!("Paste failed: {}", e)),
+        },
+        "timestamps" | "ts" => {
+            match args.first().map(String::as_str) {
+                None => app.show_timestamps = !app.show_timestamps,
+                Some("off") => app.show_timestamps = false,
+                Some(mode) => match TIMESTAMP_MODES.iter().find(|(name, _)| *name == mode) {
+                    Some(&(_, mode)) => {
+                        app.timestamp_mode = mode;
+                        app.show_timestamps = true;
+                    }
+                    None => {
+                        app.add_output(
+                            "SYSTEM".to_string(),
+                            "Usage: :ts [clock|relative|elapsed|off]".to_string(),
+                        );
+                        return Ok(CommandResult::Continue);
+                    }
+                },
+            }
+            let status = match TIMESTAMP_MODES
+                .iter()
+                .find(|(_, m)| *m == app.timestamp_mode)
+            {
+                Some((name, _)) if app.show_timestamps => name,
+                _ => "off",
             };
             app.add_output("SYSTEM".to_string(), format!("Timestamps: {}", status));
         }
@@ -168,15 +665,54 @@ pub async fn handle_control_command(
                 "",
                 "Commands:",
                 "  :new <name> [cmd]   Create a new channel (optionally with a command)",
+                "    --template NAME   Start from a [templates.NAME] entry in config.toml",
+                "    --env KEY=VAL     Set an environment variable (repeatable)",
+                "    --docker NAME     Run inside a running container (also --podman)",
+                "    --pipe            Use pipes instead of a PTY; stderr is shown in red",
+                "    --size 200x50     Lock the terminal size instead of following clients",
+                "    --timeout 30m     Kill the process if it's still running after this long",
+                "    --every 1h        Run the command again every hour once it has exited",
+                "    --cron 0 3 * * *  Run the command again whenever the time matches",
                 "  :kill <name>        Kill a channel",
                 "  :list               List all channels",
                 "  :status [name]      Show channel status",
+                "  :info               Show session info (uptime, clients, server version)",
+                "  :clients            List the clients attached to the session",
+                "  :wall <message>     Show a note to every attached client",
                 "  :sub <ch> [ch...]   Subscribe to channel output (:sub * for all)",
                 "  :unsub <ch>         Unsubscribe from channel",
                 "  :subs               Show current subscriptions",
                 "  :view [channel|all] Toggle or set view mode",
+                "  :view hex           Show the channel's raw output as a hexdump",
+                "  :view grid          Tile every subscribed channel into equal panes",
+                "  :split <ch>         Show a channel in a new pane below (:vsplit alongside)",
+                "  :close / :only      Close the focused pane / all other panes",
+                "  :diff <ch> <ch>     Compare two channels side by side (:diff again to leave)",
+                "  :popup [cmd]        Run a command (or a shell) in a floating window until it exits",
+                "  :run <cmd>          Run a command in a scratch channel, closed again if it succeeds",
+                "  :zoom               Maximize the focused pane, or restore the layout (Alt+Z)",
+                "  :pin [ch] / :unpin  Keep a channel at the front of the status bar (default: active)",
+                "  :sidebar            Show or hide the channel list (Alt+S to select with arrows/Enter)",
+                "  :fold [all|none]    Fold/unfold the output section at the bottom of the view (Alt+F)",
+                "  :mark [name]        Mark the line at the bottom of the view, or list marks",
+                "  :jump <name>        Scroll back to a mark (:unmark <name> removes it)",
+                "  :search <regex>     Highlight matches and jump between them (F3/Shift+F3, Esc ends)",
+                "  :copy [n|last]      Copy the last n lines in view (default: the whole view),",
+                "                      or the last command's output (Alt+C)",
+                "  :paste              Insert the clipboard into the input line",
                 "  :clear              Clear the output area",
-                "  :timestamps         Toggle timestamp display (:ts)",
+                "  :ps [channel]       Show the processes running in a channel",
+                "  :stop [ch] [--term|--kill]  Interrupt the command a channel is running,",
+                "                      leaving its shell alone (Alt+X)",
+                "  :timestamps [mode]  Toggle timestamps, or show clock, relative or elapsed times (:ts)",
+                "  :reload             Reload config.toml (also picked up automatically)",
+                "  :set <key> [value]  Show or change a setting (--save writes config.toml)",
+                "  :keys               List active key bindings ([keybindings] in config.toml)",
+                "  :alias [name [cmd]] List, show or define an alias (--save writes config.toml)",
+                "  :macro record <n>   Record input lines until :macro stop [--save]",
+                "  :macro play <n>     Replay a macro (--delay MS, optional target channel)",
+                "  :<script command>   Run a command registered by a Lua script",
+                "  :detach             Exit, leaving the session running (restored on reattach)",
                 "  :quit               Exit nexus",
                 "",
                 "Channel switching:",
@@ -184,6 +720,7 @@ pub async fn handle_control_command(
                 "  #<name> <cmd>       Send command to channel without switching",
                 "  Alt+1-9             Quick switch to channel by number",
                 "  Ctrl+Left/Right     Switch to previous/next channel",
+                "  Alt+Left/Right      Focus previous/next split pane",
                 "",
                 "Scrolling:",
                 "  Page Up/Down        Scroll output by page",
@@ -206,20 +743,488 @@ pub async fn handle_control_command(
                 "Mouse:",
                 "  Click channel       Switch to clicked channel in status bar",
                 "  Scroll wheel        Scroll output up/down",
+                "  Drag in output      Select text; it is copied when you let go",
             ];
 
             for line in help_lines {
                 app.add_output("SYSTEM".to_string(), line.to_string());
             }
         }
+        "reload" => reload_config(app, msg_tx).await?,
+        "keys" => {
+            app.add_output("SYSTEM".to_string(), "Key bindings:".to_string());
+            for line in app.keymap.describe() {
+                app.add_output("SYSTEM".to_string(), line);
+            }
+        }
+        "set" => {
+            let save = args.first().map(|a| a == "--save").unwrap_or(false);
+            let rest = if save { &args[1..] } else { &args[..] };
+            match rest {
+                [key] => {
+                    let message = match app.config.get(key) {
+                        Some(value) => format!("{} = {}", key, value),
+                        None => format!("Unknown setting '{}'", key),
+                    };
+                    app.add_output("SYSTEM".to_string(), message);
+                }
+                [key, value @ ..] if !value.is_empty() => {
+                    let value = value.join(" ");
+                    let mut config = app.config.clone();
+                    match config.set(key, &value) {
+                        Ok(()) => {
+                            app.apply_config(config);
+                            msg_tx
+                                .send(ClientMessage::SetConfig {
+                                    key: key.clone(),
+                                    value: value.clone(),
+                                })
+                                .await?;
+                            let mut message = format!("{} = {}", key, value);
+                            if save {
+                                match app.config.save() {
+                                    Ok(()) => message.push_str(" (saved)"),
+                                    Err(e) => message.push_str(&format!(" (not saved: {})", e)),
+                                }
+                            }
+                            app.add_output("SYSTEM".to_string(), message);
+                        }
+                        Err(e) => app.add_output("SYSTEM".to_string(), format!("Error: {}", e)),
+                    }
+                }
+                _ => app.add_output(
+                    "SYSTEM".to_string(),
+                    "Usage: :set [--save] <key> [value]".to_string(),
+                ),
+            }
+        }
+        "alias" => {
+            let save = args.first().map(|a| a == "--save").unwrap_or(false);
+            let rest = if save { &args[1..] } else { &args[..] };
+            match rest {
+                [] => {
+                    if app.config.aliases.is_empty() {
+                        app.add_output("SYSTEM".to_string(), "No aliases defined".to_string());
+                    }
+                    let lines: Vec<String> = app
+                        .config
+                        .aliases
+                        .iter()
+                        .map(|(name, expansion)| format!("  :{} = {}", name, expansion))
+                        .collect();
+                    for line in lines {
+                        app.add_output("SYSTEM".to_string(), line);
+                    }
+                }
+                [name] => {
+                    let name = name.trim_matches(':');
+                    let message = match app.config.aliases.get(name) {
+                        Some(expansion) => format!(":{} = {}", name, expansion),
+                        None => format!("No alias named '{}'", name),
+                    };
+                    app.add_output("SYSTEM".to_string(), message);
+                }
+                [name, expansion @ ..] => {
+                    let name = name.trim_matches(':').to_string();
+                    let expansion = expansion.join(" ");
+                    let mut message = format!(":{} = {}", name, expansion);
+                    app.config.aliases.insert(name, expansion);
+                    if save {
+                        match app.config.save() {
+                            Ok(()) => message.push_str(" (saved)"),
+                            Err(e) => message.push_str(&format!(" (not saved: {})", e)),
+                        }
+                    }
+                    app.add_output("SYSTEM".to_string(), message);
+                }
+            }
+        }
+        "macro" => handle_macro_command(&args, app),
         "quit" | "exit" => return Ok(CommandResult::Exit),
+        "detach" => {
+            msg_tx.send(ClientMessage::Detach).await?;
+            return Ok(CommandResult::Exit);
+        }
         _ => {
-            app.add_output(
-                "SYSTEM".to_string(),
-                format!("Unknown command: {}", command),
-            );
+            // Not built in; the server reports it as unknown unless a script registered it
+            msg_tx
+                .send(ClientMessage::ScriptCommand {
+                    name: command.to_string(),
+                    args,
+                })
+                .await?;
         }
     }
 
     Ok(CommandResult::Continue)
 }
+
+/// Signal the command in the foreground of `channel` (default: the active
+/// one) without touching the channel's own process
+pub async fn stop_foreground(
+    app: &mut App,
+    msg_tx: &Sender<ClientMessage>,
+    channel: Option<String>,
+    signal: StopSignal,
+) -> Result<()> {
+    if !app.server_supports(Capability::SignalForeground) {
+        app.add_output(
+            "SYSTEM".to_string(),
+            "The server doesn't support stopping foreground commands".to_string(),
+        );
+        return Ok(());
+    }
+    let Some(channel) = channel.or_else(|| app.active_channel.clone()) else {
+        app.add_output(
+            "SYSTEM".to_string(),
+            "Usage: :stop [channel] [--term|--kill]".to_string(),
+        );
+        return Ok(());
+    };
+    let command = app
+        .channels
+        .iter()
+        .find(|c| c.name == channel)
+        .and_then(|c| c.foreground.clone())
+        .unwrap_or_else(|| "the foreground command".to_string());
+    app.add_output(
+        "SYSTEM".to_string(),
+        format!("Sending {} to {} in #{}", signal.name(), command, channel),
+    );
+    msg_tx
+        .send(ClientMessage::SignalForeground { channel, signal })
+        .await?;
+    Ok(())
+}
+
+/// Copy text to the clipboard, reporting how it went
+pub fn copy_to_clipboard(app: &mut App, text: Option<String>) {
+    let message = match text {
+        None => "Nothing to copy".to_string(),
+        Some(text) => match app.clipboard.copy(&text) {
+            Ok(()) => format!("Copied {} lines", text.lines().count()),
+            Err(e) => format!("Copy failed: {}", e),
+        },
+    };
+    app.add_output("SYSTEM".to_string(), message);
+}
+
+/// `:mark` without a name: the active channel's marks with the line each
+/// sits at
+fn list_marks(app: &mut App) {
+    let Some(channel) = app.active_channel.clone() else {
+        return;
+    };
+    let buffer = app.channel_buffers.get(&channel).map(Vec::as_slice);
+    let trimmed = app.trimmed.get(&channel).copied().unwrap_or(0);
+    let lines: Vec<String> = app
+        .marks
+        .get(&channel)
+        .into_iter()
+        .flatten()
+        .map(|(name, mark)| {
+            let line = buffer
+                .and_then(|buffer| Some(&buffer[mark.resolve(buffer, trimmed)?]))
+                .map(|line| strip_ansi_codes(&line.content).trim().to_string())
+                .unwrap_or_else(|| "(scrolled out)".to_string());
+            format!("  {:<16} {}", name, line)
+        })
+        .collect();
+
+    if lines.is_empty() {
+        app.add_output(
+            "SYSTEM".to_string(),
+            format!("No marks in #{} (:mark <name> sets one)", channel),
+        );
+        return;
+    }
+    app.add_output("SYSTEM".to_string(), format!("Marks in #{}:", channel));
+    for line in lines {
+        app.add_output("SYSTEM".to_string(), line);
+    }
+}
+
+/// `:macro record|stop|play|list|delete`
+fn handle_macro_command(args: &[String], app: &mut App) {
+    let usage = "Usage: :macro record <name> | stop [--save] | play <name> [--delay MS] [channel] | list | delete <name>";
+    let subcommand = args.first().map(String::as_str).unwrap_or("");
+    let message = match (subcommand, &args[args.len().min(1)..]) {
+        ("record", [name]) => {
+            if app.recording.is_some() {
+                "Already recording; use :macro stop first".to_string()
+            } else {
+                app.recording = Some(Recording::new(name));
+                format!("Recording macro '{}' (:macro stop to finish)", name)
+            }
+        }
+        ("stop", rest) if rest.is_empty() || rest == ["--save"] => {
+            if let Some(recording) = app.recording.take() {
+                let mut message = format!(
+                    "Recorded macro '{}' ({} steps)",
+                    recording.name,
+                    recording.steps.len()
+                );
+                app.config.macros.insert(recording.name, recording.steps);
+                if !rest.is_empty() {
+                    match app.config.save() {
+                        Ok(()) => message.push_str(" (saved)"),
+                        Err(e) => message.push_str(&format!(" (not saved: {})", e)),
+                    }
+                }
+                message
+            } else if let Some(playback) = app.playback.take() {
+                format!("Stopped macro '{}'", playback.name)
+            } else {
+                "Not recording".to_string()
+            }
+        }
+        ("play", rest) => match parse_play_args(rest) {
+            Ok(_) if app.playback.is_some() => "A macro is already playing".to_string(),
+            Ok(play) => match app.config.macros.get(&play.name) {
+                Some(steps) => {
+                    app.playback = Some(Playback::new(
+                        &play.name,
+                        steps,
+                        play.channel.as_deref(),
+                        play.delay,
+                    ));
+                    format!("Playing macro '{}' ({} steps)", play.name, steps.len())
+                }
+                None => format!("No macro named '{}'", play.name),
+            },
+            Err(e) => e,
+        },
+        ("list", []) => {
+            if app.config.macros.is_empty() {
+                "No macros defined".to_string()
+            } else {
+                let names: Vec<String> = app
+                    .config
+                    .macros
+                    .iter()
+                    .map(|(name, steps)| format!("{} ({})", name, steps.len()))
+                    .collect();
+                format!("Macros: {}", names.join(", "))
+            }
+        }
+        ("delete", [name]) => match app.config.macros.remove(name) {
+            Some(_) => format!("Deleted macro '{}'", name),
+            None => format!("No macro named '{}'", name),
+        },
+        _ => usage.to_string(),
+    };
+    app.add_output("SYSTEM".to_string(), message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_new_with_name_only() {
+        let parsed = parse_new_args(&args(&["shell"])).unwrap();
+        assert_eq!(parsed.name, "shell");
+        assert_eq!(parsed.command, None);
+        assert!(parsed.env.is_empty());
+    }
+
+    #[test]
+    fn parses_new_with_env_and_command() {
+        let parsed = parse_new_args(&args(&[
+            "api",
+            "--env",
+            "PORT=8080",
+            "--env=RUST_LOG=debug",
+            "cargo",
+            "run",
+            "--env",
+        ]))
+        .unwrap();
+        assert_eq!(parsed.name, "api");
+        assert_eq!(
+            parsed.env,
+            vec![
+                ("PORT".to_string(), "8080".to_string()),
+                ("RUST_LOG".to_string(), "debug".to_string()),
+            ]
+        );
+        // Options after the command belong to the command
+        assert_eq!(parsed.command.as_deref(), Some("cargo run --env"));
+    }
+
+    #[test]
+    fn parses_new_with_template() {
+        let parsed = parse_new_args(&args(&["frontend", "--template", "node-dev"])).unwrap();
+        assert_eq!(parsed.name, "frontend");
+        assert_eq!(parsed.template.as_deref(), Some("node-dev"));
+        assert_eq!(parsed.command, None);
+        assert_eq!(parsed.mode, None);
+        assert!(parse_new_args(&args(&["frontend", "-t"])).is_err());
+    }
+
+    #[test]
+    fn parses_new_in_container() {
+        let parsed = parse_new_args(&args(&["appshell", "--docker", "web", "bash"])).unwrap();
+        assert_eq!(
+            parsed.container,
+            Some(ContainerTarget::new(ContainerRuntime::Docker, "web"))
+        );
+        assert_eq!(parsed.command.as_deref(), Some("bash"));
+        assert_eq!(
+            parsed
+                .container
+                .unwrap()
+                .exec_args(Some("bash"), None, &[], true),
+            args(&[
+                "exec",
+                "-it",
+                "-e",
+                "TERM=xterm-256color",
+                "web",
+                "sh",
+                "-c",
+                "bash"
+            ])
+        );
+        assert!(parse_new_args(&args(&["appshell", "--podman"])).is_err());
+    }
+
+    #[test]
+    fn parses_new_in_pipe_mode() {
+        let parsed = parse_new_args(&args(&["build", "--pipe", "make", "--pipe"])).unwrap();
+        assert_eq!(parsed.mode, Some(ChannelMode::Pipe));
+        assert_eq!(parsed.command.as_deref(), Some("make --pipe"));
+    }
+
+    #[test]
+    fn parses_new_with_timeout() {
+        let parsed = parse_new_args(&args(&["ci", "--timeout", "1h30m", "make test"])).unwrap();
+        assert_eq!(parsed.timeout_secs, Some(5400));
+        assert_eq!(parsed.command.as_deref(), Some("make test"));
+        assert!(parse_new_args(&args(&["ci", "--timeout", "soon"])).is_err());
+        assert!(parse_new_args(&args(&["ci", "--timeout"])).is_err());
+    }
+
+    #[test]
+    fn parses_new_with_schedule() {
+        let parsed = parse_new_args(&args(&["backup", "--every", "1h", "pg_dump", "db"])).unwrap();
+        assert_eq!(parsed.schedule, Some(Schedule::Every(3600)));
+        assert_eq!(parsed.command.as_deref(), Some("pg_dump db"));
+
+        let parsed = parse_new_args(&args(&[
+            "report", "--cron", "0", "9", "*", "*", "1-5", "make",
+        ]))
+        .unwrap();
+        assert_eq!(parsed.schedule.unwrap().describe(), "cron 0 9 * * 1-5");
+        assert_eq!(parsed.command.as_deref(), Some("make"));
+
+        let parsed = parse_new_args(&args(&["nightly", "--cron", "@daily", "make"])).unwrap();
+        assert_eq!(parsed.command.as_deref(), Some("make"));
+        assert!(parse_new_args(&args(&["x", "--cron", "0", "9", "make"])).is_err());
+    }
+
+    #[test]
+    fn parses_new_with_fixed_size() {
+        let parsed = parse_new_args(&args(&["logs", "--size", "200x50", "tail -f log"])).unwrap();
+        assert_eq!(parsed.size, Some((200, 50)));
+        assert_eq!(parsed.command.as_deref(), Some("tail -f log"));
+        assert!(parse_new_args(&args(&["logs", "--size", "200"])).is_err());
+        assert!(parse_new_args(&args(&["logs", "--size", "0x50"])).is_err());
+        assert!(parse_new_args(&args(&["logs", "--size"])).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_env() {
+        assert!(parse_new_args(&args(&["api", "--env", "PORT"])).is_err());
+        assert!(parse_new_args(&args(&["api", "--env"])).is_err());
+        assert!(parse_new_args(&args(&[])).is_err());
+    }
+
+    #[tokio::test]
+    async fn popup_opens_an_ephemeral_channel() {
+        let mut app = App::new();
+        app.active_channel = Some("api".to_string());
+        let (msg_tx, mut msg_rx) = tokio::sync::mpsc::channel(8);
+
+        handle_control_command("popup", args(&["git", "log"]), &mut app, &msg_tx, "")
+            .await
+            .unwrap();
+        let Ok(ClientMessage::CreateChannel(request)) = msg_rx.try_recv() else {
+            panic!("expected a CreateChannel");
+        };
+        assert!(request.name.starts_with("popup-"));
+        assert_eq!(request.command.as_deref(), Some("git log"));
+        assert!(request.ephemeral);
+        assert!(matches!(
+            msg_rx.try_recv(),
+            Ok(ClientMessage::SwitchChannel { name }) if name == request.name
+        ));
+        let popup = app.popup.as_ref().unwrap();
+        assert_eq!(popup.channel, request.name);
+        assert_eq!(popup.title, "git log");
+        assert_eq!(popup.previous.as_deref(), Some("api"));
+
+        // Only one at a time
+        handle_control_command("popup", args(&[]), &mut app, &msg_tx, "")
+            .await
+            .unwrap();
+        assert!(msg_rx.try_recv().is_err());
+        assert_eq!(app.popup.as_ref().unwrap().title, "git log");
+
+        // Without a command it runs a shell
+        app.close_popup();
+        handle_control_command("popup", args(&[]), &mut app, &msg_tx, "")
+            .await
+            .unwrap();
+        let Ok(ClientMessage::CreateChannel(request)) = msg_rx.try_recv() else {
+            panic!("expected a CreateChannel");
+        };
+        assert_eq!(request.command, None);
+        assert_eq!(app.popup.as_ref().unwrap().title, "shell");
+    }
+
+    #[tokio::test]
+    async fn alias_names_ignore_surrounding_colons() {
+        let mut app = App::new();
+        let (msg_tx, _msg_rx) = tokio::sync::mpsc::channel(8);
+
+        handle_control_command(
+            "alias",
+            args(&["t:", "cargo", "test"]),
+            &mut app,
+            &msg_tx,
+            "",
+        )
+        .await
+        .unwrap();
+        handle_control_command(
+            "alias",
+            args(&[":b", "cargo", "build"]),
+            &mut app,
+            &msg_tx,
+            "",
+        )
+        .await
+        .unwrap();
+        assert_eq!(app.config.aliases["t"], "cargo test");
+        assert_eq!(app.config.aliases["b"], "cargo build");
+
+        for name in ["t", ":t", "t:", ":t:"] {
+            app.clear_output();
+            handle_control_command("alias", args(&[name]), &mut app, &msg_tx, "")
+                .await
+                .unwrap();
+            let shown = &app.channel_buffers["SYSTEM"];
+            assert_eq!(
+                shown.last().unwrap().content,
+                ":t = cargo test",
+                "for {}",
+                name
+            );
+        }
+    }
+}
@@ -1,4 +1,4 @@
-//! Tab completion for commands and channel names
+//! Tab completion for commands, channel names and paths
 
 /// Available control commands for completion
 pub const COMMANDS: &[&str] = &[
@@ -6,13 +6,42 @@ pub const COMMANDS: &[&str] = &[
     "kill",
     "list",
     "status",
+    "ps",
+    "stop",
+    "info",
+    "clients",
+    "wall",
     "sub",
     "unsub",
     "subs",
     "clear",
     "view",
+    "split",
+    "vsplit",
+    "close",
+    "only",
+    "diff",
+    "zoom",
+    "popup",
+    "run",
+    "sidebar",
+    "fold",
+    "mark",
+    "unmark",
+    "jump",
+    "search",
+    "copy",
+    "paste",
+    "pin",
+    "unpin",
     "timestamps",
+    "reload",
+    "set",
+    "keys",
+    "alias",
+    "macro",
     "help",
+    "detach",
     "quit",
     "exit",
 ];
@@ -30,7 +59,19 @@ pub fn complete(input: &str, channel_names: &[String]) -> Vec<String> {
             let arg_partial = partial_cmd[space_idx..].trim();
 
             // Commands that take channel names as arguments
-            if matches!(cmd, "kill" | "sub" | "unsub") {
+            if matches!(
+                cmd,
+                "kill"
+                    | "sub"
+                    | "unsub"
+                    | "split"
+                    | "vsplit"
+                    | "diff"
+                    | "pin"
+                    | "unpin"
+                    | "ps"
+                    | "stop"
+            ) {
                 return complete_channel_arg(input, arg_partial, channel_names);
             }
             return vec![];
@@ -48,6 +89,81 @@ pub fn complete(input: &str, channel_names: &[String]) -> Vec<String> {
     vec![]
 }
 
+/// The word at the end of a channel input line, to be completed as a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTarget {
+    /// Channel named with `#channel:`, or `None` for the active channel
+    pub channel: Option<String>,
+    /// Input before the word being completed
+    pub head: String,
+    /// The partial path
+    pub word: String,
+}
+
+/// Find the path word to complete in text destined for a channel
+/// (plain input or `#channel: command`); `:commands` and `#channel` switches have none.
+pub fn path_target(input: &str) -> Option<PathTarget> {
+    if input.trim_start().starts_with(':') {
+        return None;
+    }
+
+    let mut channel = None;
+    if let Some(rest) = input.trim_start().strip_prefix('#') {
+        let colon_idx = rest.find(':')?;
+        channel = Some(rest[..colon_idx].trim().to_string());
+    }
+
+    let word_start = input.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+    if channel.is_some() && !input[..word_start].contains(':') {
+        return None;
+    }
+
+    Some(PathTarget {
+        channel,
+        head: input[..word_start].to_string(),
+        word: input[word_start..].to_string(),
+    })
+}
+
+/// Split the input into (head, word) when the last word is in command position:
+/// the first word of channel input, or the command after `:new <name> [options]`
+pub fn command_word(input: &str) -> Option<(String, String)> {
+    let word_start = input.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+    let (head, word) = input.split_at(word_start);
+    if word.contains('/') {
+        return None;
+    }
+
+    if let Some(rest) = head.trim_start().strip_prefix(':') {
+        let mut words = rest.split_whitespace();
+        if words.next() != Some("new") || words.next().is_none() || word.starts_with('-') {
+            return None;
+        }
+        // Only options (with their values) may sit between the name and the command
+        while let Some(arg) = words.next() {
+            match arg {
+                "--env" | "-e" | "--template" | "-t" | "--docker" | "--podman" => {
+                    words.next()?;
+                }
+                "--pipe" => {}
+                _ if arg.starts_with("--env=") => {}
+                _ => return None,
+            }
+        }
+        return Some((head.to_string(), word.to_string()));
+    }
+
+    let target = path_target(input)?;
+    let before_word = match &target.channel {
+        Some(_) => &target.head[target.head.find(':')? + 1..],
+        None => target.head.as_str(),
+    };
+    if !before_word.trim().is_empty() {
+        return None;
+    }
+    Some((target.head, target.word))
+}
+
 /// Complete a command name
 fn complete_command(partial: &str) -> Vec<String> {
     let partial_lower = partial.to_lowercase();
@@ -154,6 +270,42 @@ mod tests {
         assert_eq!(completions, vec![":kill shell"]);
     }
 
+    #[test]
+    fn test_path_target() {
+        let target = path_target("cat src/ma").unwrap();
+        assert_eq!(target.channel, None);
+        assert_eq!(target.head, "cat ");
+        assert_eq!(target.word, "src/ma");
+
+        let target = path_target("#build: ls tar").unwrap();
+        assert_eq!(target.channel.as_deref(), Some("build"));
+        assert_eq!(target.head, "#build: ls ");
+        assert_eq!(target.word, "tar");
+
+        assert_eq!(path_target(":new sh"), None);
+        assert_eq!(path_target("#build"), None);
+    }
+
+    #[test]
+    fn test_command_word() {
+        let split = |head: &str, word: &str| Some((head.to_string(), word.to_string()));
+        assert_eq!(command_word("np"), split("", "np"));
+        assert_eq!(command_word("#build: car"), split("#build: ", "car"));
+        assert_eq!(command_word(":new worker np"), split(":new worker ", "np"));
+        assert_eq!(
+            command_word(":new worker -e A=1 --env=B=2 np"),
+            split(":new worker -e A=1 --env=B=2 ", "np")
+        );
+
+        assert_eq!(command_word("cat sr"), None);
+        assert_eq!(command_word("./scr"), None);
+        assert_eq!(command_word(":new work"), None);
+        assert_eq!(command_word(":new worker -t"), None);
+        assert_eq!(command_word(":new worker -t nod"), None);
+        assert_eq!(command_word(":new worker npm ru"), None);
+        assert_eq!(command_word(":kill bu"), None);
+    }
+
     #[test]
     fn test_common_prefix() {
         let completions = vec![
@@ -0,0 +1,110 @@
+//! Side-by-side diff of two channels' recent output (`:diff`)
+
+/// Lines from the end of each channel that are compared
+pub const DIFF_LINES: usize = 500;
+
+/// One row of the diff view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Row {
+    /// Index of the line shown on the left, if any
+    pub left: Option<usize>,
+    /// Index of the line shown on the right, if any
+    pub right: Option<usize>,
+    /// Both sides hold the same line
+    pub same: bool,
+}
+
+/// Line up `left` and `right` by their longest common subsequence. Lines
+/// only one side has are paired off row by row where both sides changed.
+pub fn side_by_side<S: AsRef<str>>(left: &[S], right: &[S]) -> Vec<Row> {
+    let (n, m) = (left.len(), right.len());
+    // common[i][j]: length of the common subsequence of left[i..], right[j..]
+    let mut common = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = if left[i].as_ref() == right[j].as_ref() {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut rows = Vec::with_capacity(n.max(m));
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    let flush = |rows: &mut Vec<Row>, removed: &mut Vec<usize>, added: &mut Vec<usize>| {
+        for k in 0..removed.len().max(added.len()) {
+            rows.push(Row {
+                left: removed.get(k).copied(),
+                right: added.get(k).copied(),
+                same: false,
+            });
+        }
+        removed.clear();
+        added.clear();
+    };
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && left[i].as_ref() == right[j].as_ref() {
+            flush(&mut rows, &mut removed, &mut added);
+            rows.push(Row {
+                left: Some(i),
+                right: Some(j),
+                same: true,
+            });
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && common[i + 1][j] >= common[i][j + 1]) {
+            removed.push(i);
+            i += 1;
+        } else {
+            added.push(j);
+            j += 1;
+        }
+    }
+    flush(&mut rows, &mut removed, &mut added);
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(left: &[&str], right: &[&str]) -> Vec<String> {
+        side_by_side(left, right)
+            .into_iter()
+            .map(|row| {
+                format!(
+                    "{}{}|{}",
+                    if row.same { ' ' } else { '!' },
+                    row.left.map_or("", |i| left[i]),
+                    row.right.map_or("", |j| right[j]),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn common_lines_line_up_and_changes_pair_off() {
+        let staging = ["boot", "GET /a 200", "GET /b 200", "done"];
+        let production = ["boot", "GET /a 500", "retry", "GET /b 200", "done", "idle"];
+        assert_eq!(
+            render(&staging, &production),
+            vec![
+                " boot|boot",
+                "!GET /a 200|GET /a 500",
+                "!|retry",
+                " GET /b 200|GET /b 200",
+                " done|done",
+                "!|idle",
+            ]
+        );
+    }
+
+    #[test]
+    fn one_side_empty() {
+        assert_eq!(render(&["a", "b"], &[]), vec!["!a|", "!b|"]);
+        assert!(render(&[], &[]).is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+//! Cached listing of executables on `$PATH` for command completion
+
+use std::collections::BTreeSet;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Executable names found on `$PATH`, rescanned only when `$PATH` or one of
+/// its directories changes
+#[derive(Debug, Default)]
+pub struct ExecutableCache {
+    path_var: Option<String>,
+    dir_mtimes: Vec<(PathBuf, Option<SystemTime>)>,
+    names: Vec<String>,
+}
+
+impl ExecutableCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Executables starting with `prefix`, sorted
+    pub fn complete(&mut self, prefix: &str) -> Vec<String> {
+        let path_var = std::env::var("PATH").ok();
+        self.refresh_if_stale(path_var);
+
+        // Names are sorted, so matches form a contiguous run
+        let start = self.names.partition_point(|name| name.as_str() < prefix);
+        self.names[start..]
+            .iter()
+            .take_while(|name| name.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    fn refresh_if_stale(&mut self, path_var: Option<String>) {
+        let dirs: Vec<PathBuf> = path_var
+            .as_deref()
+            .map(|p| std::env::split_paths(p).collect())
+            .unwrap_or_default();
+        let dir_mtimes: Vec<_> = dirs
+            .into_iter()
+            .map(|dir| {
+                let mtime = std::fs::metadata(&dir).and_then(|m| m.modified()).ok();
+                (dir, mtime)
+            })
+            .collect();
+
+        if self.path_var.is_some() && self.path_var == path_var && self.dir_mtimes == dir_mtimes {
+            return;
+        }
+
+        let mut names = BTreeSet::new();
+        for (dir, _) in &dir_mtimes {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let is_executable = entry
+                    .path()
+                    .metadata()
+                    .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                    .unwrap_or(false);
+                if is_executable {
+                    names.insert(entry.file_name().to_string_lossy().to_string());
+                }
+            }
+        }
+
+        self.names = names.into_iter().collect();
+        self.path_var = path_var;
+        self.dir_mtimes = dir_mtimes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn lists_executables_and_rescans_on_change() {
+        let dir = tempdir().unwrap();
+        let make_file = |name: &str, mode: u32| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, "").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        };
+        make_file("npm", 0o755);
+        make_file("npx", 0o755);
+        make_file("notes", 0o644);
+
+        let path_var = Some(dir.path().to_string_lossy().to_string());
+        let mut cache = ExecutableCache::new();
+        cache.refresh_if_stale(path_var.clone());
+        assert_eq!(cache.names, vec!["npm", "npx"]);
+
+        // A new PATH value forces a rescan even when directory mtimes are unchanged
+        make_file("node", 0o755);
+        cache.refresh_if_stale(Some(format!("{}:/nonexistent", dir.path().display())));
+        assert_eq!(cache.names, vec!["node", "npm", "npx"]);
+    }
+}
@@ -0,0 +1,185 @@
+//! Foldable output sections - each line matching `appearance.fold_pattern`
+//! (a shell prompt, by default) starts a section that can be collapsed to
+//! that one line
+
+use crate::client::app::BufferedLine;
+
+/// A line left showing in the output view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Visible {
+    /// Index into the channel buffer
+    pub index: usize,
+    /// Lines hidden under this one when it starts a folded section
+    pub folded: usize,
+}
+
+/// The lines of a buffer still shown once folded sections are collapsed
+pub fn visible_lines(buffer: &[BufferedLine]) -> Vec<Visible> {
+    let mut visible: Vec<Visible> = Vec::with_capacity(buffer.len());
+    let mut collapsed = false;
+    for (index, line) in buffer.iter().enumerate() {
+        if line.section_start {
+            collapsed = line.folded;
+        } else if collapsed {
+            if let Some(start) = visible.last_mut() {
+                start.folded += 1;
+            }
+            continue;
+        }
+        visible.push(Visible { index, folded: 0 });
+    }
+    visible
+}
+
+/// The same lines as [`visible_lines`], newest first, found lazily from the
+/// end of the buffer so drawing the bottom of a long scrollback only looks
+/// at the lines it shows
+pub fn visible_rev(buffer: &[BufferedLine]) -> impl Iterator<Item = Visible> + '_ {
+    let mut end = buffer.len();
+    // Section start found for the last line looked up; it holds every line
+    // from there down to that one
+    let mut section: Option<Option<usize>> = None;
+    std::iter::from_fn(move || {
+        let index = end.checked_sub(1)?;
+        let start = match section {
+            Some(start) if start.is_none_or(|start| start <= index) => start,
+            _ => {
+                let start = section_of(buffer, index);
+                section = Some(start);
+                start
+            }
+        };
+        match start {
+            // Coming from below, `index` is the folded section's last line
+            Some(start) if buffer[start].folded => {
+                end = start;
+                Some(Visible {
+                    index: start,
+                    folded: index - start,
+                })
+            }
+            _ => {
+                end = index;
+                Some(Visible { index, folded: 0 })
+            }
+        }
+    })
+}
+
+/// Start of the section holding line `index`, if it is in one
+pub fn section_of(buffer: &[BufferedLine], index: usize) -> Option<usize> {
+    let end = (index + 1).min(buffer.len());
+    buffer[..end].iter().rposition(|line| line.section_start)
+}
+
+/// Last line of the section starting at `start`
+pub fn section_end(buffer: &[BufferedLine], start: usize) -> usize {
+    buffer[start + 1..]
+        .iter()
+        .position(|line| line.section_start)
+        .map_or(buffer.len() - 1, |next| start + next)
+}
+
+/// Lines of the latest section with more than its first line: the output
+/// of the most recent command
+pub fn last_output(buffer: &[BufferedLine]) -> Option<&[BufferedLine]> {
+    buffer
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, line)| line.section_start)
+        .map(|(start, _)| &buffer[start + 1..=section_end(buffer, start)])
+        .find(|output| !output.is_empty())
+}
+
+/// Fold or unfold every section
+pub fn set_all(buffer: &mut [BufferedLine], folded: bool) {
+    for line in buffer.iter_mut().filter(|line| line.section_start) {
+        line.folded = folded;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::OutputStream;
+    use chrono::Local;
+
+    fn buffer(lines: &[&str]) -> Vec<BufferedLine> {
+        lines
+            .iter()
+            .map(|content| BufferedLine {
+                content: content.to_string(),
+                timestamp: Local::now(),
+                stream: OutputStream::Stdout,
+                section_start: content.starts_with("$ "),
+                folded: false,
+                seq: 0,
+            })
+            .collect()
+    }
+
+    fn indexes(buffer: &[BufferedLine]) -> Vec<(usize, usize)> {
+        visible_lines(buffer)
+            .into_iter()
+            .map(|v| (v.index, v.folded))
+            .collect()
+    }
+
+    #[test]
+    fn folded_section_collapses_to_its_first_line() {
+        let mut lines = buffer(&["banner", "$ make", "cc a.c", "cc b.c", "$ ls", "a.o"]);
+        assert_eq!(indexes(&lines).len(), 6);
+
+        lines[1].folded = true;
+        assert_eq!(indexes(&lines), vec![(0, 0), (1, 2), (4, 0), (5, 0)]);
+
+        set_all(&mut lines, true);
+        assert_eq!(indexes(&lines), vec![(0, 0), (1, 2), (4, 1)]);
+        set_all(&mut lines, false);
+        assert_eq!(indexes(&lines).len(), 6);
+    }
+
+    #[test]
+    fn visible_rev_matches_visible_lines_backwards() {
+        let mut lines = buffer(&[
+            "banner", "$ make", "cc a.c", "cc b.c", "$ ls", "a.o", "$ pwd",
+        ]);
+        for folded in [
+            [false, false, false],
+            [true, false, false],
+            [true, true, true],
+        ] {
+            for (start, fold) in [1, 4, 6].into_iter().zip(folded) {
+                lines[start].folded = fold;
+            }
+            let mut forward = visible_lines(&lines);
+            forward.reverse();
+            assert_eq!(visible_rev(&lines).collect::<Vec<_>>(), forward);
+        }
+    }
+
+    #[test]
+    fn sections_are_found_by_line() {
+        let lines = buffer(&["banner", "$ make", "cc a.c", "$ ls", "a.o"]);
+        assert_eq!(section_of(&lines, 0), None);
+        assert_eq!(section_of(&lines, 2), Some(1));
+        assert_eq!(section_of(&lines, 3), Some(3));
+        assert_eq!(section_of(&lines, 99), Some(3));
+        assert_eq!(section_end(&lines, 1), 2);
+        assert_eq!(section_end(&lines, 3), 4);
+    }
+
+    #[test]
+    fn last_output_skips_commands_without_output() {
+        let contents = |lines: &[BufferedLine]| -> Vec<String> {
+            lines.iter().map(|line| line.content.clone()).collect()
+        };
+        let lines = buffer(&["$ make", "cc a.c", "cc b.c", "$ cd src"]);
+        assert_eq!(
+            last_output(&lines).map(contents),
+            Some(vec!["cc a.c".to_string(), "cc b.c".to_string()])
+        );
+        assert!(last_output(&buffer(&["banner", "$ clear"])).is_none());
+    }
+}
@@ -0,0 +1,123 @@
+//! Raw channel output kept for `:view hex`, and rendering it as a hexdump
+
+/// Raw bytes kept per channel for the hex view
+const MAX_RAW_OUTPUT_BYTES: usize = 256 * 1024;
+
+/// Bytes shown per hexdump row
+pub const HEXDUMP_ROW_BYTES: usize = 16;
+
+/// The most recent raw output of one channel, as received
+#[derive(Debug, Default)]
+pub struct RawOutput {
+    data: Vec<u8>,
+    /// Bytes dropped from the front, so offsets stay those of the stream
+    dropped: usize,
+}
+
+impl RawOutput {
+    pub fn push(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+        if self.data.len() > MAX_RAW_OUTPUT_BYTES {
+            // Drop whole rows so the hexdump doesn't shift under the reader
+            let excess = self.data.len() - MAX_RAW_OUTPUT_BYTES;
+            let excess = excess.div_ceil(HEXDUMP_ROW_BYTES) * HEXDUMP_ROW_BYTES;
+            self.data.drain(..excess);
+            self.dropped += excess;
+        }
+    }
+
+    /// Number of hexdump rows
+    pub fn rows(&self) -> usize {
+        self.data.len().div_ceil(HEXDUMP_ROW_BYTES)
+    }
+
+    /// Hexdump of rows `start..end`
+    pub fn hexdump(&self, start: usize, end: usize) -> Vec<String> {
+        let from = (start * HEXDUMP_ROW_BYTES).min(self.data.len());
+        let to = (end * HEXDUMP_ROW_BYTES).min(self.data.len());
+        hexdump(&self.data[from..to], self.dropped + from)
+    }
+}
+
+/// Render bytes `hexdump -C` style, numbering rows from `offset`
+pub fn hexdump(data: &[u8], offset: usize) -> Vec<String> {
+    data.chunks(HEXDUMP_ROW_BYTES)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let mut line = format!("{:08x} ", offset + row * HEXDUMP_ROW_BYTES);
+            for i in 0..HEXDUMP_ROW_BYTES {
+                if i % 8 == 0 {
+                    line.push(' ');
+                }
+                match bytes.get(i) {
+                    Some(byte) => line.push_str(&format!("{:02x} ", byte)),
+                    None => line.push_str("   "),
+                }
+            }
+            line.push_str(" |");
+            line.extend(bytes.iter().map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            }));
+            line.push('|');
+            line
+        })
+        .collect()
+}
+
+/// Whether output looks like binary data rather than text: it has NUL
+/// bytes, isn't UTF-8, or is mostly control characters
+pub fn looks_binary(data: &[u8]) -> bool {
+    if data.contains(&0) {
+        return true;
+    }
+    let Ok(text) = std::str::from_utf8(data) else {
+        return true;
+    };
+    let controls = text
+        .chars()
+        .filter(|&c| c.is_control() && !matches!(c, '\n' | '\r' | '\t' | '\x1b' | '\x08'))
+        .count();
+    controls * 10 > text.chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_rows_like_hexdump_c() {
+        let lines = hexdump(b"Hello, world!\n\x00\x01\xffok", 0x20);
+        assert_eq!(
+            lines,
+            vec![
+                "00000020  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 01  |Hello, world!...|",
+                "00000030  ff 6f 6b                                          |.ok|",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_stream_offsets_when_trimming() {
+        let mut raw = RawOutput::default();
+        raw.push(&vec![b'a'; MAX_RAW_OUTPUT_BYTES]);
+        raw.push(b"b");
+        assert_eq!(raw.rows(), MAX_RAW_OUTPUT_BYTES / HEXDUMP_ROW_BYTES);
+        let last = raw.hexdump(raw.rows() - 1, raw.rows());
+        assert!(last[0].starts_with(&format!("{:08x} ", MAX_RAW_OUTPUT_BYTES)));
+        assert!(last[0].ends_with("|b|"));
+    }
+
+    #[test]
+    fn detects_binary_output() {
+        assert!(!looks_binary(
+            "plain text\r\n\x1b[31mred\x1b[0m ✓\n".as_bytes()
+        ));
+        assert!(looks_binary(b"ustar\x00\x00"));
+        assert!(looks_binary(b"\x1f\x8b\x08\x00"));
+        assert!(looks_binary(b"\x01\x02\x03\x04ab"));
+    }
+}
@@ -1,6 +1,7 @@
 //! Input handling - parse user input and commands
 
 use anyhow::Result;
+use std::collections::BTreeMap;
 
 /// Parsed user input
 #[derive(Debug, Clone)]
@@ -51,6 +52,32 @@ pub fn parse_input(line: &str) -> Result<ParsedInput> {
     Ok(ParsedInput::Text(line.to_string()))
 }
 
+/// Parse a line of user input after expanding a leading `:alias`
+pub fn parse_input_with_aliases(
+    line: &str,
+    aliases: &BTreeMap<String, String>,
+) -> Result<ParsedInput> {
+    parse_input(&expand_alias(line, aliases))
+}
+
+/// Replace `:name args...` with the alias expansion for `name`, keeping any
+/// extra arguments. Expansion happens once, so aliases can't loop.
+pub fn expand_alias(line: &str, aliases: &BTreeMap<String, String>) -> String {
+    let trimmed = line.trim();
+    let Some(rest) = trimmed.strip_prefix(':') else {
+        return line.to_string();
+    };
+    let (name, args) = match rest.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (rest, ""),
+    };
+    match aliases.get(name) {
+        Some(expansion) if args.is_empty() => expansion.clone(),
+        Some(expansion) => format!("{} {}", expansion, args),
+        None => line.to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,4 +123,27 @@ mod tests {
             if command == "list" && args.is_empty()
         ));
     }
+
+    #[test]
+    fn test_expand_alias() {
+        let aliases = BTreeMap::from([
+            ("b".to_string(), ":new build cargo build".to_string()),
+            ("m".to_string(), "#build: make".to_string()),
+        ]);
+
+        assert_eq!(expand_alias(":b", &aliases), ":new build cargo build");
+        assert_eq!(
+            expand_alias(":b --release", &aliases),
+            ":new build cargo build --release"
+        );
+        assert_eq!(expand_alias(":list", &aliases), ":list");
+        assert_eq!(expand_alias("b", &aliases), "b");
+
+        let result = parse_input_with_aliases(":m", &aliases).unwrap();
+        assert!(matches!(
+            result,
+            ParsedInput::SendToChannel { channel, command }
+            if channel == "build" && command == "make"
+        ));
+    }
 }
@@ -0,0 +1,547 @@
+//! Keybinding engine - key-spec parsing and action dispatch table
+
+use crate::config::KeybindingsConfig;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A key combination such as `ctrl+n` or `alt+1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeySpec {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeySpec {
+    /// Parse a key spec like "ctrl+n", "alt+1", "ctrl+left" or "pageup"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim().to_lowercase();
+        if spec.is_empty() {
+            return Err("empty key spec".to_string());
+        }
+
+        // Split off modifiers, but allow '+' itself as the final key ("ctrl++")
+        let (mods, key) = match spec.strip_suffix("++") {
+            Some(rest) => (rest, "+"),
+            None => match spec.rsplit_once('+') {
+                Some((mods, key)) => (mods, key),
+                None => ("", spec.as_str()),
+            },
+        };
+
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in mods.split('+').filter(|m| !m.is_empty()) {
+            modifiers |= match modifier {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" | "option" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                other => return Err(format!("unknown modifier '{}' in '{}'", other, spec)),
+            };
+        }
+
+        let code = match key {
+            "enter" | "return" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" | "ins" => KeyCode::Insert,
+            "esc" | "escape" => KeyCode::Esc,
+            "space" => KeyCode::Char(' '),
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" | "pgup" => KeyCode::PageUp,
+            "pagedown" | "pgdn" => KeyCode::PageDown,
+            _ => {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => match key.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                        Some(n) if (1..=12).contains(&n) => KeyCode::F(n),
+                        _ => return Err(format!("unknown key '{}' in '{}'", key, spec)),
+                    },
+                }
+            }
+        };
+
+        Ok(Self { code, modifiers }.normalized())
+    }
+
+    /// Build a spec from a terminal key event
+    pub fn from_event(event: &KeyEvent) -> Self {
+        Self {
+            code: event.code,
+            modifiers: event.modifiers,
+        }
+        .normalized()
+    }
+
+    /// Characters compare case-insensitively and ignore shift, since terminals
+    /// report shifted characters inconsistently.
+    fn normalized(mut self) -> Self {
+        if let KeyCode::Char(c) = self.code {
+            self.code = KeyCode::Char(c.to_ascii_lowercase());
+            self.modifiers.remove(KeyModifiers::SHIFT);
+        }
+        self
+    }
+}
+
+impl fmt::Display for KeySpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "shift+")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            KeyCode::PageUp => write!(f, "pageup"),
+            KeyCode::PageDown => write!(f, "pagedown"),
+            other => write!(f, "{}", format!("{:?}", other).to_lowercase()),
+        }
+    }
+}
+
+/// Actions that can be bound to keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    NextChannel,
+    PrevChannel,
+    /// Switch to the Nth channel in the status bar (1-based)
+    SwitchToChannel(usize),
+    ClearScreen,
+    Quit,
+    /// Leave the session running and exit; the server restores this view on reattach
+    Detach,
+    /// Cancel the current input, or send Ctrl+C to the channel when input is empty
+    Interrupt,
+    Eof,
+    ScrollPageUp,
+    ScrollPageDown,
+    ScrollHalfUp,
+    ScrollHalfDown,
+    ScrollTop,
+    ScrollBottom,
+    LineStart,
+    LineEnd,
+    DeleteWord,
+    DeleteToEnd,
+    DeleteToStart,
+    Complete,
+    ToggleView,
+    /// Open the fuzzy command palette
+    CommandPalette,
+    /// Send the prefix key itself through to the channel
+    SendPrefix,
+    /// Move focus to the next/previous split pane
+    FocusNextPane,
+    FocusPrevPane,
+    /// Maximize the focused pane, or restore the layout
+    ToggleZoom,
+    /// Show the channel sidebar and focus it, or hide it
+    ToggleSidebar,
+    /// Fold or unfold the output section at the bottom of the view
+    ToggleFold,
+    /// Scroll to the next/previous error-looking line
+    NextError,
+    PrevError,
+    /// Copy the output of the most recent command to the clipboard
+    CopyLastOutput,
+    /// Interrupt the command in the active channel's foreground, leaving
+    /// its shell running
+    StopForeground,
+    /// Scroll to the next/previous match of the `:search`
+    SearchNext,
+    SearchPrev,
+}
+
+/// When an action applies, so one key can do different things depending on input state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyContext {
+    Any,
+    EmptyInput,
+    NonEmptyInput,
+}
+
+impl Action {
+    /// Parse an action name as used in `[keybindings.prefixed]`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let action = match name.trim() {
+            "next_channel" => Action::NextChannel,
+            "prev_channel" => Action::PrevChannel,
+            "clear_screen" => Action::ClearScreen,
+            "quit" => Action::Quit,
+            "detach" => Action::Detach,
+            "interrupt" => Action::Interrupt,
+            "eof" => Action::Eof,
+            "scroll_page_up" => Action::ScrollPageUp,
+            "scroll_page_down" => Action::ScrollPageDown,
+            "scroll_half_up" => Action::ScrollHalfUp,
+            "scroll_half_down" => Action::ScrollHalfDown,
+            "scroll_top" => Action::ScrollTop,
+            "scroll_bottom" => Action::ScrollBottom,
+            "line_start" => Action::LineStart,
+            "line_end" => Action::LineEnd,
+            "delete_word" => Action::DeleteWord,
+            "delete_to_end" => Action::DeleteToEnd,
+            "delete_to_start" => Action::DeleteToStart,
+            "complete" => Action::Complete,
+            "toggle_view" => Action::ToggleView,
+            "command_palette" => Action::CommandPalette,
+            "send_prefix" => Action::SendPrefix,
+            "focus_next_pane" => Action::FocusNextPane,
+            "focus_prev_pane" => Action::FocusPrevPane,
+            "toggle_zoom" => Action::ToggleZoom,
+            "toggle_sidebar" => Action::ToggleSidebar,
+            "toggle_fold" => Action::ToggleFold,
+            "next_error" => Action::NextError,
+            "prev_error" => Action::PrevError,
+            "copy_last_output" => Action::CopyLastOutput,
+            "stop_foreground" => Action::StopForeground,
+            "search_next" => Action::SearchNext,
+            "search_prev" => Action::SearchPrev,
+            other => {
+                let n = other.strip_prefix("channel_")?.parse().ok()?;
+                Action::SwitchToChannel(n)
+            }
+        };
+        Some(action)
+    }
+
+    fn context(&self) -> KeyContext {
+        match self {
+            Action::Eof
+            | Action::ScrollPageUp
+            | Action::ScrollPageDown
+            | Action::ScrollHalfUp
+            | Action::ScrollTop
+            | Action::ScrollBottom
+            | Action::ToggleView => KeyContext::EmptyInput,
+            Action::LineStart | Action::LineEnd | Action::DeleteToStart | Action::Complete => {
+                KeyContext::NonEmptyInput
+            }
+            _ => KeyContext::Any,
+        }
+    }
+
+    fn applies(&self, input_empty: bool) -> bool {
+        match self.context() {
+            KeyContext::Any => true,
+            KeyContext::EmptyInput => input_empty,
+            KeyContext::NonEmptyInput => !input_empty,
+        }
+    }
+}
+
+/// Pair each configurable action with its key specs from config
+fn configured_bindings(config: &KeybindingsConfig) -> [(Action, &str); 32] {
+    [
+        (Action::NextChannel, &config.next_channel),
+        (Action::PrevChannel, &config.prev_channel),
+        (Action::ClearScreen, &config.clear_screen),
+        (Action::Quit, &config.quit),
+        (Action::Detach, &config.detach),
+        (Action::Interrupt, &config.interrupt),
+        (Action::Eof, &config.eof),
+        (Action::ScrollPageUp, &config.scroll_page_up),
+        (Action::ScrollPageDown, &config.scroll_page_down),
+        (Action::ScrollHalfUp, &config.scroll_half_up),
+        (Action::ScrollHalfDown, &config.scroll_half_down),
+        (Action::ScrollTop, &config.scroll_top),
+        (Action::ScrollBottom, &config.scroll_bottom),
+        (Action::LineStart, &config.line_start),
+        (Action::LineEnd, &config.line_end),
+        (Action::DeleteWord, &config.delete_word),
+        (Action::DeleteToEnd, &config.delete_to_end),
+        (Action::DeleteToStart, &config.delete_to_start),
+        (Action::Complete, &config.complete),
+        (Action::ToggleView, &config.toggle_view),
+        (Action::CommandPalette, &config.command_palette),
+        (Action::FocusNextPane, &config.focus_next_pane),
+        (Action::FocusPrevPane, &config.focus_prev_pane),
+        (Action::ToggleZoom, &config.toggle_zoom),
+        (Action::ToggleSidebar, &config.toggle_sidebar),
+        (Action::ToggleFold, &config.toggle_fold),
+        (Action::NextError, &config.next_error),
+        (Action::PrevError, &config.prev_error),
+        (Action::CopyLastOutput, &config.copy_last_output),
+        (Action::StopForeground, &config.stop_foreground),
+        (Action::SearchNext, &config.search_next),
+        (Action::SearchPrev, &config.search_prev),
+    ]
+}
+
+/// Dispatch table from key specs to actions
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    bindings: HashMap<KeySpec, Vec<Action>>,
+
+    /// Prefix key that arms `prefixed` bindings for the next keypress
+    prefix: Option<KeySpec>,
+
+    /// Bindings available after the prefix key
+    prefixed: HashMap<KeySpec, Action>,
+}
+
+impl Keymap {
+    /// Build a keymap from config, returning any bindings that failed to parse
+    pub fn from_config(config: &KeybindingsConfig) -> (Self, Vec<String>) {
+        let mut keymap = Keymap::default();
+        let mut errors = Vec::new();
+
+        for (action, specs) in configured_bindings(config) {
+            for spec in specs.split(',').filter(|s| !s.trim().is_empty()) {
+                match KeySpec::parse(spec) {
+                    Ok(key) => keymap.bind(key, action),
+                    Err(e) => errors.push(format!("Invalid keybinding for {:?}: {}", action, e)),
+                }
+            }
+        }
+
+        match config.channel_number_modifier.as_str() {
+            "" | "none" => {}
+            modifier => {
+                for n in 1..=9 {
+                    match KeySpec::parse(&format!("{}+{}", modifier, n)) {
+                        Ok(key) => keymap.bind(key, Action::SwitchToChannel(n)),
+                        Err(e) => {
+                            errors.push(format!("Invalid channel_number_modifier: {}", e));
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        if !config.prefix.trim().is_empty() {
+            match KeySpec::parse(&config.prefix) {
+                Ok(prefix) => {
+                    keymap.prefix = Some(prefix);
+                    // Pressing the prefix twice passes it through, as in tmux
+                    keymap.prefixed.insert(prefix, Action::SendPrefix);
+                    for (spec, name) in &config.prefixed {
+                        match (KeySpec::parse(spec), Action::from_name(name)) {
+                            (Ok(key), Some(action)) => {
+                                keymap.prefixed.insert(key, action);
+                            }
+                            (Err(e), _) => {
+                                errors.push(format!("Invalid prefixed keybinding: {}", e))
+                            }
+                            (_, None) => errors.push(format!(
+                                "Unknown action '{}' for prefixed key '{}'",
+                                name, spec
+                            )),
+                        }
+                    }
+                }
+                Err(e) => errors.push(format!("Invalid prefix key: {}", e)),
+            }
+        }
+
+        (keymap, errors)
+    }
+
+    /// Whether this key event is the configured prefix key
+    pub fn is_prefix(&self, event: &KeyEvent) -> bool {
+        self.prefix == Some(KeySpec::from_event(event))
+    }
+
+    /// Resolve the key pressed after the prefix
+    pub fn resolve_prefixed(&self, event: &KeyEvent) -> Option<Action> {
+        self.prefixed.get(&KeySpec::from_event(event)).copied()
+    }
+
+    /// Bytes to send to a channel for the prefix key itself
+    pub fn prefix_bytes(&self) -> Option<Vec<u8>> {
+        let prefix = self.prefix?;
+        match prefix.code {
+            KeyCode::Char(c) if prefix.modifiers.contains(KeyModifiers::CONTROL) => {
+                Some(vec![(c as u8) & 0x1f])
+            }
+            KeyCode::Char(c) => Some(c.to_string().into_bytes()),
+            _ => None,
+        }
+    }
+
+    /// Add a binding; keys may carry several actions for different contexts
+    pub fn bind(&mut self, key: KeySpec, action: Action) {
+        let actions = self.bindings.entry(key).or_default();
+        if !actions.contains(&action) {
+            actions.push(action);
+        }
+    }
+
+    /// Keys bound to an action, for showing hints
+    pub fn keys_for(&self, action: Action) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|(_, actions)| actions.contains(&action))
+            .map(|(key, _)| key.to_string())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    /// Resolve a key event to the first action that applies in the current input state
+    pub fn resolve(&self, event: &KeyEvent, input_empty: bool) -> Option<Action> {
+        self.bindings
+            .get(&KeySpec::from_event(event))?
+            .iter()
+            .copied()
+            .find(|action| action.applies(input_empty))
+    }
+
+    /// All bindings as sorted "key  action" lines for display
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .bindings
+            .iter()
+            .flat_map(|(key, actions)| {
+                actions
+                    .iter()
+                    .map(move |action| format!("  {:<18} {:?}", key.to_string(), action))
+            })
+            .collect();
+        lines.sort();
+
+        if let Some(prefix) = self.prefix {
+            let mut prefixed: Vec<String> = self
+                .prefixed
+                .iter()
+                .map(|(key, action)| format!("  {} {:<13} {:?}", prefix, key.to_string(), action))
+                .collect();
+            prefixed.sort();
+            lines.extend(prefixed);
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn parses_key_specs() {
+        assert_eq!(
+            KeySpec::parse("ctrl+n").unwrap(),
+            KeySpec {
+                code: KeyCode::Char('n'),
+                modifiers: KeyModifiers::CONTROL
+            }
+        );
+        assert_eq!(
+            KeySpec::parse("Alt+1").unwrap(),
+            KeySpec {
+                code: KeyCode::Char('1'),
+                modifiers: KeyModifiers::ALT
+            }
+        );
+        assert_eq!(
+            KeySpec::parse("ctrl+shift+left").unwrap(),
+            KeySpec {
+                code: KeyCode::Left,
+                modifiers: KeyModifiers::CONTROL | KeyModifiers::SHIFT
+            }
+        );
+        assert_eq!(KeySpec::parse("pageup").unwrap().code, KeyCode::PageUp);
+        assert_eq!(KeySpec::parse("f5").unwrap().code, KeyCode::F(5));
+        assert_eq!(KeySpec::parse("ctrl++").unwrap().code, KeyCode::Char('+'));
+        assert!(KeySpec::parse("hyper+x").is_err());
+        assert!(KeySpec::parse("ctrl+nope").is_err());
+    }
+
+    #[test]
+    fn default_keymap_resolves_by_input_state() {
+        let (keymap, errors) = Keymap::from_config(&KeybindingsConfig::default());
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let ctrl_u = key(KeyCode::Char('u'), KeyModifiers::CONTROL);
+        assert_eq!(keymap.resolve(&ctrl_u, true), Some(Action::ScrollHalfUp));
+        assert_eq!(keymap.resolve(&ctrl_u, false), Some(Action::DeleteToStart));
+
+        let home = key(KeyCode::Home, KeyModifiers::NONE);
+        assert_eq!(keymap.resolve(&home, true), Some(Action::ScrollTop));
+        assert_eq!(keymap.resolve(&home, false), Some(Action::LineStart));
+
+        let alt_3 = key(KeyCode::Char('3'), KeyModifiers::ALT);
+        assert_eq!(
+            keymap.resolve(&alt_3, true),
+            Some(Action::SwitchToChannel(3))
+        );
+
+        let alt_d = key(KeyCode::Char('d'), KeyModifiers::ALT);
+        assert_eq!(keymap.resolve(&alt_d, false), Some(Action::Detach));
+
+        let plain = key(KeyCode::Char('x'), KeyModifiers::NONE);
+        assert_eq!(keymap.resolve(&plain, true), None);
+    }
+
+    #[test]
+    fn rebinding_replaces_defaults() {
+        let config = KeybindingsConfig {
+            next_channel: "alt+n, f2".to_string(),
+            ..Default::default()
+        };
+        let (keymap, errors) = Keymap::from_config(&config);
+        assert!(errors.is_empty());
+
+        let alt_n = key(KeyCode::Char('N'), KeyModifiers::ALT | KeyModifiers::SHIFT);
+        assert_eq!(keymap.resolve(&alt_n, true), Some(Action::NextChannel));
+        let f2 = key(KeyCode::F(2), KeyModifiers::NONE);
+        assert_eq!(keymap.resolve(&f2, false), Some(Action::NextChannel));
+        let ctrl_n = key(KeyCode::Char('n'), KeyModifiers::CONTROL);
+        assert_eq!(keymap.resolve(&ctrl_n, true), None);
+    }
+
+    #[test]
+    fn prefix_mode_resolves_following_key() {
+        let config = KeybindingsConfig {
+            prefix: "ctrl+b".to_string(),
+            ..Default::default()
+        };
+        let (keymap, errors) = Keymap::from_config(&config);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let ctrl_b = key(KeyCode::Char('b'), KeyModifiers::CONTROL);
+        assert!(keymap.is_prefix(&ctrl_b));
+        assert_eq!(keymap.resolve_prefixed(&ctrl_b), Some(Action::SendPrefix));
+        assert_eq!(keymap.prefix_bytes(), Some(vec![2]));
+
+        let n = key(KeyCode::Char('n'), KeyModifiers::NONE);
+        assert_eq!(keymap.resolve_prefixed(&n), Some(Action::NextChannel));
+        let d = key(KeyCode::Char('d'), KeyModifiers::NONE);
+        assert_eq!(keymap.resolve_prefixed(&d), Some(Action::Detach));
+        let four = key(KeyCode::Char('4'), KeyModifiers::NONE);
+        assert_eq!(
+            keymap.resolve_prefixed(&four),
+            Some(Action::SwitchToChannel(4))
+        );
+        let z = key(KeyCode::Char('z'), KeyModifiers::NONE);
+        assert_eq!(keymap.resolve_prefixed(&z), Some(Action::ToggleZoom));
+        let x = key(KeyCode::Char('x'), KeyModifiers::NONE);
+        assert_eq!(keymap.resolve_prefixed(&x), None);
+    }
+
+    #[test]
+    fn prefix_disabled_by_default() {
+        let (keymap, _) = Keymap::from_config(&KeybindingsConfig::default());
+        assert!(!keymap.is_prefix(&key(KeyCode::Char('b'), KeyModifiers::CONTROL)));
+        assert_eq!(Action::from_name("bogus"), None);
+    }
+}
@@ -0,0 +1,313 @@
+//! Split-pane layouts: several channels sharing the output area
+
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+
+/// How a split arranges its panes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Split {
+    /// One above the other (`:split`)
+    Stacked,
+    /// Side by side (`:vsplit`)
+    SideBySide,
+}
+
+impl Split {
+    fn direction(self) -> Direction {
+        match self {
+            Split::Stacked => Direction::Vertical,
+            Split::SideBySide => Direction::Horizontal,
+        }
+    }
+}
+
+/// A tree of panes; each leaf shows one channel
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Pane(String),
+    Split(Split, Vec<Node>),
+}
+
+impl Node {
+    fn leaf_count(&self) -> usize {
+        match self {
+            Node::Pane(_) => 1,
+            Node::Split(_, children) => children.iter().map(Node::leaf_count).sum(),
+        }
+    }
+
+    /// The `index`th leaf, counting left to right
+    fn leaf_mut(&mut self, index: usize) -> Option<&mut String> {
+        match self {
+            Node::Pane(channel) => (index == 0).then_some(channel),
+            Node::Split(_, children) => {
+                let mut index = index;
+                for child in children {
+                    let count = child.leaf_count();
+                    if index < count {
+                        return child.leaf_mut(index);
+                    }
+                    index -= count;
+                }
+                None
+            }
+        }
+    }
+
+    /// Split the `index`th leaf, putting a pane for `channel` after it. A
+    /// leaf already in a split the same way gets a sibling instead of a new
+    /// level, so repeated splits share the space evenly.
+    fn split(&mut self, index: usize, split: Split, channel: &str) {
+        if let Node::Split(existing, children) = self {
+            let mut offset = index;
+            for position in 0..children.len() {
+                let count = children[position].leaf_count();
+                if offset < count {
+                    if *existing == split && matches!(children[position], Node::Pane(_)) {
+                        children.insert(position + 1, Node::Pane(channel.to_string()));
+                    } else {
+                        children[position].split(offset, split, channel);
+                    }
+                    return;
+                }
+                offset -= count;
+            }
+        } else if let Node::Pane(current) = self {
+            let current = std::mem::take(current);
+            *self = Node::Split(
+                split,
+                vec![Node::Pane(current), Node::Pane(channel.to_string())],
+            );
+        }
+    }
+
+    /// Remove the `index`th leaf, collapsing splits left with one pane.
+    /// Returns false if the leaf is this node itself.
+    fn remove(&mut self, index: usize) -> bool {
+        let Node::Split(_, children) = self else {
+            return false;
+        };
+        let mut offset = index;
+        for position in 0..children.len() {
+            let count = children[position].leaf_count();
+            if offset < count {
+                if !children[position].remove(offset) {
+                    children.remove(position);
+                }
+                break;
+            }
+            offset -= count;
+        }
+        if children.len() == 1 {
+            *self = children.remove(0);
+        }
+        true
+    }
+
+    fn areas<'a>(&'a self, area: Rect, out: &mut Vec<(Rect, &'a str)>) {
+        match self {
+            Node::Pane(channel) => out.push((area, channel)),
+            Node::Split(split, children) => {
+                let count = children.len() as u32;
+                let areas = Layout::default()
+                    .direction(split.direction())
+                    .constraints(vec![Constraint::Ratio(1, count); children.len()])
+                    .split(area);
+                for (child, area) in children.iter().zip(areas.iter()) {
+                    child.areas(*area, out);
+                }
+            }
+        }
+    }
+}
+
+/// Tile `count` equal panes over `area`, filling rows left to right: up to
+/// 2x2 for four, 3x3 for nine and so on. The last row may have fewer panes,
+/// which then share its width.
+pub fn grid(area: Rect, count: usize) -> Vec<Rect> {
+    if count == 0 {
+        return Vec::new();
+    }
+    let columns = (1..=count).find(|c| c * c >= count).unwrap_or(count);
+    let rows = count.div_ceil(columns);
+    let row_areas = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Ratio(1, rows as u32); rows])
+        .split(area);
+    row_areas
+        .iter()
+        .enumerate()
+        .flat_map(|(row, row_area)| {
+            let in_row = columns.min(count - row * columns);
+            Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Ratio(1, in_row as u32); in_row])
+                .split(*row_area)
+                .to_vec()
+        })
+        .collect()
+}
+
+/// Panes sharing the output area. The focused pane always shows the active
+/// channel (and is where input goes); the others keep showing the channel
+/// they were left on.
+#[derive(Debug, Clone, Default)]
+pub struct PaneLayout {
+    /// `None` while the output area is a single pane
+    root: Option<Node>,
+    /// Focused leaf, counting left to right
+    focus: usize,
+}
+
+impl PaneLayout {
+    /// Whether the output area is split
+    pub fn is_split(&self) -> bool {
+        self.root.is_some()
+    }
+
+    /// Split the focused pane (showing `active`) and focus a new pane for
+    /// `channel` after it
+    pub fn split(&mut self, split: Split, active: &str, channel: &str) {
+        let root = self
+            .root
+            .get_or_insert_with(|| Node::Pane(active.to_string()));
+        if let Some(focused) = root.leaf_mut(self.focus) {
+            *focused = active.to_string();
+        }
+        root.split(self.focus, split, channel);
+        self.focus += 1;
+    }
+
+    /// Move focus `steps` panes on (negative to go back), wrapping around.
+    /// The pane being left keeps showing `active`; returns the channel of
+    /// the newly focused pane, to make active.
+    pub fn focus_by(&mut self, steps: isize, active: &str) -> Option<String> {
+        let root = self.root.as_mut()?;
+        if let Some(focused) = root.leaf_mut(self.focus) {
+            *focused = active.to_string();
+        }
+        let count = root.leaf_count() as isize;
+        self.focus = (self.focus as isize + steps).rem_euclid(count) as usize;
+        root.leaf_mut(self.focus).cloned()
+    }
+
+    /// Close the focused pane, returning the channel of the pane focused
+    /// instead
+    pub fn close(&mut self) -> Option<String> {
+        let root = self.root.as_mut()?;
+        root.remove(self.focus);
+        let count = root.leaf_count();
+        self.focus = self.focus.min(count - 1);
+        let channel = root.leaf_mut(self.focus).cloned();
+        if count == 1 {
+            self.root = None;
+            self.focus = 0;
+        }
+        channel
+    }
+
+    /// Go back to a single pane
+    pub fn close_others(&mut self) {
+        self.root = None;
+        self.focus = 0;
+    }
+
+    /// Each pane's area and channel, with whether it's focused. The focused
+    /// pane's stored channel may be stale; it shows the active channel.
+    pub fn panes(&self, area: Rect) -> Vec<(Rect, &str, bool)> {
+        let Some(root) = &self.root else {
+            return Vec::new();
+        };
+        let mut areas = Vec::new();
+        root.areas(area, &mut areas);
+        areas
+            .into_iter()
+            .enumerate()
+            .map(|(index, (area, channel))| (area, channel, index == self.focus))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 80,
+        height: 24,
+    };
+
+    fn channels(layout: &PaneLayout) -> Vec<(String, bool)> {
+        layout
+            .panes(AREA)
+            .into_iter()
+            .map(|(_, channel, focused)| (channel.to_string(), focused))
+            .collect()
+    }
+
+    #[test]
+    fn splits_share_the_area_evenly() {
+        let mut layout = PaneLayout::default();
+        assert!(layout.panes(AREA).is_empty());
+
+        layout.split(Split::Stacked, "shell", "build");
+        layout.split(Split::Stacked, "build", "logs");
+        let panes = layout.panes(AREA);
+        let heights: Vec<u16> = panes.iter().map(|(area, _, _)| area.height).collect();
+        assert_eq!(heights, vec![8, 8, 8]);
+        assert_eq!(
+            channels(&layout),
+            vec![
+                ("shell".to_string(), false),
+                ("build".to_string(), false),
+                ("logs".to_string(), true),
+            ]
+        );
+
+        // A side-by-side split of the last pane nests inside the stack
+        layout.split(Split::SideBySide, "logs", "db");
+        let (area, _, _) = layout.panes(AREA)[3];
+        assert_eq!((area.x, area.y, area.width), (40, 16, 40));
+    }
+
+    #[test]
+    fn focus_wraps_and_remembers_each_panes_channel() {
+        let mut layout = PaneLayout::default();
+        layout.split(Split::SideBySide, "shell", "build");
+        // While focused, the build pane was switched to "tests"
+        assert_eq!(layout.focus_by(1, "tests"), Some("shell".to_string()));
+        assert_eq!(layout.focus_by(-1, "shell"), Some("tests".to_string()));
+    }
+
+    #[test]
+    fn grid_tiles_panes_in_rows() {
+        assert!(grid(AREA, 0).is_empty());
+        assert_eq!(grid(AREA, 1), vec![AREA]);
+
+        let four = grid(AREA, 4);
+        let corners: Vec<(u16, u16)> = four.iter().map(|r| (r.x, r.y)).collect();
+        assert_eq!(corners, vec![(0, 0), (40, 0), (0, 12), (40, 12)]);
+
+        // Five panes take a 3x2 grid; the second row splits its width in two
+        let five = grid(AREA, 5);
+        assert_eq!(five[0].width, 27);
+        assert_eq!((five[3].y, five[3].width), (12, 40));
+    }
+
+    #[test]
+    fn closing_panes_collapses_the_layout() {
+        let mut layout = PaneLayout::default();
+        layout.split(Split::Stacked, "shell", "build");
+        layout.split(Split::SideBySide, "build", "logs");
+
+        assert_eq!(layout.close(), Some("build".to_string()));
+        assert_eq!(
+            channels(&layout),
+            vec![("shell".to_string(), false), ("build".to_string(), true)]
+        );
+        assert_eq!(layout.close(), Some("shell".to_string()));
+        assert!(!layout.is_split());
+        assert_eq!(layout.close(), None);
+    }
+}
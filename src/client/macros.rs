@@ -0,0 +1,166 @@
+//! Macro recording and playback of input lines
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// A macro being recorded
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub name: String,
+    pub steps: Vec<String>,
+}
+
+impl Recording {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Record a submitted line; `:macro` commands themselves are never recorded
+    pub fn record(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || is_macro_command(line) {
+            return;
+        }
+        self.steps.push(line.to_string());
+    }
+}
+
+/// Whether a line is a `:macro ...` command
+pub fn is_macro_command(line: &str) -> bool {
+    let line = line.trim_start();
+    line == ":macro" || line.starts_with(":macro ")
+}
+
+/// Options for `:macro play`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayArgs {
+    pub name: String,
+    /// Channel to send plain-text steps to instead of the active one
+    pub channel: Option<String>,
+    pub delay: Duration,
+}
+
+/// Parse `<name> [--delay MS] [#channel|channel]`
+pub fn parse_play_args(args: &[String]) -> Result<PlayArgs, String> {
+    let usage = "Usage: :macro play <name> [--delay MS] [channel]";
+    let mut iter = args.iter();
+    let name = iter.next().ok_or(usage)?.clone();
+    let mut parsed = PlayArgs {
+        name,
+        channel: None,
+        delay: Duration::ZERO,
+    };
+
+    while let Some(arg) = iter.next() {
+        let delay = match arg.as_str() {
+            "--delay" | "-d" => Some(iter.next().ok_or(usage)?.as_str()),
+            _ => arg.strip_prefix("--delay="),
+        };
+        match delay {
+            Some(ms) => {
+                let ms: u64 = ms
+                    .parse()
+                    .map_err(|_| format!("Invalid delay '{}', expected milliseconds", ms))?;
+                parsed.delay = Duration::from_millis(ms);
+            }
+            None if parsed.channel.is_none() => {
+                parsed.channel = Some(arg.trim_start_matches('#').to_string());
+            }
+            None => return Err(usage.to_string()),
+        }
+    }
+    Ok(parsed)
+}
+
+/// A macro being played back, one step per `delay`
+#[derive(Debug, Clone)]
+pub struct Playback {
+    pub name: String,
+    steps: VecDeque<String>,
+    delay: Duration,
+    next_at: Instant,
+}
+
+impl Playback {
+    /// Start playing `steps`, redirecting plain-text steps to `channel` if given
+    pub fn new(name: &str, steps: &[String], channel: Option<&str>, delay: Duration) -> Self {
+        let steps = steps
+            .iter()
+            .map(|step| match channel {
+                Some(channel) if !step.starts_with(':') && !step.starts_with('#') => {
+                    format!("#{}: {}", channel, step)
+                }
+                _ => step.clone(),
+            })
+            .collect();
+        Self {
+            name: name.to_string(),
+            steps,
+            delay,
+            next_at: Instant::now(),
+        }
+    }
+
+    /// Next step if it is due at `now`
+    pub fn next_due(&mut self, now: Instant) -> Option<String> {
+        if now < self.next_at {
+            return None;
+        }
+        let step = self.steps.pop_front()?;
+        self.next_at = now + self.delay;
+        Some(step)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn records_lines_but_not_macro_commands() {
+        let mut recording = Recording::new("deploy");
+        recording.record("make");
+        recording.record(":macro stop");
+        recording.record("  ");
+        recording.record("#build: cargo test");
+        assert_eq!(recording.steps, vec!["make", "#build: cargo test"]);
+    }
+
+    #[test]
+    fn parses_play_args() {
+        let parsed = parse_play_args(&args(&["deploy", "--delay", "250", "#build"])).unwrap();
+        assert_eq!(parsed.name, "deploy");
+        assert_eq!(parsed.channel.as_deref(), Some("build"));
+        assert_eq!(parsed.delay, Duration::from_millis(250));
+
+        assert!(parse_play_args(&[]).is_err());
+        assert!(parse_play_args(&args(&["deploy", "--delay=soon"])).is_err());
+    }
+
+    #[test]
+    fn playback_redirects_text_and_waits_between_steps() {
+        let steps = args(&["make", ":list", "#web: npm start"]);
+        let mut playback = Playback::new("m", &steps, Some("build"), Duration::from_secs(60));
+
+        let now = Instant::now();
+        assert_eq!(playback.next_due(now).as_deref(), Some("#build: make"));
+        assert_eq!(playback.next_due(now), None);
+
+        let later = now + Duration::from_secs(60);
+        assert_eq!(playback.next_due(later).as_deref(), Some(":list"));
+        let later = later + Duration::from_secs(60);
+        assert_eq!(playback.next_due(later).as_deref(), Some("#web: npm start"));
+        assert!(playback.is_finished());
+    }
+}
@@ -0,0 +1,77 @@
+//! Named scrollback marks (`:mark`, `:jump`)
+
+use crate::client::app::BufferedLine;
+
+/// Where a mark sits in a channel's output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mark {
+    /// Line number counting from the channel's first line, for marks set by
+    /// this client
+    pub line: Option<usize>,
+    /// Output chunk that completed the marked line; 0 for lines that came
+    /// from no chunk. Marks shared by other clients only have this.
+    pub seq: u64,
+}
+
+impl Mark {
+    /// Index into a channel buffer of the marked line, given how many lines
+    /// have been trimmed from the front of the buffer. `None` once the line
+    /// has been trimmed.
+    pub fn resolve(&self, buffer: &[BufferedLine], trimmed: usize) -> Option<usize> {
+        if let Some(line) = self.line {
+            return line.checked_sub(trimmed).filter(|&i| i < buffer.len());
+        }
+        let mut numbered = buffer.iter().enumerate().filter(|(_, l)| l.seq != 0);
+        let (first, oldest) = numbered.next()?;
+        if oldest.seq > self.seq {
+            return None;
+        }
+        std::iter::once((first, oldest))
+            .chain(numbered)
+            .find(|(_, l)| l.seq >= self.seq)
+            .map(|(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::OutputStream;
+    use chrono::Local;
+
+    fn buffer(seqs: &[u64]) -> Vec<BufferedLine> {
+        seqs.iter()
+            .map(|&seq| BufferedLine {
+                content: String::new(),
+                timestamp: Local::now(),
+                stream: OutputStream::Stdout,
+                section_start: false,
+                folded: false,
+                seq,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn local_marks_keep_their_line_as_the_buffer_is_trimmed() {
+        let lines = buffer(&[4, 4, 5, 6]);
+        let mark = Mark {
+            line: Some(12),
+            seq: 5,
+        };
+        assert_eq!(mark.resolve(&lines, 10), Some(2));
+        assert_eq!(mark.resolve(&lines, 13), None);
+        assert_eq!(mark.resolve(&lines, 5), None);
+    }
+
+    #[test]
+    fn shared_marks_find_the_first_line_of_their_chunk() {
+        let lines = buffer(&[0, 4, 4, 6, 7]);
+        let at = |seq| Mark { line: None, seq }.resolve(&lines, 0);
+        assert_eq!(at(4), Some(1));
+        assert_eq!(at(5), Some(3));
+        assert_eq!(at(7), Some(4));
+        assert_eq!(at(8), None);
+        assert_eq!(at(3), None);
+    }
+}
@@ -1,30 +1,89 @@
 //! Client - user-facing terminal interface
 
+mod ansi;
+mod api;
 mod app;
+pub mod cli;
+mod clipboard;
 mod commands;
 mod completion;
+mod diff;
+mod executables;
+mod folds;
+mod hexdump;
 mod input;
+mod keybindings;
+mod layout;
+mod macros;
+mod marks;
+mod palette;
+mod search;
+mod selection;
+mod sidebar;
+mod theme;
+mod timestamps;
 mod ui;
-
-use crate::client::app::{App, ChannelInfo, ViewMode};
-use crate::client::commands::{handle_control_command, CommandResult};
-use crate::client::input::{parse_input, ParsedInput};
-use crate::config::Config;
-use crate::protocol::{ChannelEvent, ClientMessage, ServerMessage};
+mod wrap;
+
+pub use crate::client::api::{ClientEvent, EventStream, NexusClient};
+
+use crate::channel::OutputStream;
+use crate::client::ansi::strip_ansi_codes;
+use crate::client::app::{App, ChannelInfo, InfoPopup, PendingPing, Reconnect, ViewMode};
+use crate::client::commands::{handle_control_command, reload_config, CommandResult};
+use crate::client::input::{parse_input_with_aliases, ParsedInput};
+use crate::client::keybindings::Action;
+use crate::client::palette::{Palette, PaletteAction};
+use crate::config::{duration, Config, Profile, ProfileLayout};
+use crate::protocol::{
+    Capability, ChannelEvent, ClientMessage, CreateChannelRequest, ProcessInfo, ScheduleInfo,
+    ServerMessage, StopSignal, CAPABILITIES,
+};
 use crate::server::connection::{read_message, write_message};
+use crate::server::tls::ClientTls;
+use crate::server::{daemon, registry};
 use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local};
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEvent, KeyModifiers, MouseEventKind},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{backend::CrosstermBackend, Terminal};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::time::Duration;
-use tokio::net::UnixStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::net::{TcpStream, UnixStream};
 use tokio::sync::mpsc;
-use tokio::time::sleep;
+
+/// How often to check config.toml for changes
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often to check for due macro playback steps
+const MACRO_TICK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often to ping the server to check it's still answering
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often to redraw while a channel's startup spinner is showing
+const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often to ask for the active channel's CPU and memory use while the
+/// status bar shows it
+const USAGE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often to redraw with nothing changed, keeping the clock and
+/// elapsed times current
+const IDLE_REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Modification time of the config file, if it exists
+fn config_modified_time() -> Option<std::time::SystemTime> {
+    std::fs::metadata(Config::config_path())
+        .and_then(|meta| meta.modified())
+        .ok()
+}
 
 /// Command history for input recall
 struct CommandHistory {
@@ -65,6 +124,17 @@ impl CommandHistory {
         self.saved_input.clear();
     }
 
+    /// Seed history with entries from the server, keeping anything added locally since
+    fn load(&mut self, entries: Vec<String>) {
+        let local = std::mem::replace(&mut self.entries, entries);
+        for entry in local {
+            self.add(&entry);
+        }
+        let excess = self.entries.len().saturating_sub(self.max_entries);
+        self.entries.drain(..excess);
+        self.position = None;
+    }
+
     /// Move up in history (older), returning the command to display
     fn up(&mut self, current_input: &str) -> Option<&str> {
         if self.entries.is_empty() {
@@ -109,10 +179,24 @@ impl CommandHistory {
     }
 }
 
-/// Start a new session (spawns server if needed)
-pub async fn start_new_session(name: &str) -> Result<()> {
+/// Start a new session (spawns server if needed), optionally bringing up a profile
+pub async fn start_new_session(name: &str, profile: Option<Profile>) -> Result<()> {
     tracing::info!("Starting new session: {}", name);
 
+    let stream = connect_or_spawn(name).await?;
+    let connector = Connector::Local(Config::load()?.socket_path(name));
+    let (reader, writer) = stream.into_split();
+    run_client_loop(
+        connector,
+        (Box::new(reader), Box::new(writer)),
+        profile,
+        AttachOptions::default(),
+    )
+    .await
+}
+
+/// Connect to a session's server, spawning `nexus-server` first if it isn't running
+pub(crate) async fn connect_or_spawn(name: &str) -> Result<UnixStream> {
     let config = Config::load()?;
     let socket_path = config.socket_path(name);
 
@@ -124,12 +208,19 @@ pub async fn start_new_session(name: &str) -> Result<()> {
     // Try to connect
     let stream = match UnixStream::connect(&socket_path).await {
         Ok(s) => s,
+        Err(_)
+            if config.general.shared_server && start_on_shared_server(name, &socket_path).await =>
+        {
+            UnixStream::connect(&socket_path)
+                .await
+                .map_err(|_| anyhow!("nexus-server failed to start session '{}'", name))?
+        }
         Err(_) => {
-            // Spawn server
-            println!("nexus: spawning server for session '{}'...", name);
+            // Spawn server (on stderr so `nexus run` keeps stdout clean)
+            eprintln!("nexus: spawning server for session '{}'...", name);
             let exe = std::env::current_exe()?
                 .parent()
-                .unwrap_or_else(|| std::path::Path::new("."))
+                .unwrap_or_else(|| Path::new("."))
                 .join("nexus-server");
 
             let server_bin = if exe.exists() {
@@ -138,34 +229,64 @@ pub async fn start_new_session(name: &str) -> Result<()> {
                 "nexus-server".to_string()
             };
 
-            Command::new(server_bin)
+            // The server takes the startup lock before daemonizing, so once
+            // this returns, waiting on the lock waits for the socket. A losing
+            // racer exits on its own after finding the winner's socket.
+            let status = Command::new(server_bin)
                 .arg("--session")
                 .arg(name)
                 .stdout(Stdio::null())
                 .stderr(Stdio::null())
-                .spawn()
+                .status()
                 .context("Failed to spawn nexus-server")?;
+            tracing::debug!("nexus-server exited with {}", status);
 
-            // Wait for socket to appear
-            let mut attempts = 0;
-            loop {
-                sleep(Duration::from_millis(100)).await;
-                if let Ok(s) = UnixStream::connect(&socket_path).await {
-                    break s;
-                }
-                attempts += 1;
-                if attempts > 20 {
-                    return Err(anyhow!("Timed out waiting for server to start"));
-                }
-            }
+            let lock_path = daemon::lock_path(&socket_path);
+            tokio::task::spawn_blocking(move || daemon::wait_for_startup(&lock_path)).await??;
+            UnixStream::connect(&socket_path)
+                .await
+                .map_err(|_| anyhow!("nexus-server failed to start for session '{}'", name))?
         }
     };
+    Ok(stream)
+}
+
+/// Ask a running server to host session `name`, returning whether one did.
+/// Once the server acks, the session's startup lock is held until its socket
+/// is bound.
+async fn start_on_shared_server(name: &str, socket_path: &Path) -> bool {
+    let Some(runtime_dir) = socket_path.parent() else {
+        return false;
+    };
+    for (other, other_socket) in cli::session_sockets(runtime_dir) {
+        let Ok(client) = NexusClient::connect(&other_socket).await else {
+            continue;
+        };
+        match client.start_session(name).await {
+            Ok(()) => {
+                tracing::debug!("Session '{}' hosted by the server of '{}'", name, other);
+                let lock_path = daemon::lock_path(socket_path);
+                return tokio::task::spawn_blocking(move || daemon::wait_for_startup(&lock_path))
+                    .await
+                    .is_ok_and(|waited| waited.is_ok());
+            }
+            Err(e) => tracing::debug!("Server of '{}' can't host '{}': {}", other, name, e),
+        }
+    }
+    false
+}
 
-    run_client_loop(stream).await
+/// How a client attaches to a session
+#[derive(Debug, Clone, Default)]
+pub struct AttachOptions {
+    /// Watch read-only
+    pub observe: bool,
+    /// Name other clients see this one by
+    pub name: Option<String>,
 }
 
 /// Attach to an existing session
-pub async fn attach_session(name: &str) -> Result<()> {
+pub async fn attach_session(name: &str, options: AttachOptions) -> Result<()> {
     tracing::info!("Attaching to session: {}", name);
 
     let config = Config::load()?;
@@ -175,38 +296,237 @@ pub async fn attach_session(name: &str) -> Result<()> {
         return Err(anyhow!("Session '{}' not found", name));
     }
 
-    let stream = UnixStream::connect(&socket_path)
+    let connector = Connector::Local(socket_path);
+    let connection = connector
+        .connect(&options)
         .await
         .context("Failed to connect to session")?;
+    run_client_loop(connector, connection, None, options).await
+}
+
+/// Attach to a session served over TCP (optionally TLS), authenticating with
+/// `token`
+pub async fn attach_remote(
+    addr: &str,
+    token: &str,
+    tls: Option<&ClientTls>,
+    options: AttachOptions,
+) -> Result<()> {
+    tracing::info!("Attaching to remote session at {}", addr);
+
+    let connector = Connector::Remote {
+        addr: addr.to_string(),
+        token: token.to_string(),
+        tls: tls.cloned(),
+    };
+    // Failures are reported before taking over the terminal so they print cleanly
+    let connection = connector.connect(&options).await?;
+    run_client_loop(connector, connection, None, options).await
+}
 
-    run_client_loop(stream).await
+type BoxedReader = Box<dyn AsyncRead + Unpin + Send>;
+type BoxedWriter = Box<dyn AsyncWrite + Unpin + Send>;
+
+/// How long a reconnection attempt may take
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How the UI reaches its session's server, so it can reconnect when the
+/// connection drops
+enum Connector {
+    /// The session's Unix socket
+    Local(PathBuf),
+    /// A TCP listener, optionally over TLS, authenticated with a token
+    Remote {
+        addr: String,
+        token: String,
+        tls: Option<ClientTls>,
+    },
+}
+
+impl Connector {
+    /// Open a connection, authenticating to remote servers
+    async fn connect(&self, options: &AttachOptions) -> Result<(BoxedReader, BoxedWriter)> {
+        match self {
+            Connector::Local(socket_path) => {
+                let (reader, writer) = UnixStream::connect(socket_path).await?.into_split();
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+            Connector::Remote { addr, token, tls } => {
+                let (reader, mut writer): (BoxedReader, BoxedWriter) = match tls {
+                    Some(tls) => {
+                        let (reader, writer) = tokio::io::split(tls.connect(addr).await?);
+                        (Box::new(reader), Box::new(writer))
+                    }
+                    None => {
+                        let (reader, writer) = TcpStream::connect(addr)
+                            .await
+                            .with_context(|| format!("Failed to connect to {}", addr))?
+                            .into_split();
+                        (Box::new(reader), Box::new(writer))
+                    }
+                };
+                let reader = authenticate(reader, &mut writer, token, options).await?;
+                Ok((reader, writer))
+            }
+        }
+    }
+
+    /// Follow a session rename, so reconnecting finds the moved socket
+    fn renamed(&mut self, name: &str) {
+        if let Connector::Local(socket_path) = self {
+            *socket_path = socket_path.with_file_name(format!("{}.sock", name));
+        }
+    }
+}
+
+/// Present `token` to a TCP server and wait for its Welcome, returning a
+/// reader that yields the Welcome again so the UI sees it like any other
+async fn authenticate(
+    mut reader: BoxedReader,
+    writer: &mut BoxedWriter,
+    token: &str,
+    options: &AttachOptions,
+) -> Result<BoxedReader> {
+    let hello = ClientMessage::Hello {
+        protocol_version: crate::protocol::PROTOCOL_VERSION,
+        token: Some(token.to_string()),
+        identity: None,
+        capabilities: CAPABILITIES.to_vec(),
+        observe: options.observe,
+        name: options.name.clone(),
+    };
+    write_message(writer, &crate::protocol::serialize(&hello)?).await?;
+    match read_message(&mut reader).await? {
+        Some(bytes) => match crate::protocol::deserialize(&bytes)? {
+            ServerMessage::Welcome { .. } => {
+                let welcome = std::io::Cursor::new(crate::protocol::frame_message(&bytes));
+                Ok(Box::new(welcome.chain(reader)))
+            }
+            ServerMessage::Error { message, .. } => Err(anyhow!(message)),
+            other => Err(anyhow!("Unexpected handshake reply: {:?}", other)),
+        },
+        None => Err(anyhow!("Server closed the connection")),
+    }
+}
+
+/// Forward a connection's messages to the UI, reporting `generation` on
+/// `lost_tx` once the connection ends
+fn spawn_server_reader(
+    mut reader: BoxedReader,
+    server_tx: mpsc::Sender<ServerMessage>,
+    lost_tx: mpsc::Sender<u64>,
+    generation: u64,
+) {
+    tokio::spawn(async move {
+        loop {
+            match read_message(&mut reader).await {
+                Ok(Some(data)) => match crate::protocol::deserialize::<ServerMessage>(&data) {
+                    Ok(msg) => {
+                        if server_tx.send(msg).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(_e) => {
+                        tracing::error!("Failed to deserialize: {}", _e);
+                    }
+                },
+                Ok(None) => break, // EOF
+                Err(e) => {
+                    tracing::error!("Connection error: {}", e);
+                    break;
+                }
+            }
+        }
+        let _ = lost_tx.send(generation).await;
+    });
+}
+
+/// Messages that bring a fresh connection up to date with the UI: handshake,
+/// resuming the old connection (or resubscribing), channel list, history and
+/// terminal size
+fn resync_messages(app: &App, size: Option<ratatui::layout::Size>) -> Vec<ClientMessage> {
+    let mut messages = vec![ClientMessage::Hello {
+        protocol_version: crate::protocol::PROTOCOL_VERSION,
+        token: None,
+        identity: client_identity(),
+        capabilities: CAPABILITIES.to_vec(),
+        observe: app.attach.observe,
+        name: app.attach.name.clone(),
+    }];
+    match &app.resume_token {
+        Some(token) => messages.push(ClientMessage::Resume {
+            token: token.clone(),
+            last_seen: app.output_seqs.clone(),
+        }),
+        None if !app.subscriptions.is_empty() => messages.push(ClientMessage::Subscribe {
+            channels: app.subscriptions.clone(),
+        }),
+        None => {}
+    }
+    messages.push(ClientMessage::ListChannels);
+    messages.push(ClientMessage::GetHistory { channel: None });
+    if let Some(size) = size {
+        messages.push(ClientMessage::Resize {
+            cols: size.width,
+            rows: size.height,
+        });
+    }
+    messages
+}
+
+/// Connect to the server again and bring the new connection up to date
+/// with the UI
+async fn reconnect(
+    connector: &Connector,
+    app: &App,
+    size: Option<ratatui::layout::Size>,
+) -> Result<(BoxedReader, BoxedWriter)> {
+    let (reader, mut writer) =
+        tokio::time::timeout(RECONNECT_TIMEOUT, connector.connect(&app.attach))
+            .await
+            .map_err(|_| anyhow!("timed out"))??;
+    for msg in resync_messages(app, size) {
+        write_message(&mut writer, &crate::protocol::serialize(&msg)?).await?;
+    }
+    Ok((reader, writer))
+}
+
+/// What to send once the server answers a `Resume`: when there was nothing
+/// to resume from (e.g. the server restarted), resubscribe, replaying
+/// whatever the server still has
+fn after_resume(app: &App, restored: bool) -> Option<ClientMessage> {
+    (!restored && !app.subscriptions.is_empty()).then(|| ClientMessage::Subscribe {
+        channels: app.subscriptions.clone(),
+    })
 }
 
 /// List available sessions
 pub async fn list_sessions() -> Result<()> {
     let config = Config::load()?;
-    let runtime_dir = config.runtime_dir();
+    let sessions = cli::session_sockets(&config.runtime_dir());
 
-    if !runtime_dir.exists() {
+    if sessions.is_empty() {
         println!("No sessions found.");
         return Ok(());
     }
 
-    let mut found = false;
-    for entry in std::fs::read_dir(runtime_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.extension().and_then(|s| s.to_str()) == Some("sock") {
-            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
-                println!("{}", stem);
-                found = true;
-            }
+    for (name, socket) in sessions {
+        match registry::lookup(&socket) {
+            Some(info) => println!(
+                "{}: {} client{}, {} channel{} (created {})",
+                name,
+                info.client_count,
+                if info.client_count == 1 { "" } else { "s" },
+                info.channel_count,
+                if info.channel_count == 1 { "" } else { "s" },
+                info.created_at
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S"),
+            ),
+            // Not running, or started on demand by systemd
+            None => println!("{}", name),
         }
     }
-
-    if !found {
-        println!("No sessions found.");
-    }
     Ok(())
 }
 
@@ -221,98 +541,575 @@ pub async fn kill_session(name: &str) -> Result<()> {
         return Err(anyhow!("Session '{}' not found", name));
     }
 
-    let mut stream = UnixStream::connect(&socket_path).await?;
+    shutdown_server(&socket_path).await?;
+    println!("Session '{}' killed.", name);
+    Ok(())
+}
+
+/// How long to wait for a server to exit after `Shutdown`
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
-    // Handshake
-    let hello = ClientMessage::Hello {
-        protocol_version: 1,
+/// Ask the server behind `socket_path` to kill its channels and exit, waiting
+/// until it closes the connection. A socket nobody is listening on is just
+/// removed.
+pub(crate) async fn shutdown_server(socket_path: &Path) -> Result<()> {
+    let mut client = match NexusClient::connect(socket_path).await {
+        Ok(client) => client,
+        Err(_) => {
+            std::fs::remove_file(socket_path)?;
+            return Ok(());
+        }
     };
-    write_message(&mut stream, &crate::protocol::serialize(&hello)?).await?;
+    let mut events = client
+        .take_events()
+        .ok_or_else(|| anyhow!("Event stream unavailable"))?;
+    client.send(ClientMessage::Shutdown).await?;
+
+    // The stream ends when the server process goes away
+    tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+        while events.next().await.is_some() {}
+    })
+    .await
+    .map_err(|_| anyhow!("Server did not shut down within {:?}", SHUTDOWN_TIMEOUT))
+}
 
-    // Send Shutdown
-    let shutdown = ClientMessage::Shutdown;
-    write_message(&mut stream, &crate::protocol::serialize(&shutdown)?).await?;
+/// Attach to session or create if doesn't exist
+pub async fn attach_or_create(name: &str) -> Result<()> {
+    start_new_session(name, None).await
+}
 
-    println!("Session '{}' killed.", name);
+/// Messages that bring up the channels described by a profile
+fn profile_messages(profile: &Profile) -> Vec<ClientMessage> {
+    let mut messages: Vec<ClientMessage> = profile
+        .channels
+        .iter()
+        .map(|channel| {
+            ClientMessage::CreateChannel(CreateChannelRequest {
+                name: channel.name.clone(),
+                command: channel.command.clone(),
+                working_dir: profile
+                    .channel_cwd(channel)
+                    .map(|dir| dir.to_string_lossy().to_string()),
+                env: channel
+                    .env
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+                template: channel.template.clone(),
+                mode: channel.mode,
+                timeout_secs: channel.timeout,
+                schedule: channel.schedule.clone(),
+                ..Default::default()
+            })
+        })
+        .collect();
+
+    // Creating a channel subscribes us to it; drop the ones the profile doesn't want
+    let keep = profile.subscribed_channels();
+    let unwanted: Vec<String> = profile
+        .channels
+        .iter()
+        .map(|c| c.name.clone())
+        .filter(|name| !keep.contains(name))
+        .collect();
+    if !unwanted.is_empty() {
+        messages.push(ClientMessage::Unsubscribe { channels: unwanted });
+    }
+
+    if let Some(focus) = &profile.focus {
+        messages.push(ClientMessage::SwitchChannel {
+            name: focus.clone(),
+        });
+    }
+
+    messages
+}
+
+/// Switch the server's active channel to whatever the app now considers active
+async fn send_active_channel(app: &App, msg_tx: &mpsc::Sender<ClientMessage>) -> Result<()> {
+    if let Some(ch) = &app.active_channel {
+        msg_tx
+            .send(ClientMessage::SwitchChannel { name: ch.clone() })
+            .await?;
+    }
     Ok(())
 }
 
-/// Attach to session or create if doesn't exist
-pub async fn attach_or_create(name: &str) -> Result<()> {
-    start_new_session(name).await
+/// Close the popup once its channel is over; the server drops the channel
+/// itself since popups are ephemeral
+async fn close_finished_popup(
+    app: &mut App,
+    channel: &str,
+    msg_tx: &mpsc::Sender<ClientMessage>,
+) -> Result<()> {
+    if app.popup.as_ref().is_none_or(|p| p.channel != channel) {
+        return Ok(());
+    }
+    if let Some(name) = app.close_popup() {
+        msg_tx.send(ClientMessage::SwitchChannel { name }).await?;
+    }
+    msg_tx
+        .send(ClientMessage::Unsubscribe {
+            channels: vec![channel.to_string()],
+        })
+        .await?;
+    Ok(())
+}
+
+/// Lines of a `:run` channel's output copied to SYSTEM when it closes
+const RUN_OUTPUT_LINES: usize = 20;
+
+/// Wrap up a `:run` channel once its command is over. A failure stays open
+/// to be read, when the server keeps it; otherwise the channel closes,
+/// leaving its last lines of output under SYSTEM.
+async fn finish_scratch_run(
+    app: &mut App,
+    channel: &str,
+    exit_code: Option<i32>,
+    killed: bool,
+    msg_tx: &mpsc::Sender<ClientMessage>,
+) -> Result<()> {
+    let Some(run) = app.scratch_runs.get(channel) else {
+        return Ok(());
+    };
+    let failed = !killed && exit_code != Some(0);
+    let outcome = match exit_code {
+        _ if killed => "was killed".to_string(),
+        Some(0) => "finished".to_string(),
+        Some(code) => format!("failed with status {}", code),
+        None => "failed".to_string(),
+    };
+    if failed && app.server_supports(Capability::KeepOnFailure) {
+        app.add_output(
+            "SYSTEM".to_string(),
+            format!(
+                "#{} {}: {} (kept open; :kill {} closes it)",
+                channel, outcome, run.command, channel
+            ),
+        );
+        return Ok(());
+    }
+
+    let summary = format!("#{} {}: {}", channel, outcome, run.command);
+    let output: Vec<String> = app
+        .channel_buffers
+        .get(channel)
+        .map(|buffer| {
+            let end = buffer
+                .iter()
+                .rposition(|line| !strip_ansi_codes(&line.content).trim().is_empty())
+                .map_or(0, |last| last + 1);
+            buffer[end.saturating_sub(RUN_OUTPUT_LINES)..end]
+                .iter()
+                .map(|line| format!("  {}", line.content))
+                .collect()
+        })
+        .unwrap_or_default();
+    app.add_output("SYSTEM".to_string(), summary);
+    for line in output {
+        app.add_output("SYSTEM".to_string(), line);
+    }
+    if let Some(name) = app.discard_run(channel) {
+        msg_tx.send(ClientMessage::SwitchChannel { name }).await?;
+    }
+    msg_tx
+        .send(ClientMessage::Unsubscribe {
+            channels: vec![channel.to_string()],
+        })
+        .await?;
+    Ok(())
 }
 
-/// Handle scroll keys when input buffer is empty
-fn handle_scroll_keys(key: &KeyEvent, app: &mut App) -> bool {
+/// Tab completion of the current input.
+///
+/// Commands, channels and executables in command position complete locally;
+/// anything else is sent to the server as a path relative to the channel's
+/// working directory.
+async fn complete_input(app: &mut App, msg_tx: &mpsc::Sender<ClientMessage>) -> Result<()> {
+    let channel_names: Vec<String> = app.channels.iter().map(|c| c.name.clone()).collect();
+    let input = app.line_editor.content().to_string();
+    let completions = crate::client::completion::complete(&input, &channel_names);
+
+    if completions.is_empty() {
+        if let Some((head, word)) = crate::client::completion::command_word(&input) {
+            let executables: Vec<String> = app
+                .executables
+                .complete(&word)
+                .into_iter()
+                .map(|name| format!("{}{}", head, name))
+                .collect();
+            if !executables.is_empty() {
+                apply_completions(app, executables);
+                return Ok(());
+            }
+        }
+
+        if let Some(target) = crate::client::completion::path_target(&input) {
+            msg_tx
+                .send(ClientMessage::CompletePath {
+                    channel: target.channel,
+                    partial: target.word,
+                })
+                .await?;
+            return Ok(());
+        }
+    }
+
+    apply_completions(app, completions);
+    Ok(())
+}
+
+/// Identity sent in `Hello` (`$NEXUS_IDENTITY`, or the user name). The
+/// server saves this client's view under it when detaching and shows it to
+/// other clients; permissions go by the connection itself.
+fn client_identity() -> Option<String> {
+    std::env::var("NEXUS_IDENTITY")
+        .or_else(|_| std::env::var("USER"))
+        .ok()
+        .filter(|identity| !identity.is_empty())
+}
+
+/// Apply path completions from the server if the input hasn't moved on
+fn apply_path_completions(app: &mut App, partial: &str, candidates: Vec<String>) {
+    let input = app.line_editor.content().to_string();
+    let Some(target) = crate::client::completion::path_target(&input) else {
+        return;
+    };
+    if target.word != partial {
+        return;
+    }
+    let completions = candidates
+        .into_iter()
+        .map(|candidate| format!("{}{}", target.head, candidate))
+        .collect();
+    apply_completions(app, completions);
+}
+
+/// Uptime as its two largest units, e.g. `3h 12m`
+fn format_uptime(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Fill in a single completion, or extend to the common prefix and list the choices
+fn apply_completions(app: &mut App, completions: Vec<String>) {
+    if completions.len() == 1 {
+        app.line_editor.set(&completions[0]);
+        app.completions = None;
+    } else if !completions.is_empty() {
+        if let Some(prefix) = crate::client::completion::common_prefix(&completions) {
+            if prefix.len() > app.line_editor.content().len() {
+                app.line_editor.set(&prefix);
+            }
+        }
+        app.completions = Some(completions);
+    } else {
+        app.completions = None;
+    }
+}
+
+/// Run a key-bound action
+async fn run_key_action(
+    action: Action,
+    app: &mut App,
+    msg_tx: &mpsc::Sender<ClientMessage>,
+    history: &mut HashMap<String, CommandHistory>,
+    channel_key: &str,
+) -> Result<CommandResult> {
     // Determine visible rows (approximate or fix constant)
     // We can assume a reasonable page size or update app with rect size
     let page_size = 20;
 
-    match key.code {
-        KeyCode::PageUp => {
-            app.scroll_up(page_size);
-            true
-        }
-        KeyCode::PageDown => {
-            app.scroll_down(page_size);
-            true
-        }
-        KeyCode::Home => {
-            let active = app.active_channel.clone();
-            app.scroll_to_bottom(active.as_deref());
-            if let Some(ch) = app.active_channel.clone() {
-                app.scroll_offsets.insert(ch, usize::MAX); // Special case for top?
-                                                           // Wait, scroll_to_bottom puts offset 0.
-                                                           // Home should scroll to TOP (oldest).
-                                                           // Logic in renderer was: scroll_up(usize::MAX)
-                app.scroll_up(usize::MAX);
+    if action != Action::Complete {
+        app.completions = None;
+    }
+
+    match action {
+        Action::NextChannel => {
+            app.next_channel();
+            send_active_channel(app, msg_tx).await?;
+        }
+        Action::PrevChannel => {
+            app.prev_channel();
+            send_active_channel(app, msg_tx).await?;
+        }
+        Action::SwitchToChannel(number) => {
+            if let Some(channel) = number.checked_sub(1).and_then(|idx| app.channels.get(idx)) {
+                msg_tx
+                    .send(ClientMessage::SwitchChannel {
+                        name: channel.name.clone(),
+                    })
+                    .await?;
             }
-            true
-        }
-        KeyCode::End => {
-            let active = app.active_channel.clone();
-            app.scroll_to_bottom(active.as_deref());
-            true
-        }
-        KeyCode::Tab => {
-            if !app.line_editor.is_empty() {
-                let channel_names: Vec<String> =
-                    app.channels.iter().map(|c| c.name.clone()).collect();
-                let completions =
-                    crate::client::completion::complete(app.line_editor.content(), &channel_names);
-
-                if completions.len() == 1 {
-                    app.line_editor.set(&completions[0]);
-                    app.completions = None;
-                } else if !completions.is_empty() {
-                    if let Some(prefix) = crate::client::completion::common_prefix(&completions) {
-                        if prefix.len() > app.line_editor.content().len() {
-                            app.line_editor.set(&prefix);
-                        }
-                    }
-                    app.completions = Some(completions);
-                } else {
-                    app.completions = None;
-                }
+        }
+        Action::ClearScreen => app.clear_output(),
+        Action::Quit => return Ok(CommandResult::Exit),
+        Action::Detach => {
+            msg_tx.send(ClientMessage::Detach).await?;
+            return Ok(CommandResult::Exit);
+        }
+        Action::Interrupt => {
+            if app.line_editor.is_empty() {
+                msg_tx.send(ClientMessage::Input { data: vec![3] }).await?;
             } else {
-                app.view_mode = match app.view_mode {
-                    ViewMode::ActiveChannel => ViewMode::AllChannels,
-                    ViewMode::AllChannels => ViewMode::ActiveChannel,
-                };
+                app.line_editor.clear();
+                if let Some(h) = history.get_mut(channel_key) {
+                    h.reset_position();
+                }
             }
-            true
         }
-        _ => false,
+        Action::Eof => msg_tx.send(ClientMessage::Input { data: vec![4] }).await?,
+        Action::ScrollPageUp => app.scroll_up(page_size),
+        Action::ScrollPageDown => app.scroll_down(page_size),
+        Action::ScrollHalfUp => app.scroll_up(page_size / 2),
+        Action::ScrollHalfDown => app.scroll_down(page_size / 2),
+        Action::ScrollTop => app.scroll_up(usize::MAX),
+        Action::ScrollBottom => app.scroll_view_to_bottom(),
+        Action::LineStart => {
+            app.line_editor.move_home();
+        }
+        Action::LineEnd => {
+            app.line_editor.move_end();
+        }
+        Action::DeleteWord => {
+            app.line_editor.delete_word_backward();
+        }
+        Action::DeleteToEnd => {
+            app.line_editor.delete_to_end();
+        }
+        Action::DeleteToStart => {
+            app.line_editor.delete_to_start();
+        }
+        Action::Complete => complete_input(app, msg_tx).await?,
+        Action::CommandPalette => {
+            let recent = history
+                .get(channel_key)
+                .map(|h| h.entries.as_slice())
+                .unwrap_or_default();
+            app.palette = Some(Palette::new(app, recent));
+        }
+        Action::SendPrefix => {
+            if let Some(data) = app.keymap.prefix_bytes() {
+                msg_tx.send(ClientMessage::Input { data }).await?;
+            }
+        }
+        Action::ToggleView => app.view_mode = app.view_mode.toggled(),
+        Action::FocusNextPane => {
+            app.focus_pane(1);
+            send_active_channel(app, msg_tx).await?;
+        }
+        Action::FocusPrevPane => {
+            app.focus_pane(-1);
+            send_active_channel(app, msg_tx).await?;
+        }
+        Action::ToggleZoom => {
+            app.toggle_zoom();
+        }
+        Action::ToggleSidebar => app.toggle_sidebar(),
+        Action::ToggleFold => {
+            app.toggle_fold();
+        }
+        Action::NextError => {
+            app.jump_to_error(true);
+        }
+        Action::PrevError => {
+            app.jump_to_error(false);
+        }
+        Action::SearchNext => app.step_search(true),
+        Action::SearchPrev => app.step_search(false),
+        Action::CopyLastOutput => {
+            let output = app.last_command_output();
+            commands::copy_to_clipboard(app, output);
+        }
+        Action::StopForeground => {
+            commands::stop_foreground(app, msg_tx, None, StopSignal::Interrupt).await?;
+        }
     }
+
+    Ok(CommandResult::Continue)
 }
 
-/// Main client loop
-async fn run_client_loop(stream: UnixStream) -> Result<()> {
-    let (mut reader, mut writer) = stream.into_split();
+/// Act on a submitted input line: channel text, `#channel` switches or `:commands`
+async fn submit_input(
+    input_content: &str,
+    app: &mut App,
+    msg_tx: &mpsc::Sender<ClientMessage>,
+) -> Result<CommandResult> {
+    if let Some(recording) = app.recording.as_mut() {
+        recording.record(input_content);
+    }
+
+    match parse_input_with_aliases(input_content, &app.config.aliases) {
+        Ok(ParsedInput::Text(text)) => {
+            let mut data = text.into_bytes();
+            data.push(b'\n');
+            msg_tx.send(ClientMessage::Input { data }).await?;
+        }
+        Ok(ParsedInput::SwitchChannel(name)) => {
+            msg_tx.send(ClientMessage::SwitchChannel { name }).await?;
+        }
+        Ok(ParsedInput::SendToChannel { channel, command }) => {
+            msg_tx
+                .send(ClientMessage::InputTo {
+                    channel,
+                    data: format!("{}\n", command).into_bytes(),
+                })
+                .await?;
+        }
+        Ok(ParsedInput::ControlCommand { command, args }) => {
+            return handle_control_command(&command, args, app, msg_tx, input_content).await;
+        }
+        Err(_) => {} // Ignore parse errors for now
+    }
+    Ok(CommandResult::Continue)
+}
 
-    // 1. Handshake
+/// Handle a key while the command palette is open
+async fn handle_palette_key(
+    key: KeyEvent,
+    app: &mut App,
+    msg_tx: &mpsc::Sender<ClientMessage>,
+) -> Result<CommandResult> {
+    let Some(palette) = app.palette.as_mut() else {
+        return Ok(CommandResult::Continue);
+    };
+
+    match key.code {
+        KeyCode::Esc => app.palette = None,
+        KeyCode::Up => palette.select_prev(),
+        KeyCode::Down | KeyCode::Tab => palette.select_next(),
+        KeyCode::Backspace => palette.pop_char(),
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => app.palette = None,
+        KeyCode::Char(c)
+            if !key
+                .modifiers
+                .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+        {
+            palette.push_char(c)
+        }
+        KeyCode::Enter => {
+            let action = palette.selected_entry().map(|entry| entry.action.clone());
+            app.palette = None;
+            match action {
+                Some(PaletteAction::Run(text)) => return submit_input(&text, app, msg_tx).await,
+                Some(PaletteAction::Insert(text)) => app.line_editor.set(&text),
+                None => {}
+            }
+        }
+        _ => {}
+    }
+    Ok(CommandResult::Continue)
+}
+
+/// `:ps` output: one process a line, indented under its parent
+fn process_lines(processes: &[ProcessInfo]) -> Vec<String> {
+    let mut lines = vec![format!("{:>7} S COMMAND", "PID")];
+    for process in processes {
+        let branch = match process.depth {
+            0 => String::new(),
+            depth => format!("{}└ ", "  ".repeat(depth - 1)),
+        };
+        lines.push(format!(
+            "{:>7} {} {}{}",
+            process.pid, process.state, branch, process.command
+        ));
+    }
+    lines
+}
+
+/// `:status` lines for a scheduled channel: when it runs next, then its
+/// latest runs, newest first
+fn schedule_lines(schedule: &ScheduleInfo) -> Vec<String> {
+    let time = |millis: i64| {
+        DateTime::from_timestamp_millis(millis).map(|time| time.with_timezone(&Local))
+    };
+    let mut lines = vec![match schedule.next_run.and_then(time) {
+        Some(next) => format!(
+            "  {}, next run {}",
+            schedule.description,
+            next.format("%Y-%m-%d %H:%M:%S")
+        ),
+        None => format!("  {}, no further runs", schedule.description),
+    }];
+    for run in schedule.runs.iter().rev() {
+        let Some(started) = time(run.started_at) else {
+            continue;
+        };
+        let outcome = match (run.finished_at, run.exit_code) {
+            (None, _) => "running".to_string(),
+            (Some(finished), code) => format!(
+                "{} after {}",
+                match code {
+                    Some(0) => "✓".to_string(),
+                    Some(code) => format!("✗ {}", code),
+                    None => "killed".to_string(),
+                },
+                timestamps::duration((finished - run.started_at) / 1000)
+            ),
+        };
+        lines.push(format!(
+            "  run {}  {}",
+            started.format("%Y-%m-%d %H:%M:%S"),
+            outcome
+        ));
+    }
+    lines
+}
+
+/// Handle a key while the sidebar has focus. Returns false for keys it
+/// leaves to the input line.
+async fn handle_sidebar_key(
+    key: KeyEvent,
+    app: &mut App,
+    msg_tx: &mpsc::Sender<ClientMessage>,
+) -> Result<bool> {
+    let count = app.channels.len();
+    let Some(sidebar) = app.sidebar.as_mut() else {
+        return Ok(false);
+    };
+
+    match key.code {
+        KeyCode::Up => sidebar.select_prev(count),
+        KeyCode::Down => sidebar.select_next(count),
+        KeyCode::Esc => sidebar.focused = false,
+        KeyCode::Enter => {
+            sidebar.focused = false;
+            if let Some(channel) = app.channels.get(sidebar.selected) {
+                msg_tx
+                    .send(ClientMessage::SwitchChannel {
+                        name: channel.name.clone(),
+                    })
+                    .await?;
+            }
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Main client loop
+async fn run_client_loop(
+    mut connector: Connector,
+    (reader, mut writer): (BoxedReader, BoxedWriter),
+    profile: Option<Profile>,
+    options: AttachOptions,
+) -> Result<()> {
+    // 1. Handshake; the identity lets the server restore our view after a detach
     let hello = ClientMessage::Hello {
-        protocol_version: 1,
+        protocol_version: crate::protocol::PROTOCOL_VERSION,
+        token: None,
+        identity: client_identity(),
+        capabilities: CAPABILITIES.to_vec(),
+        observe: options.observe,
+        name: options.name.clone(),
     };
     write_message(&mut writer, &crate::protocol::serialize(&hello)?).await?;
 
@@ -326,16 +1123,25 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
     // Load config
     let config = Config::load()?;
 
-    // Notification settings
-    let notify_bell = config.notifications.bell;
-    let notify_title = config.notifications.title_update;
-    let notify_cooldown = std::time::Duration::from_secs(config.notifications.cooldown_seconds);
     let mut last_notification: HashMap<String, std::time::Instant> = HashMap::new();
+    let mut config_mtime = config_modified_time();
+    let mut config_poll = tokio::time::interval(CONFIG_POLL_INTERVAL);
+    let mut macro_tick = tokio::time::interval(MACRO_TICK_INTERVAL);
+    let mut ping_tick = tokio::time::interval(PING_INTERVAL);
+    let mut spinner_tick = tokio::time::interval(SPINNER_INTERVAL);
+    let mut idle_redraw = tokio::time::interval(IDLE_REDRAW_INTERVAL);
+    let mut usage_tick = tokio::time::interval(USAGE_POLL_INTERVAL);
+    let mut next_frame = tokio::time::Instant::now();
+    let mut ping_nonce = 0;
 
     // Channels
     let (input_tx, mut input_rx) = mpsc::channel(100);
     let (server_tx, mut server_rx) = mpsc::channel(100);
     let (msg_tx, mut msg_rx) = mpsc::channel(100);
+    // Connection generation reported when a connection drops, so a late
+    // report about an old connection is ignored
+    let (lost_tx, mut lost_rx) = mpsc::channel::<u64>(4);
+    let mut generation = 0;
 
     // Input thread
     std::thread::spawn(move || loop {
@@ -347,39 +1153,35 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
     });
 
     // Server read task
-    tokio::spawn(async move {
-        loop {
-            match read_message(&mut reader).await {
-                Ok(Some(data)) => match crate::protocol::deserialize::<ServerMessage>(&data) {
-                    Ok(msg) => {
-                        if server_tx.send(msg).await.is_err() {
-                            break;
-                        }
-                    }
-                    Err(_e) => {
-                        tracing::error!("Failed to deserialize: {}", _e);
-                    }
-                },
-                Ok(None) => break, // EOF
-                Err(e) => {
-                    tracing::error!("Connection error: {}", e);
-                    break;
-                }
-            }
-        }
-    });
+    spawn_server_reader(reader, server_tx.clone(), lost_tx.clone(), generation);
 
-    // Request channel list
+    // Request channel list and the session's shared command history
     msg_tx.send(ClientMessage::ListChannels).await?;
+    msg_tx
+        .send(ClientMessage::GetHistory { channel: None })
+        .await?;
 
     // App State
     let mut app = App::new();
-    app.show_channel_numbers = config.appearance.show_channel_numbers;
-    // app.line_wrap = config.appearance.line_wrap; // If we support line wrap toggle
+    app.apply_config(config);
+    app.attach = options;
+
+    if let Some(profile) = &profile {
+        for msg in profile_messages(profile) {
+            msg_tx.send(msg).await?;
+        }
+        match profile.layout {
+            ProfileLayout::Channel => {}
+            ProfileLayout::All => app.view_mode = ViewMode::AllChannels,
+            ProfileLayout::Grid => app.view_mode = ViewMode::Grid,
+        }
+    }
 
     let mut history: HashMap<String, CommandHistory> = HashMap::new();
     let mut should_exit = false;
-    let mut line_buffers: HashMap<String, String> = HashMap::new();
+    // Partial lines, with when each one started
+    let mut line_buffers: HashMap<(String, OutputStream), (String, DateTime<Local>)> =
+        HashMap::new();
 
     // Send initial resize
     if let Ok(size) = terminal.size() {
@@ -392,29 +1194,132 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
     }
 
     loop {
-        // Draw UI
-        terminal.draw(|f| ui::draw(f, &mut app))?;
-
-        // Set title
-        if notify_title {
-            let title = if let Some(active) = &app.active_channel {
-                format!("nexus: #{}", active)
-            } else {
-                "nexus".to_string()
-            };
-            let _ = execute!(std::io::stdout(), crossterm::terminal::SetTitle(title));
+        // Draw only when something changed, and at most once a frame
+        if app.dirty && tokio::time::Instant::now() >= next_frame {
+            terminal.draw(|f| ui::draw(f, &mut app))?;
+            app.dirty = false;
+            next_frame = tokio::time::Instant::now() + app.frame_interval();
+
+            // Set title
+            if app.config.notifications.title_update {
+                let title = if let Some(active) = &app.active_channel {
+                    format!("nexus: #{}", active)
+                } else {
+                    "nexus".to_string()
+                };
+                let _ = execute!(std::io::stdout(), crossterm::terminal::SetTitle(title));
+            }
         }
 
         tokio::select! {
             Some(msg) = server_rx.recv() => {
+                app.dirty = true;
                 match msg {
-                    ServerMessage::Welcome { .. } => {}, // Ignore
+                    ServerMessage::Welcome { session_id, resume_token, capabilities, .. } => {
+                        // A different session (the server restarted) numbers
+                        // its output afresh, so what we have can't be resumed
+                        if app.session_id.is_some_and(|id| id != session_id) {
+                            app.output_seqs.clear();
+                            app.clear_output();
+                            line_buffers.clear();
+                        }
+                        app.session_id = Some(session_id);
+                        app.resume_token = resume_token;
+                        app.server_capabilities = capabilities;
+                        if let Some(request) = app.notification_request() {
+                            msg_tx.send(request).await?;
+                        }
+                        // Session name and client count for the status bar
+                        msg_tx.send(ClientMessage::GetSessionInfo).await?;
+                        if app.shares_marks() {
+                            msg_tx.send(ClientMessage::ListMarks).await?;
+                        }
+                    },
+                    ServerMessage::Pong { nonce } => {
+                        app.pending_ping.take_if(|ping| ping.nonce == nonce);
+                    },
+                    ServerMessage::Resumed { restored } => {
+                        if let Some(msg) = after_resume(&app, restored) {
+                            msg_tx.send(msg).await?;
+                        }
+                    },
+                    ServerMessage::SessionInfo { info, uptime_secs } => {
+                        app.session_name = Some(info.name.clone());
+                        app.client_count = Some(info.client_count);
+                        if !std::mem::take(&mut app.info_requested) {
+                            continue;
+                        }
+                        app.add_output("SYSTEM".to_string(), format!(
+                            "Session '{}' ({}): up {}, {} client(s), {} channel(s), nexus-server {}",
+                            info.name,
+                            info.id,
+                            format_uptime(uptime_secs),
+                            info.client_count,
+                            info.channel_count,
+                            if info.server_version.is_empty() { "unknown" } else { &info.server_version },
+                        ));
+                    },
+                    ServerMessage::ClientList { clients } => {
+                        app.client_count = Some(clients.len());
+                        app.add_output("SYSTEM".to_string(), format!("{} client(s) attached:", clients.len()));
+                        for client in clients {
+                            let mut details = vec![format!("attached {}", format_uptime(client.attached_secs))];
+                            if let Some(identity) = client.identity.as_ref().filter(|_| client.name.is_some()) {
+                                details.push(format!("as {}", identity));
+                            }
+                            if client.observer {
+                                details.push("observing".to_string());
+                            }
+                            if client.is_self {
+                                details.push("this client".to_string());
+                            }
+                            app.add_output("SYSTEM".to_string(), format!("  {} ({})", client.label(), details.join(", ")));
+                        }
+                    },
+                    ServerMessage::ClientJoined { client } => {
+                        app.client_count = app.client_count.map(|count| count + 1);
+                        let how = if client.observer { "started observing" } else { "attached" };
+                        app.add_output("SYSTEM".to_string(), format!("{} {}", client.label(), how));
+                    },
+                    ServerMessage::ClientLeft { client } => {
+                        app.client_count = app.client_count.map(|count| count.saturating_sub(1));
+                        app.add_output("SYSTEM".to_string(), format!("{} detached", client.label()));
+                    },
+                    ServerMessage::Wall { from, message } => {
+                        app.add_output("SYSTEM".to_string(), format!("[wall] {}: {}", from, message));
+                    },
+                    ServerMessage::PinnedChanged { channels } => app.set_pinned(channels),
+                    ServerMessage::MarkChanged { channel, name, seq } if app.shares_marks() => {
+                        app.shared_mark(channel, name, seq);
+                    },
+                    ServerMessage::Processes { channel, processes } => {
+                        app.info = Some(InfoPopup {
+                            title: format!("Processes in #{}", channel),
+                            lines: process_lines(&processes),
+                            scroll: 0,
+                        });
+                    }
+                    ServerMessage::Marks { marks } if app.shares_marks() => {
+                        for mark in marks {
+                            app.shared_mark(mark.channel, mark.name, Some(mark.seq));
+                        }
+                    },
+                    ServerMessage::Notification { message, .. } => {
+                        // Asked for explicitly, so it rings whatever `bell` says
+                        print!("\x07");
+                        app.add_output("SYSTEM".to_string(), format!("[notify] {}", message));
+                    },
                     ServerMessage::Status { channels: status } => {
-                        if status.is_empty() {
+                        for s in &status {
+                            app.usage.insert(s.name.clone(), (s.cpu_percent, s.rss_bytes));
+                        }
+                        if app.pending_usage_polls > 0 {
+                            app.pending_usage_polls -= 1;
+                        } else if status.is_empty() {
                             app.add_output("SYSTEM".to_string(), "No status available.".to_string());
                         } else {
                             for s in status {
-                                app.add_output("SYSTEM".to_string(), format!(
+                                let mut line = format!(
                                     "#{} {} pid={:?} exit={:?} cwd={} cmd={}",
                                     s.name,
                                     if s.running { "running" } else { "stopped" },
@@ -422,17 +1327,34 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
                                     s.exit_code,
                                     s.working_dir,
                                     s.command
-                                ));
+                                );
+                                if let Some(usage) = ui::usage_label(s.cpu_percent, s.rss_bytes) {
+                                    line.push_str(&format!(" ({})", usage));
+                                }
+                                app.add_output("SYSTEM".to_string(), line);
+                                for line in s.schedule.iter().flat_map(schedule_lines) {
+                                    app.add_output("SYSTEM".to_string(), line);
+                                }
                             }
                         }
                     },
-                    ServerMessage::Output { channel, data, .. } => {
+                    ServerMessage::Output { channel, data, timestamp, stream, seq } => {
+                        // Skip chunks we already have, e.g. replayed after a reconnect
+                        if seq != 0 {
+                            if app.output_seqs.get(&channel).is_some_and(|&last| seq <= last) {
+                                continue;
+                            }
+                            app.output_seqs.insert(channel.clone(), seq);
+                        }
                         let is_background = Some(channel.as_str()) != app.active_channel.as_deref();
                         if let Some(c) = app.channels.iter_mut().find(|c| c.name == channel) {
+                            // Output means it's up, even if Ready is still on its way
+                            c.starting = false;
                             if is_background {
                                 c.has_new_output = true;
 
                                 let now = std::time::Instant::now();
+                                let notify_cooldown = Duration::from_secs(app.config.notifications.cooldown_seconds);
                                 let should_notify = last_notification
                                     .get(&channel)
                                     .map(|&last| now.duration_since(last) >= notify_cooldown)
@@ -440,7 +1362,7 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
 
                                 if should_notify {
                                     last_notification.insert(channel.clone(), now);
-                                    if notify_bell {
+                                    if app.config.notifications.bell {
                                         // Bell
                                         print!("\x07");
                                     }
@@ -448,17 +1370,25 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
                             }
                         }
 
+                        app.raw_output.entry(channel.clone()).or_default().push(&data);
+                        if hexdump::looks_binary(&data) && app.binary_hinted.insert(channel.clone()) {
+                            app.add_output(
+                                "SYSTEM".to_string(),
+                                format!("#{} emitted binary output; :view hex shows it as a hexdump", channel),
+                            );
+                        }
                         let text = String::from_utf8_lossy(&data);
                         if !text.is_empty() {
-                            let buffer = line_buffers.entry(channel.clone()).or_default();
-                            buffer.push_str(&text);
-
-                            while let Some(newline_pos) = buffer.find('\n') {
-                                let line = buffer[..newline_pos].to_string();
-                                *buffer = buffer[newline_pos + 1..].to_string();
-                                let clean_line = line.trim_end_matches('\r').to_string();
-                                // We don't strip ANSI here, let UI handle it
-                                app.add_output(channel.clone(), clean_line);
+                            // Buffer partial lines per stream so stdout and stderr don't splice
+                            // Lines are stamped with when the server saw them start,
+                            // so replayed output keeps its original times
+                            let received = timestamps::local_time(timestamp);
+                            let pending = line_buffers
+                                .entry((channel.clone(), stream))
+                                .or_insert_with(|| (String::new(), received));
+                            // We don't strip ANSI here, let UI handle it
+                            for (line, started) in timestamps::split_lines(pending, &text, received, app.max_buffer_bytes) {
+                                app.add_stream_output(channel.clone(), line, stream, seq, started);
                             }
                         }
                     },
@@ -466,30 +1396,45 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
                         let active_from_server = list.iter().find(|info| info.is_active).map(|info| info.name.clone());
                         app.subscriptions = list.iter().filter(|info| info.is_subscribed).map(|info| info.name.clone()).collect();
 
+                        let pinned = list.iter().filter(|info| info.is_pinned).map(|info| info.name.clone()).collect();
                         app.channels = list.into_iter().map(|info| ChannelInfo {
                             name: info.name,
                             running: info.running,
+                            starting: info.is_starting,
                             has_new_output: false,
                             exit_code: None,
+                            container: info.container,
+                            started_at: DateTime::from_timestamp_millis(info.started_at)
+                                .filter(|_| info.started_at > 0)
+                                .map(|time| time.with_timezone(&Local)),
+                            foreground: info.foreground,
                         }).collect();
+                        app.set_pinned(pinned);
 
                         if let Some(active) = active_from_server {
                             app.active_channel = Some(active);
-                        } else if app.active_channel.is_none() {
-                             if let Some(c) = app.channels.first() {
-                                 app.active_channel = Some(c.name.clone());
-                             }
+                        } else if !app.active_channel.as_ref().is_some_and(|active| app.channels.iter().any(|c| &c.name == active)) {
+                             // Nothing active yet, or our channel is gone (e.g.
+                             // after reconnecting to a restarted server)
+                             app.active_channel = app.channels.first().map(|c| c.name.clone());
                         }
                     },
                     ServerMessage::Event(event) => {
                          match event {
-                            ChannelEvent::Created { name } => {
+                            ChannelEvent::Created { name, container } => {
                                 app.channels.push(ChannelInfo {
                                     name: name.clone(),
                                     running: true,
+                                    // Unless its first output beat this event here
+                                    starting: !app.channel_buffers.contains_key(&name),
                                     has_new_output: false,
                                     exit_code: None,
+                                    container,
+                                    started_at: Some(Local::now()),
+                                    foreground: None,
                                 });
+                                // A channel recreated under a pinned name stays pinned
+                                app.order_channels();
                                 if app.active_channel.is_none() {
                                     app.active_channel = Some(name);
                                 }
@@ -498,12 +1443,50 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
                                 if let Some(c) = app.channels.iter_mut().find(|c| c.name == name) {
                                     c.running = false;
                                     c.exit_code = exit_code;
+                                    c.foreground = None;
                                 }
+                                close_finished_popup(&mut app, &name, &msg_tx).await?;
+                                finish_scratch_run(&mut app, &name, exit_code, false, &msg_tx).await?;
                             }
                             ChannelEvent::Killed { name } => {
                                 if let Some(c) = app.channels.iter_mut().find(|c| c.name == name) {
                                     c.running = false;
                                     c.exit_code = None;
+                                    c.foreground = None;
+                                }
+                                close_finished_popup(&mut app, &name, &msg_tx).await?;
+                                finish_scratch_run(&mut app, &name, None, true, &msg_tx).await?;
+                            }
+                            ChannelEvent::TimedOut { name, timeout_secs } => {
+                                if let Some(c) = app.channels.iter_mut().find(|c| c.name == name) {
+                                    c.running = false;
+                                    c.exit_code = None;
+                                    c.foreground = None;
+                                }
+                                app.add_output("SYSTEM".to_string(), format!(
+                                    "#{} timed out after {} and was killed",
+                                    name,
+                                    duration::format(timeout_secs)
+                                ));
+                            }
+                            ChannelEvent::Restarted { name } => {
+                                if let Some(c) = app.channels.iter_mut().find(|c| c.name == name) {
+                                    c.running = true;
+                                    c.starting = true;
+                                    c.exit_code = None;
+                                    c.started_at = Some(Local::now());
+                                    c.foreground = None;
+                                }
+                                app.add_output("SYSTEM".to_string(), format!("#{} restarted", name));
+                            }
+                            ChannelEvent::Foreground { name, command } => {
+                                if let Some(c) = app.channels.iter_mut().find(|c| c.name == name) {
+                                    c.foreground = command;
+                                }
+                            }
+                            ChannelEvent::Ready { name } => {
+                                if let Some(c) = app.channels.iter_mut().find(|c| c.name == name) {
+                                    c.starting = false;
                                 }
                             }
                             ChannelEvent::ActiveChanged { name } => {
@@ -523,111 +1506,152 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
                             }
                         }
                     },
-                    ServerMessage::Error { message } => {
+                    ServerMessage::History { histories } => {
+                        for entry in histories {
+                            history
+                                .entry(entry.channel)
+                                .or_insert_with(|| CommandHistory::new(1000))
+                                .load(entry.entries);
+                        }
+                    },
+                    ServerMessage::HistoryAppended { channel, entry } => {
+                        history
+                            .entry(channel)
+                            .or_insert_with(|| CommandHistory::new(1000))
+                            .add(&entry);
+                    },
+                    ServerMessage::PathCompletions { partial, candidates } => {
+                        apply_path_completions(&mut app, &partial, candidates);
+                    },
+                    ServerMessage::ScriptResult { message: Some(message), .. } => {
+                            app.add_output("SYSTEM".to_string(), message);
+                    },
+                    ServerMessage::Error { message, .. } => {
                         app.add_output("SYSTEM".to_string(), format!("Error: {}", message));
                     },
+                    ServerMessage::SessionRenamed { name } => {
+                        connector.renamed(&name);
+                        app.session_name = Some(name.clone());
+                        app.add_output("SYSTEM".to_string(), format!("Session renamed to '{}'", name));
+                    },
                     _ => {} // Ignore other server messages
                 }
             },
 
             Some(event) = input_rx.recv() => {
+                app.dirty = true;
                 match event {
                     Event::Resize(cols, rows) => {
                         msg_tx.send(ClientMessage::Resize { cols, rows }).await?;
                         terminal.autoresize()?;
                     },
                     Event::Mouse(mouse_event) => {
-                        // TODO: Implement mouse clicking on channel tabs if possible
-                        // For now we just ignore or maybe handle scrolling
-                         match mouse_event.kind {
+                        let tab = app
+                            .channel_tab_at(mouse_event.column, mouse_event.row)
+                            .map(str::to_string);
+                        match mouse_event.kind {
                             MouseEventKind::ScrollUp => {
-                                app.scroll_up(3);
+                                app.scroll_wheel(mouse_event.column, mouse_event.row, true);
                             }
                             MouseEventKind::ScrollDown => {
-                                app.scroll_down(3);
+                                app.scroll_wheel(mouse_event.column, mouse_event.row, false);
+                            }
+                            MouseEventKind::Down(MouseButton::Left) => match tab {
+                                Some(name) => msg_tx.send(ClientMessage::SwitchChannel { name }).await?,
+                                None => app.start_selection(mouse_event.column, mouse_event.row),
+                            },
+                            MouseEventKind::Down(MouseButton::Middle) => {
+                                if let Some(name) = tab {
+                                    msg_tx.send(ClientMessage::KillChannel { name }).await?;
+                                }
+                            }
+                            MouseEventKind::Down(MouseButton::Right) => {
+                                if let Some(name) = tab {
+                                    app.palette = Some(Palette::for_channel(&app, &name));
+                                }
+                            }
+                            MouseEventKind::Drag(MouseButton::Left) => {
+                                app.extend_selection(mouse_event.column, mouse_event.row);
+                            }
+                            MouseEventKind::Up(MouseButton::Left) => {
+                                if let Some(text) = app.finish_selection() {
+                                    if let Err(e) = app.clipboard.copy(&text) {
+                                        app.add_output("SYSTEM".to_string(), format!("Copy failed: {}", e));
+                                    }
+                                }
                             }
                             _ => {} // Ignore other mouse events
                         }
                     },
                     Event::Key(key) => {
-                        if app.line_editor.is_empty() && handle_scroll_keys(&key, &mut app) {
+                        let channel_key = app.active_channel.clone().unwrap_or_default();
+
+                        if let Some(info) = app.info.as_mut() {
+                            match key.code {
+                                KeyCode::Up => info.scroll_by(-1),
+                                KeyCode::Down => info.scroll_by(1),
+                                KeyCode::PageUp => info.scroll_by(-10),
+                                KeyCode::PageDown => info.scroll_by(10),
+                                KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') => app.info = None,
+                                _ => {}
+                            }
                             continue;
                         }
 
-                        let channel_key = app.active_channel.clone().unwrap_or_default();
+                        if app.palette.is_some() {
+                            if let CommandResult::Exit = handle_palette_key(key, &mut app, &msg_tx).await? {
+                                should_exit = true;
+                            }
+                            continue;
+                        }
+
+                        if app.sidebar.as_ref().is_some_and(|s| s.focused)
+                            && handle_sidebar_key(key, &mut app, &msg_tx).await?
+                        {
+                            continue;
+                        }
+
+                        if app.prefix_pending {
+                            app.prefix_pending = false;
+                            if let Some(action) = app.keymap.resolve_prefixed(&key) {
+                                if let CommandResult::Exit =
+                                    run_key_action(action, &mut app, &msg_tx, &mut history, &channel_key).await?
+                                {
+                                    should_exit = true;
+                                }
+                            }
+                            continue;
+                        }
+
+                        if app.keymap.is_prefix(&key) {
+                            app.prefix_pending = true;
+                            continue;
+                        }
+
+                        if let Some(action) = app.keymap.resolve(&key, app.line_editor.is_empty()) {
+                            if let CommandResult::Exit =
+                                run_key_action(action, &mut app, &msg_tx, &mut history, &channel_key).await?
+                            {
+                                should_exit = true;
+                            }
+                            continue;
+                        }
 
                         match key.code {
                             KeyCode::Char(c) => {
                                 app.completions = None;
-                                if key.modifiers.contains(KeyModifiers::ALT) {
-                                    if let Some(digit) = c.to_digit(10) {
-                                        if (1..=9).contains(&digit) {
-                                            let idx = (digit - 1) as usize;
-                                            if let Some(channel) = app.channels.get(idx) {
-                                                msg_tx.send(ClientMessage::SwitchChannel { name: channel.name.clone() }).await?;
-                                            }
-                                        }
-                                    }
-                                } else if key.modifiers.contains(KeyModifiers::CONTROL) {
-                                    match c {
-                                        'c' => {
-                                            if app.line_editor.is_empty() {
-                                                msg_tx.send(ClientMessage::Input { data: vec![3] }).await?;
-                                            } else {
-                                                app.line_editor.clear();
-                                                if let Some(h) = history.get_mut(&channel_key) { h.reset_position(); }
-                                            }
-                                        }
-                                        '\\' => should_exit = true,
-                                        'd' => {
-                                            if app.line_editor.is_empty() {
-                                                 msg_tx.send(ClientMessage::Input { data: vec![4] }).await?;
-                                            }
-                                        },
-                                        'a' => { app.line_editor.move_home(); },
-                                        'e' => { app.line_editor.move_end(); },
-                                        'w' => { app.line_editor.delete_word_backward(); },
-                                        'k' => { app.line_editor.delete_to_end(); },
-                                        'u' => {
-                                             if app.line_editor.is_empty() {
-                                                 app.scroll_up(10);
-                                             } else {
-                                                 app.line_editor.delete_to_start();
-                                             }
-                                        },
-                                        'b' => { app.scroll_down(10); },
-                                        _ => {} // Ignore other control chars
-                                    }
-                                } else {
+                                // Unbound modifier combinations are ignored rather than typed
+                                if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
                                     app.line_editor.insert(c);
-                                    let active = app.active_channel.clone();
-                                    app.scroll_to_bottom(active.as_deref());
+                                    app.scroll_view_to_bottom();
                                     if let Some(h) = history.get_mut(&channel_key) { h.reset_position(); }
                                 }
                             }
+                            KeyCode::Esc => app.end_search(),
                             KeyCode::Backspace => { app.line_editor.backspace(); },
                             KeyCode::Delete => { app.line_editor.delete(); },
-                            KeyCode::Left => {
-                                if key.modifiers.contains(KeyModifiers::CONTROL) {
-                                    // Switch channel
-                                    app.prev_channel();
-                                    if let Some(ch) = &app.active_channel {
-                                        msg_tx.send(ClientMessage::SwitchChannel { name: ch.clone() }).await?;
-                                    }
-                                } else {
-                                    app.line_editor.move_left();
-                                }
-                            },
-                            KeyCode::Right => {
-                                if key.modifiers.contains(KeyModifiers::CONTROL) {
-                                    app.next_channel();
-                                     if let Some(ch) = &app.active_channel {
-                                        msg_tx.send(ClientMessage::SwitchChannel { name: ch.clone() }).await?;
-                                    }
-                                } else {
-                                    app.line_editor.move_right();
-                                }
-                            },
+                            KeyCode::Left => { app.line_editor.move_left(); },
+                            KeyCode::Right => { app.line_editor.move_right(); },
                             KeyCode::Up => {
                                 let h = history.entry(channel_key.clone()).or_insert_with(|| CommandHistory::new(1000));
                                 if let Some(cmd) = h.up(app.line_editor.content()) {
@@ -644,36 +1668,14 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
                                 let input_content = app.line_editor.take();
                                 if !input_content.is_empty() {
                                     history.entry(channel_key.clone()).or_insert_with(|| CommandHistory::new(1000)).add(&input_content);
+                                    msg_tx.send(ClientMessage::AppendHistory {
+                                        channel: channel_key.clone(),
+                                        entry: input_content.clone(),
+                                    }).await?;
                                 }
 
-                                match parse_input(&input_content) {
-                                    Ok(ParsedInput::Text(text)) => {
-                                        let mut data = text.into_bytes();
-                                        data.push(b'\n');
-                                        msg_tx.send(ClientMessage::Input { data }).await?;
-                                    }
-                                    Ok(ParsedInput::SwitchChannel(name)) => {
-                                        msg_tx.send(ClientMessage::SwitchChannel { name }).await?;
-                                    }
-                                    Ok(ParsedInput::SendToChannel { channel, command }) => {
-                                        msg_tx.send(ClientMessage::InputTo {
-                                            channel,
-                                            data: format!("{}\n", command).into_bytes()
-                                        }).await?;
-                                    }
-                                    Ok(ParsedInput::ControlCommand { command, args }) => {
-                                        match handle_control_command(
-                                            &command,
-                                            args,
-                                            &mut app,
-                                            &msg_tx,
-                                            &input_content
-                                        ).await? {
-                                            CommandResult::Exit => should_exit = true,
-                                            CommandResult::Continue => {} // Do nothing
-                                        }
-                                    }
-                                    Err(_) => {} // Ignore parse errors for now
+                                if let CommandResult::Exit = submit_input(&input_content, &mut app, &msg_tx).await? {
+                                    should_exit = true;
                                 }
                             },
                             _ => {} // Ignore other key events
@@ -683,11 +1685,99 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
                 }
             },
 
+            _ = config_poll.tick() => {
+                let mtime = config_modified_time();
+                if mtime != config_mtime {
+                    config_mtime = mtime;
+                    app.dirty = true;
+                    if mtime.is_some() {
+                        reload_config(&mut app, &msg_tx).await?;
+                    }
+                }
+            }
+
+            _ = spinner_tick.tick(), if app.channels.iter().any(|c| c.is_starting()) => {
+                app.dirty = true;
+            }
+            _ = idle_redraw.tick() => app.dirty = true,
+            // A redraw held back to keep to the frame rate
+            _ = tokio::time::sleep_until(next_frame), if app.dirty => {}
+            _ = macro_tick.tick(), if app.playback.is_some() => {
+                app.dirty = true;
+                let now = std::time::Instant::now();
+                while let Some(step) = app.playback.as_mut().and_then(|p| p.next_due(now)) {
+                    if let CommandResult::Exit = submit_input(&step, &mut app, &msg_tx).await? {
+                        should_exit = true;
+                        break;
+                    }
+                }
+                if let Some(playback) = app.playback.take_if(|p| p.is_finished()) {
+                    app.add_output("SYSTEM".to_string(), format!("Macro '{}' finished", playback.name));
+                }
+            }
+
+            // One ping in flight at a time; the status bar flags a server
+            // that leaves it unanswered
+            _ = ping_tick.tick(), if app.disconnected.is_none()
+                && app.pending_ping.is_none()
+                && app.server_supports(Capability::Heartbeat) => {
+                app.dirty = true;
+                ping_nonce += 1;
+                app.pending_ping = Some(PendingPing { nonce: ping_nonce, sent_at: std::time::Instant::now() });
+                msg_tx.send(ClientMessage::Ping { nonce: ping_nonce }).await?;
+            }
+
+            _ = usage_tick.tick(), if app.disconnected.is_none()
+                && app.config.appearance.status_segments.usage => {
+                if let Some(channel) = app.active_channel.clone() {
+                    app.pending_usage_polls += 1;
+                    msg_tx.send(ClientMessage::GetStatus { channel: Some(channel) }).await?;
+                }
+            }
+
             Some(msg) = msg_rx.recv() => {
-                 let bytes = crate::protocol::serialize(&msg)?;
-                 if write_message(&mut writer, &bytes).await.is_err() {
-                     break;
-                 }
+                if app.disconnected.is_some() {
+                    app.dirty = true;
+                    if matches!(msg, ClientMessage::Input { .. } | ClientMessage::InputTo { .. }) {
+                        app.add_output("SYSTEM".to_string(), "Not connected to the server; input not sent".to_string());
+                    }
+                    continue;
+                }
+                let bytes = crate::protocol::serialize(&msg)?;
+                if write_message(&mut writer, &bytes).await.is_err() {
+                    app.disconnected = Some(Reconnect::new());
+                    app.dirty = true;
+                }
+            }
+
+            Some(lost) = lost_rx.recv() => {
+                if lost == generation && app.disconnected.is_none() {
+                    app.disconnected = Some(Reconnect::new());
+                    app.dirty = true;
+                }
+            }
+
+            _ = tokio::time::sleep_until(
+                app.disconnected.as_ref().map_or_else(tokio::time::Instant::now, |r| r.retry_at)
+            ), if app.disconnected.is_some() => {
+                app.dirty = true;
+                match reconnect(&connector, &app, terminal.size().ok()).await {
+                    Ok((reader, new_writer)) => {
+                        generation += 1;
+                        spawn_server_reader(reader, server_tx.clone(), lost_tx.clone(), generation);
+                        writer = new_writer;
+                        app.disconnected = None;
+                        app.pending_ping = None;
+                        app.pending_usage_polls = 0;
+                        app.add_output("SYSTEM".to_string(), "Reconnected to server".to_string());
+                    }
+                    Err(e) => {
+                        tracing::debug!("Reconnect failed: {:#}", e);
+                        if let Some(reconnect) = app.disconnected.as_mut() {
+                            reconnect.failed();
+                        }
+                    }
+                }
             }
 
             else => break, // All channels closed
@@ -698,6 +1788,16 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
         }
     }
 
+    // Deliver anything queued on the way out, such as a Detach
+    while let Ok(msg) = msg_rx.try_recv() {
+        let Ok(bytes) = crate::protocol::serialize(&msg) else {
+            break;
+        };
+        if write_message(&mut writer, &bytes).await.is_err() {
+            break;
+        }
+    }
+
     disable_raw_mode()?;
     execute!(
         terminal.backend_mut(),
@@ -708,3 +1808,157 @@ async fn run_client_loop(stream: UnixStream) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ServerListener;
+    use tempfile::tempdir;
+
+    /// Run a server for session `name` on `socket_path` with a `cat` channel
+    /// per entry in `channels`
+    async fn start_server(
+        name: &str,
+        socket_path: &Path,
+        channels: &[&str],
+    ) -> (mpsc::Sender<()>, tokio::task::JoinHandle<Result<()>>) {
+        let server = ServerListener::new(name.to_string(), socket_path.to_path_buf());
+        let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+        let handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+        let admin = loop {
+            match NexusClient::connect(socket_path).await {
+                Ok(client) => break client,
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        };
+        for channel in channels {
+            admin.create_channel(channel, Some("cat")).await.unwrap();
+        }
+        admin.detach().await.unwrap();
+        (shutdown_tx, handle)
+    }
+
+    /// Read messages until `done` accepts one, returning everything read
+    async fn read_until(
+        reader: &mut BoxedReader,
+        done: impl Fn(&ServerMessage) -> bool,
+    ) -> Vec<ServerMessage> {
+        let mut messages = Vec::new();
+        let read = async {
+            while let Some(bytes) = read_message(reader).await.unwrap() {
+                let msg: ServerMessage = crate::protocol::deserialize(&bytes).unwrap();
+                let finished = done(&msg);
+                messages.push(msg);
+                if finished {
+                    return;
+                }
+            }
+            panic!("connection closed");
+        };
+        tokio::time::timeout(Duration::from_secs(10), read)
+            .await
+            .expect("expected message never arrived");
+        messages
+    }
+
+    #[tokio::test]
+    async fn reconnecting_to_a_restarted_server_restores_subscriptions() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("restart.sock");
+        if std::os::unix::net::UnixListener::bind(&socket_path).is_err() {
+            eprintln!("Skipping reconnect test: unix sockets not permitted in this environment");
+            return;
+        }
+        std::fs::remove_file(&socket_path).unwrap();
+        let connector = Connector::Local(socket_path.clone());
+
+        let (shutdown_tx, handle) = start_server("restart", &socket_path, &["api", "logs"]).await;
+        let mut app = App::new();
+        app.subscriptions = vec!["api".to_string(), "logs".to_string()];
+        let (mut reader, _writer) = reconnect(&connector, &app, None).await.unwrap();
+        let messages = read_until(&mut reader, |msg| {
+            matches!(
+                msg,
+                ServerMessage::Event(ChannelEvent::SubscriptionChanged { .. })
+            )
+        })
+        .await;
+        let Some(ServerMessage::Welcome { resume_token, .. }) = messages.first() else {
+            panic!("expected a welcome first, got {:?}", messages);
+        };
+        app.resume_token = resume_token.clone();
+        assert!(app.resume_token.is_some());
+
+        let _ = shutdown_tx.send(()).await;
+        let _ = tokio::time::timeout(Duration::from_secs(5), handle).await;
+
+        // The new server knows nothing of the old connection
+        let (shutdown_tx, handle) = start_server("restart", &socket_path, &["api", "logs"]).await;
+        let (mut reader, mut writer) = reconnect(&connector, &app, None).await.unwrap();
+        let messages = read_until(&mut reader, |msg| {
+            matches!(msg, ServerMessage::Resumed { .. })
+        })
+        .await;
+        assert!(matches!(
+            messages.last(),
+            Some(ServerMessage::Resumed { restored: false })
+        ));
+
+        let resubscribe = after_resume(&app, false).expect("should resubscribe");
+        assert!(after_resume(&app, true).is_none());
+        write_message(
+            &mut writer,
+            &crate::protocol::serialize(&resubscribe).unwrap(),
+        )
+        .await
+        .unwrap();
+        write_message(
+            &mut writer,
+            &crate::protocol::serialize(&ClientMessage::ListChannels).unwrap(),
+        )
+        .await
+        .unwrap();
+        let messages = read_until(&mut reader, |msg| match msg {
+            ServerMessage::ChannelList { channels } => channels.iter().all(|c| c.is_subscribed),
+            _ => false,
+        })
+        .await;
+        assert!(messages.iter().any(|msg| matches!(
+            msg,
+            ServerMessage::Event(ChannelEvent::SubscriptionChanged { subscribed })
+                if subscribed.len() == 2
+        )));
+
+        let _ = shutdown_tx.send(()).await;
+        let _ = tokio::time::timeout(Duration::from_secs(5), handle).await;
+    }
+
+    #[tokio::test]
+    async fn finished_popups_close_and_unsubscribe() {
+        let mut app = App::new();
+        app.active_channel = Some("api".to_string());
+        app.open_popup("popup-1".to_string(), "htop".to_string());
+        let (msg_tx, mut msg_rx) = mpsc::channel(8);
+
+        // Other channels exiting leave it open
+        close_finished_popup(&mut app, "api", &msg_tx)
+            .await
+            .unwrap();
+        assert!(app.popup.is_some());
+        assert!(msg_rx.try_recv().is_err());
+
+        close_finished_popup(&mut app, "popup-1", &msg_tx)
+            .await
+            .unwrap();
+        assert!(app.popup.is_none());
+        assert_eq!(app.active_channel.as_deref(), Some("api"));
+        assert!(matches!(
+            msg_rx.try_recv(),
+            Ok(ClientMessage::SwitchChannel { name }) if name == "api"
+        ));
+        assert!(matches!(
+            msg_rx.try_recv(),
+            Ok(ClientMessage::Unsubscribe { channels }) if channels == ["popup-1"]
+        ));
+    }
+}
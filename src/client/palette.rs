@@ -0,0 +1,390 @@
+//! Command palette - fuzzy-searchable commands, channel switches and recent input
+
+use crate::client::app::App;
+use crate::client::keybindings::Action;
+
+/// Maximum recent commands offered in the palette
+const MAX_RECENT: usize = 20;
+
+/// Commands offered in the palette: name, description, whether it needs arguments,
+/// and the key action it corresponds to (for hints)
+const PALETTE_COMMANDS: &[(&str, &str, bool, Option<Action>)] = &[
+    ("new", "Create a channel", true, None),
+    ("kill", "Kill a channel", true, None),
+    ("list", "List channels", false, None),
+    ("status", "Show channel status", false, None),
+    ("info", "Show session info", false, None),
+    ("clients", "List attached clients", false, None),
+    ("wall", "Send a note to every client", true, None),
+    ("sub", "Subscribe to channels", true, None),
+    ("unsub", "Unsubscribe from channels", true, None),
+    ("subs", "Show subscriptions", false, None),
+    ("clear", "Clear output", false, Some(Action::ClearScreen)),
+    (
+        "view",
+        "Toggle interleaved view",
+        false,
+        Some(Action::ToggleView),
+    ),
+    ("split", "Show a channel in a pane below", true, None),
+    ("vsplit", "Show a channel in a pane alongside", true, None),
+    ("close", "Close the focused pane", false, None),
+    ("only", "Close all other panes", false, None),
+    ("diff", "Compare two channels side by side", true, None),
+    (
+        "zoom",
+        "Maximize the focused pane",
+        false,
+        Some(Action::ToggleZoom),
+    ),
+    ("popup", "Run a command in a floating window", true, None),
+    (
+        "run",
+        "Run a one-shot command in a scratch channel",
+        true,
+        None,
+    ),
+    ("sidebar", "Show or hide the channel list", false, None),
+    (
+        "fold",
+        "Fold or unfold the output section in view",
+        false,
+        Some(Action::ToggleFold),
+    ),
+    ("ps", "Show the processes running in a channel", true, None),
+    (
+        "stop",
+        "Interrupt the command a channel is running",
+        true,
+        Some(Action::StopForeground),
+    ),
+    ("pin", "Pin a channel to the front", true, None),
+    ("unpin", "Unpin a channel", true, None),
+    (
+        "mark",
+        "Mark the line at the bottom of the view",
+        true,
+        None,
+    ),
+    ("jump", "Scroll back to a mark", true, None),
+    ("search", "Search the channel's output", true, None),
+    ("copy", "Copy the lines in view", true, None),
+    (
+        "paste",
+        "Insert the clipboard into the input line",
+        false,
+        None,
+    ),
+    ("timestamps", "Toggle timestamps", false, None),
+    ("reload", "Reload configuration", false, None),
+    ("set", "Show or change a setting", true, None),
+    ("keys", "List keybindings", false, None),
+    ("alias", "List or define aliases", true, None),
+    ("macro", "Record or play a macro", true, None),
+    ("help", "Show help", false, None),
+    (
+        "detach",
+        "Detach, leaving the session running",
+        false,
+        Some(Action::Detach),
+    ),
+    ("quit", "Exit nexus", false, Some(Action::Quit)),
+];
+
+/// What happens when a palette entry is chosen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteAction {
+    /// Submit this text as if typed and entered
+    Run(String),
+    /// Put this text in the input line for editing
+    Insert(String),
+}
+
+/// A single palette row
+#[derive(Debug, Clone)]
+pub struct PaletteEntry {
+    pub label: String,
+    pub description: String,
+    /// Keybinding that performs the same action, if any
+    pub hint: Option<String>,
+    pub action: PaletteAction,
+}
+
+/// Palette state while it is open
+#[derive(Debug, Clone)]
+pub struct Palette {
+    pub query: String,
+    pub selected: usize,
+    entries: Vec<PaletteEntry>,
+    /// Indices into `entries` that match `query`, best first
+    matches: Vec<usize>,
+}
+
+impl Palette {
+    /// Build the palette from the app state and the recent commands (oldest first)
+    pub fn new(app: &App, recent: &[String]) -> Self {
+        let hint = |action: Action| app.keymap.keys_for(action).into_iter().next();
+        let mut entries = Vec::new();
+
+        for (number, channel) in app.channels.iter().enumerate() {
+            entries.push(PaletteEntry {
+                label: format!("switch to #{}", channel.name),
+                description: if channel.running {
+                    "Channel".to_string()
+                } else {
+                    "Channel (exited)".to_string()
+                },
+                hint: hint(Action::SwitchToChannel(number + 1)),
+                action: PaletteAction::Run(format!("#{}", channel.name)),
+            });
+        }
+
+        for &(name, description, takes_args, action) in PALETTE_COMMANDS {
+            entries.push(PaletteEntry {
+                label: format!(":{}", name),
+                description: description.to_string(),
+                hint: action.and_then(hint),
+                action: if takes_args {
+                    PaletteAction::Insert(format!(":{} ", name))
+                } else {
+                    PaletteAction::Run(format!(":{}", name))
+                },
+            });
+        }
+
+        let mut seen = Vec::new();
+        for command in recent.iter().rev() {
+            if seen.len() >= MAX_RECENT {
+                break;
+            }
+            if seen.contains(command) {
+                continue;
+            }
+            seen.push(command.clone());
+            entries.push(PaletteEntry {
+                label: command.clone(),
+                description: "Recent".to_string(),
+                hint: None,
+                action: PaletteAction::Insert(command.clone()),
+            });
+        }
+
+        Self::with_entries(entries)
+    }
+
+    /// Actions on one channel, offered when its status bar tab is
+    /// right-clicked
+    pub fn for_channel(app: &App, name: &str) -> Self {
+        let entry = |label: String, description: &str, action: PaletteAction| PaletteEntry {
+            label,
+            description: description.to_string(),
+            hint: None,
+            action,
+        };
+        let pin = if app.is_pinned(name) { "unpin" } else { "pin" };
+        let entries = vec![
+            entry(
+                format!("switch to #{}", name),
+                "Channel",
+                PaletteAction::Run(format!("#{}", name)),
+            ),
+            entry(
+                format!(":{} {}", pin, name),
+                if app.is_pinned(name) {
+                    "Unpin the channel"
+                } else {
+                    "Pin the channel to the front"
+                },
+                PaletteAction::Run(format!(":{} {}", pin, name)),
+            ),
+            entry(
+                format!(":split {}", name),
+                "Show the channel in a pane below",
+                PaletteAction::Run(format!(":split {}", name)),
+            ),
+            entry(
+                format!(":vsplit {}", name),
+                "Show the channel in a pane alongside",
+                PaletteAction::Run(format!(":vsplit {}", name)),
+            ),
+            entry(
+                format!(":diff {}", name),
+                "Compare with another channel",
+                PaletteAction::Insert(format!(":diff {} ", name)),
+            ),
+            entry(
+                format!(":kill {}", name),
+                "Kill the channel",
+                PaletteAction::Run(format!(":kill {}", name)),
+            ),
+        ];
+        Self::with_entries(entries)
+    }
+
+    fn with_entries(entries: Vec<PaletteEntry>) -> Self {
+        let mut palette = Self {
+            query: String::new(),
+            selected: 0,
+            entries,
+            matches: Vec::new(),
+        };
+        palette.refilter();
+        palette
+    }
+
+    /// Matching entries in display order
+    pub fn visible(&self) -> impl Iterator<Item = &PaletteEntry> {
+        self.matches.iter().map(|&idx| &self.entries[idx])
+    }
+
+    /// The highlighted entry
+    pub fn selected_entry(&self) -> Option<&PaletteEntry> {
+        self.matches
+            .get(self.selected)
+            .map(|&idx| &self.entries[idx])
+    }
+
+    pub fn push_char(&mut self, c: char) {
+        self.query.push(c);
+        self.refilter();
+    }
+
+    pub fn pop_char(&mut self) {
+        self.query.pop();
+        self.refilter();
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.matches.is_empty() {
+            self.selected = (self.selected + 1) % self.matches.len();
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        if !self.matches.is_empty() {
+            self.selected = (self.selected + self.matches.len() - 1) % self.matches.len();
+        }
+    }
+
+    fn refilter(&mut self) {
+        let mut scored: Vec<(i64, usize)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, entry)| fuzzy_score(&self.query, &entry.label).map(|s| (s, idx)))
+            .collect();
+        // Stable sort keeps the natural entry order for equal scores
+        scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        self.matches = scored.into_iter().map(|(_, idx)| idx).collect();
+        self.selected = 0;
+    }
+}
+
+/// Score `candidate` against `query` as a case-insensitive subsequence match.
+///
+/// Returns `None` when not every query character appears in order. Consecutive
+/// matches and matches at word starts score higher; skipped characters cost a little.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.chars().collect();
+    let mut score = 0i64;
+    let mut pos = 0usize;
+    let mut prev_match: Option<usize> = None;
+
+    for qc in query.chars().filter(|c| !c.is_whitespace()) {
+        let qc = qc.to_ascii_lowercase();
+        let found = (pos..candidate.len()).find(|&i| candidate[i].to_ascii_lowercase() == qc)?;
+
+        score += 1;
+        if prev_match.is_some_and(|p| p + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        score -= (found - pos) as i64;
+
+        prev_match = Some(found);
+        pos = found + 1;
+    }
+
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::app::ChannelInfo;
+
+    #[test]
+    fn fuzzy_score_prefers_tight_matches() {
+        assert!(fuzzy_score("xyz", ":status").is_none());
+        assert_eq!(fuzzy_score("", ":status"), Some(0));
+
+        let tight = fuzzy_score("sta", ":status").unwrap();
+        let loose = fuzzy_score("sta", ":subs timestamps").unwrap();
+        assert!(tight > loose);
+
+        let word_start = fuzzy_score("b", "switch to #build").unwrap();
+        let mid_word = fuzzy_score("u", "switch to #build").unwrap();
+        assert!(word_start > mid_word);
+    }
+
+    #[test]
+    fn palette_includes_channels_commands_and_recent() {
+        let mut app = App::new();
+        app.channels.push(ChannelInfo {
+            name: "build".to_string(),
+            running: true,
+            starting: false,
+            has_new_output: false,
+            exit_code: None,
+            container: None,
+            started_at: None,
+            foreground: None,
+        });
+        let recent = vec![
+            "make".to_string(),
+            "cargo test".to_string(),
+            "make".to_string(),
+        ];
+
+        let mut palette = Palette::new(&app, &recent);
+        palette.query = "build".to_string();
+        palette.refilter();
+        let entry = palette.selected_entry().unwrap();
+        assert_eq!(entry.action, PaletteAction::Run("#build".to_string()));
+        assert_eq!(entry.hint.as_deref(), Some("alt+1"));
+
+        palette.query = "make".to_string();
+        palette.refilter();
+        let recents: Vec<_> = palette
+            .visible()
+            .filter(|e| e.description == "Recent")
+            .map(|e| e.label.as_str())
+            .collect();
+        assert_eq!(recents, vec!["make"]);
+
+        palette.query = "new".to_string();
+        palette.refilter();
+        assert_eq!(
+            palette.selected_entry().unwrap().action,
+            PaletteAction::Insert(":new ".to_string())
+        );
+    }
+
+    #[test]
+    fn channel_palette_acts_on_that_channel() {
+        let app = App::new();
+        let mut palette = Palette::for_channel(&app, "api");
+        assert_eq!(
+            palette.selected_entry().unwrap().action,
+            PaletteAction::Run("#api".to_string())
+        );
+        palette.query = "kill".to_string();
+        palette.refilter();
+        assert_eq!(
+            palette.selected_entry().unwrap().action,
+            PaletteAction::Run(":kill api".to_string())
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Searching output (`:search`), of the active channel or of every channel
+//! in the interleaved view: every match in view is highlighted and the
+//! status bar counts through them ("match 3/17")
+
+use crate::client::ansi::strip_ansi_codes;
+use crate::client::app::BufferedLine;
+use ratatui::{
+    style::Style,
+    text::{Line, Span},
+};
+use regex::{Regex, RegexBuilder};
+
+/// A line that matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// Line number, counting from the first line of the searched buffer
+    pub line: usize,
+    /// Channel the line came from
+    pub channel: String,
+}
+
+/// An active search
+#[derive(Debug, Clone)]
+pub struct Search {
+    pub query: String,
+    pub regex: Regex,
+    /// Channel whose output is searched; `None` searches the interleaved
+    /// output of every channel
+    pub channel: Option<String>,
+    /// Matching lines, oldest first
+    pub matches: Vec<Match>,
+    /// Index into `matches` of the match in view
+    pub current: Option<usize>,
+}
+
+impl Search {
+    /// Search for `query`, ignoring case unless it has capitals
+    pub fn new(query: &str, channel: Option<&str>) -> Result<Self, regex::Error> {
+        let regex = RegexBuilder::new(query)
+            .case_insensitive(!query.chars().any(char::is_uppercase))
+            .build()?;
+        Ok(Self {
+            query: query.to_string(),
+            regex,
+            channel: channel.map(str::to_string),
+            matches: Vec::new(),
+            current: None,
+        })
+    }
+
+    pub fn is_match(&self, content: &str) -> bool {
+        self.regex.is_match(&strip_ansi_codes(content))
+    }
+
+    /// Find every match among `lines`, which have `trimmed` lines gone from
+    /// their front
+    pub fn scan<'a>(
+        &mut self,
+        lines: impl IntoIterator<Item = (&'a str, &'a BufferedLine)>,
+        trimmed: usize,
+    ) {
+        self.matches = lines
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (_, line))| self.is_match(&line.content))
+            .map(|(i, (channel, _))| Match {
+                line: trimmed + i,
+                channel: channel.to_string(),
+            })
+            .collect();
+        self.current = None;
+    }
+
+    /// Note a new line if it matches
+    pub fn push(&mut self, line: usize, channel: &str, content: &str) {
+        if self.is_match(content) {
+            self.matches.push(Match {
+                line,
+                channel: channel.to_string(),
+            });
+        }
+    }
+
+    /// Whether the line numbered `line` is the match in view
+    pub fn is_current(&self, line: usize) -> bool {
+        self.current.map(|i| self.matches[i].line) == Some(line)
+    }
+
+    /// Forget matches on lines trimmed from the buffer
+    pub fn trim(&mut self, trimmed: usize) {
+        let gone = self.matches.partition_point(|m| m.line < trimmed);
+        if gone > 0 {
+            self.matches.drain(..gone);
+            self.current = self.current.and_then(|i| i.checked_sub(gone));
+        }
+    }
+
+    /// Move to the next (later) or previous match after the one in view,
+    /// or else the last match at or before line `from`. Returns its line.
+    pub fn step(&mut self, forward: bool, from: usize) -> Option<usize> {
+        let next = match self.current {
+            Some(i) if forward => (i + 1 < self.matches.len()).then_some(i + 1),
+            Some(i) => i.checked_sub(1),
+            None => self
+                .matches
+                .partition_point(|m| m.line <= from)
+                .checked_sub(1)
+                .or((!self.matches.is_empty()).then_some(0)),
+        }?;
+        self.current = Some(next);
+        Some(self.matches[next].line)
+    }
+
+    /// Status bar text; across channels, the current match's channel too
+    pub fn status(&self) -> String {
+        match (self.current, self.matches.len()) {
+            (_, 0) => format!("/{}: no matches", self.query),
+            (Some(i), n) if self.channel.is_none() => format!(
+                "/{}: match {}/{} in #{}",
+                self.query,
+                i + 1,
+                n,
+                self.matches[i].channel
+            ),
+            (Some(i), n) => format!("/{}: match {}/{}", self.query, i + 1, n),
+            (None, n) => format!("/{}: {} matches", self.query, n),
+        }
+    }
+}
+
+/// Patch `style` onto the parts of a line `regex` matches
+pub fn highlight(line: Line<'static>, regex: &Regex, style: Style) -> Line<'static> {
+    let text: String = line.spans.iter().map(|s| s.content.as_ref()).collect();
+    let ranges: Vec<(usize, usize)> = regex
+        .find_iter(&text)
+        .filter(|m| !m.is_empty())
+        .map(|m| (m.start(), m.end()))
+        .collect();
+    if ranges.is_empty() {
+        return line;
+    }
+
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    for span in line.spans {
+        let content = span.content.as_ref();
+        let end = offset + content.len();
+        // Cut the span wherever a match starts or ends inside it
+        let mut cuts = vec![offset, end];
+        for &(start, stop) in &ranges {
+            cuts.extend([start, stop].into_iter().filter(|&c| c > offset && c < end));
+        }
+        cuts.sort_unstable();
+        cuts.dedup();
+        for pair in cuts.windows(2) {
+            let piece = &content[pair[0] - offset..pair[1] - offset];
+            let matched = ranges.iter().any(|&(s, e)| s <= pair[0] && pair[1] <= e);
+            let piece_style = if matched {
+                span.style.patch(style)
+            } else {
+                span.style
+            };
+            spans.push(Span::styled(piece.to_string(), piece_style));
+        }
+        offset = end;
+    }
+    Line::from(spans).style(line.style)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::style::{Color, Modifier};
+
+    #[test]
+    fn smart_case_and_stepping() {
+        let mut search = Search::new("req-42", Some("api")).unwrap();
+        assert!(search.is_match("GET /users REQ-42 200"));
+        assert!(!Search::new("Req", None).unwrap().is_match("req"));
+
+        for line in [3, 8, 20] {
+            search.push(line, "api", "req-42");
+        }
+        assert_eq!(search.step(false, 10), Some(8));
+        assert_eq!(search.status(), "/req-42: match 2/3");
+        assert_eq!(search.step(true, 10), Some(20));
+        assert_eq!(search.step(true, 10), None);
+        assert_eq!(search.step(false, 10), Some(8));
+
+        search.trim(5);
+        assert_eq!(search.matches.len(), 2);
+        assert!(search.is_current(8));
+        assert_eq!(search.status(), "/req-42: match 1/2");
+    }
+
+    #[test]
+    fn searches_across_channels_label_matches() {
+        let line = |content: &str| BufferedLine {
+            content: content.to_string(),
+            timestamp: chrono::Local::now(),
+            stream: crate::channel::OutputStream::Stdout,
+            section_start: false,
+            folded: false,
+            seq: 0,
+        };
+        let lines = [
+            ("api", line("req 7 start")),
+            ("db", line("query for req 7")),
+            ("api", line("req 8 start")),
+        ];
+        let mut search = Search::new("req 7", None).unwrap();
+        search.scan(lines.iter().map(|(ch, l)| (*ch, l)), 100);
+        assert_eq!(search.step(false, 102), Some(101));
+        assert_eq!(search.status(), "/req 7: match 2/2 in #db");
+    }
+
+    #[test]
+    fn highlights_matches_across_spans() {
+        let line = Line::from(vec![
+            Span::styled("err", Style::default().fg(Color::Red)),
+            Span::raw("or: bad"),
+        ]);
+        let regex = Regex::new("error|bad").unwrap();
+        let mark = Style::default().add_modifier(Modifier::REVERSED);
+        let line = highlight(line, &regex, mark);
+        let parts: Vec<(&str, bool)> = line
+            .spans
+            .iter()
+            .map(|s| {
+                (
+                    s.content.as_ref(),
+                    s.style.add_modifier.contains(Modifier::REVERSED),
+                )
+            })
+            .collect();
+        assert_eq!(
+            parts,
+            vec![("err", true), ("or", true), (": ", false), ("bad", true)]
+        );
+        assert_eq!(line.spans[0].style.fg, Some(Color::Red));
+    }
+}
@@ -0,0 +1,157 @@
+//! Click-drag selection of output text, read back from the drawn screen
+
+use ratatui::{buffer::Buffer, layout::Rect};
+use unicode_width::UnicodeWidthStr;
+
+/// A selection being dragged out with the mouse, in screen cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    /// Cell where the drag started, as (column, row)
+    pub anchor: (u16, u16),
+    /// Cell under the mouse now
+    pub cursor: (u16, u16),
+}
+
+impl Selection {
+    pub fn new(column: u16, row: u16) -> Self {
+        Self {
+            anchor: (column, row),
+            cursor: (column, row),
+        }
+    }
+
+    /// Start and end cells in reading order
+    fn ends(&self) -> ((u16, u16), (u16, u16)) {
+        let key = |(column, row): (u16, u16)| (row, column);
+        if key(self.anchor) <= key(self.cursor) {
+            (self.anchor, self.cursor)
+        } else {
+            (self.cursor, self.anchor)
+        }
+    }
+
+    /// Whether a cell is selected. Like a terminal, the selection runs from
+    /// the start cell to the end of its row, over whole rows, and into the
+    /// last row up to the end cell.
+    pub fn contains(&self, column: u16, row: u16) -> bool {
+        let ((start_col, start_row), (end_col, end_row)) = self.ends();
+        (start_row..=end_row).contains(&row)
+            && (row != start_row || column >= start_col)
+            && (row != end_row || column <= end_col)
+    }
+}
+
+/// What was drawn in the output area, kept while a selection is made so the
+/// selected text can be read back
+#[derive(Debug, Clone, Default)]
+pub struct ScreenText {
+    area: Rect,
+    /// Cell symbols of each row
+    rows: Vec<Vec<String>>,
+    /// First column of output text in each row, past any channel prefix
+    text_start: Vec<u16>,
+}
+
+impl ScreenText {
+    /// Read the cells of `area`. With `prefixed` (the interleaved view),
+    /// each row's text starts after its `#channel │ ` prefix.
+    pub fn capture(buffer: &Buffer, area: Rect, prefixed: bool) -> Self {
+        let area = area.intersection(buffer.area);
+        let mut rows = Vec::with_capacity(area.height as usize);
+        let mut text_start = Vec::with_capacity(area.height as usize);
+        for y in area.top()..area.bottom() {
+            let row: Vec<String> = (area.left()..area.right())
+                .map(|x| buffer[(x, y)].symbol().to_string())
+                .collect();
+            let start = prefixed
+                .then(|| {
+                    row.windows(2)
+                        .position(|pair| pair[0] == "│" && pair[1] == " ")
+                })
+                .flatten()
+                .map_or(0, |i| i as u16 + 2);
+            rows.push(row);
+            text_start.push(start);
+        }
+        Self {
+            area,
+            rows,
+            text_start,
+        }
+    }
+
+    /// The selected text, one line per screen row with trailing blanks
+    /// trimmed. Channel prefixes are left out.
+    pub fn selected(&self, selection: &Selection) -> String {
+        let mut lines = Vec::new();
+        for (i, row) in self.rows.iter().enumerate() {
+            let y = self.area.y + i as u16;
+            let mut line = String::new();
+            let mut skip = 0;
+            for (j, symbol) in row.iter().enumerate() {
+                // Cells covered by a wide character hold a blank
+                if skip > 0 {
+                    skip -= 1;
+                    continue;
+                }
+                skip = symbol.width().saturating_sub(1);
+                let x = self.area.x + j as u16;
+                if j as u16 >= self.text_start[i] && selection.contains(x, y) {
+                    line.push_str(symbol);
+                }
+            }
+            if (0..row.len()).any(|j| selection.contains(self.area.x + j as u16, y)) {
+                lines.push(line.trim_end().to_string());
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Clamp a screen cell into the captured area
+    pub fn clamp(&self, column: u16, row: u16) -> (u16, u16) {
+        if self.area.is_empty() {
+            return (column, row);
+        }
+        (
+            column.clamp(self.area.left(), self.area.right() - 1),
+            row.clamp(self.area.top(), self.area.bottom() - 1),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(lines: &[&str], prefixed: bool) -> ScreenText {
+        let area = Rect::new(0, 0, 16, lines.len() as u16);
+        let mut buffer = Buffer::empty(area);
+        for (y, line) in lines.iter().enumerate() {
+            buffer.set_string(0, y as u16, line, ratatui::style::Style::default());
+        }
+        ScreenText::capture(&buffer, area, prefixed)
+    }
+
+    #[test]
+    fn selection_runs_across_rows_in_reading_order() {
+        let screen = screen(&["hello world", "second line", "third"], false);
+        let mut selection = Selection::new(3, 2);
+        selection.cursor = (6, 0);
+        assert_eq!(screen.selected(&selection), "world\nsecond line\nthir");
+        assert_eq!(screen.selected(&Selection::new(0, 1)), "s");
+    }
+
+    #[test]
+    fn interleaved_prefixes_are_left_out() {
+        let screen = screen(&["#a   │ one", "#b   │ two 世界"], true);
+        let mut selection = Selection::new(0, 0);
+        selection.cursor = (15, 1);
+        assert_eq!(screen.selected(&selection), "one\ntwo 世界");
+    }
+
+    #[test]
+    fn clamps_into_the_area() {
+        let screen = screen(&["a", "b"], false);
+        assert_eq!(screen.clamp(40, 9), (15, 1));
+    }
+}
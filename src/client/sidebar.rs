@@ -0,0 +1,83 @@
+//! Channel list sidebar - every channel with its state and latest output
+
+use crate::client::app::BufferedLine;
+
+/// Sidebar state while it is shown
+#[derive(Debug, Clone, Default)]
+pub struct Sidebar {
+    /// Highlighted channel, as an index into the channel list
+    pub selected: usize,
+    /// Arrow keys and Enter go to the sidebar rather than the input line
+    pub focused: bool,
+}
+
+impl Sidebar {
+    /// Show the sidebar with keyboard focus, highlighting channel `selected`
+    pub fn focused_on(selected: usize) -> Self {
+        Self {
+            selected,
+            focused: true,
+        }
+    }
+
+    pub fn select_next(&mut self, count: usize) {
+        if count > 0 {
+            self.selected = (self.selected + 1) % count;
+        }
+    }
+
+    pub fn select_prev(&mut self, count: usize) {
+        if count > 0 {
+            self.selected = (self.selected + count - 1) % count;
+        }
+    }
+}
+
+/// The last non-blank output line of a channel, trimmed, for the sidebar
+pub fn last_output(buffer: &[BufferedLine]) -> Option<&str> {
+    buffer
+        .iter()
+        .rev()
+        .map(|line| line.content.trim())
+        .find(|content| !content.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::OutputStream;
+    use chrono::Local;
+
+    fn line(content: &str) -> BufferedLine {
+        BufferedLine {
+            content: content.to_string(),
+            timestamp: Local::now(),
+            stream: OutputStream::Stdout,
+            section_start: false,
+            folded: false,
+            seq: 0,
+        }
+    }
+
+    #[test]
+    fn selection_wraps_around() {
+        let mut sidebar = Sidebar::focused_on(2);
+        sidebar.select_next(3);
+        assert_eq!(sidebar.selected, 0);
+        sidebar.select_prev(3);
+        assert_eq!(sidebar.selected, 2);
+        sidebar.select_next(0);
+        assert_eq!(sidebar.selected, 2);
+    }
+
+    #[test]
+    fn last_output_skips_blank_lines() {
+        assert_eq!(last_output(&[]), None);
+        let buffer = [
+            line("Compiling nexus"),
+            line("  Finished dev  "),
+            line("   "),
+        ];
+        assert_eq!(last_output(&buffer), Some("Finished dev"));
+    }
+}
@@ -0,0 +1,456 @@
+//! Color themes - named color schemes for the UI (`[theme]`)
+
+use crate::client::ansi::ColorSupport;
+use crate::config::ThemeConfig;
+use ratatui::style::Color;
+use std::str::FromStr;
+
+/// Names of the bundled schemes
+pub const SCHEMES: [&str; 4] = ["dark", "light", "solarized", "gruvbox"];
+
+/// `[theme.colors]` keys, one per color role
+const ROLES: [&str; 15] = [
+    "muted",
+    "border",
+    "border_focused",
+    "selection_fg",
+    "selection_bg",
+    "active",
+    "activity",
+    "success",
+    "error",
+    "accent",
+    "prompt_channel",
+    "prompt_symbol",
+    "badge_fg",
+    "alert_fg",
+    "alert_bg",
+];
+
+/// Names of the alternate channel palettes
+pub const PALETTES: [&str; 3] = ["basic", "bright", "colorblind"];
+
+/// Colors used throughout the UI
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    /// Secondary text: mode indicator, snippets, descriptions, idle channels
+    pub muted: Color,
+    /// Separators and pane borders
+    pub border: Color,
+    /// Borders of whatever has the keyboard (sidebar, popup, palette)
+    pub border_focused: Color,
+    /// Highlighted entry in lists
+    pub selection_fg: Color,
+    pub selection_bg: Color,
+    /// Active channel and focused pane
+    pub active: Color,
+    /// New output, completions and other things wanting attention
+    pub activity: Color,
+    /// Channels that exited cleanly
+    pub success: Color,
+    /// Failed channels and stderr
+    pub error: Color,
+    /// Container labels and status segments
+    pub accent: Color,
+    /// Channel name in the prompt
+    pub prompt_channel: Color,
+    /// The `❯` in the prompt
+    pub prompt_symbol: Color,
+    /// Text on status bar badges such as `[PREFIX]`
+    pub badge_fg: Color,
+    /// Disconnect banner and recording badge
+    pub alert_fg: Color,
+    pub alert_bg: Color,
+    /// Colors handed out to channels in the interleaved view
+    pub channel_palette: Vec<Color>,
+    /// Fixed channel colors from `[theme.channels]`, exact names first
+    pub channel_rules: Vec<(String, Color)>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    /// Build the theme named in config with its color overrides applied,
+    /// returning any names or colors that failed to parse
+    pub fn from_config(config: &ThemeConfig) -> (Self, Vec<String>) {
+        let mut errors = Vec::new();
+        let mut theme = Self::named(&config.name).unwrap_or_else(|| {
+            errors.push(format!(
+                "Unknown theme '{}' (available: {})",
+                config.name,
+                SCHEMES.join(", ")
+            ));
+            Self::dark()
+        });
+
+        for (role, value) in &config.colors {
+            let color = match Color::from_str(value) {
+                Ok(color) => color,
+                Err(_) => {
+                    errors.push(format!(
+                        "Invalid color '{}' for theme.colors.{}",
+                        value, role
+                    ));
+                    continue;
+                }
+            };
+            match theme.role_mut(role) {
+                Some(slot) => *slot = color,
+                None => errors.push(format!("Unknown theme color '{}'", role)),
+            }
+        }
+
+        if let Some(name) = &config.palette {
+            match palette(name) {
+                Some(colors) => theme.channel_palette = colors,
+                None => errors.push(format!(
+                    "Unknown palette '{}' (available: {})",
+                    name,
+                    PALETTES.join(", ")
+                )),
+            }
+        }
+
+        for (pattern, value) in &config.channels {
+            match Color::from_str(value) {
+                Ok(color) => theme.channel_rules.push((pattern.clone(), color)),
+                Err(_) => errors.push(format!(
+                    "Invalid color '{}' for channel '{}'",
+                    value, pattern
+                )),
+            }
+        }
+        theme
+            .channel_rules
+            .sort_by_key(|(pattern, _)| pattern.contains(['*', '?']));
+
+        (theme, errors)
+    }
+
+    /// Reduce every color to what the terminal can show
+    pub fn adapt(&mut self, colors: ColorSupport) {
+        for role in ROLES {
+            if let Some(slot) = self.role_mut(role) {
+                *slot = colors.adapt(*slot);
+            }
+        }
+        for color in self
+            .channel_palette
+            .iter_mut()
+            .chain(self.channel_rules.iter_mut().map(|(_, color)| color))
+        {
+            *color = colors.adapt(*color);
+        }
+    }
+
+    /// Color fixed for `channel` by `[theme.channels]`, if any
+    pub fn channel_color(&self, channel: &str) -> Option<Color> {
+        self.channel_rules
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, channel))
+            .map(|(_, color)| *color)
+    }
+
+    /// A bundled scheme by name
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            "solarized" => Some(Self::solarized()),
+            "gruvbox" => Some(Self::gruvbox()),
+            _ => None,
+        }
+    }
+
+    /// Color slot for a `[theme.colors]` key
+    fn role_mut(&mut self, role: &str) -> Option<&mut Color> {
+        Some(match role {
+            "muted" => &mut self.muted,
+            "border" => &mut self.border,
+            "border_focused" => &mut self.border_focused,
+            "selection_fg" => &mut self.selection_fg,
+            "selection_bg" => &mut self.selection_bg,
+            "active" => &mut self.active,
+            "activity" => &mut self.activity,
+            "success" => &mut self.success,
+            "error" => &mut self.error,
+            "accent" => &mut self.accent,
+            "prompt_channel" => &mut self.prompt_channel,
+            "prompt_symbol" => &mut self.prompt_symbol,
+            "badge_fg" => &mut self.badge_fg,
+            "alert_fg" => &mut self.alert_fg,
+            "alert_bg" => &mut self.alert_bg,
+            _ => return None,
+        })
+    }
+
+    /// The terminal's own palette on a dark background
+    fn dark() -> Self {
+        Self {
+            muted: Color::DarkGray,
+            border: Color::DarkGray,
+            border_focused: Color::Cyan,
+            selection_fg: Color::White,
+            selection_bg: Color::DarkGray,
+            active: Color::Green,
+            activity: Color::Yellow,
+            success: Color::Green,
+            error: Color::Red,
+            accent: Color::Cyan,
+            prompt_channel: Color::Cyan,
+            prompt_symbol: Color::Green,
+            badge_fg: Color::Black,
+            alert_fg: Color::White,
+            alert_bg: Color::Red,
+            channel_palette: vec![
+                Color::Blue,
+                Color::Magenta,
+                Color::Cyan,
+                Color::Yellow,
+                Color::Green,
+                Color::Red,
+            ],
+            channel_rules: Vec::new(),
+        }
+    }
+
+    /// The terminal's own palette on a light background
+    fn light() -> Self {
+        Self {
+            muted: Color::Gray,
+            border: Color::Gray,
+            border_focused: Color::Blue,
+            selection_fg: Color::White,
+            selection_bg: Color::Blue,
+            active: Color::Green,
+            activity: Color::Magenta,
+            success: Color::Green,
+            error: Color::Red,
+            accent: Color::Blue,
+            prompt_channel: Color::Blue,
+            prompt_symbol: Color::Green,
+            badge_fg: Color::White,
+            alert_fg: Color::White,
+            alert_bg: Color::Red,
+            channel_palette: vec![
+                Color::Blue,
+                Color::Magenta,
+                Color::Green,
+                Color::Red,
+                Color::Cyan,
+                Color::Black,
+            ],
+            channel_rules: Vec::new(),
+        }
+    }
+
+    /// Solarized dark
+    fn solarized() -> Self {
+        let (base03, base02, base01, base3) = (
+            Color::Rgb(0x00, 0x2b, 0x36),
+            Color::Rgb(0x07, 0x36, 0x42),
+            Color::Rgb(0x58, 0x6e, 0x75),
+            Color::Rgb(0xfd, 0xf6, 0xe3),
+        );
+        let (yellow, orange, red, magenta) = (
+            Color::Rgb(0xb5, 0x89, 0x00),
+            Color::Rgb(0xcb, 0x4b, 0x16),
+            Color::Rgb(0xdc, 0x32, 0x2f),
+            Color::Rgb(0xd3, 0x36, 0x82),
+        );
+        let (violet, blue, cyan, green) = (
+            Color::Rgb(0x6c, 0x71, 0xc4),
+            Color::Rgb(0x26, 0x8b, 0xd2),
+            Color::Rgb(0x2a, 0xa1, 0x98),
+            Color::Rgb(0x85, 0x99, 0x00),
+        );
+        Self {
+            muted: base01,
+            border: base01,
+            border_focused: blue,
+            selection_fg: base3,
+            selection_bg: base02,
+            active: green,
+            activity: yellow,
+            success: green,
+            error: red,
+            accent: cyan,
+            prompt_channel: blue,
+            prompt_symbol: green,
+            badge_fg: base03,
+            alert_fg: base3,
+            alert_bg: red,
+            channel_palette: vec![blue, magenta, cyan, yellow, green, orange, violet],
+            channel_rules: Vec::new(),
+        }
+    }
+
+    /// Gruvbox dark
+    fn gruvbox() -> Self {
+        let (bg, bg2, gray, fg) = (
+            Color::Rgb(0x28, 0x28, 0x28),
+            Color::Rgb(0x50, 0x49, 0x45),
+            Color::Rgb(0x92, 0x83, 0x74),
+            Color::Rgb(0xeb, 0xdb, 0xb2),
+        );
+        let (red, green, yellow, blue) = (
+            Color::Rgb(0xfb, 0x49, 0x34),
+            Color::Rgb(0xb8, 0xbb, 0x26),
+            Color::Rgb(0xfa, 0xbd, 0x2f),
+            Color::Rgb(0x83, 0xa5, 0x98),
+        );
+        let (purple, aqua, orange, dark_red) = (
+            Color::Rgb(0xd3, 0x86, 0x9b),
+            Color::Rgb(0x8e, 0xc0, 0x7c),
+            Color::Rgb(0xfe, 0x80, 0x19),
+            Color::Rgb(0xcc, 0x24, 0x1d),
+        );
+        Self {
+            muted: gray,
+            border: bg2,
+            border_focused: blue,
+            selection_fg: fg,
+            selection_bg: bg2,
+            active: green,
+            activity: yellow,
+            success: green,
+            error: red,
+            accent: aqua,
+            prompt_channel: blue,
+            prompt_symbol: green,
+            badge_fg: bg,
+            alert_fg: fg,
+            alert_bg: dark_red,
+            channel_palette: vec![blue, purple, aqua, yellow, green, orange],
+            channel_rules: Vec::new(),
+        }
+    }
+}
+
+/// An alternate channel palette by name
+fn palette(name: &str) -> Option<Vec<Color>> {
+    match name {
+        "basic" => Some(Theme::dark().channel_palette),
+        "bright" => Some(vec![
+            Color::LightBlue,
+            Color::LightMagenta,
+            Color::LightCyan,
+            Color::LightYellow,
+            Color::LightGreen,
+            Color::LightRed,
+        ]),
+        // Okabe-Ito: distinguishable with the common color vision deficiencies
+        "colorblind" => Some(vec![
+            Color::Rgb(0xe6, 0x9f, 0x00),
+            Color::Rgb(0x56, 0xb4, 0xe9),
+            Color::Rgb(0x00, 0x9e, 0x73),
+            Color::Rgb(0xf0, 0xe4, 0x42),
+            Color::Rgb(0x00, 0x72, 0xb2),
+            Color::Rgb(0xd5, 0x5e, 0x00),
+            Color::Rgb(0xcc, 0x79, 0xa7),
+        ]),
+        _ => None,
+    }
+}
+
+/// Match `text` against a glob where `*` is any run of characters and `?`
+/// any one character
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it resumes from
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((after, from)) => {
+                    p = after;
+                    t = from + 1;
+                    star = Some((after, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_named_scheme_and_overrides() {
+        let config: ThemeConfig = toml::from_str(
+            r##"
+            name = "gruvbox"
+            colors = { active = "magenta", border = "#101010" }
+            "##,
+        )
+        .unwrap();
+        let (theme, errors) = Theme::from_config(&config);
+
+        assert!(errors.is_empty());
+        assert_eq!(theme.active, Color::Magenta);
+        assert_eq!(theme.border, Color::Rgb(0x10, 0x10, 0x10));
+        assert_eq!(theme.error, Theme::gruvbox().error);
+    }
+
+    #[test]
+    fn fixes_channel_colors_by_name_then_glob() {
+        let config: ThemeConfig = toml::from_str(
+            r#"
+            palette = "colorblind"
+            [channels]
+            "test-*" = "yellow"
+            test-e2e = "red"
+            "#,
+        )
+        .unwrap();
+        let (theme, errors) = Theme::from_config(&config);
+
+        assert!(errors.is_empty());
+        assert_eq!(theme.channel_color("test-e2e"), Some(Color::Red));
+        assert_eq!(theme.channel_color("test-unit"), Some(Color::Yellow));
+        assert_eq!(theme.channel_color("build"), None);
+        assert_eq!(theme.channel_palette, palette("colorblind").unwrap());
+    }
+
+    #[test]
+    fn globs_match_any_run_or_single_character() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("web-*-db", "web-prod-db"));
+        assert!(glob_match("log?", "log1"));
+        assert!(!glob_match("log?", "log12"));
+        assert!(!glob_match("web-*", "api-web"));
+    }
+
+    #[test]
+    fn reports_unknown_schemes_and_colors() {
+        let config: ThemeConfig = toml::from_str(
+            r#"
+            name = "neon"
+            palette = "neon"
+            colors = { active = "not-a-color", glow = "red" }
+            "#,
+        )
+        .unwrap();
+        let (theme, errors) = Theme::from_config(&config);
+
+        assert_eq!(errors.len(), 4);
+        assert_eq!(theme, Theme::dark());
+    }
+}
@@ -0,0 +1,178 @@
+//! Timestamps shown before output lines (`:ts`): the time of day, how long
+//! ago each line came, or how far into its channel's run; the lengths of
+//! pauses marked between lines; and splitting output into stamped lines
+
+use crate::config::TimestampMode;
+use chrono::{DateTime, Local};
+
+/// Label for a line stamped `timestamp` as of `now`, from a channel whose
+/// process started at `started`, if known; elapsed times fall back to the
+/// time of day without it
+pub fn label(
+    mode: TimestampMode,
+    timestamp: DateTime<Local>,
+    started: Option<DateTime<Local>>,
+    now: DateTime<Local>,
+) -> String {
+    match (mode, started) {
+        (TimestampMode::Relative, _) => format!("{:>7}", age((now - timestamp).num_seconds())),
+        (TimestampMode::Elapsed, Some(started)) => elapsed((timestamp - started).num_seconds()),
+        _ => timestamp.format("%H:%M:%S").to_string(),
+    }
+}
+
+/// "now", "42s ago", "5m ago", "3h ago", "2d ago"
+fn age(secs: i64) -> String {
+    match secs.max(0) {
+        0 => "now".to_string(),
+        secs @ 1..60 => format!("{}s ago", secs),
+        secs @ 60..3600 => format!("{}m ago", secs / 60),
+        secs @ 3600..86400 => format!("{}h ago", secs / 3600),
+        secs => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// "+01:05", or "+2:03:10" past the first hour
+fn elapsed(secs: i64) -> String {
+    let secs = secs.max(0);
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("+{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("+{:02}:{:02}", minutes, seconds)
+    }
+}
+
+/// A pause between lines: "42s", "5m 12s", "2h 05m", "3d 4h"
+pub fn duration(secs: i64) -> String {
+    match secs.max(0) {
+        secs @ 0..60 => format!("{}s", secs),
+        secs @ 60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        secs @ 3600..86400 => format!("{}h {:02}m", secs / 3600, secs / 60 % 60),
+        secs => format!("{}d {}h", secs / 86400, secs / 3600 % 24),
+    }
+}
+
+/// Local time for a server timestamp in milliseconds, or now when it's out
+/// of range
+pub fn local_time(millis: i64) -> DateTime<Local> {
+    DateTime::from_timestamp_millis(millis)
+        .map_or_else(Local::now, |time| time.with_timezone(&Local))
+}
+
+/// Add a chunk of output received at `received` to a stream's partial line
+/// and return the lines it completes, each stamped with when it started and
+/// without its line ending. A partial line past `max_bytes` is returned as
+/// is rather than held without bound.
+pub fn split_lines(
+    pending: &mut (String, DateTime<Local>),
+    text: &str,
+    received: DateTime<Local>,
+    max_bytes: usize,
+) -> Vec<(String, DateTime<Local>)> {
+    let (buffer, started) = pending;
+    if buffer.is_empty() {
+        *started = received;
+    }
+    buffer.push_str(text);
+
+    let mut lines = Vec::new();
+    while let Some(newline_pos) = buffer.find('\n') {
+        let line = buffer[..newline_pos].trim_end_matches('\r').to_string();
+        buffer.drain(..=newline_pos);
+        lines.push((line, *started));
+        *started = received;
+    }
+    if buffer.len() > max_bytes {
+        lines.push((std::mem::take(buffer), *started));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Offset, TimeDelta};
+
+    #[test]
+    fn labels_by_mode() {
+        let now = Local::now();
+        let started = now - TimeDelta::seconds(3_790);
+        let line = now - TimeDelta::seconds(125);
+        assert_eq!(
+            label(TimestampMode::Relative, line, Some(started), now),
+            " 2m ago"
+        );
+        assert_eq!(
+            label(TimestampMode::Elapsed, line, Some(started), now),
+            "+1:01:05"
+        );
+        assert_eq!(
+            label(TimestampMode::Elapsed, line, None, now),
+            line.format("%H:%M:%S").to_string()
+        );
+        assert_eq!(age(0), "now");
+        assert_eq!(age(59), "59s ago");
+        assert_eq!(age(2 * 86_400), "2d ago");
+        assert_eq!(elapsed(65), "+01:05");
+    }
+
+    #[test]
+    fn durations_pick_two_units() {
+        assert_eq!(duration(42), "42s");
+        assert_eq!(duration(312), "5m 12s");
+        assert_eq!(duration(7_500), "2h 05m");
+        assert_eq!(duration(3 * 86_400 + 4 * 3600 + 59), "3d 4h");
+    }
+
+    #[test]
+    fn converts_server_millis_to_local_time() {
+        let time = local_time(1_700_000_000_123);
+        assert_eq!(time.timestamp_millis(), 1_700_000_000_123);
+        let offset = TimeDelta::seconds(time.offset().fix().local_minus_utc().into());
+        assert_eq!(
+            time.naive_local(),
+            DateTime::from_timestamp_millis(1_700_000_000_123)
+                .unwrap()
+                .naive_utc()
+                + offset
+        );
+        assert!(time.format("%H:%M:%S%.3f").to_string().ends_with(".123"));
+        // Out of range falls back to now
+        let before = Local::now();
+        assert!(local_time(i64::MAX) >= before);
+    }
+
+    #[test]
+    fn splits_chunks_into_stamped_lines() {
+        let first = local_time(1_000);
+        let second = local_time(2_000);
+        let third = local_time(3_000);
+        let mut pending = (String::new(), first);
+
+        assert!(split_lines(&mut pending, "par", first, 1024).is_empty());
+        // A line is stamped with when its first chunk came
+        assert_eq!(
+            split_lines(&mut pending, "tial\r\nnext\n", second, 1024),
+            vec![("partial".to_string(), first), ("next".to_string(), second),]
+        );
+        assert!(pending.0.is_empty());
+
+        assert_eq!(
+            split_lines(&mut pending, "a\r\n\r\nb", third, 1024),
+            vec![("a".to_string(), third), (String::new(), third)]
+        );
+        assert_eq!(pending.0, "b");
+        assert_eq!(
+            split_lines(&mut pending, "\n", local_time(4_000), 1024),
+            vec![("b".to_string(), third)]
+        );
+
+        // Lines that never end are flushed past the cap
+        assert_eq!(
+            split_lines(&mut pending, "0123456789", first, 8),
+            vec![("0123456789".to_string(), first)]
+        );
+        assert!(pending.0.is_empty());
+    }
+}
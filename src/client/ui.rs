@@ -1,21 +1,34 @@
-use crate::client::app::{App, ViewMode};
+use crate::channel::OutputStream;
+use crate::client::ansi::{sanitize_output, strip_ansi_codes, styled_line};
+use crate::client::app::{App, BufferedLine, InfoPopup, Popup, ViewMode};
+use crate::client::diff::{self, DIFF_LINES};
+use crate::client::folds;
+use crate::client::layout::grid;
+use crate::client::palette::Palette;
+use crate::client::search;
+use crate::client::selection::{ScreenText, Selection};
+use crate::client::sidebar::{last_output, Sidebar};
+use crate::client::timestamps;
+use crate::client::wrap::{fit_line, truncate_str};
 use chrono::{DateTime, Local};
 use ratatui::{
     layout::{Constraint, Direction, Layout, Position, Rect},
-    style::{Color, Modifier, Style},
+    style::{Modifier, Style},
     text::{Line, Span, Text},
-    widgets::{Block, List, ListItem, Paragraph},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph},
     Frame,
 };
-use regex::Regex;
-use std::sync::LazyLock;
+use std::borrow::Cow;
+use std::fmt::Write;
+use unicode_width::UnicodeWidthStr;
 
-static ANSI_ESCAPE_RE: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"\x1b\[[0-9;?]*[a-zA-Z~]|\x1b\][^\x07]*(?:\x07|\x1b\\)|\x1b[a-zA-Z]").unwrap()
-});
+/// Frames of the spinner shown next to starting channels
+const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
 
-pub fn strip_ansi_codes(s: &str) -> String {
-    ANSI_ESCAPE_RE.replace_all(s, "").to_string()
+/// Spinner frame for the current time, advancing every 100ms
+fn spinner_frame() -> &'static str {
+    let tenths = Local::now().timestamp_millis() / 100;
+    SPINNER_FRAMES[tenths.rem_euclid(SPINNER_FRAMES.len() as i64) as usize]
 }
 
 pub fn draw(f: &mut Frame, app: &mut App) {
@@ -37,141 +50,654 @@ pub fn draw(f: &mut Frame, app: &mut App) {
         .split(f.area());
 
     // Status Bar
-    draw_status_bar(f, app, chunks[0]);
-
-    // Top Separator
-    let _sep = Block::default().style(Style::default().fg(Color::DarkGray));
-    f.render_widget(Span::raw("─".repeat(chunks[1].width as usize)), chunks[1]);
+    app.status_bar_channel_rects = draw_status_bar(f, app, chunks[0]);
+
+    // Top Separator, replaced by a banner while the server is unreachable
+    if let Some(reconnect) = &app.disconnected {
+        let banner = format!(
+            " Disconnected from server; reconnecting (attempt {})... ",
+            reconnect.attempts + 1
+        );
+        let style = Style::default()
+            .fg(app.theme.alert_fg)
+            .bg(app.theme.alert_bg);
+        f.render_widget(
+            Paragraph::new(Span::styled(banner, style)).style(style),
+            chunks[1],
+        );
+    } else {
+        f.render_widget(separator(app, chunks[1]), chunks[1]);
+    }
 
-    // Output
-    draw_output(f, app, chunks[2]);
+    // Output, with the channel sidebar to its left when shown
+    let output_area = match app.sidebar.clone() {
+        Some(sidebar) => {
+            let width = (chunks[2].width / 3).clamp(16, 32).min(chunks[2].width);
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Length(width), Constraint::Min(0)])
+                .split(chunks[2]);
+            draw_sidebar(f, app, &sidebar, columns[0]);
+            columns[1]
+        }
+        None => chunks[2],
+    };
+    draw_output(f, app, output_area);
+    app.output_area = output_area;
+    if let Some(selection) = app.selection {
+        let prefixed = app.view_mode == ViewMode::AllChannels && !app.is_tiled();
+        app.screen_text = ScreenText::capture(f.buffer_mut(), output_area, prefixed);
+        highlight_selection(f, app, &selection, output_area);
+    }
 
     // Bottom Separator
-    f.render_widget(Span::raw("─".repeat(chunks[3].width as usize)), chunks[3]);
+    f.render_widget(separator(app, chunks[3]), chunks[3]);
 
     // Completions and Input
     if let Some(completions) = &app.completions {
         // Render completions
         let comp_text = format!("Completions: {}", completions.join("  "));
-        let p = Paragraph::new(Span::styled(comp_text, Style::default().fg(Color::Yellow)));
+        let p = Paragraph::new(Span::styled(
+            comp_text,
+            Style::default().fg(app.theme.activity),
+        ));
         f.render_widget(p, chunks[4]);
 
         draw_input(f, app, chunks[5]);
     } else {
         draw_input(f, app, chunks[4]);
     }
+
+    if let Some(popup) = &app.popup {
+        draw_popup(f, app, popup, chunks[2]);
+    }
+
+    if let Some(info) = &app.info {
+        draw_info(f, app, info, chunks[2]);
+    }
+
+    if let Some(palette) = &app.palette {
+        draw_palette(f, app, palette, chunks[2]);
+    }
+}
+
+/// Show the cells of a mouse selection in the theme's selection colors
+fn highlight_selection(f: &mut Frame, app: &App, selection: &Selection, area: Rect) {
+    let style = Style::default()
+        .fg(app.theme.selection_fg)
+        .bg(app.theme.selection_bg);
+    let buffer = f.buffer_mut();
+    for y in area.top()..area.bottom() {
+        for x in area.left()..area.right() {
+            if selection.contains(x, y) {
+                buffer[(x, y)].set_style(style);
+            }
+        }
+    }
+}
+
+/// Horizontal rule across `area`
+fn separator(app: &App, area: Rect) -> Span<'static> {
+    Span::styled(
+        "─".repeat(area.width as usize),
+        Style::default().fg(app.theme.border),
+    )
+}
+
+/// Channel list: one entry per channel with its state, activity marker and
+/// latest output line
+fn draw_sidebar(f: &mut Frame, app: &App, sidebar: &Sidebar, area: Rect) {
+    let theme = &app.theme;
+    let border_color = if sidebar.focused {
+        theme.border_focused
+    } else {
+        theme.border
+    };
+    let block = Block::default()
+        .borders(Borders::RIGHT)
+        .border_style(Style::default().fg(border_color));
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let width = inner.width as usize;
+    let items: Vec<ListItem> = app
+        .channels
+        .iter()
+        .enumerate()
+        .map(|(i, channel)| {
+            let is_active = app.active_channel.as_deref() == Some(&channel.name);
+            let (marker, marker_color) = if channel.is_starting() {
+                (spinner_frame(), theme.muted)
+            } else if channel.running {
+                ("●", theme.active)
+            } else if channel.exit_code == Some(0) {
+                ("✓", theme.success)
+            } else {
+                ("✗", theme.error)
+            };
+            let mut name_style = Style::default();
+            if is_active {
+                name_style = name_style.fg(theme.active).add_modifier(Modifier::BOLD);
+            } else if channel.has_new_output {
+                name_style = name_style.fg(theme.activity);
+            }
+            let activity = if channel.has_new_output { " *" } else { "" };
+            let title = format!("{} #{}{}", i + 1, channel.name, activity);
+            let title = truncate_str(&title, width.saturating_sub(2)).to_string();
+            let snippet = app
+                .channel_buffers
+                .get(&channel.name)
+                .and_then(|buffer| last_output(buffer))
+                .map(|line| {
+                    truncate_str(&sanitize_output(line), width.saturating_sub(2)).to_string()
+                })
+                .unwrap_or_default();
+
+            let mut item = ListItem::new(Text::from(vec![
+                Line::from(vec![
+                    Span::styled(marker, Style::default().fg(marker_color)),
+                    Span::raw(" "),
+                    Span::styled(title, name_style),
+                ]),
+                Line::from(Span::styled(
+                    format!("  {}", snippet),
+                    Style::default().fg(theme.muted),
+                )),
+            ]));
+            if sidebar.focused && i == sidebar.selected {
+                item = item.style(
+                    Style::default()
+                        .bg(theme.selection_bg)
+                        .fg(theme.selection_fg),
+                );
+            }
+            item
+        })
+        .collect();
+
+    // Keep the selection in view; each channel takes two rows
+    let rows = (inner.height as usize / 2).max(1);
+    let skip = sidebar.selected.saturating_sub(rows - 1);
+    f.render_widget(
+        List::new(items.into_iter().skip(skip).collect::<Vec<_>>()),
+        inner,
+    );
+}
+
+/// Popup channel in a floating window centered over the output area
+fn draw_popup(f: &mut Frame, app: &App, popup: &Popup, area: Rect) {
+    let width = (area.width * 4 / 5).max(40).min(area.width);
+    let height = (area.height * 4 / 5).max(5).min(area.height);
+    let window = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" {} ", popup.title))
+        .border_style(Style::default().fg(app.theme.border_focused));
+    let inner = block.inner(window);
+    f.render_widget(Clear, window);
+    f.render_widget(block, window);
+    f.render_widget(List::new(channel_lines(app, &popup.channel, inner)), inner);
+}
+
+/// Floating text window, centered over the output area and sized to fit
+/// its text where there's room
+fn draw_info(f: &mut Frame, app: &App, info: &InfoPopup, area: Rect) {
+    let longest = info
+        .lines
+        .iter()
+        .map(|line| line.width())
+        .max()
+        .unwrap_or(0);
+    let width = (longest as u16 + 2)
+        .clamp(40, area.width.max(40))
+        .min(area.width);
+    let height = (info.lines.len() as u16 + 2)
+        .clamp(5, (area.height * 4 / 5).max(5))
+        .min(area.height);
+    let window = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" {} ", info.title))
+        .title_bottom(Line::from(" Esc to close ").right_aligned())
+        .border_style(Style::default().fg(app.theme.border_focused));
+    let inner = block.inner(window);
+    f.render_widget(Clear, window);
+    f.render_widget(block, window);
+    let items: Vec<ListItem> = info
+        .lines
+        .iter()
+        .skip(info.scroll)
+        .take(inner.height as usize)
+        .map(|line| ListItem::new(truncate_str(line, inner.width as usize).to_string()))
+        .collect();
+    f.render_widget(List::new(items), inner);
+}
+
+/// Command palette overlay, centered over the output area
+fn draw_palette(f: &mut Frame, app: &App, palette: &Palette, area: Rect) {
+    let theme = &app.theme;
+    let width = (area.width * 3 / 5).max(40).min(area.width);
+    let height = (area.height * 3 / 5).max(5).min(area.height);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 4,
+        width,
+        height,
+    };
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" > {} ", palette.query))
+        .border_style(Style::default().fg(theme.border_focused));
+    let inner = block.inner(popup);
+    f.render_widget(Clear, popup);
+    f.render_widget(block, popup);
+
+    // Keep the selection in view
+    let rows = inner.height as usize;
+    let skip = palette.selected.saturating_sub(rows.saturating_sub(1));
+
+    let items: Vec<ListItem> = palette
+        .visible()
+        .enumerate()
+        .skip(skip)
+        .take(rows)
+        .map(|(idx, entry)| {
+            let mut spans = vec![
+                Span::raw(entry.label.clone()),
+                Span::raw("  "),
+                Span::styled(entry.description.clone(), Style::default().fg(theme.muted)),
+            ];
+            if let Some(hint) = &entry.hint {
+                let used: usize = spans.iter().map(|s| s.width()).sum();
+                let pad = (inner.width as usize).saturating_sub(used + hint.width());
+                spans.push(Span::raw(" ".repeat(pad)));
+                spans.push(Span::styled(
+                    hint.clone(),
+                    Style::default().fg(theme.activity),
+                ));
+            }
+            let style = if idx == palette.selected {
+                Style::default()
+                    .bg(theme.selection_bg)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            ListItem::new(Line::from(spans)).style(style)
+        })
+        .collect();
+
+    f.render_widget(List::new(items), inner);
 }
 
-fn draw_status_bar(f: &mut Frame, app: &App, area: Rect) {
+/// Draw the status bar, returning where each channel's tab went
+fn draw_status_bar(f: &mut Frame, app: &App, area: Rect) -> Vec<(String, Rect)> {
+    let theme = &app.theme;
     let mut spans = Vec::new();
 
     // Mode indicator
     let mode_str = match app.view_mode {
         ViewMode::ActiveChannel => "[channel]",
         ViewMode::AllChannels => "[all]",
+        ViewMode::Hex => "[hex]",
+        ViewMode::Grid => "[grid]",
+        ViewMode::Diff => "[diff]",
     };
-    spans.push(Span::styled(mode_str, Style::default().fg(Color::DarkGray)));
+    spans.push(Span::styled(mode_str, Style::default().fg(theme.muted)));
     spans.push(Span::raw(" "));
 
-    // Channels
+    if app.zoomed && app.is_tiled() {
+        spans.push(Span::styled(
+            "[ZOOM]",
+            Style::default().fg(theme.badge_fg).bg(theme.active),
+        ));
+        spans.push(Span::raw(" "));
+    }
+
+    if app.prefix_pending {
+        spans.push(Span::styled(
+            "[PREFIX]",
+            Style::default().fg(theme.badge_fg).bg(theme.activity),
+        ));
+        spans.push(Span::raw(" "));
+    }
+
+    if app.attach.observe {
+        spans.push(Span::styled(
+            "[OBSERVING]",
+            Style::default().fg(theme.badge_fg).bg(theme.accent),
+        ));
+        spans.push(Span::raw(" "));
+    }
+
+    if let Some(recording) = &app.recording {
+        spans.push(Span::styled(
+            format!("[REC {}]", recording.name),
+            Style::default().fg(theme.alert_fg).bg(theme.alert_bg),
+        ));
+        spans.push(Span::raw(" "));
+    }
+
+    if app.server_unresponsive() {
+        spans.push(Span::styled(
+            "[NO RESPONSE]",
+            Style::default().fg(theme.badge_fg).bg(theme.activity),
+        ));
+        spans.push(Span::raw(" "));
+    }
+
+    let segments = status_segments(app);
+    let segments_width: usize = segments.iter().map(|s| s.width()).sum();
+
+    // Channels, dropping unpinned ones that don't fit; pinned channels and
+    // the active one are always shown
+    let scrolled = app.view_scrolled();
+    let used: usize = spans.iter().map(|s| s.width()).sum();
+    let reserved = segments_width + if scrolled { " ↑ SCROLLED".width() } else { 0 };
+    let mut budget = (area.width as usize).saturating_sub(used + reserved);
+    let mut entries = Vec::new();
     for (i, channel) in app.channels.iter().enumerate() {
         let is_active = app.active_channel.as_deref() == Some(&channel.name);
+        let is_pinned = app.is_pinned(&channel.name);
 
         let mut style = Style::default();
-        if is_active {
-            style = style.fg(Color::Green).add_modifier(Modifier::BOLD);
+        if channel.is_starting() {
+            style = style.fg(theme.muted).add_modifier(Modifier::DIM);
+            if is_active {
+                style = style.add_modifier(Modifier::BOLD);
+            }
+        } else if is_active {
+            style = style.fg(theme.active).add_modifier(Modifier::BOLD);
         } else if channel.has_new_output {
-            style = style.fg(Color::Yellow);
+            style = style.fg(theme.activity);
         } else if !channel.running {
             if channel.exit_code == Some(0) {
-                style = style.fg(Color::Green);
+                style = style.fg(theme.success);
             } else {
-                style = style.fg(Color::Red);
+                style = style.fg(theme.error);
             }
         } else {
-            style = style.fg(Color::DarkGray);
+            style = style.fg(theme.muted);
+        }
+        if is_pinned {
+            style = style.add_modifier(Modifier::UNDERLINED);
         }
 
+        let indicator = if channel.is_starting() {
+            format!(" {}", spinner_frame())
+        } else {
+            channel.status_indicator().to_string()
+        };
         let prefix = if app.show_channel_numbers && i < 9 {
-            format!(
-                "[{}:#{}{}]",
-                i + 1,
-                channel.name,
-                channel.status_indicator()
-            )
+            format!("[{}:#{}{}]", i + 1, channel.name, indicator)
         } else {
-            format!("[#{}{}]", channel.name, channel.status_indicator())
+            format!("[#{}{}]", channel.name, indicator)
         };
 
-        spans.push(Span::styled(prefix, style));
-        spans.push(Span::raw(" "));
+        let mut entry = vec![Span::styled(prefix, style)];
+        // Container channels carry their runtime and container, e.g. ⧉docker:web
+        if let Some(container) = &channel.container {
+            entry.push(Span::styled(
+                format!("⧉{}", container),
+                Style::default().fg(theme.accent),
+            ));
+        }
+        // Whatever the shell is running right now, e.g. (vim)
+        if let Some(command) = channel
+            .foreground
+            .as_ref()
+            .filter(|_| app.config.appearance.show_foreground)
+        {
+            entry.push(Span::styled(
+                format!("({})", command),
+                Style::default().fg(theme.muted),
+            ));
+        }
+        entry.push(Span::raw(" "));
+        entries.push((is_pinned || is_active, entry, &channel.name));
+    }
+
+    let width = |entry: &[Span]| -> usize { entry.iter().map(|s| s.width()).sum() };
+    let total: usize = entries.iter().map(|(_, entry, _)| width(entry)).sum();
+    if total > budget {
+        // Room for the "[+N] " marker, then the must-show channels
+        budget = budget.saturating_sub(6);
+        for (keep, entry, _) in &entries {
+            if *keep {
+                budget = budget.saturating_sub(width(entry));
+            }
+        }
+        for (keep, entry, _) in entries.iter_mut() {
+            if !*keep && width(entry) <= budget {
+                budget -= width(entry);
+                *keep = true;
+            }
+        }
+    } else {
+        entries.iter_mut().for_each(|(keep, _, _)| *keep = true);
+    }
+    let hidden = entries.iter().filter(|(keep, _, _)| !keep).count();
+    let mut tabs = Vec::new();
+    let mut x = area.x as usize + spans.iter().map(|s| s.width()).sum::<usize>();
+    for (_, entry, name) in entries.into_iter().filter(|(keep, _, _)| *keep) {
+        // The tab, without the space after it
+        let tab_width = width(&entry) - 1;
+        let right = (area.x + area.width) as usize;
+        if x < right {
+            tabs.push((
+                name.clone(),
+                Rect::new(x as u16, area.y, tab_width.min(right - x) as u16, 1),
+            ));
+        }
+        x += tab_width + 1;
+        spans.extend(entry);
+    }
+    if hidden > 0 {
+        spans.push(Span::styled(
+            format!("[+{}] ", hidden),
+            Style::default().fg(theme.muted),
+        ));
     }
 
     // Scroll indicator
-    if app.is_scrolled(app.active_channel.as_deref()) {
+    if scrolled {
         spans.push(Span::styled(
             " ↑ SCROLLED",
-            Style::default().fg(Color::Yellow),
+            Style::default().fg(theme.activity),
         ));
     }
 
+    if !segments.is_empty() {
+        let used: usize = spans.iter().map(|s| s.width()).sum();
+        let pad = (area.width as usize).saturating_sub(used + segments_width);
+        spans.push(Span::raw(" ".repeat(pad)));
+        spans.extend(segments);
+    }
+
     f.render_widget(Paragraph::new(Line::from(spans)), area);
+    tabs
+}
+
+/// Right-aligned status segments enabled under `[appearance.status_segments]`,
+/// skipping any whose value isn't known yet
+fn status_segments(app: &App) -> Vec<Span<'static>> {
+    let config = &app.config.appearance.status_segments;
+    let mut values = Vec::new();
+    values.extend(app.search.as_ref().map(|search| search.status()));
+    if config.session {
+        values.extend(app.session_name.as_ref().map(|name| format!("[{}]", name)));
+    }
+    if config.host {
+        values.extend(app.hostname.as_ref().map(|host| format!("@{}", host)));
+    }
+    if config.clients {
+        values.extend(
+            app.client_count
+                .map(|count| format!("{} client{}", count, if count == 1 { "" } else { "s" })),
+        );
+    }
+    if config.usage {
+        values.extend(
+            app.active_channel
+                .as_ref()
+                .and_then(|channel| app.usage.get(channel))
+                .and_then(|&(cpu, rss)| usage_label(cpu, rss)),
+        );
+    }
+    if config.clock {
+        // A bad format fails to render rather than panicking
+        let mut clock = String::new();
+        if write!(clock, "{}", Local::now().format(&config.clock_format)).is_ok() {
+            values.push(clock);
+        }
+    }
+
+    let mut spans = Vec::new();
+    for value in values {
+        if !spans.is_empty() {
+            spans.push(Span::styled(" │ ", Style::default().fg(app.theme.muted)));
+        }
+        spans.push(Span::styled(value, Style::default().fg(app.theme.accent)));
+    }
+    spans
+}
+
+/// "cpu 12% mem 85.3M", leaving out whichever isn't known
+pub fn usage_label(cpu_percent: Option<f32>, rss_bytes: Option<u64>) -> Option<String> {
+    let cpu = cpu_percent.map(|cpu| format!("cpu {:.0}%", cpu));
+    let mem = rss_bytes.map(|bytes| format!("mem {}", format_bytes(bytes)));
+    match (cpu, mem) {
+        (Some(cpu), Some(mem)) => Some(format!("{} {}", cpu, mem)),
+        (cpu, mem) => cpu.or(mem),
+    }
+}
+
+/// Bytes in the largest unit that keeps a whole number: "512K", "85.3M"
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0}{}", value, UNITS[unit])
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
 }
 
 fn draw_output(f: &mut Frame, app: &mut App, area: Rect) {
     let mut list_items: Vec<ListItem> = Vec::new();
     let height = area.height as usize;
 
-    let format_line = |content: &str, timestamp: DateTime<Local>, show_ts: bool| -> String {
-        if show_ts {
-            format!("[{}] {}", timestamp.format("%H:%M:%S"), content)
+    if app.is_tiled() && !app.zoomed {
+        if app.view_mode == ViewMode::Grid {
+            draw_grid(f, app, area);
         } else {
-            content.to_string()
+            draw_panes(f, app, area);
         }
-    };
+        return;
+    }
 
-    if app.view_mode == ViewMode::ActiveChannel {
-        if let Some(ch) = app.active_channel.clone() {
-            if let Some(buffer) = app.channel_buffers.get(&ch) {
-                let scroll_offset = app.scroll_offsets.get(&ch).copied().unwrap_or(0);
-                let end_index = buffer.len().saturating_sub(scroll_offset);
-                let start_index = end_index.saturating_sub(height);
-
-                for line in &buffer[start_index..end_index] {
-                    let content = format_line(&line.content, line.timestamp, app.show_timestamps);
-                    list_items.push(ListItem::new(Text::raw(strip_ansi_codes(&content))));
-                }
+    if let (ViewMode::Diff, Some((left, right))) = (app.view_mode, &app.diff) {
+        draw_diff(f, app, left, right, area);
+        return;
+    }
+
+    if app.view_mode == ViewMode::ActiveChannel || app.view_mode == ViewMode::Grid {
+        if let Some(ch) = app.view_channel() {
+            list_items = channel_lines(app, ch, area);
+        }
+    } else if app.view_mode == ViewMode::Hex {
+        if let Some(raw) = app
+            .active_channel
+            .as_ref()
+            .and_then(|ch| app.raw_output.get(ch))
+        {
+            let scroll_offset = app
+                .active_channel
+                .as_ref()
+                .and_then(|ch| app.scroll_offsets.get(ch))
+                .copied()
+                .unwrap_or(0);
+            let end_row = raw.rows().saturating_sub(scroll_offset);
+            let start_row = end_row.saturating_sub(height);
+            for line in raw.hexdump(start_row, end_row) {
+                list_items.push(ListItem::new(Text::raw(line)));
             }
         }
     } else {
         // ViewMode::AllChannels
-        let buffer = &app.interleaved_buffer;
-        let scroll_offset = 0; // TODO: interleaved scroll
-        let end_index = buffer.len().saturating_sub(scroll_offset);
-        let start_index = end_index.saturating_sub(height);
-
-        // Fix slice range
-        let start = start_index.min(buffer.len());
-        let end = end_index.min(buffer.len());
-
-        let visible_items: Vec<(String, String, DateTime<Local>)> = buffer[start..end]
-            .iter()
-            .map(|(n, l)| (n.clone(), l.content.clone(), l.timestamp))
+        let len = app.interleaved_buffer.len();
+        let end = len.saturating_sub(app.interleaved_scroll);
+        let start = end.saturating_sub(height);
+
+        // Colors are looked up first, since assigning one needs `app` mutably
+        let colors: Vec<_> = (start..end)
+            .map(|i| {
+                let name = app.interleaved_buffer[i].0.clone();
+                app.get_channel_color(&name)
+            })
             .collect();
-
-        for (ch_name, content_str, timestamp) in visible_items {
-            let content = format_line(&content_str, timestamp, app.show_timestamps);
-            let color = app.get_channel_color(&ch_name);
-
-            let text = Text::raw(strip_ansi_codes(&content));
-            for mut line_content in text.lines {
-                line_content.spans.insert(
-                    0,
-                    Span::styled(format!("#{:<8} │ ", ch_name), Style::default().fg(color)),
+        let window = &app.interleaved_buffer[start..end];
+
+        let wrap = app.config.appearance.line_wrap;
+        let now = Local::now();
+        let mut rows = Vec::new();
+        // Newest first; every line takes at least one row
+        for (i, ((ch_name, line), color)) in window.iter().zip(colors).enumerate().rev() {
+            let number = app.interleaved_trimmed + start + i;
+            let content = if app.show_timestamps {
+                let stamp = app.timestamp_label(ch_name, line.timestamp, now);
+                format!("[{}] {}", stamp, line.content)
+            } else {
+                line.content.clone()
+            };
+            let stream = line.stream;
+            let timestamp = line.timestamp;
+
+            // Rows after the first keep the column of the `│`
+            let prefix = format!("#{:<8} │ ", ch_name);
+            let prefix_width = Span::raw(prefix.as_str()).width();
+            let continuation = format!("{:1$}│ ", "", prefix_width.saturating_sub(2));
+            let mut line = styled_line(&content, stream_style(app, stream), app.color_support);
+            if let Some(search) = app.search.as_ref().filter(|s| s.channel.is_none()) {
+                line = search::highlight(line, &search.regex, match_style(app, search, number));
+            }
+            let width = (area.width as usize).saturating_sub(prefix_width);
+            let mut fitted = fit_line(line, width, wrap);
+            for (i, row) in fitted.iter_mut().enumerate() {
+                let lead = if i == 0 { &prefix } else { &continuation };
+                row.spans
+                    .insert(0, Span::styled(lead.clone(), Style::default().fg(color)));
+            }
+            if let Some((_, previous)) = (start + i)
+                .checked_sub(1)
+                .map(|i| &app.interleaved_buffer[i])
+            {
+                fitted.splice(
+                    0..0,
+                    gap_separator(app, previous.timestamp, timestamp, area.width),
                 );
-                list_items.push(ListItem::new(line_content));
             }
+            rows.push(fitted);
         }
+        list_items = stack_rows(rows, height);
     }
 
     if list_items.is_empty() && app.show_welcome {
@@ -185,7 +711,7 @@ fn draw_output(f: &mut Frame, app: &mut App, area: Rect) {
             "  :quit              Exit",
         ];
         let p = Paragraph::new(Text::from(welcome_text.join("\n")))
-            .style(Style::default().fg(Color::DarkGray))
+            .style(Style::default().fg(app.theme.muted))
             .block(Block::default());
         f.render_widget(p, area);
     } else {
@@ -193,30 +719,255 @@ fn draw_output(f: &mut Frame, app: &mut App, area: Rect) {
     }
 }
 
+/// Draw each pane of a split layout, titled with its channel. The focused
+/// pane shows the active channel.
+fn draw_panes(f: &mut Frame, app: &App, area: Rect) {
+    for (pane_area, channel, focused) in app.panes.panes(area) {
+        let channel = match (app.view_channel(), focused) {
+            (Some(active), true) => active,
+            _ => channel,
+        };
+        draw_pane(f, app, channel, focused, pane_area, area);
+    }
+}
+
+/// Tile every subscribed channel over the output area, highlighting the
+/// active one
+fn draw_grid(f: &mut Frame, app: &App, area: Rect) {
+    let channels = app.grid_channels();
+    if channels.is_empty() {
+        let p = Paragraph::new("No subscribed channels to show (:sub <channel>)")
+            .style(Style::default().fg(app.theme.muted));
+        f.render_widget(p, area);
+        return;
+    }
+    for (pane_area, channel) in grid(area, channels.len()).into_iter().zip(&channels) {
+        let focused = app.view_channel() == Some(channel.as_str());
+        draw_pane(f, app, channel, focused, pane_area, area);
+    }
+}
+
+/// One titled pane of a split or grid; panes away from the left edge of the
+/// output area get a separating border
+fn draw_pane(f: &mut Frame, app: &App, channel: &str, focused: bool, area: Rect, outer: Rect) {
+    let title_style = if focused {
+        Style::default()
+            .fg(app.theme.active)
+            .add_modifier(Modifier::BOLD)
+    } else {
+        Style::default().fg(app.theme.muted)
+    };
+    let mut borders = Borders::TOP;
+    if area.x > outer.x {
+        borders |= Borders::LEFT;
+    }
+    let block = Block::default()
+        .borders(borders)
+        .border_style(Style::default().fg(app.theme.border))
+        .title(Span::styled(format!(" #{} ", channel), title_style));
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    f.render_widget(List::new(channel_lines(app, channel, inner)), inner);
+}
+
+/// Two channels' latest output side by side, lined up on their common lines.
+/// Lines only one side has are drawn in the error color, bold.
+fn draw_diff(f: &mut Frame, app: &App, left: &str, right: &str, area: Rect) {
+    let recent = |channel: &str| -> &[BufferedLine] {
+        let buffer = app.channel_buffers.get(channel).map_or(&[][..], |b| b);
+        &buffer[buffer.len().saturating_sub(DIFF_LINES)..]
+    };
+    let sides = [recent(left), recent(right)];
+    let text = sides.map(|lines| {
+        lines
+            .iter()
+            .map(|line| strip_ansi_codes(&line.content))
+            .collect::<Vec<_>>()
+    });
+    let rows = diff::side_by_side(&text[0], &text[1]);
+
+    let halves = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(area);
+    let changed = Style::default()
+        .fg(app.theme.error)
+        .add_modifier(Modifier::BOLD);
+    for (side, (channel, half)) in [left, right].into_iter().zip(halves.iter()).enumerate() {
+        let borders = if side == 0 {
+            Borders::TOP
+        } else {
+            Borders::TOP | Borders::LEFT
+        };
+        let block = Block::default()
+            .borders(borders)
+            .border_style(Style::default().fg(app.theme.border))
+            .title(Span::styled(
+                format!(" #{} ", channel),
+                Style::default().fg(app.theme.active),
+            ));
+        let inner = block.inner(*half);
+        f.render_widget(block, *half);
+
+        let height = inner.height as usize;
+        let end = rows.len().saturating_sub(app.diff_scroll);
+        let items: Vec<ListItem> = rows[end.saturating_sub(height)..end]
+            .iter()
+            .map(|row| {
+                let index = if side == 0 { row.left } else { row.right };
+                let line = match index {
+                    Some(i) if row.same => {
+                        let line = &sides[side][i];
+                        styled_line(
+                            &line.content,
+                            stream_style(app, line.stream),
+                            app.color_support,
+                        )
+                    }
+                    Some(i) => Line::from(Span::styled(text[side][i].clone(), changed)),
+                    None => Line::default(),
+                };
+                let fitted = fit_line(line, inner.width as usize, false);
+                ListItem::new(Text::from(fitted))
+            })
+            .collect();
+        f.render_widget(List::new(items), inner);
+    }
+}
+
+/// Rows filling `area` with a channel's buffer up to its scroll offset,
+/// wrapped or truncated to the area's width per `line_wrap`. The scroll
+/// offset counts buffer lines, so it stays put when the width changes.
+fn channel_lines(app: &App, channel: &str, area: Rect) -> Vec<ListItem<'static>> {
+    let Some(buffer) = app.channel_buffers.get(channel) else {
+        return Vec::new();
+    };
+    let height = area.height as usize;
+    let scroll_offset = app.scroll_offsets.get(channel).copied().unwrap_or(0);
+    let trimmed = app.trimmed.get(channel).copied().unwrap_or(0);
+    let now = Local::now();
+
+    // Only the lines that end up on screen are formatted
+    let rows = folds::visible_rev(buffer).skip(scroll_offset).map(|shown| {
+        let line = &buffer[shown.index];
+        let content = if app.show_timestamps {
+            let stamp = app.timestamp_label(channel, line.timestamp, now);
+            Cow::Owned(format!("[{}] {}", stamp, line.content))
+        } else {
+            Cow::Borrowed(line.content.as_str())
+        };
+        let mut styled = styled_line(&content, stream_style(app, line.stream), app.color_support);
+        if let Some(search) = app
+            .search
+            .as_ref()
+            .filter(|s| s.channel.as_deref() == Some(channel))
+        {
+            let style = match_style(app, search, trimmed + shown.index);
+            styled = search::highlight(styled, &search.regex, style);
+        }
+        if shown.folded > 0 {
+            styled.spans.push(Span::styled(
+                format!(" ▸ {} lines folded", shown.folded),
+                Style::default().fg(app.theme.muted),
+            ));
+        }
+        let mut rows = fit_line(styled, area.width as usize, app.config.appearance.line_wrap);
+        if let Some(previous) = shown.index.checked_sub(1).map(|i| &buffer[i]) {
+            rows.splice(
+                0..0,
+                gap_separator(app, previous.timestamp, line.timestamp, area.width),
+            );
+        }
+        rows
+    });
+    stack_rows(rows, height)
+}
+
+/// A muted rule across the output marking a pause of at least
+/// `appearance.gap_separator_secs` between two lines, if there was one
+fn gap_separator(
+    app: &App,
+    before: DateTime<Local>,
+    after: DateTime<Local>,
+    width: u16,
+) -> Option<Line<'static>> {
+    let threshold = app.config.appearance.gap_separator_secs;
+    let secs = (after - before).num_seconds();
+    if threshold == 0 || secs < threshold as i64 {
+        return None;
+    }
+    let label = format!("──── {} later ", timestamps::duration(secs));
+    let rule = format!(
+        "{}{}",
+        label,
+        "─".repeat((width as usize).saturating_sub(label.width()))
+    );
+    Some(Line::styled(
+        truncate_str(&rule, width as usize).to_string(),
+        Style::default()
+            .fg(app.theme.muted)
+            .add_modifier(Modifier::DIM),
+    ))
+}
+
+/// Highlight for search matches on line `number`: the selection colors for
+/// the match in view, reversed video for the rest
+fn match_style(app: &App, search: &search::Search, number: usize) -> Style {
+    if search.is_current(number) {
+        Style::default()
+            .fg(app.theme.selection_fg)
+            .bg(app.theme.selection_bg)
+    } else {
+        Style::default().add_modifier(Modifier::REVERSED)
+    }
+}
+
+/// Stack the rows of lines given newest first, keeping the bottom `height`
+/// rows in display order
+fn stack_rows(
+    lines: impl IntoIterator<Item = Vec<Line<'static>>>,
+    height: usize,
+) -> Vec<ListItem<'static>> {
+    let mut rows = Vec::new();
+    for line in lines {
+        if rows.len() >= height {
+            break;
+        }
+        rows.extend(line.into_iter().rev());
+    }
+    rows.truncate(height);
+    rows.into_iter().rev().map(ListItem::new).collect()
+}
+
+/// Base style for an output line; stderr from pipe-mode channels takes the
+/// theme's error color
+fn stream_style(app: &App, stream: OutputStream) -> Style {
+    match stream {
+        OutputStream::Stdout => Style::default(),
+        OutputStream::Stderr => Style::default().fg(app.theme.error),
+    }
+}
+
 fn draw_input(f: &mut Frame, app: &App, area: Rect) {
     let channel_name = app.active_channel.as_deref().unwrap_or("none");
 
     // Construct prompt: #channel ❯ input
     let prompt_prefix = format!("#{} ❯ ", channel_name);
-    let prefix_len = prompt_prefix.chars().count();
+    let prefix_width = prompt_prefix.width();
 
     let input_content = app.line_editor.content();
-    let _full_line = format!("{}{}", prompt_prefix, input_content);
 
     let p = Paragraph::new(Line::from(vec![
         Span::styled(
             format!("#{}", channel_name),
-            Style::default().fg(Color::Cyan),
+            Style::default().fg(app.theme.prompt_channel),
         ),
-        Span::styled(" ❯ ", Style::default().fg(Color::Green)),
+        Span::styled(" ❯ ", Style::default().fg(app.theme.prompt_symbol)),
         Span::raw(input_content),
     ]));
 
     f.render_widget(p, area);
 
-    let cursor_char_idx = input_content[..app.line_editor.cursor_position()]
-        .chars()
-        .count();
-    let cursor_x = area.x + prefix_len as u16 + cursor_char_idx as u16;
+    let cursor_x = area.x + (prefix_width + app.line_editor.cursor_column()) as u16;
     f.set_cursor_position(Position::new(cursor_x, area.y));
 }
@@ -0,0 +1,156 @@
+//! Fitting output lines to the view width - soft wrapping, or truncation
+//! with an ellipsis when `line_wrap` is off. Text is measured a grapheme
+//! cluster at a time, so combining marks and emoji sequences stay whole.
+
+use ratatui::text::{Line, Span};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Display width of a grapheme cluster; control characters take no room
+fn grapheme_width(grapheme: &str) -> usize {
+    if grapheme.chars().all(char::is_control) {
+        0
+    } else {
+        grapheme.width()
+    }
+}
+
+/// Cut plain text to at most `width` columns
+pub fn truncate_str(text: &str, width: usize) -> &str {
+    let mut used = 0;
+    for (i, grapheme) in text.grapheme_indices(true) {
+        used += grapheme_width(grapheme);
+        if used > width {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+/// Split a styled line into rows at most `width` columns wide, keeping each
+/// span's style. A wide character never straddles two rows. An empty line
+/// still takes one row.
+pub fn wrap_line(line: Line<'static>, width: usize) -> Vec<Line<'static>> {
+    let width = width.max(1);
+    let style = line.style;
+    let mut rows = Vec::new();
+    let mut row: Vec<Span<'static>> = Vec::new();
+    let mut used = 0;
+
+    for span in line.spans {
+        let mut piece = String::new();
+        for grapheme in span.content.graphemes(true) {
+            let w = grapheme_width(grapheme);
+            if used + w > width && used > 0 {
+                if !piece.is_empty() {
+                    row.push(Span::styled(std::mem::take(&mut piece), span.style));
+                }
+                rows.push(Line::from(std::mem::take(&mut row)).style(style));
+                used = 0;
+            }
+            piece.push_str(grapheme);
+            used += w;
+        }
+        if !piece.is_empty() {
+            row.push(Span::styled(piece, span.style));
+        }
+    }
+    if !row.is_empty() || rows.is_empty() {
+        rows.push(Line::from(row).style(style));
+    }
+    rows
+}
+
+/// Cut a styled line to `width` columns, ending it with `…` if anything was
+/// cut off
+pub fn truncate_line(line: Line<'static>, width: usize) -> Line<'static> {
+    if line.width() <= width {
+        return line;
+    }
+    let style = line.style;
+    let budget = width.saturating_sub(1);
+    let mut spans = Vec::new();
+    let mut used = 0;
+    'spans: for span in line.spans {
+        let mut piece = String::new();
+        for grapheme in span.content.graphemes(true) {
+            let w = grapheme_width(grapheme);
+            if used + w > budget {
+                if !piece.is_empty() {
+                    spans.push(Span::styled(piece, span.style));
+                }
+                break 'spans;
+            }
+            piece.push_str(grapheme);
+            used += w;
+        }
+        if !piece.is_empty() {
+            spans.push(Span::styled(piece, span.style));
+        }
+    }
+    if width > 0 {
+        spans.push(Span::raw("…"));
+    }
+    Line::from(spans).style(style)
+}
+
+/// Rows for `line` in a view `width` columns wide
+pub fn fit_line(line: Line<'static>, width: usize, wrap: bool) -> Vec<Line<'static>> {
+    if wrap {
+        wrap_line(line, width)
+    } else {
+        vec![truncate_line(line, width)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::style::{Color, Style};
+
+    fn text(line: &Line) -> String {
+        line.spans
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect()
+    }
+
+    #[test]
+    fn wraps_at_width_keeping_span_styles() {
+        let red = Style::default().fg(Color::Red);
+        let line = Line::from(vec![Span::raw("abcd"), Span::styled("efgh", red)]);
+
+        let rows = wrap_line(line, 3);
+
+        assert_eq!(
+            rows.iter().map(text).collect::<Vec<_>>(),
+            vec!["abc", "def", "gh"]
+        );
+        assert_eq!(rows[1].spans[1].style, red);
+        assert_eq!(wrap_line(Line::default(), 3).len(), 1);
+    }
+
+    #[test]
+    fn wide_characters_move_to_the_next_row() {
+        let rows = wrap_line(Line::raw("ab界c"), 3);
+        assert_eq!(rows.iter().map(text).collect::<Vec<_>>(), vec!["ab", "界c"]);
+    }
+
+    #[test]
+    fn grapheme_clusters_stay_whole() {
+        // "e" with a combining acute accent, and a ZWJ emoji sequence
+        let rows = wrap_line(Line::raw("ae\u{301}👩\u{200d}🔬b"), 2);
+        assert_eq!(
+            rows.iter().map(text).collect::<Vec<_>>(),
+            vec!["ae\u{301}", "👩\u{200d}🔬", "b"]
+        );
+        assert_eq!(truncate_str("日本語", 5), "日本");
+        assert_eq!(truncate_str("e\u{301}x", 1), "e\u{301}");
+    }
+
+    #[test]
+    fn truncates_with_an_ellipsis() {
+        assert_eq!(text(&truncate_line(Line::raw("abcdef"), 4)), "abc…");
+        assert_eq!(text(&truncate_line(Line::raw("abc"), 4)), "abc");
+    }
+}
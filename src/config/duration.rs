@@ -0,0 +1,130 @@
+//! Durations written the way people type them: `90s`, `30m`, `1h30m`, `2d`
+
+/// Seconds in a duration such as `30m` or `1h30m`; a bare number is seconds
+pub fn parse(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let invalid = || {
+        format!(
+            "Invalid duration '{}' (expected e.g. 90s, 30m, 1h30m)",
+            text
+        )
+    };
+    if let Ok(secs) = text.parse() {
+        return (secs > 0).then_some(secs).ok_or_else(invalid);
+    }
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(invalid()),
+        };
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        total = value
+            .checked_mul(unit)
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(invalid)?;
+        digits.clear();
+    }
+    if !digits.is_empty() || total == 0 {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+/// The shortest form [`parse`] reads back: `1h30m`, `45s`
+pub fn format(secs: u64) -> String {
+    if secs == 0 {
+        return "0s".to_string();
+    }
+    [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)]
+        .iter()
+        .fold((String::new(), secs), |(mut text, left), &(unit, size)| {
+            if left >= size {
+                text.push_str(&format!("{}{}", left / size, unit));
+            }
+            (text, left % size)
+        })
+        .0
+}
+
+/// Serde for a duration, written as a string (`"30m"`) but also read from a
+/// number of seconds
+pub mod secs {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    pub(super) enum Raw {
+        Secs(u64),
+        Text(String),
+    }
+
+    impl Raw {
+        pub(super) fn secs<E: de::Error>(self) -> Result<u64, E> {
+            match self {
+                Raw::Secs(0) => Err(E::custom("Invalid duration 0 (must be at least 1s)")),
+                Raw::Secs(secs) => Ok(secs),
+                Raw::Text(text) => super::parse(&text).map_err(E::custom),
+            }
+        }
+    }
+
+    pub fn serialize<S: Serializer>(secs: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::format(*secs))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        Raw::deserialize(deserializer)?.secs()
+    }
+}
+
+/// [`secs`] for an optional duration
+pub mod option {
+    use super::secs::Raw;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(secs: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match secs {
+            Some(secs) => super::secs::serialize(secs, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        Option::<Raw>::deserialize(deserializer)?
+            .map(Raw::secs)
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats() {
+        assert_eq!(parse("30m"), Ok(1800));
+        assert_eq!(parse("1h30m"), Ok(5400));
+        assert_eq!(parse("2d"), Ok(172_800));
+        assert_eq!(parse("45"), Ok(45));
+        assert!(parse("30x").is_err());
+        assert!(parse("1h30").is_err());
+        assert!(parse("m").is_err());
+        assert!(parse("0m").is_err());
+        assert!(parse("0").is_err());
+
+        assert_eq!(format(5400), "1h30m");
+        assert_eq!(format(86_461), "1d1m1s");
+        assert_eq!(parse(&format(93_784)), Ok(93_784));
+    }
+}
@@ -1,7 +1,16 @@
 //! Configuration management
 
-use anyhow::Result;
+pub mod duration;
+mod profile;
+
+pub use profile::{Profile, ProfileChannel, ProfileLayout, DEFAULT_PROFILE_FILE};
+
+use crate::channel::schedule::Schedule;
+use crate::channel::{ChannelConfig, ChannelMode, RestartPolicy};
+use crate::protocol::{NotificationKind, NotificationPrefs};
+use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 /// Main configuration structure
@@ -10,8 +19,213 @@ use std::path::PathBuf;
 pub struct Config {
     pub general: GeneralConfig,
     pub appearance: AppearanceConfig,
+    pub theme: ThemeConfig,
     pub keybindings: KeybindingsConfig,
     pub notifications: NotificationsConfig,
+
+    /// Reusable channel templates, keyed by name (`[templates.<name>]`)
+    pub templates: BTreeMap<String, ChannelTemplate>,
+
+    /// Channels created when a session server starts
+    pub startup: StartupConfig,
+
+    /// Command aliases: `b = ":new build cargo build"` makes `:b` run the expansion
+    pub aliases: BTreeMap<String, String>,
+
+    /// Saved macros: input lines replayed in order by `:macro play <name>`
+    pub macros: BTreeMap<String, Vec<String>>,
+
+    /// Shell commands the server runs on events
+    pub hooks: HooksConfig,
+
+    /// Optional HTTP control API served by each session server
+    pub http: HttpConfig,
+
+    /// Optional TCP listener for attaching from other machines
+    pub tcp: TcpConfig,
+
+    /// Permissions of the session's Unix socket
+    pub socket: SocketConfig,
+
+    /// Which clients may type into or kill particular channels, keyed by
+    /// channel name (`[acl.<channel>]`)
+    pub acl: BTreeMap<String, ChannelAcl>,
+}
+
+/// Something a client can do to a channel that an ACL can restrict
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelAction {
+    Input,
+    Kill,
+}
+
+/// Access rules for one channel (`[acl.<channel>]`). Each list names the
+/// clients allowed to take that action, by local user name (Unix socket) or
+/// certificate common name (mutual TLS); leaving a list out keeps the action
+/// open to every client, an empty list closes it to all.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelAcl {
+    /// Identities that may send input to the channel
+    pub input: Option<Vec<String>>,
+
+    /// Identities that may kill the channel
+    pub kill: Option<Vec<String>>,
+}
+
+impl ChannelAcl {
+    /// Whether a client known as `identity` (`None` if the transport can't
+    /// tell) may take `action`
+    pub fn allows(&self, action: ChannelAction, identity: Option<&str>) -> bool {
+        let allowed = match action {
+            ChannelAction::Input => &self.input,
+            ChannelAction::Kill => &self.kill,
+        };
+        match allowed {
+            None => true,
+            Some(identities) => {
+                identity.is_some_and(|identity| identities.iter().any(|i| i == identity))
+            }
+        }
+    }
+}
+
+/// TCP transport settings (`[tcp]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TcpConfig {
+    /// Accept clients over TCP in addition to the Unix socket (off by default)
+    pub enabled: bool,
+
+    /// Address to listen on, e.g. `0.0.0.0:7421` to accept remote clients
+    pub bind: String,
+
+    /// Shared secret clients must send in `Hello`. When unset, the server
+    /// generates one and writes it to `<session>.tcp-token` in the runtime dir.
+    pub token: Option<String>,
+
+    /// Server certificate chain (PEM); setting `cert` and `key` enables TLS
+    pub cert: Option<PathBuf>,
+
+    /// Private key for `cert` (PEM)
+    pub key: Option<PathBuf>,
+
+    /// CA bundle (PEM) that client certificates must chain to; enables mutual TLS
+    pub client_ca: Option<PathBuf>,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: "127.0.0.1:7421".to_string(),
+            token: None,
+            cert: None,
+            key: None,
+            client_ca: None,
+        }
+    }
+}
+
+/// Unix socket permissions (`[socket]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SocketConfig {
+    /// Permission bits in octal. The default, "0600", keeps the session to
+    /// its owner; "0660" with `group` set shares it with that group.
+    pub mode: String,
+
+    /// Group (name or numeric id) to give the socket
+    pub group: Option<String>,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            mode: "0600".to_string(),
+            group: None,
+        }
+    }
+}
+
+impl SocketConfig {
+    /// `mode` as permission bits
+    pub fn mode_bits(&self) -> Result<u32> {
+        let digits = self.mode.trim_start_matches("0o");
+        u32::from_str_radix(digits, 8)
+            .ok()
+            .filter(|&mode| mode <= 0o777)
+            .ok_or_else(|| anyhow!("Invalid socket mode '{}', expected e.g. 0660", self.mode))
+    }
+}
+
+/// HTTP control API settings (`[http]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Serve the API (off by default)
+    pub enabled: bool,
+
+    /// Address to listen on; keep this on localhost
+    pub bind: String,
+
+    /// Bearer token required on every request. When unset, the server
+    /// generates one and writes it to `<session>.http-token` in the runtime dir.
+    pub token: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: "127.0.0.1:7420".to_string(),
+            token: None,
+        }
+    }
+}
+
+/// Shell commands run by the server when events happen (`[hooks]`).
+///
+/// Commands run with `sh -c` and get `NEXUS_EVENT`, `NEXUS_SESSION` and
+/// event-specific variables such as `NEXUS_CHANNEL` in their environment.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Run after a channel is created
+    pub channel_created: Vec<String>,
+
+    /// Run when a channel's process exits (`NEXUS_EXIT_CODE` is set when known)
+    pub channel_exited: Vec<String>,
+
+    /// Run when a client attaches (`NEXUS_CLIENT_ID`)
+    pub client_attached: Vec<String>,
+
+    /// Run when a line of channel output matches a pattern (`[[hooks.output_match]]`)
+    pub output_match: Vec<OutputMatchHook>,
+}
+
+/// A hook triggered by channel output matching a regex
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputMatchHook {
+    /// Regex matched against each output line (`NEXUS_MATCH` holds the line)
+    pub pattern: String,
+
+    /// Only watch this channel (all channels when unset)
+    pub channel: Option<String>,
+
+    pub command: String,
+}
+
+/// Channels to create automatically when a session server first starts
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StartupConfig {
+    /// Channels to create, in order (`[[startup.channels]]`)
+    pub channels: Vec<ProfileChannel>,
+
+    /// Channel to make active (defaults to the first one created)
+    pub focus: Option<String>,
 }
 
 /// General settings
@@ -24,8 +238,29 @@ pub struct GeneralConfig {
     /// Maximum lines to keep in scrollback
     pub history_limit: usize,
 
+    /// Maximum bytes of scrollback to keep per channel, on the server and
+    /// in the client; longer lines are cut to fit
+    pub history_bytes: usize,
+
+    /// Compress older scrollback on the server (zstd)
+    pub compress_scrollback: bool,
+
+    /// Append scrollback evicted from memory to a file per channel, so a
+    /// client resuming after a long gap still gets everything it missed
+    pub spill_scrollback: bool,
+
     /// Socket directory
     pub runtime_dir: Option<PathBuf>,
+
+    /// Host new sessions in an already running server process instead of
+    /// spawning one `nexus-server` per session
+    pub shared_server: bool,
+
+    /// Share `:mark`s with the session so other clients can `:jump` to them
+    pub share_marks: bool,
+
+    /// Clipboard used for copying selections and `:copy`/`:paste`
+    pub clipboard: ClipboardMode,
 }
 
 impl Default for GeneralConfig {
@@ -33,7 +268,13 @@ impl Default for GeneralConfig {
         Self {
             default_shell: std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string()),
             history_limit: 10000,
+            history_bytes: 8 * 1024 * 1024,
+            compress_scrollback: true,
+            spill_scrollback: false,
             runtime_dir: None,
+            shared_server: false,
+            share_marks: false,
+            clipboard: ClipboardMode::Auto,
         }
     }
 }
@@ -48,6 +289,9 @@ pub struct AppearanceConfig {
     /// Show timestamps in output
     pub show_timestamps: bool,
 
+    /// What the timestamps show
+    pub timestamp_mode: TimestampMode,
+
     /// Color-code channels
     pub channel_colors: bool,
 
@@ -56,6 +300,37 @@ pub struct AppearanceConfig {
 
     /// Show channel numbers in status bar for Alt+N shortcuts
     pub show_channel_numbers: bool,
+
+    /// Optional segments at the right of the status bar
+    /// (`[appearance.status_segments]`)
+    pub status_segments: StatusSegmentsConfig,
+
+    /// Colors the terminal can show; colors in output and themes are reduced
+    /// to fit
+    pub color_depth: ColorDepth,
+
+    /// Regex for lines that start a foldable output section, matched
+    /// without color codes; empty disables folding
+    pub fold_pattern: String,
+
+    /// Regexes for error-looking output lines, which the next/previous
+    /// error keys jump between
+    pub error_patterns: Vec<String>,
+
+    /// Most redraws a second, so output floods don't redraw on every chunk;
+    /// 0 draws on every change
+    pub max_fps: u32,
+
+    /// Lines scrolled per mouse wheel notch
+    pub scroll_lines: usize,
+
+    /// Seconds of silence between two output lines that get a separator
+    /// showing the pause; 0 turns separators off
+    pub gap_separator_secs: u64,
+
+    /// Show the command running in each channel's foreground (`vim`,
+    /// `cargo`...) next to it in the status bar
+    pub show_foreground: bool,
 }
 
 impl Default for AppearanceConfig {
@@ -63,13 +338,140 @@ impl Default for AppearanceConfig {
         Self {
             status_bar_position: StatusBarPosition::Top,
             show_timestamps: false,
+            timestamp_mode: TimestampMode::Clock,
             channel_colors: true,
             line_wrap: true,
             show_channel_numbers: true,
+            status_segments: StatusSegmentsConfig::default(),
+            color_depth: ColorDepth::Auto,
+            fold_pattern: r"^\S*[$#%>❯] ".to_string(),
+            error_patterns: [
+                r"(?i)\berror\b",
+                r"\bFAIL(ED|URE)?\b",
+                r"(?i)^\s*fail(ed|ure)?\b",
+                r"\bpanicked at\b",
+                r"^Traceback ",
+                r"\bException\b",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            max_fps: 60,
+            scroll_lines: 3,
+            gap_separator_secs: 60,
+            show_foreground: true,
         }
     }
 }
 
+/// Right-aligned status bar segments, each off by default
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatusSegmentsConfig {
+    /// Current local time, formatted with `clock_format`
+    pub clock: bool,
+
+    /// strftime format for the clock, e.g. "%H:%M:%S"
+    pub clock_format: String,
+
+    /// Name of the attached session
+    pub session: bool,
+
+    /// Hostname of the machine running the client
+    pub host: bool,
+
+    /// Number of clients attached to the session
+    pub clients: bool,
+
+    /// CPU and memory use of the active channel's processes
+    pub usage: bool,
+}
+
+impl Default for StatusSegmentsConfig {
+    fn default() -> Self {
+        Self {
+            clock: false,
+            clock_format: "%H:%M".to_string(),
+            session: false,
+            host: false,
+            clients: false,
+            usage: false,
+        }
+    }
+}
+
+/// Color scheme (`[theme]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    /// Bundled scheme: "dark", "light", "solarized" or "gruvbox"
+    pub name: String,
+
+    /// Colors overriding the scheme's, keyed by role (`active = "#b8bb26"`).
+    /// Values are color names, `#rrggbb` or 256-color indexes.
+    pub colors: BTreeMap<String, String>,
+
+    /// Palette channels rotate through instead of the scheme's own:
+    /// "basic", "bright" or "colorblind"
+    pub palette: Option<String>,
+
+    /// Fixed colors for channels, keyed by name or glob (`"test-*" = "yellow"`).
+    /// Exact names win over globs.
+    pub channels: BTreeMap<String, String>,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        Self {
+            name: "dark".to_string(),
+            colors: BTreeMap::new(),
+            palette: None,
+            channels: BTreeMap::new(),
+        }
+    }
+}
+
+/// How many colors the terminal supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorDepth {
+    /// Detect from `COLORTERM` and `TERM`
+    #[default]
+    Auto,
+    /// 24-bit RGB
+    Truecolor,
+    /// The 256-color palette
+    Ansi256,
+    /// The 16 basic colors
+    Ansi16,
+}
+
+/// Which clipboard copy and paste use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardMode {
+    /// The native clipboard, or the terminal's over SSH or when there is none
+    #[default]
+    Auto,
+    /// The system clipboard (X11, Wayland via XWayland, macOS, Windows)
+    Native,
+    /// The terminal's clipboard through OSC 52 escapes; copying only
+    Osc52,
+}
+
+/// What output timestamps show
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampMode {
+    /// Time of day the line came
+    #[default]
+    Clock,
+    /// How long ago the line came ("2m ago")
+    Relative,
+    /// Time since the channel's process started ("+01:05")
+    Elapsed,
+}
+
 /// Status bar position
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
 #[serde(rename_all = "lowercase")]
@@ -80,24 +482,134 @@ pub enum StatusBarPosition {
 }
 
 /// Keybinding configuration
+///
+/// Each action takes one or more comma-separated key specs such as
+/// `"ctrl+n"`, `"alt+1"`, `"ctrl+left"` or `"pageup"`. Set an action to
+/// `""` to unbind it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct KeybindingsConfig {
     pub next_channel: String,
     pub prev_channel: String,
     pub clear_screen: String,
+    pub quit: String,
+    /// Detach, leaving the session running
+    pub detach: String,
+    /// Cancel input, or send Ctrl+C to the channel when input is empty
+    pub interrupt: String,
+    /// Send EOF to the channel (only when input is empty)
+    pub eof: String,
+    pub scroll_page_up: String,
+    pub scroll_page_down: String,
+    pub scroll_half_up: String,
+    pub scroll_half_down: String,
+    pub scroll_top: String,
+    pub scroll_bottom: String,
+    pub line_start: String,
+    pub line_end: String,
+    pub delete_word: String,
+    pub delete_to_end: String,
+    pub delete_to_start: String,
+    pub complete: String,
+    pub toggle_view: String,
+    /// Open the fuzzy command palette
+    pub command_palette: String,
+    /// Move focus between split panes
+    pub focus_next_pane: String,
+    pub focus_prev_pane: String,
+    /// Maximize the focused pane of a split or grid, or restore the layout
+    pub toggle_zoom: String,
+    /// Show the channel sidebar and move the keyboard to it, or hide it
+    pub toggle_sidebar: String,
+    /// Fold or unfold the output section at the bottom of the view
+    pub toggle_fold: String,
+    /// Scroll to the next/previous line matching `appearance.error_patterns`
+    pub next_error: String,
+    pub prev_error: String,
+    /// Copy the output of the most recent command (found by
+    /// `appearance.fold_pattern`) to the clipboard
+    pub copy_last_output: String,
+    /// Interrupt the command in the active channel's foreground, leaving
+    /// the shell running
+    pub stop_foreground: String,
+    /// Scroll to the next/previous match of the `:search`
+    pub search_next: String,
+    pub search_prev: String,
+    /// Modifier used with 1-9 to jump to a channel by number ("none" to disable)
+    pub channel_number_modifier: String,
+
+    /// tmux-style prefix key (e.g. "ctrl+b"); empty disables prefix mode
+    pub prefix: String,
+
+    /// Keys accepted after the prefix, mapped to action names
+    /// (e.g. `n = "next_channel"`, `"1" = "channel_1"`, `"ctrl+b" = "send_prefix"`)
+    pub prefixed: BTreeMap<String, String>,
 }
 
 impl Default for KeybindingsConfig {
     fn default() -> Self {
         Self {
-            next_channel: "ctrl+n".to_string(),
-            prev_channel: "ctrl+p".to_string(),
+            next_channel: "ctrl+n, ctrl+right".to_string(),
+            prev_channel: "ctrl+left".to_string(),
             clear_screen: "ctrl+l".to_string(),
+            quit: "ctrl+\\".to_string(),
+            detach: "alt+d".to_string(),
+            interrupt: "ctrl+c".to_string(),
+            eof: "ctrl+d".to_string(),
+            scroll_page_up: "pageup".to_string(),
+            scroll_page_down: "pagedown".to_string(),
+            scroll_half_up: "ctrl+u".to_string(),
+            scroll_half_down: "ctrl+b".to_string(),
+            scroll_top: "home".to_string(),
+            scroll_bottom: "end".to_string(),
+            line_start: "ctrl+a, home".to_string(),
+            line_end: "ctrl+e, end".to_string(),
+            delete_word: "ctrl+w".to_string(),
+            delete_to_end: "ctrl+k".to_string(),
+            delete_to_start: "ctrl+u".to_string(),
+            complete: "tab".to_string(),
+            toggle_view: "tab".to_string(),
+            command_palette: "ctrl+p".to_string(),
+            focus_next_pane: "alt+right".to_string(),
+            focus_prev_pane: "alt+left".to_string(),
+            toggle_zoom: "alt+z".to_string(),
+            toggle_sidebar: "alt+s".to_string(),
+            toggle_fold: "alt+f".to_string(),
+            next_error: "alt+n".to_string(),
+            prev_error: "alt+p".to_string(),
+            copy_last_output: "alt+c".to_string(),
+            stop_foreground: "alt+x".to_string(),
+            search_next: "f3".to_string(),
+            search_prev: "shift+f3".to_string(),
+            channel_number_modifier: "alt".to_string(),
+            prefix: String::new(),
+            prefixed: default_prefixed_bindings(),
         }
     }
 }
 
+fn default_prefixed_bindings() -> BTreeMap<String, String> {
+    let mut bindings: BTreeMap<String, String> = [
+        ("n", "next_channel"),
+        ("p", "prev_channel"),
+        ("l", "clear_screen"),
+        ("d", "detach"),
+        ("[", "scroll_page_up"),
+        ("]", "scroll_page_down"),
+        ("tab", "toggle_view"),
+        ("z", "toggle_zoom"),
+        ("s", "toggle_sidebar"),
+        ("f", "toggle_fold"),
+    ]
+    .into_iter()
+    .map(|(key, action)| (key.to_string(), action.to_string()))
+    .collect();
+    for n in 1..=9 {
+        bindings.insert(n.to_string(), format!("channel_{}", n));
+    }
+    bindings
+}
+
 /// Notification settings for background channel activity
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -110,6 +622,13 @@ pub struct NotificationsConfig {
 
     /// Minimum seconds between notifications per channel (cooldown)
     pub cooldown_seconds: u64,
+
+    /// Channel activity the server should push to this client as
+    /// high-priority notifications: "output", "exit" and/or "failure"
+    pub push: Vec<NotificationKind>,
+
+    /// Channels `push` applies to (default: every channel)
+    pub push_channels: Vec<String>,
 }
 
 impl Default for NotificationsConfig {
@@ -118,10 +637,71 @@ impl Default for NotificationsConfig {
             bell: false,
             title_update: true,
             cooldown_seconds: 1,
+            push: Vec::new(),
+            push_channels: Vec::new(),
+        }
+    }
+}
+
+impl NotificationsConfig {
+    /// What to ask the server to push, from `push` and `push_channels`
+    pub fn push_prefs(&self) -> NotificationPrefs {
+        NotificationPrefs {
+            channels: self.push_channels.clone(),
+            kinds: self.push.clone(),
         }
     }
 }
 
+/// Reusable channel definition referenced by `:new <name> --template <template>`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelTemplate {
+    /// Command to run (defaults to the user's shell)
+    pub command: Option<String>,
+
+    /// Working directory
+    pub cwd: Option<PathBuf>,
+
+    /// Environment variables to set
+    pub env: BTreeMap<String, String>,
+
+    /// Initial terminal size as `[cols, rows]`
+    pub size: Option<(u16, u16)>,
+
+    /// Restart policy: "never", "on-failure" or "always"
+    pub restart: RestartPolicy,
+
+    /// "pty" (default) or "pipe" to keep stdout and stderr apart
+    pub mode: ChannelMode,
+
+    /// Kill the process if it runs longer than this ("30m", "2h")
+    #[serde(with = "duration::option")]
+    pub timeout: Option<u64>,
+
+    /// Run the command again on a schedule: `{ every = "1h" }` or
+    /// `{ cron = "0 3 * * *" }`
+    pub schedule: Option<Schedule>,
+}
+
+impl ChannelTemplate {
+    /// Build a channel config for `name` from this template
+    pub fn to_channel_config(&self, name: impl Into<String>) -> ChannelConfig {
+        let mut config = ChannelConfig::new(name)
+            .with_restart(self.restart)
+            .with_mode(self.mode);
+        config.command = self.command.clone();
+        config.working_dir = self.cwd.clone();
+        config.size = self.size;
+        config.timeout_secs = self.timeout;
+        config.schedule = self.schedule.clone();
+        for (key, value) in &self.env {
+            config = config.with_env(key, value);
+        }
+        config
+    }
+}
+
 impl Config {
     /// Load config from file, or return defaults if not found
     pub fn load() -> Result<Self> {
@@ -136,6 +716,88 @@ impl Config {
         }
     }
 
+    /// Write the config back to the config file, changing only the settings
+    /// that differ from what the file says so its comments and layout stay
+    pub fn save(&self) -> Result<()> {
+        let config_path = Self::config_path();
+        if let Some(parent) = config_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let existing = match std::fs::read_to_string(&config_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", config_path.display()))
+            }
+        };
+        let content = self.edit_toml(&existing)?;
+        std::fs::write(&config_path, content)
+            .with_context(|| format!("Failed to write {}", config_path.display()))?;
+        Ok(())
+    }
+
+    /// `existing` config file contents updated to this config
+    fn edit_toml(&self, existing: &str) -> Result<String> {
+        let mut document: toml_edit::DocumentMut = existing.parse()?;
+        let on_disk: Config = toml::from_str(existing)?;
+        let before: toml_edit::DocumentMut = toml::to_string(&on_disk)?.parse()?;
+        let after: toml_edit::DocumentMut = toml::to_string(self)?.parse()?;
+        merge_changes(document.as_table_mut(), before.as_table(), after.as_table());
+        Ok(document.to_string())
+    }
+
+    /// Look up a setting by dotted key (e.g. `appearance.line_wrap`)
+    pub fn get(&self, key: &str) -> Option<String> {
+        let root = toml::Value::try_from(self).ok()?;
+        let value = key
+            .split('.')
+            .try_fold(&root, |value, part| value.get(part))?;
+        Some(match value {
+            toml::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+    }
+
+    /// Change a setting by dotted key, parsing `value` as the setting's type.
+    ///
+    /// The config is left untouched if the key is unknown or the value is invalid.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let mut root = toml::Value::try_from(&*self)?;
+        let (section, leaf) = key
+            .rsplit_once('.')
+            .ok_or_else(|| anyhow!("Unknown setting '{}'", key))?;
+
+        let table = section
+            .split('.')
+            .try_fold(&mut root, |value, part| value.get_mut(part))
+            .and_then(|value| value.as_table_mut())
+            .ok_or_else(|| anyhow!("Unknown setting '{}'", key))?;
+        let current = table
+            .get(leaf)
+            .ok_or_else(|| anyhow!("Unknown setting '{}'", key))?;
+
+        let new_value = match current {
+            toml::Value::Boolean(_) => match value {
+                "true" | "on" | "yes" => toml::Value::Boolean(true),
+                "false" | "off" | "no" => toml::Value::Boolean(false),
+                _ => bail!("'{}' expects true or false", key),
+            },
+            toml::Value::Integer(_) => toml::Value::Integer(
+                value
+                    .parse()
+                    .map_err(|_| anyhow!("'{}' expects a number", key))?,
+            ),
+            toml::Value::String(_) => toml::Value::String(value.to_string()),
+            _ => bail!("'{}' cannot be set from the prompt", key),
+        };
+        table.insert(leaf.to_string(), new_value);
+
+        *self = root
+            .try_into()
+            .map_err(|e| anyhow!("Invalid value for '{}': {}", key, e))?;
+        Ok(())
+    }
+
     /// Get the config file path
     pub fn config_path() -> PathBuf {
         dirs::config_dir()
@@ -144,6 +806,22 @@ impl Config {
             .join("config.toml")
     }
 
+    /// Directory of Lua scripts loaded by the server
+    pub fn scripts_dir() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("nexus")
+            .join("scripts")
+    }
+
+    /// Directory of WASM plugins loaded by the server
+    pub fn plugins_dir() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("nexus")
+            .join("plugins")
+    }
+
     /// Get the runtime directory for sockets
     pub fn runtime_dir(&self) -> PathBuf {
         self.general
@@ -159,3 +837,217 @@ impl Config {
         self.runtime_dir().join(format!("{}.sock", session_name))
     }
 }
+
+/// Apply the differences between `before` and `after` to `target`, leaving
+/// everything they agree on (and its formatting) as it is
+fn merge_changes(
+    target: &mut toml_edit::Table,
+    before: &toml_edit::Table,
+    after: &toml_edit::Table,
+) {
+    for (key, item) in after.iter() {
+        let previous = before.get(key);
+        if previous.map(|p| p.to_string()) == Some(item.to_string()) {
+            continue;
+        }
+        match (item, previous.and_then(toml_edit::Item::as_table)) {
+            (toml_edit::Item::Table(table), previous) => {
+                let empty = toml_edit::Table::new();
+                let entry = target.entry(key).or_insert_with(|| {
+                    let mut table = toml_edit::Table::new();
+                    table.set_implicit(true);
+                    toml_edit::Item::Table(table)
+                });
+                match entry.as_table_mut() {
+                    Some(target) => merge_changes(target, previous.unwrap_or(&empty), table),
+                    None => *entry = item.clone(),
+                }
+            }
+            (toml_edit::Item::Value(value), _) => match target.get_mut(key) {
+                Some(toml_edit::Item::Value(current)) => {
+                    let decor = current.decor().clone();
+                    *current = value.clone();
+                    *current.decor_mut() = decor;
+                }
+                _ => {
+                    target.insert(key, toml_edit::value(value.clone()));
+                }
+            },
+            _ => {
+                target.insert(key, item.clone());
+            }
+        }
+    }
+    for (key, _) in before.iter() {
+        if !after.contains_key(key) {
+            target.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_typed_values_by_dotted_key() {
+        let mut config = Config::default();
+
+        config.set("appearance.line_wrap", "false").unwrap();
+        config.set("notifications.cooldown_seconds", "5").unwrap();
+        config
+            .set("appearance.status_bar_position", "bottom")
+            .unwrap();
+
+        assert!(!config.appearance.line_wrap);
+        assert_eq!(config.notifications.cooldown_seconds, 5);
+        assert!(matches!(
+            config.appearance.status_bar_position,
+            StatusBarPosition::Bottom
+        ));
+        assert_eq!(config.get("appearance.line_wrap").as_deref(), Some("false"));
+
+        config
+            .set("appearance.status_segments.clock", "on")
+            .unwrap();
+        assert!(config.appearance.status_segments.clock);
+        assert!(!config.appearance.status_segments.host);
+    }
+
+    #[test]
+    fn saving_edits_only_changed_settings() {
+        let existing = r#"# My nexus setup
+[appearance]
+# Long lines are easier to grep unwrapped
+line_wrap = false  # for now
+
+[aliases]
+b = "cargo build"
+c = "cargo check"
+"#;
+        let mut config: Config = toml::from_str(existing).unwrap();
+        config.set("appearance.line_wrap", "true").unwrap();
+        config.set("general.history_limit", "42").unwrap();
+        config.aliases.remove("c");
+        config
+            .aliases
+            .insert("t".to_string(), "cargo test".to_string());
+
+        let saved = config.edit_toml(existing).unwrap();
+        assert!(saved.starts_with("# My nexus setup\n[appearance]\n"));
+        assert!(saved
+            .contains("# Long lines are easier to grep unwrapped\nline_wrap = true  # for now\n"));
+        assert!(saved.contains("b = \"cargo build\""));
+        assert!(saved.contains("t = \"cargo test\""));
+        assert!(!saved.contains("cargo check"));
+        assert!(saved.contains("[general]\nhistory_limit = 42\n"));
+        // Settings left at their defaults aren't written out
+        assert!(!saved.contains("show_timestamps"));
+        let reloaded: Config = toml::from_str(&saved).unwrap();
+        assert_eq!(
+            toml::to_string(&reloaded).unwrap(),
+            toml::to_string(&config).unwrap()
+        );
+
+        // Nothing changed, nothing rewritten
+        let unchanged: Config = toml::from_str(existing).unwrap();
+        assert_eq!(unchanged.edit_toml(existing).unwrap(), existing);
+    }
+
+    #[test]
+    fn default_output_patterns_compile() {
+        let appearance = AppearanceConfig::default();
+        assert!(regex::Regex::new(&appearance.fold_pattern)
+            .unwrap()
+            .is_match("user@host:~/nexus$ cargo test"));
+
+        let errors = regex::RegexSet::new(&appearance.error_patterns).unwrap();
+        assert!(errors.is_match("error[E0308]: mismatched types"));
+        assert!(errors.is_match("test auth::login ... FAILED"));
+        assert!(errors.is_match("thread 'main' panicked at src/main.rs:2:5"));
+        assert!(!errors.is_match("test result: ok. 12 passed; 0 failed"));
+        assert!(!errors.is_match("Finished dev [unoptimized] target(s)"));
+    }
+
+    #[test]
+    fn channel_acls_restrict_listed_actions() {
+        let config: Config = toml::from_str(
+            r#"
+            [acl.prod]
+            input = ["alice", "bob"]
+            kill = []
+            "#,
+        )
+        .unwrap();
+        let acl = &config.acl["prod"];
+
+        assert!(acl.allows(ChannelAction::Input, Some("alice")));
+        assert!(!acl.allows(ChannelAction::Input, Some("mallory")));
+        assert!(!acl.allows(ChannelAction::Input, None));
+        assert!(!acl.allows(ChannelAction::Kill, Some("alice")));
+        assert!(ChannelAcl::default().allows(ChannelAction::Kill, None));
+        // Lists can't be loosened from the prompt
+        assert!(Config::default().set("acl.prod.input", "mallory").is_err());
+    }
+
+    #[test]
+    fn template_timeouts_and_schedules() {
+        let config: Config = toml::from_str(
+            r#"
+            [templates.ci]
+            command = "make test"
+            timeout = "1h30m"
+
+            [templates.quick]
+            timeout = 90
+            schedule = { every = "1h" }
+
+            [templates.nightly]
+            schedule = { cron = "0 3 * * *" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.templates["ci"].timeout, Some(5400));
+        assert_eq!(
+            config.templates["ci"].to_channel_config("ci").timeout_secs,
+            Some(5400)
+        );
+        assert_eq!(config.templates["quick"].timeout, Some(90));
+        assert_eq!(
+            config.templates["quick"].schedule,
+            Some(Schedule::Every(3600))
+        );
+        assert_eq!(
+            config.templates["nightly"]
+                .schedule
+                .as_ref()
+                .map(Schedule::describe),
+            Some("cron 0 3 * * *".to_string())
+        );
+
+        let saved = toml::to_string(&config).unwrap();
+        assert!(saved.contains(r#"timeout = "1h30m""#));
+        assert!(toml::from_str::<Config>("[templates.bad]\ntimeout = \"soon\"").is_err());
+        for zero in ["0", "\"0s\""] {
+            let text = format!("[templates.bad]\nschedule = {{ every = {} }}", zero);
+            assert!(toml::from_str::<Config>(&text).is_err(), "{}", text);
+        }
+        assert!(toml::from_str::<Config>("[templates.bad]\ntimeout = 0").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_bad_values() {
+        let mut config = Config::default();
+
+        assert!(config.set("appearance.nope", "true").is_err());
+        assert!(config.set("line_wrap", "true").is_err());
+        assert!(config.set("notifications.bell", "maybe").is_err());
+        assert!(config
+            .set("appearance.status_bar_position", "middle")
+            .is_err());
+        assert!(matches!(
+            config.appearance.status_bar_position,
+            StatusBarPosition::Top
+        ));
+    }
+}
@@ -0,0 +1,222 @@
+//! Session profiles - declarative sets of channels to start together
+
+use crate::channel::{schedule::Schedule, ChannelMode};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Default profile file looked up in the current directory
+pub const DEFAULT_PROFILE_FILE: &str = "nexus.toml";
+
+/// A session profile loaded from a `nexus.toml`-style file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Session name to use when none is given on the command line
+    pub session: Option<String>,
+
+    /// Base directory for relative channel `cwd`s (defaults to the profile's directory)
+    pub root: Option<PathBuf>,
+
+    /// Initial view layout
+    pub layout: ProfileLayout,
+
+    /// Channel to make active once everything is started
+    pub focus: Option<String>,
+
+    /// Channel or group names to stay subscribed to (all channels when empty)
+    pub subscribe: Vec<String>,
+
+    /// Channels to start, in order
+    pub channels: Vec<ProfileChannel>,
+}
+
+/// A channel entry in a profile (`[[channels]]`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileChannel {
+    pub name: String,
+
+    /// Command to run (defaults to the user's shell)
+    pub command: Option<String>,
+
+    /// Working directory, relative to the profile root
+    pub cwd: Option<PathBuf>,
+
+    /// Group this channel belongs to (usable in `subscribe`)
+    pub group: Option<String>,
+
+    /// Environment variables to set
+    pub env: BTreeMap<String, String>,
+
+    /// Config template to start from
+    pub template: Option<String>,
+
+    /// "pty" or "pipe" (overrides the template's mode)
+    pub mode: Option<ChannelMode>,
+
+    /// Kill the process if it runs longer than this ("30m", "2h")
+    #[serde(with = "crate::config::duration::option")]
+    pub timeout: Option<u64>,
+
+    /// Run the command again on a schedule: `{ every = "1h" }` or
+    /// `{ cron = "0 3 * * *" }`
+    pub schedule: Option<Schedule>,
+}
+
+/// Initial view layout for a profile
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileLayout {
+    /// Show only the active channel
+    #[default]
+    Channel,
+    /// Show all subscribed channels interleaved
+    All,
+    /// Tile all subscribed channels into equal panes
+    Grid,
+}
+
+impl Profile {
+    /// Load a profile from a TOML file, resolving its root directory
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read profile {}", path.display()))?;
+        let mut profile: Profile = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse profile {}", path.display()))?;
+
+        // The server resolves relative directories against its own cwd, so
+        // everything it's sent is made absolute here
+        let path = std::path::absolute(path)
+            .with_context(|| format!("Failed to resolve profile path {}", path.display()))?;
+        let base_dir = path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("/"));
+        profile.root = Some(match profile.root.take() {
+            Some(root) if root.is_absolute() => root,
+            Some(root) => base_dir.join(root),
+            None => base_dir,
+        });
+
+        Ok(profile)
+    }
+
+    /// Resolve a channel's working directory against the profile root.
+    ///
+    /// Templated channels without an explicit `cwd` keep the template's directory.
+    pub fn channel_cwd(&self, channel: &ProfileChannel) -> Option<PathBuf> {
+        match (&channel.cwd, &self.root) {
+            (Some(cwd), _) if cwd.is_absolute() => Some(cwd.clone()),
+            (Some(cwd), Some(root)) => Some(root.join(cwd)),
+            (Some(cwd), None) => Some(cwd.clone()),
+            (None, _) if channel.template.is_some() => None,
+            (None, root) => root.clone(),
+        }
+    }
+
+    /// Channels that should remain subscribed, with group names expanded
+    pub fn subscribed_channels(&self) -> Vec<String> {
+        if self.subscribe.is_empty() {
+            return self.channels.iter().map(|c| c.name.clone()).collect();
+        }
+        self.channels
+            .iter()
+            .filter(|c| {
+                self.subscribe
+                    .iter()
+                    .any(|entry| *entry == c.name || c.group.as_deref() == Some(entry.as_str()))
+            })
+            .map(|c| c.name.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const PROFILE: &str = r#"
+        session = "webapp"
+        layout = "all"
+        focus = "shell"
+        subscribe = ["web"]
+
+        [[channels]]
+        name = "frontend"
+        command = "npm run dev"
+        cwd = "frontend"
+        group = "web"
+
+        [[channels]]
+        name = "backend"
+        command = "cargo watch -x run"
+        group = "web"
+        env = { RUST_LOG = "debug" }
+
+        [[channels]]
+        name = "shell"
+        cwd = "/tmp"
+    "#;
+
+    #[test]
+    fn loads_profile_relative_to_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_PROFILE_FILE);
+        std::fs::write(&path, PROFILE).unwrap();
+
+        let profile = Profile::load(&path).unwrap();
+        assert_eq!(profile.session.as_deref(), Some("webapp"));
+        assert_eq!(profile.layout, ProfileLayout::All);
+        assert_eq!(profile.channels.len(), 3);
+
+        let frontend = &profile.channels[0];
+        assert_eq!(
+            profile.channel_cwd(frontend),
+            Some(dir.path().join("frontend"))
+        );
+        assert_eq!(
+            profile.channel_cwd(&profile.channels[1]),
+            Some(dir.path().to_path_buf())
+        );
+        assert_eq!(
+            profile.channel_cwd(&profile.channels[2]),
+            Some(PathBuf::from("/tmp"))
+        );
+    }
+
+    #[test]
+    fn resolves_relative_profile_paths_to_absolute_dirs() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join(DEFAULT_PROFILE_FILE), PROFILE).unwrap();
+        // The same file, reached from the current directory
+        let cwd = std::env::current_dir().unwrap();
+        let mut relative: PathBuf = cwd.components().skip(1).map(|_| "..").collect();
+        relative.push(dir.path().strip_prefix("/").unwrap());
+        relative.push(DEFAULT_PROFILE_FILE);
+        assert!(relative.is_relative());
+
+        let profile = Profile::load(&relative).unwrap();
+        let root = profile.root.clone().unwrap();
+        assert!(root.is_absolute(), "{}", root.display());
+        assert!(root.join(DEFAULT_PROFILE_FILE).exists());
+        assert_eq!(
+            profile.channel_cwd(&profile.channels[0]),
+            Some(root.join("frontend"))
+        );
+    }
+
+    #[test]
+    fn expands_groups_in_subscriptions() {
+        let mut profile: Profile = toml::from_str(PROFILE).unwrap();
+        assert_eq!(profile.subscribed_channels(), vec!["frontend", "backend"]);
+
+        profile.subscribe.clear();
+        assert_eq!(
+            profile.subscribed_channels(),
+            vec!["frontend", "backend", "shell"]
+        );
+    }
+}
@@ -4,6 +4,7 @@
 //! - Channel management (PTY spawning, I/O handling)
 //! - Client-server protocol
 //! - Configuration management
+//! - A typed async client ([`client::NexusClient`]) for driving sessions programmatically
 //!
 //! # Architecture
 //!
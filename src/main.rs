@@ -2,7 +2,9 @@
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use nexus::client;
+use nexus::client::{self, AttachOptions};
+use nexus::config::{Profile, DEFAULT_PROFILE_FILE};
+use nexus::server::tls::ClientTls;
 
 #[derive(Parser)]
 #[command(name = "nexus")]
@@ -25,22 +27,135 @@ struct Cli {
 enum Commands {
     /// Start a new session
     New {
-        /// Session name
-        #[arg(default_value = "default")]
-        name: String,
+        /// Session name (defaults to the profile's session, then "default")
+        name: Option<String>,
+
+        /// Start the channels described in a profile file (defaults to ./nexus.toml)
+        #[arg(long, num_args = 0..=1, default_missing_value = DEFAULT_PROFILE_FILE)]
+        profile: Option<std::path::PathBuf>,
     },
     /// Attach to an existing session
     Attach {
         /// Session name
         name: String,
+
+        /// Watch read-only: see output, but input and changes are refused
+        #[arg(long)]
+        observe: bool,
+
+        /// Name other clients see this one by (e.g. "laptop")
+        #[arg(long = "name")]
+        client_name: Option<String>,
+    },
+    /// Attach to a session on another machine over TCP
+    Connect {
+        /// Server address (host:port)
+        addr: String,
+
+        /// Shared secret from the server's `[tcp]` config or `<session>.tcp-token`
+        /// (defaults to $NEXUS_TOKEN)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Use TLS, verifying the server against this CA bundle (PEM)
+        #[arg(long)]
+        ca: Option<std::path::PathBuf>,
+
+        /// Client certificate (PEM) for servers that require mutual TLS
+        #[arg(long, requires = "ca", requires = "key")]
+        cert: Option<std::path::PathBuf>,
+
+        /// Private key (PEM) for --cert
+        #[arg(long, requires = "cert")]
+        key: Option<std::path::PathBuf>,
+
+        /// Name to verify the server certificate against (defaults to the host)
+        #[arg(long, requires = "ca")]
+        server_name: Option<String>,
+
+        /// Watch read-only: see output, but input and changes are refused
+        #[arg(long)]
+        observe: bool,
+
+        /// Name other clients see this one by (e.g. "laptop")
+        #[arg(long = "name")]
+        client_name: Option<String>,
+    },
+    /// Run a command in a temporary channel and exit with its status
+    Run {
+        /// Session to run in (started if needed; defaults to "default")
+        #[arg(short, long)]
+        session: Option<String>,
+
+        /// Command and arguments, after `--`
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Type a line of input into a channel without attaching
+    Send {
+        /// Session to send to (defaults to "default")
+        #[arg(short, long)]
+        session: Option<String>,
+
+        /// Channel to send to (defaults to the session's active channel)
+        #[arg(short, long)]
+        channel: Option<String>,
+
+        /// Don't press Enter after the text
+        #[arg(short = 'n', long)]
+        no_newline: bool,
+
+        /// Text to send
+        text: String,
+    },
+    /// Print a channel's recent output, optionally following new output
+    Tail {
+        /// Session name
+        session: String,
+
+        /// Channel name
+        channel: String,
+
+        /// Keep printing output until the channel exits
+        #[arg(short, long)]
+        follow: bool,
+
+        /// Number of buffered lines to print first
+        #[arg(short = 'n', long)]
+        lines: Option<usize>,
+    },
+    /// Wait for a channel's process to exit and exit with its status
+    Wait {
+        /// Session name
+        session: String,
+
+        /// Channel name
+        channel: String,
     },
     /// List available sessions
-    List,
+    List {
+        /// Query each server and print JSON for scripts
+        #[arg(long)]
+        json: bool,
+    },
     /// Kill a session
     Kill {
         /// Session name
         name: String,
     },
+    /// Rename a running session
+    RenameSession {
+        /// Current session name
+        old: String,
+
+        /// New session name
+        new: String,
+    },
+    /// Shut down every session's server, or just the named one
+    KillServer {
+        /// Session name (defaults to all sessions)
+        name: Option<String>,
+    },
 }
 
 #[tokio::main]
@@ -56,16 +171,89 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Some(Commands::New { name }) => {
+        Some(Commands::New { name, profile }) => {
+            let profile = profile.map(|path| Profile::load(&path)).transpose()?;
+            let name = name
+                .or_else(|| profile.as_ref().and_then(|p| p.session.clone()))
+                .unwrap_or_else(|| "default".to_string());
             tracing::info!("Creating new session: {}", name);
-            client::start_new_session(&name).await
+            client::start_new_session(&name, profile).await
         }
-        Some(Commands::Attach { name }) => {
+        Some(Commands::Attach {
+            name,
+            observe,
+            client_name,
+        }) => {
             tracing::info!("Attaching to session: {}", name);
-            client::attach_session(&name).await
+            client::attach_session(
+                &name,
+                AttachOptions {
+                    observe,
+                    name: client_name,
+                },
+            )
+            .await
+        }
+        Some(Commands::Connect {
+            addr,
+            token,
+            ca,
+            cert,
+            key,
+            server_name,
+            observe,
+            client_name,
+        }) => {
+            let token = token
+                .or_else(|| std::env::var("NEXUS_TOKEN").ok())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("A token is required: pass --token or set NEXUS_TOKEN")
+                })?;
+            let tls = ca.map(|ca| ClientTls {
+                ca,
+                cert,
+                key,
+                server_name,
+            });
+            let options = AttachOptions {
+                observe,
+                name: client_name,
+            };
+            client::attach_remote(&addr, &token, tls.as_ref(), options).await
+        }
+        Some(Commands::Run { session, command }) => {
+            let session = session
+                .or(cli.session)
+                .unwrap_or_else(|| "default".to_string());
+            let code = client::cli::run(&session, &command).await?;
+            std::process::exit(code);
+        }
+        Some(Commands::Send {
+            session,
+            channel,
+            no_newline,
+            text,
+        }) => {
+            let session = session
+                .or(cli.session)
+                .unwrap_or_else(|| "default".to_string());
+            client::cli::send(&session, channel.as_deref(), &text, !no_newline).await
+        }
+        Some(Commands::Tail {
+            session,
+            channel,
+            follow,
+            lines,
+        }) => client::cli::tail(&session, &channel, follow, lines).await,
+        Some(Commands::Wait { session, channel }) => {
+            let code = client::cli::wait(&session, &channel).await?;
+            std::process::exit(code);
         }
-        Some(Commands::List) => client::list_sessions().await,
+        Some(Commands::List { json: true }) => client::cli::list_json().await,
+        Some(Commands::List { json: false }) => client::list_sessions().await,
         Some(Commands::Kill { name }) => client::kill_session(&name).await,
+        Some(Commands::RenameSession { old, new }) => client::cli::rename_session(&old, &new).await,
+        Some(Commands::KillServer { name }) => client::cli::kill_server(name.as_deref()).await,
         None => {
             // Default: attach to default session or create if doesn't exist
             let session_name = cli.session.unwrap_or_else(|| "default".to_string());
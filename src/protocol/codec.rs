@@ -0,0 +1,72 @@
+//! Version-dispatching message encoding
+//!
+//! Every protocol version frames messages the same way but may encode the
+//! MessagePack payload differently. The server keeps one [`Codec`] per
+//! connection so it can keep speaking the version each client asked for.
+
+use super::{ProtocolError, PROTOCOL_VERSION};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Payload encoding for one protocol version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// Structs encoded as positional arrays; new fields can only be appended
+    V1,
+    /// Structs encoded as maps keyed by field name, so peers skip fields they
+    /// don't know and missing ones fall back to their defaults wherever they
+    /// sit
+    V2,
+}
+
+impl Codec {
+    /// Codec for a protocol version this build can speak
+    pub fn for_version(version: u32) -> Result<Self> {
+        match version {
+            1 => Ok(Codec::V1),
+            2 => Ok(Codec::V2),
+            _ => bail!(ProtocolError::VersionMismatch {
+                client: version,
+                server: PROTOCOL_VERSION
+            }),
+        }
+    }
+
+    /// Codec for the newest version this build speaks
+    pub fn current() -> Self {
+        Codec::V2
+    }
+
+    /// Codec for the oldest version the server still accepts; anything sent
+    /// before a client has said which version it speaks uses this
+    pub fn oldest() -> Self {
+        Codec::V1
+    }
+
+    /// Protocol version this codec implements
+    pub fn version(self) -> u32 {
+        match self {
+            Codec::V1 => 1,
+            Codec::V2 => 2,
+        }
+    }
+
+    /// Encode a message's payload
+    pub fn encode<T: Serialize>(self, msg: &T) -> Result<Vec<u8>> {
+        Ok(match self {
+            Codec::V1 => rmp_serde::to_vec(msg)?,
+            Codec::V2 => rmp_serde::to_vec_named(msg)?,
+        })
+    }
+
+    /// Decode a message's payload. Both encodings decode the same way, so
+    /// this accepts either whatever the codec.
+    pub fn decode<'a, T: Deserialize<'a>>(self, bytes: &'a [u8]) -> Result<T> {
+        rmp_serde::from_slice(bytes).map_err(|e| {
+            anyhow!(ProtocolError::MalformedMessage(format!(
+                "Failed to deserialize: {}",
+                e
+            )))
+        })
+    }
+}
@@ -1,13 +1,38 @@
 //! Message types for nexus protocol
 
-use serde::{Deserialize, Serialize};
+use crate::channel::schedule::Schedule;
+use crate::channel::{ChannelMode, ContainerTarget, OutputStream};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use uuid::Uuid;
 
 /// Messages sent from client to server
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClientMessage {
     /// Handshake with protocol version
-    Hello { protocol_version: u32 },
+    Hello {
+        protocol_version: u32,
+        /// Shared secret, required on TCP connections
+        #[serde(default)]
+        token: Option<String>,
+        /// Stable name for this client; a client that detached under the same
+        /// identity gets its subscriptions and active channel back
+        #[serde(default)]
+        identity: Option<String>,
+        /// Optional features the client understands
+        #[serde(default)]
+        capabilities: Vec<Capability>,
+        /// Attach read-only: output and events are delivered, but anything
+        /// that would change the session is refused
+        #[serde(default)]
+        observe: bool,
+        /// Name shown to other clients, e.g. the machine attaching
+        #[serde(default)]
+        name: Option<String>,
+    },
 
     /// Send input to active channel
     Input { data: Vec<u8> },
@@ -16,11 +41,7 @@ pub enum ClientMessage {
     InputTo { channel: String, data: Vec<u8> },
 
     /// Create a new channel
-    CreateChannel {
-        name: String,
-        command: Option<String>,
-        working_dir: Option<String>,
-    },
+    CreateChannel(CreateChannelRequest),
 
     /// Destroy a channel
     KillChannel { name: String },
@@ -40,14 +61,137 @@ pub enum ClientMessage {
     /// Request channel status
     GetStatus { channel: Option<String> },
 
+    /// Request the session's metadata
+    GetSessionInfo,
+
+    /// Request the list of attached clients
+    ListClients,
+
+    /// Show a note to every attached client
+    Wall { message: String },
+
+    /// Choose which channel activity this client gets `Notification`s for,
+    /// replacing any earlier choice
+    SetNotifications { prefs: NotificationPrefs },
+
+    /// Pin a channel so clients list it first, or unpin it. Pins belong to
+    /// the session, so every client sees them and they outlast detaching.
+    SetPinned { channel: String, pinned: bool },
+
+    /// Share a scrollback mark with the session, anchored at output chunk
+    /// `seq`, or remove it when `seq` is `None`
+    SetMark {
+        channel: String,
+        name: String,
+        seq: Option<u64>,
+    },
+
+    /// Request every mark shared with the session
+    ListMarks,
+
+    /// Request the processes running in a channel
+    GetProcesses { channel: String },
+
+    /// Signal the command in a channel's foreground (its process group),
+    /// leaving the channel's own process, such as its shell, running
+    SignalForeground { channel: String, signal: StopSignal },
+
+    /// Re-read the server's configuration file
+    ReloadConfig,
+
+    /// Change a server-side setting by dotted key (e.g. `general.history_limit`)
+    SetConfig { key: String, value: String },
+
+    /// Request command history for a channel, or for every channel when `None`
+    GetHistory { channel: Option<String> },
+
+    /// Record a command entered for a channel in the shared history
+    AppendHistory { channel: String, entry: String },
+
+    /// Complete a file or directory path relative to a channel's working directory
+    /// (the active channel when `channel` is `None`)
+    CompletePath {
+        channel: Option<String>,
+        partial: String,
+    },
+
+    /// Run a command registered by a server-side script
+    ScriptCommand { name: String, args: Vec<String> },
+
     /// Terminal resize event
     Resize { cols: u16, rows: u16 },
 
-    /// Detach from session (server keeps running)
+    /// Detach from session (server keeps running). The server stops sending
+    /// to this client, remembers its view under its identity and then closes
+    /// the connection.
     Detach,
 
     /// Graceful shutdown request
     Shutdown,
+
+    /// Rename the session, moving its socket to match
+    RenameSession { name: String },
+
+    /// Start another session in this server's process
+    StartSession { name: String },
+
+    /// Heartbeat; the server answers with a `Pong` carrying the same nonce
+    Ping { nonce: u64 },
+
+    /// Any other message tagged with a caller-chosen id, which the server
+    /// echoes in the `Ack` or `Error` answering it
+    Request {
+        id: u64,
+        message: Box<ClientMessage>,
+    },
+
+    /// Pick up where a dropped connection left off: restore the view saved
+    /// under `token` (from its `Welcome`) and replay only the output after
+    /// the last sequence number seen per channel
+    Resume {
+        token: String,
+        #[serde(default)]
+        last_seen: HashMap<String, u64>,
+    },
+}
+
+/// Options for a new channel, carried by [`ClientMessage::CreateChannel`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateChannelRequest {
+    pub name: String,
+    pub command: Option<String>,
+    pub working_dir: Option<String>,
+    /// Extra environment variables for the channel process
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    /// Name of a server-side channel template to start from
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Run inside a running container instead of on the server host
+    #[serde(default)]
+    pub container: Option<ContainerTarget>,
+    /// PTY (default) or plain pipes with separate stdout/stderr
+    #[serde(default)]
+    pub mode: Option<ChannelMode>,
+    /// Remove the channel once its process exits
+    #[serde(default)]
+    pub ephemeral: bool,
+    /// Keep an ephemeral channel whose process exits with a failure
+    /// status; only honoured by servers that advertise
+    /// [`Capability::KeepOnFailure`]
+    #[serde(default)]
+    pub keep_on_failure: bool,
+    /// Lock the terminal at this (cols, rows) size instead of following
+    /// the attached clients
+    #[serde(default)]
+    pub size: Option<(u16, u16)>,
+    /// Kill the process if it's still running after this many seconds
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// Run the command again on this schedule; only honoured by
+    /// servers that advertise [`Capability::Schedules`]
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
 }
 
 /// Messages sent from server to client
@@ -57,13 +201,29 @@ pub enum ServerMessage {
     Welcome {
         session_id: Uuid,
         protocol_version: u32,
+        /// Token to present in `Resume` after reconnecting
+        #[serde(default)]
+        resume_token: Option<String>,
+        /// Optional features the server offers; empty from servers that
+        /// predate capability negotiation
+        #[serde(default)]
+        capabilities: Vec<Capability>,
     },
 
     /// Output from a channel
     Output {
         channel: String,
-        data: Vec<u8>,
+        /// Shared with the server's buffer and every other recipient
+        #[serde(with = "shared_bytes")]
+        data: Bytes,
         timestamp: i64,
+        /// Stream the chunk came from; pipe-mode channels report stderr separately
+        #[serde(default)]
+        stream: OutputStream,
+        /// Position in the channel's output, counting up by one per chunk
+        /// from 1; 0 from servers that don't number output
+        #[serde(default)]
+        seq: u64,
     },
 
     /// Channel event notification
@@ -75,18 +235,162 @@ pub enum ServerMessage {
     /// Status response
     Status { channels: Vec<ChannelStatus> },
 
+    /// Session metadata response; uptime is measured by the server
+    SessionInfo { info: SessionInfo, uptime_secs: u64 },
+
+    /// Attached clients response, longest attached first
+    ClientList { clients: Vec<ClientInfo> },
+
+    /// Another client attached; only sent to clients that advertised
+    /// [`Capability::Presence`]
+    ClientJoined { client: ClientInfo },
+
+    /// Another client detached or disconnected; only sent to clients that
+    /// advertised [`Capability::Presence`]
+    ClientLeft { client: ClientInfo },
+
+    /// A note from a client (including this one) to everyone attached; only
+    /// sent to clients that advertised [`Capability::Wall`]
+    Wall { from: String, message: String },
+
+    /// Channel activity the client asked to be notified about with
+    /// `SetNotifications`
+    Notification {
+        channel: String,
+        kind: NotificationKind,
+        message: String,
+    },
+
+    /// The session's pinned channels changed; only sent to clients that
+    /// advertised [`Capability::Pins`]
+    PinnedChanged { channels: Vec<String> },
+
+    /// A shared mark was set or (with `seq: None`) removed; only sent to
+    /// clients that advertised [`Capability::Marks`]
+    MarkChanged {
+        channel: String,
+        name: String,
+        seq: Option<u64>,
+    },
+
+    /// Marks shared with the session, in response to `ListMarks`
+    Marks { marks: Vec<SharedMark> },
+
+    /// Processes running in a channel, in response to `GetProcesses`
+    Processes {
+        channel: String,
+        processes: Vec<ProcessInfo>,
+    },
+
+    /// Command history response
+    History { histories: Vec<ChannelHistory> },
+
+    /// A command was added to the shared history by another client
+    HistoryAppended { channel: String, entry: String },
+
+    /// Path completion response; candidates replace `partial` in full
+    PathCompletions {
+        partial: String,
+        candidates: Vec<String>,
+    },
+
+    /// Result of a script command, with the message it returned (if any)
+    ScriptResult {
+        command: String,
+        message: Option<String>,
+    },
+
     /// Error response
-    Error { message: String },
+    Error {
+        message: String,
+        /// Id of the `Request` that failed, if it was tagged
+        #[serde(default)]
+        request_id: Option<u64>,
+    },
 
     /// Acknowledgment (for commands that need confirmation)
-    Ack { for_command: String },
+    Ack {
+        for_command: String,
+        /// Id of the `Request` acknowledged, if it was tagged
+        #[serde(default)]
+        request_id: Option<u64>,
+    },
+
+    /// The session was renamed; sent to every client
+    SessionRenamed { name: String },
+
+    /// Heartbeat response
+    Pong { nonce: u64 },
+
+    /// Resume response; `restored` is false when the token was unknown (e.g.
+    /// expired, or the server restarted), in which case nothing was replayed
+    Resumed { restored: bool },
+}
+
+impl ServerMessage {
+    /// Tag an `Ack` or `Error` with the id of the request it answers; other
+    /// replies are returned unchanged
+    pub fn with_request_id(mut self, id: u64) -> Self {
+        match &mut self {
+            ServerMessage::Ack { request_id, .. } | ServerMessage::Error { request_id, .. } => {
+                *request_id = Some(id);
+            }
+            _ => {}
+        }
+        self
+    }
+}
+
+/// Optional protocol features, exchanged in `Hello` and `Welcome` so either
+/// side only uses what the other understands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    /// Shared command history (`GetHistory`, `AppendHistory`)
+    HistoryFetch,
+    /// `Resume` and numbered output
+    Resume,
+    /// `Request` ids echoed in acks and errors
+    RequestIds,
+    /// `Ping`/`Pong` heartbeats
+    Heartbeat,
+    /// `ClientJoined`/`ClientLeft` notices
+    Presence,
+    /// `Wall` notes between clients
+    Wall,
+    /// `SetNotifications` and `Notification`
+    Notifications,
+    /// `SetPinned` and `PinnedChanged`
+    Pins,
+    /// `SetMark`, `ListMarks`, `MarkChanged` and `Marks`
+    Marks,
+    /// `GetProcesses` and `Processes`
+    Processes,
+    /// `ChannelEvent::Foreground`
+    Foreground,
+    /// `SignalForeground`
+    SignalForeground,
+    /// `ChannelEvent::TimedOut`
+    Timeouts,
+    /// `CreateChannel { schedule }` and `ChannelStatus::schedule`
+    Schedules,
+    /// `CreateChannel { keep_on_failure }`
+    KeepOnFailure,
+    /// A capability from a newer peer that this build doesn't know
+    #[serde(other)]
+    Unknown,
 }
 
 /// Channel lifecycle events
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ChannelEvent {
     /// New channel created
-    Created { name: String },
+    Created {
+        name: String,
+        /// Container label (`docker:web`) for container channels
+        #[serde(default)]
+        container: Option<String>,
+    },
 
     /// Channel exited
     Exited {
@@ -97,9 +401,29 @@ pub enum ChannelEvent {
     /// Channel was killed
     Killed { name: String },
 
+    /// Channel was killed for running past its timeout, sent along with the
+    /// usual `Killed` event. Only sent to clients that advertised
+    /// [`Capability::Timeouts`].
+    TimedOut { name: String, timeout_secs: u64 },
+
+    /// Channel was restarted by its restart policy
+    Restarted { name: String },
+
+    /// A starting channel produced its first output (or stayed quiet past
+    /// the startup timeout) and is now running
+    Ready { name: String },
+
     /// Active channel changed
     ActiveChanged { name: String },
 
+    /// The command in a channel's foreground changed: `None` once the
+    /// channel's own process (its shell, say) is back in the foreground.
+    /// Only sent to clients that advertised [`Capability::Foreground`].
+    Foreground {
+        name: String,
+        command: Option<String>,
+    },
+
     /// Subscription changed
     SubscriptionChanged { subscribed: Vec<String> },
 }
@@ -111,6 +435,159 @@ pub struct ChannelInfo {
     pub running: bool,
     pub is_active: bool,
     pub is_subscribed: bool,
+    /// Container label (`docker:web`) for container channels
+    #[serde(default)]
+    pub container: Option<String>,
+    /// Pinned to the front of channel lists
+    #[serde(default)]
+    pub is_pinned: bool,
+    /// Spawned but not ready yet; see [`ChannelEvent::Ready`]
+    #[serde(default)]
+    pub is_starting: bool,
+    /// When the process was spawned, in milliseconds since the epoch; 0
+    /// from servers that don't say
+    #[serde(default)]
+    pub started_at: i64,
+    /// Command in the foreground, if it isn't the channel's own process
+    #[serde(default)]
+    pub foreground: Option<String>,
+}
+
+/// Channel activity a client can ask to be notified about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotificationKind {
+    /// New output; at most one notification per channel every few seconds
+    Output,
+    /// The channel's process exited, however it ended
+    Exit,
+    /// The channel's process exited with a non-zero status or was killed
+    /// by a signal
+    Failure,
+}
+
+/// Which channel activity a client wants pushed as notifications
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NotificationPrefs {
+    /// Channels to watch; empty watches every channel
+    #[serde(default)]
+    pub channels: Vec<String>,
+    #[serde(default)]
+    pub kinds: Vec<NotificationKind>,
+}
+
+impl NotificationPrefs {
+    /// Whether `kind` activity on `channel` should be notified
+    pub fn wants(&self, channel: &str, kind: NotificationKind) -> bool {
+        self.kinds.contains(&kind)
+            && (self.channels.is_empty() || self.channels.iter().any(|name| name == channel))
+    }
+}
+
+/// A client attached to the session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientInfo {
+    pub id: Uuid,
+    /// Name the client gave in its `Hello`
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub identity: Option<String>,
+    /// Attached read-only
+    #[serde(default)]
+    pub observer: bool,
+    /// How long the client has been attached
+    pub attached_secs: u64,
+    /// Whether this is the client that asked for the list
+    #[serde(default)]
+    pub is_self: bool,
+}
+
+impl ClientInfo {
+    /// How to refer to the client: its name, else its identity, else the
+    /// start of its id
+    pub fn label(&self) -> String {
+        self.name
+            .clone()
+            .or_else(|| self.identity.clone())
+            .unwrap_or_else(|| self.id.simple().to_string()[..8].to_string())
+    }
+}
+
+/// Command history for one channel, oldest entry first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelHistory {
+    pub channel: String,
+    pub entries: Vec<String>,
+}
+
+/// A named scrollback position shared with the session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharedMark {
+    pub channel: String,
+    pub name: String,
+    /// Output chunk the mark sits at, as numbered in `Output`
+    pub seq: u64,
+}
+
+/// A process running in a channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// Levels below the channel's own process, which is at 0
+    pub depth: usize,
+    /// State letter from `/proc`: `R` running, `S` sleeping, `T` stopped...
+    pub state: char,
+    /// Command line, or the executable name when it has none
+    pub command: String,
+}
+
+/// Signal sent by `SignalForeground`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StopSignal {
+    /// `SIGINT`, as Ctrl+C would send
+    Interrupt,
+    /// `SIGTERM`
+    Terminate,
+    /// `SIGKILL`, for commands that ignore the others
+    Kill,
+}
+
+impl StopSignal {
+    pub fn name(self) -> &'static str {
+        match self {
+            StopSignal::Interrupt => "SIGINT",
+            StopSignal::Terminate => "SIGTERM",
+            StopSignal::Kill => "SIGKILL",
+        }
+    }
+}
+
+/// Session metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// Unique session identifier
+    pub id: Uuid,
+
+    /// Human-readable session name
+    pub name: String,
+
+    /// When the session was created
+    pub created_at: DateTime<Utc>,
+
+    /// Socket path for this session
+    pub socket_path: PathBuf,
+
+    /// Number of connected clients
+    pub client_count: usize,
+
+    /// Number of active channels
+    pub channel_count: usize,
+
+    /// Version of the nexus-server hosting the session
+    #[serde(default)]
+    pub server_version: String,
 }
 
 /// Detailed channel status
@@ -124,4 +601,51 @@ pub struct ChannelStatus {
     pub command: String,
     pub created_at: i64,
     pub output_lines: usize,
+    /// CPU use of the process and everything it started, in percent of one
+    /// core, since the last status request; unknown where the server can't
+    /// read `/proc`
+    #[serde(default)]
+    pub cpu_percent: Option<f32>,
+    /// Resident memory of the process and everything it started
+    #[serde(default)]
+    pub rss_bytes: Option<u64>,
+    /// When a scheduled channel runs and how its latest runs went
+    #[serde(default)]
+    pub schedule: Option<ScheduleInfo>,
+}
+
+/// A scheduled channel's schedule, in `ChannelStatus`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleInfo {
+    /// "every 1h", "cron 0 3 * * *"
+    pub description: String,
+    /// When it next runs, in milliseconds since the epoch
+    pub next_run: Option<i64>,
+    /// Latest runs, oldest first
+    pub runs: Vec<RunInfo>,
+}
+
+/// One run of a scheduled channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunInfo {
+    /// Milliseconds since the epoch
+    pub started_at: i64,
+    /// `None` while it's running
+    pub finished_at: Option<i64>,
+    /// `None` if it was killed or is still running
+    pub exit_code: Option<i32>,
+}
+
+/// Serde for [`Bytes`] payloads, encoded as a sequence exactly like a
+/// `Vec<u8>` so peers decoding into one still can
+mod shared_bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(bytes.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        Vec::<u8>::deserialize(deserializer).map(Bytes::from)
+    }
 }
@@ -2,16 +2,46 @@
 //!
 //! Uses MessagePack for efficient binary serialization.
 
+mod codec;
 mod message;
 
-pub use message::{ChannelEvent, ChannelInfo, ChannelStatus, ClientMessage, ServerMessage};
+pub use codec::Codec;
+pub use message::{
+    Capability, ChannelEvent, ChannelHistory, ChannelInfo, ChannelStatus, ClientInfo,
+    ClientMessage, CreateChannelRequest, NotificationKind, NotificationPrefs, ProcessInfo, RunInfo,
+    ScheduleInfo, ServerMessage, SessionInfo, SharedMark, StopSignal,
+};
 
 use anyhow::{anyhow, bail, Result};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 /// Protocol version for compatibility checking
-pub const PROTOCOL_VERSION: u32 = 1;
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest client protocol version the server still accepts; features added
+/// since are negotiated through [`Capability`] flags
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Capabilities this build implements, advertised on both sides of the
+/// handshake
+pub const CAPABILITIES: &[Capability] = &[
+    Capability::HistoryFetch,
+    Capability::Resume,
+    Capability::RequestIds,
+    Capability::Heartbeat,
+    Capability::Presence,
+    Capability::Wall,
+    Capability::Notifications,
+    Capability::Pins,
+    Capability::Marks,
+    Capability::Processes,
+    Capability::Foreground,
+    Capability::SignalForeground,
+    Capability::Timeouts,
+    Capability::Schedules,
+    Capability::KeepOnFailure,
+];
 
 /// Protocol-specific errors
 #[derive(Debug, Error)]
@@ -32,19 +62,14 @@ pub enum ProtocolError {
 /// Maximum message size to prevent DoS attacks (10 MB)
 pub const MAX_MESSAGE_SIZE: u32 = 10 * 1024 * 1024;
 
-/// Serialize a message to MessagePack bytes
+/// Serialize a message to MessagePack bytes, encoded for [`PROTOCOL_VERSION`]
 pub fn serialize<T: Serialize>(msg: &T) -> Result<Vec<u8>> {
-    Ok(rmp_serde::to_vec(msg)?)
+    Codec::current().encode(msg)
 }
 
-/// Deserialize a message from MessagePack bytes
+/// Deserialize a message from MessagePack bytes of any supported version
 pub fn deserialize<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> Result<T> {
-    rmp_serde::from_slice(bytes).map_err(|e| {
-        anyhow!(ProtocolError::MalformedMessage(format!(
-            "Failed to deserialize: {}",
-            e
-        )))
-    })
+    Codec::current().decode(bytes)
 }
 
 /// Frame a message with length prefix for streaming
@@ -105,6 +130,18 @@ pub fn check_version_compatibility(client_version: u32, server_version: u32) ->
     Ok(())
 }
 
+/// Check that a server speaking [`PROTOCOL_VERSION`] can serve a client
+/// speaking `client_version`: anything from [`MIN_PROTOCOL_VERSION`] up
+pub fn check_client_version(client_version: u32) -> Result<()> {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&client_version) {
+        bail!(ProtocolError::VersionMismatch {
+            client: client_version,
+            server: PROTOCOL_VERSION
+        });
+    }
+    Ok(())
+}
+
 /// Serialize and frame a message in one operation
 pub fn serialize_and_frame<T: Serialize>(msg: &T) -> Result<Vec<u8>> {
     let payload = serialize(msg)?;
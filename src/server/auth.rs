@@ -0,0 +1,339 @@
+//! Shared-secret tokens for the TCP and HTTP listeners, who may open the
+//! Unix socket, and who is on the other end of a connection
+
+use crate::config::SocketConfig;
+use anyhow::{bail, Context, Result};
+use std::ffi::CString;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use uuid::Uuid;
+use yasna::tags::{TAG_BMPSTRING, TAG_IA5STRING, TAG_PRINTABLESTRING, TAG_UTF8STRING};
+
+/// Generate a token and save it readable only by the current user
+///
+/// The token goes to a new private file that is then renamed over `path`, so
+/// an existing file or symlink there never keeps its owner or mode.
+pub(super) fn write_token(path: &Path) -> Result<String> {
+    let token = Uuid::new_v4().simple().to_string();
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(dir)?;
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file path", path.display()))?;
+    let temp_path = dir.join(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        Uuid::new_v4().simple()
+    ));
+
+    let written = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&temp_path)
+        .and_then(|mut file| file.write_all(token.as_bytes()))
+        .and_then(|()| std::fs::rename(&temp_path, path));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+    }
+    Ok(token)
+}
+
+/// Give a freshly bound socket the configured group and mode
+pub(super) fn apply_socket_permissions(path: &Path, config: &SocketConfig) -> Result<()> {
+    let mode = config.mode_bits()?;
+    if let Some(group) = &config.group {
+        std::os::unix::fs::chown(path, None, Some(group_id(group)?))
+            .with_context(|| format!("Failed to give {} to group '{}'", path.display(), group))?;
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set the mode of {}", path.display()))?;
+    Ok(())
+}
+
+/// Look up a group by name, or take it as a numeric id
+fn group_id(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = CString::new(group)?;
+    // SAFETY: a zeroed `group` is a valid out-parameter for getgrnam_r
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    // SAFETY: every pointer is valid for the call, and `buf` outlives it
+    let rc = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if rc != 0 || found.is_null() {
+        bail!("Unknown group '{}'", group);
+    }
+    Ok(entry.gr_gid)
+}
+
+/// Name of the local user with `uid`, or the number if it has none
+pub(super) fn user_name(uid: u32) -> String {
+    // SAFETY: a zeroed `passwd` is a valid out-parameter for getpwuid_r
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    // SAFETY: every pointer is valid for the call, and `buf` outlives it
+    let rc = unsafe { libc::getpwuid_r(uid, &mut entry, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if rc != 0 || found.is_null() {
+        return uid.to_string();
+    }
+    // SAFETY: on success `pw_name` points at a NUL-terminated string in `buf`
+    unsafe { std::ffi::CStr::from_ptr(entry.pw_name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Whether `uid` is the user running the server
+pub(super) fn is_own_uid(uid: u32) -> bool {
+    // SAFETY: geteuid can't fail
+    uid == unsafe { libc::geteuid() }
+}
+
+/// Most specific common name (CN) in the subject of a DER certificate
+pub(super) fn certificate_common_name(der: &[u8]) -> Option<String> {
+    // 2.5.4.3, id-at-commonName
+    const COMMON_NAME: &[u64] = &[2, 5, 4, 3];
+
+    let der = CertificateDer::from(der);
+    let certificate = webpki::EndEntityCert::try_from(&der).ok()?;
+    // webpki hands back the subject without its outer SEQUENCE
+    let subject = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| writer.next().write_der(certificate.subject()))
+    });
+    let attributes = yasna::parse_ber(&subject, |reader| {
+        reader.collect_sequence_of(|reader| {
+            reader.collect_set_of(|reader| {
+                reader.read_sequence(|reader| {
+                    let oid = reader.next().read_oid()?;
+                    let value = reader.next().read_tagged_der()?;
+                    Ok((oid, value))
+                })
+            })
+        })
+    })
+    .ok()?;
+
+    // Names go from the most general part to the most specific
+    let (_, value) = attributes
+        .into_iter()
+        .flatten()
+        .rfind(|(oid, _)| oid.components() == COMMON_NAME)?;
+    match value.tag() {
+        TAG_UTF8STRING | TAG_PRINTABLESTRING | TAG_IA5STRING => {
+            String::from_utf8(value.value().to_vec()).ok()
+        }
+        TAG_BMPSTRING => {
+            let units: Vec<u16> = value
+                .value()
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16(&units).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Compare a presented token with the expected one in constant time
+pub(super) fn tokens_match(presented: &str, expected: &str) -> bool {
+    let (a, b) = (presented.as_bytes(), expected.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use tempfile::tempdir;
+
+    #[test]
+    fn writes_private_tokens_and_compares_exactly() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("dev.token");
+        let token = write_token(&path).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), token);
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        assert!(tokens_match(&token, &token));
+        assert!(!tokens_match("secret", "secreT"));
+        assert!(!tokens_match("secret", "secret2"));
+    }
+
+    #[test]
+    fn replaces_existing_token_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("dev.token");
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let token = write_token(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), token);
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // A symlink is replaced rather than written through
+        let target = dir.path().join("elsewhere");
+        std::fs::write(&target, "untouched").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::os::unix::fs::symlink(&target, &path).unwrap();
+        let token = write_token(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), token);
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "untouched");
+        assert!(!std::fs::symlink_metadata(&path)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn reads_certificate_common_names() {
+        use rcgen::{CertificateParams, DnType, KeyPair};
+
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::OrganizationName, "nexus");
+        params.distinguished_name.push(DnType::CommonName, "alice");
+        let cert = params.self_signed(&key).unwrap();
+        assert_eq!(
+            certificate_common_name(cert.der()).as_deref(),
+            Some("alice")
+        );
+
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        let cert = params.self_signed(&key).unwrap();
+        assert_eq!(certificate_common_name(cert.der()), None);
+        assert_eq!(certificate_common_name(&cert.der()[..40]), None);
+        assert_eq!(certificate_common_name(b"not a certificate"), None);
+    }
+
+    /// A certificate with `subject` as its subject and issuer: well-formed,
+    /// but not validly signed
+    fn certificate_with_subject(subject: &[&[(&[u64], &str)]]) -> Vec<u8> {
+        use yasna::models::ObjectIdentifier;
+
+        let name = yasna::construct_der(|writer| {
+            writer.write_sequence_of(|writer| {
+                for rdn in subject {
+                    writer.next().write_set_of(|writer| {
+                        for (oid, value) in *rdn {
+                            writer.next().write_sequence(|writer| {
+                                writer.next().write_oid(&ObjectIdentifier::from_slice(oid));
+                                writer.next().write_utf8_string(value);
+                            });
+                        }
+                    });
+                }
+            })
+        });
+        // ecdsa-with-SHA256
+        let algorithm = |writer: yasna::DERWriter| {
+            writer.write_sequence(|writer| {
+                writer
+                    .next()
+                    .write_oid(&ObjectIdentifier::from_slice(&[1, 2, 840, 10045, 4, 3, 2]))
+            })
+        };
+        yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_sequence(|writer| {
+                    writer
+                        .next()
+                        .write_tagged(yasna::Tag::context(0), |writer| writer.write_u8(2));
+                    writer.next().write_u8(1);
+                    algorithm(writer.next());
+                    writer.next().write_der(&name);
+                    writer
+                        .next()
+                        .write_der(b"\x30\x1e\x17\x0d250101000000Z\x17\x0d350101000000Z");
+                    writer.next().write_der(&name);
+                    writer.next().write_sequence(|writer| {
+                        algorithm(writer.next());
+                        writer.next().write_bitvec_bytes(&[4, 1, 2], 24);
+                    });
+                });
+                algorithm(writer.next());
+                writer.next().write_bitvec_bytes(&[0], 8);
+            })
+        })
+    }
+
+    #[test]
+    fn reads_the_most_specific_common_name() {
+        const COMMON_NAME: &[u64] = &[2, 5, 4, 3];
+        const ORGANIZATION: &[u64] = &[2, 5, 4, 10];
+        const UNIT: &[u64] = &[2, 5, 4, 11];
+
+        let der = certificate_with_subject(&[
+            &[(COMMON_NAME, "nexus-ca")],
+            &[(ORGANIZATION, "nexus")],
+            &[(UNIT, "ops"), (COMMON_NAME, "alice")],
+        ]);
+        assert_eq!(certificate_common_name(&der).as_deref(), Some("alice"));
+
+        let der = certificate_with_subject(&[&[(ORGANIZATION, "nexus"), (UNIT, "ops")]]);
+        assert_eq!(certificate_common_name(&der), None);
+
+        let der = certificate_with_subject(&[&[(COMMON_NAME, "alice")]]);
+        assert_eq!(certificate_common_name(&der).as_deref(), Some("alice"));
+        for len in 0..der.len() {
+            assert_eq!(certificate_common_name(&der[..len]), None);
+        }
+    }
+
+    #[test]
+    fn names_local_users() {
+        // SAFETY: geteuid can't fail
+        let uid = unsafe { libc::geteuid() };
+        assert!(is_own_uid(uid));
+        assert!(!user_name(uid).is_empty());
+        assert_eq!(user_name(u32::MAX - 7), (u32::MAX - 7).to_string());
+    }
+
+    #[test]
+    fn applies_socket_mode_and_group() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("dev.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let gid = std::fs::metadata(&path).unwrap().gid();
+
+        let config = SocketConfig {
+            mode: "0660".to_string(),
+            group: Some(gid.to_string()),
+        };
+        apply_socket_permissions(&path, &config).unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+        assert_eq!(metadata.gid(), gid);
+
+        let bad_mode = SocketConfig {
+            mode: "0999".to_string(),
+            group: None,
+        };
+        assert!(apply_socket_permissions(&path, &bad_mode).is_err());
+        assert!(group_id("no-such-group-for-nexus").is_err());
+    }
+}
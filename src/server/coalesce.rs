@@ -0,0 +1,117 @@
+//! Merging of small output chunks before they're broadcast, so a flooding
+//! channel sends a few large messages instead of thousands of tiny ones
+
+use crate::channel::{ChannelManagerEvent, OutputStream};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long a chunk may wait for more output to merge with
+const COALESCE_WINDOW: Duration = Duration::from_millis(5);
+
+/// Merged chunks stop growing past this many bytes
+const MAX_COALESCED_BYTES: usize = 64 * 1024;
+
+/// Output held back during the coalescing window, in arrival order
+#[derive(Default)]
+pub(super) struct OutputCoalescer {
+    pending: Vec<(String, OutputStream, Vec<u8>)>,
+    /// When the oldest pending chunk has to go out
+    deadline: Option<Instant>,
+}
+
+impl OutputCoalescer {
+    /// Hold a chunk back, appending it to the channel's latest pending chunk
+    /// when that came from the same stream and has room
+    pub(super) fn push(&mut self, channel_name: String, data: Vec<u8>, stream: OutputStream) {
+        self.deadline
+            .get_or_insert_with(|| Instant::now() + COALESCE_WINDOW);
+        let latest = self
+            .pending
+            .iter_mut()
+            .rev()
+            .find(|(name, ..)| *name == channel_name);
+        match latest {
+            Some((_, latest_stream, buffer))
+                if *latest_stream == stream && buffer.len() + data.len() <= MAX_COALESCED_BYTES =>
+            {
+                buffer.extend_from_slice(&data);
+            }
+            _ => self.pending.push((channel_name, stream, data)),
+        }
+    }
+
+    /// When pending output is due, if there is any
+    pub(super) fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Take the pending output as channel events, in the order it arrived
+    pub(super) fn take(&mut self) -> Vec<ChannelManagerEvent> {
+        self.deadline = None;
+        self.pending
+            .drain(..)
+            .map(|(channel_name, stream, data)| ChannelManagerEvent::Output {
+                channel_name,
+                data,
+                stream,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outputs(events: Vec<ChannelManagerEvent>) -> Vec<(String, OutputStream, Vec<u8>)> {
+        events
+            .into_iter()
+            .map(|event| match event {
+                ChannelManagerEvent::Output {
+                    channel_name,
+                    data,
+                    stream,
+                } => (channel_name, stream, data),
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn merges_chunks_per_channel_and_stream() {
+        let mut coalescer = OutputCoalescer::default();
+        assert!(coalescer.deadline().is_none());
+
+        coalescer.push("a".to_string(), b"y\n".to_vec(), OutputStream::Stdout);
+        coalescer.push("b".to_string(), b"1".to_vec(), OutputStream::Stdout);
+        coalescer.push("a".to_string(), b"y\n".to_vec(), OutputStream::Stdout);
+        coalescer.push("a".to_string(), b"oops".to_vec(), OutputStream::Stderr);
+        coalescer.push("a".to_string(), b"!".to_vec(), OutputStream::Stderr);
+        assert!(coalescer.deadline().is_some());
+
+        assert_eq!(
+            outputs(coalescer.take()),
+            vec![
+                ("a".to_string(), OutputStream::Stdout, b"y\ny\n".to_vec()),
+                ("b".to_string(), OutputStream::Stdout, b"1".to_vec()),
+                ("a".to_string(), OutputStream::Stderr, b"oops!".to_vec()),
+            ]
+        );
+        assert!(coalescer.deadline().is_none());
+        assert!(coalescer.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn caps_merged_chunk_size() {
+        let mut coalescer = OutputCoalescer::default();
+        let chunk = vec![b'y'; MAX_COALESCED_BYTES / 2 + 1];
+        coalescer.push("a".to_string(), chunk.clone(), OutputStream::Stdout);
+        coalescer.push("a".to_string(), chunk.clone(), OutputStream::Stdout);
+
+        let sizes: Vec<usize> = outputs(coalescer.take())
+            .into_iter()
+            .map(|(_, _, data)| data.len())
+            .collect();
+        assert_eq!(sizes, vec![chunk.len(), chunk.len()]);
+    }
+}
@@ -0,0 +1,95 @@
+//! Filesystem path completion for client input
+
+use std::path::{Path, PathBuf};
+
+/// Maximum candidates returned for one completion request
+const MAX_CANDIDATES: usize = 200;
+
+/// Complete `partial` as a path relative to `base`.
+///
+/// Candidates are returned sorted, in the same form as the input (relative,
+/// absolute or `~/`), with a trailing `/` on directories. Dotfiles are only
+/// offered when the partial name starts with `.`.
+pub fn complete_path(base: &Path, partial: &str) -> Vec<String> {
+    let (dir_part, name_prefix) = match partial.rfind('/') {
+        Some(idx) => partial.split_at(idx + 1),
+        None => ("", partial),
+    };
+
+    let search_dir = if dir_part.is_empty() {
+        base.to_path_buf()
+    } else if let Some(rest) = dir_part.strip_prefix("~/") {
+        match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(rest),
+            None => return Vec::new(),
+        }
+    } else {
+        base.join(dir_part)
+    };
+
+    let Ok(entries) = std::fs::read_dir(&search_dir) else {
+        return Vec::new();
+    };
+
+    let mut candidates: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with(name_prefix) {
+                return None;
+            }
+            if name.starts_with('.') && !name_prefix.starts_with('.') {
+                return None;
+            }
+            // Follow symlinks so links to directories complete as directories
+            let is_dir = entry.path().is_dir();
+            Some(format!(
+                "{}{}{}",
+                dir_part,
+                name,
+                if is_dir { "/" } else { "" }
+            ))
+        })
+        .collect();
+
+    candidates.sort();
+    candidates.truncate(MAX_CANDIDATES);
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn completes_relative_to_base() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "").unwrap();
+        std::fs::write(dir.path().join("src/lib.rs"), "").unwrap();
+        std::fs::write(dir.path().join("Cargo.toml"), "").unwrap();
+        std::fs::write(dir.path().join(".env"), "").unwrap();
+
+        assert_eq!(complete_path(dir.path(), "s"), vec!["src/"]);
+        assert_eq!(
+            complete_path(dir.path(), "src/"),
+            vec!["src/lib.rs", "src/main.rs"]
+        );
+        assert_eq!(complete_path(dir.path(), ""), vec!["Cargo.toml", "src/"]);
+        assert_eq!(complete_path(dir.path(), "."), vec![".env"]);
+        assert!(complete_path(dir.path(), "missing/").is_empty());
+    }
+
+    #[test]
+    fn keeps_absolute_paths_absolute() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+        let partial = format!("{}/no", dir.path().display());
+
+        assert_eq!(
+            complete_path(Path::new("/"), &partial),
+            vec![format!("{}/notes.txt", dir.path().display())]
+        );
+    }
+}
@@ -1,15 +1,25 @@
 //! Client connection handling
 
 use crate::protocol::{
-    deserialize, frame_message, serialize, ClientMessage, ServerMessage, PROTOCOL_VERSION,
+    deserialize, frame_message, Capability, ClientInfo, ClientMessage, Codec, NotificationKind,
+    NotificationPrefs, ServerMessage, CAPABILITIES, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
 };
 use anyhow::{anyhow, Result};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::unix::OwnedWriteHalf;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Notify};
 use uuid::Uuid;
 
+/// How long a send may wait for room in a tracked client's queue before the
+/// message is dropped
+const SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Least time between two output notifications for the same channel
+const OUTPUT_NOTIFICATION_INTERVAL: Duration = Duration::from_secs(10);
+
 /// Represents a connected client
 pub struct ClientConnection {
     /// Unique client identifier
@@ -20,6 +30,65 @@ pub struct ClientConnection {
 
     /// Channels this client is subscribed to
     subscriptions: HashSet<String>,
+
+    /// Identity the client presented in its Hello, if any. It's the
+    /// client's own claim, so it only labels the client and its saved view.
+    identity: Option<String>,
+
+    /// Who the transport says is connected: the local user on the Unix
+    /// socket, or the certificate name of a mutual-TLS client. ACLs go by
+    /// this.
+    principal: Option<String>,
+
+    /// Connected over the Unix socket as the user running the server
+    owner: bool,
+
+    /// Name the client gave in its Hello, if any
+    name: Option<String>,
+
+    /// When the client connected
+    attached_at: Instant,
+
+    /// Set once the other clients have been told it attached
+    announced: bool,
+
+    /// Optional protocol features the client said it understands
+    capabilities: HashSet<Capability>,
+
+    /// Protocol version the client speaks, shared with its writer task so
+    /// messages get encoded the way it expects
+    protocol_version: Arc<AtomicU32>,
+
+    /// Terminal size the client last reported, as (cols, rows)
+    size: Option<(u16, u16)>,
+
+    /// Attached read-only; it may watch but not change the session
+    observer: bool,
+
+    /// Channel activity the client wants notifications for
+    notifications: NotificationPrefs,
+
+    /// When each channel's last output notification went out
+    output_notified: HashMap<String, Instant>,
+
+    /// Set once the client has detached; it gets no more broadcasts
+    detached: bool,
+
+    /// Secret the client presents to resume this connection's view after
+    /// reconnecting
+    resume_token: String,
+
+    /// Messages written out by the client's writer task, when it has one
+    written: Option<Arc<AtomicU64>>,
+
+    /// Write count last seen by [`ClientConnection::is_stalled`] and since when
+    progress: (u64, Instant),
+
+    /// Set after a send timed out; later sends don't wait until one succeeds
+    send_blocked: AtomicBool,
+
+    /// Signalled to make the connection's handler hang up
+    disconnect: Arc<Notify>,
 }
 
 impl ClientConnection {
@@ -29,6 +98,24 @@ impl ClientConnection {
             id: Uuid::new_v4(),
             sender,
             subscriptions: HashSet::new(),
+            identity: None,
+            principal: None,
+            owner: false,
+            name: None,
+            attached_at: Instant::now(),
+            announced: false,
+            capabilities: HashSet::new(),
+            protocol_version: Arc::new(AtomicU32::new(MIN_PROTOCOL_VERSION)),
+            size: None,
+            observer: false,
+            notifications: NotificationPrefs::default(),
+            output_notified: HashMap::new(),
+            detached: false,
+            resume_token: Uuid::new_v4().simple().to_string(),
+            written: None,
+            progress: (0, Instant::now()),
+            send_blocked: AtomicBool::new(false),
+            disconnect: Arc::new(Notify::new()),
         }
     }
 
@@ -37,12 +124,79 @@ impl ClientConnection {
         self.id
     }
 
-    /// Send a message to the client
+    /// Terminal size the client last reported
+    pub fn size(&self) -> Option<(u16, u16)> {
+        self.size
+    }
+
+    /// Record the client's terminal size
+    pub fn set_size(&mut self, cols: u16, rows: u16) {
+        self.size = Some((cols, rows));
+    }
+
+    /// Send a message to the client.
+    ///
+    /// Clients with a tracked writer only get [`SEND_TIMEOUT`] to make room
+    /// in their queue, so one that stopped reading can't hold up the server.
     pub async fn send(&self, msg: ServerMessage) -> Result<()> {
-        self.sender
-            .send(msg)
-            .await
-            .map_err(|_| anyhow!("Failed to send message to client"))
+        if self.written.is_none() {
+            return self
+                .sender
+                .send(msg)
+                .await
+                .map_err(|_| anyhow!("Failed to send message to client"));
+        }
+
+        let result = if self.send_blocked.load(Ordering::Relaxed) {
+            self.sender.try_send(msg).map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => anyhow!("Client is not reading"),
+                mpsc::error::TrySendError::Closed(_) => anyhow!("Failed to send message to client"),
+            })
+        } else {
+            self.sender
+                .send_timeout(msg, SEND_TIMEOUT)
+                .await
+                .map_err(|e| match e {
+                    mpsc::error::SendTimeoutError::Timeout(_) => anyhow!("Client is not reading"),
+                    mpsc::error::SendTimeoutError::Closed(_) => {
+                        anyhow!("Failed to send message to client")
+                    }
+                })
+        };
+        self.send_blocked.store(result.is_err(), Ordering::Relaxed);
+        result
+    }
+
+    /// Start counting the messages the client's writer task writes out,
+    /// returning the counter to hand to [`client_writer_task`]
+    pub fn track_writes(&mut self) -> Arc<AtomicU64> {
+        Arc::clone(self.written.get_or_insert_with(Default::default))
+    }
+
+    /// Whether messages have been queued for the client without any being
+    /// written out for `timeout`, i.e. it stopped reading. Only clients with
+    /// tracked writes can stall; call this periodically.
+    pub fn is_stalled(&mut self, timeout: Duration) -> bool {
+        let Some(written) = &self.written else {
+            return false;
+        };
+        let written = written.load(Ordering::Relaxed);
+        let queued = self.sender.max_capacity() - self.sender.capacity();
+        if queued == 0 || written != self.progress.0 {
+            self.progress = (written, Instant::now());
+            return false;
+        }
+        self.progress.1.elapsed() >= timeout
+    }
+
+    /// Signal the connection's handler to hang up
+    pub fn disconnect(&self) {
+        self.disconnect.notify_one();
+    }
+
+    /// Notified when the client should be disconnected
+    pub fn disconnect_signal(&self) -> Arc<Notify> {
+        Arc::clone(&self.disconnect)
     }
 
     /// Subscribe to channels; returns newly added channel names.
@@ -72,6 +226,155 @@ impl ClientConnection {
         self.subscriptions.contains(channel)
     }
 
+    /// Identity the client presented, if any
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+
+    /// Record the identity the client presented
+    pub fn set_identity(&mut self, identity: String) {
+        self.identity = Some(identity);
+    }
+
+    /// Who the transport says is connected, if it could tell
+    pub fn principal(&self) -> Option<&str> {
+        self.principal.as_deref()
+    }
+
+    /// Record who the transport says is connected
+    pub fn set_principal(&mut self, principal: String) {
+        self.principal = Some(principal);
+    }
+
+    /// Whether the client is the user running the server
+    pub fn is_owner(&self) -> bool {
+        self.owner
+    }
+
+    /// Mark the client as the user running the server
+    pub fn set_owner(&mut self) {
+        self.owner = true;
+    }
+
+    /// Record the name the client gave
+    pub fn set_name(&mut self, name: String) {
+        self.name = Some(name);
+    }
+
+    /// Mark the client as announced to the others
+    pub fn set_announced(&mut self) {
+        self.announced = true;
+    }
+
+    /// Whether the other clients were told the client attached
+    pub fn is_announced(&self) -> bool {
+        self.announced
+    }
+
+    /// When the client connected
+    pub fn attached_at(&self) -> Instant {
+        self.attached_at
+    }
+
+    /// What other clients are told about this one
+    pub fn info(&self) -> ClientInfo {
+        ClientInfo {
+            id: self.id,
+            name: self.name.clone(),
+            identity: self.identity.clone(),
+            observer: self.observer,
+            attached_secs: self.attached_at.elapsed().as_secs(),
+            is_self: false,
+        }
+    }
+
+    /// Record the capabilities the client advertised, ignoring unknown ones
+    pub fn set_capabilities(&mut self, capabilities: &[Capability]) {
+        self.capabilities = capabilities
+            .iter()
+            .copied()
+            .filter(|capability| *capability != Capability::Unknown)
+            .collect();
+    }
+
+    /// Protocol version the client speaks; the oldest supported one until
+    /// its Hello says otherwise
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version.load(Ordering::Relaxed)
+    }
+
+    /// Record the (already validated) protocol version from the client's Hello
+    pub fn set_protocol_version(&self, version: u32) {
+        self.protocol_version.store(version, Ordering::Relaxed);
+    }
+
+    /// Handle to the client's protocol version for [`client_writer_task`]
+    pub fn protocol_version_handle(&self) -> Arc<AtomicU32> {
+        Arc::clone(&self.protocol_version)
+    }
+
+    /// Whether the client advertised a capability
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Mark the client detached, dropping its subscriptions so output stops
+    /// at once; returns the subscriptions it had
+    pub fn detach(&mut self) -> Vec<String> {
+        let subscriptions = self.get_subscriptions();
+        self.subscriptions.clear();
+        self.detached = true;
+        subscriptions
+    }
+
+    /// Whether the client has detached
+    pub fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    /// Make the client a read-only observer. There's no way back; a client
+    /// that wants to type has to reconnect.
+    pub fn set_observer(&mut self) {
+        self.observer = true;
+    }
+
+    /// Whether the client is a read-only observer
+    pub fn is_observer(&self) -> bool {
+        self.observer
+    }
+
+    /// Replace the client's notification preferences
+    pub fn set_notifications(&mut self, prefs: NotificationPrefs) {
+        self.notifications = prefs;
+        self.output_notified.clear();
+    }
+
+    /// Whether to notify the client of `kind` activity on `channel` now.
+    /// Output notifications are rate-limited per channel, so a `true` for
+    /// one counts as sent.
+    pub fn take_notification(&mut self, channel: &str, kind: NotificationKind) -> bool {
+        if self.detached || !self.notifications.wants(channel, kind) {
+            return false;
+        }
+        if kind == NotificationKind::Output {
+            let now = Instant::now();
+            if self
+                .output_notified
+                .get(channel)
+                .is_some_and(|last| now.duration_since(*last) < OUTPUT_NOTIFICATION_INTERVAL)
+            {
+                return false;
+            }
+            self.output_notified.insert(channel.to_string(), now);
+        }
+        true
+    }
+
+    /// Token the client can resume this connection with
+    pub fn resume_token(&self) -> &str {
+        &self.resume_token
+    }
+
     /// Get a list of current subscriptions
     pub fn get_subscriptions(&self) -> Vec<String> {
         let mut subs: Vec<_> = self.subscriptions.iter().cloned().collect();
@@ -111,23 +414,59 @@ pub async fn write_message<W: AsyncWriteExt + Unpin>(writer: &mut W, payload: &[
     Ok(())
 }
 
-/// Task to write outgoing messages to the client
-pub async fn client_writer_task(
-    mut writer: OwnedWriteHalf,
+/// Most queued messages [`client_writer_task`] combines into one write
+const MAX_BATCH_MESSAGES: usize = 64;
+
+/// A batch stops taking more messages once it's this many bytes
+const MAX_BATCH_BYTES: usize = 256 * 1024;
+
+/// Task to write outgoing messages to the client, encoding each for the
+/// protocol version the client currently speaks.
+///
+/// Messages already queued when one is written go out with it, framed back
+/// to back in a single write and flush.
+pub async fn client_writer_task<W: AsyncWriteExt + Unpin>(
+    mut writer: W,
     mut receiver: mpsc::Receiver<ServerMessage>,
+    written: Arc<AtomicU64>,
+    protocol_version: Arc<AtomicU32>,
 ) {
-    while let Some(msg) = receiver.recv().await {
-        match serialize(&msg) {
-            Ok(payload) => {
-                if let Err(e) = write_message(&mut writer, &payload).await {
-                    tracing::error!("Failed to write message to client: {}", e);
-                    break;
+    let mut batch = Vec::new();
+    while let Some(first) = receiver.recv().await {
+        batch.clear();
+        let mut count = 0;
+        let mut next = Some(first);
+        while let Some(msg) = next {
+            let codec = Codec::for_version(protocol_version.load(Ordering::Relaxed))
+                .unwrap_or(Codec::oldest());
+            match codec.encode(&msg) {
+                Ok(payload) => {
+                    batch.extend_from_slice(&frame_message(&payload));
+                    count += 1;
+                }
+                Err(e) => {
+                    tracing::error!("Failed to serialize message: {}", e);
                 }
             }
-            Err(e) => {
-                tracing::error!("Failed to serialize message: {}", e);
-            }
+            next = if count < MAX_BATCH_MESSAGES && batch.len() < MAX_BATCH_BYTES {
+                receiver.try_recv().ok()
+            } else {
+                None
+            };
+        }
+        if batch.is_empty() {
+            continue;
         }
+
+        let result = async {
+            writer.write_all(&batch).await?;
+            writer.flush().await
+        };
+        if let Err(e) = result.await {
+            tracing::error!("Failed to write message to client: {}", e);
+            break;
+        }
+        written.fetch_add(count as u64, Ordering::Relaxed);
     }
 
     tracing::debug!("Client writer task finished");
@@ -139,14 +478,19 @@ pub fn parse_client_message(bytes: &[u8]) -> Result<ClientMessage> {
 }
 
 /// Create a welcome message for a new client
-pub fn create_welcome_message(session_id: Uuid) -> ServerMessage {
+pub fn create_welcome_message(session_id: Uuid, resume_token: &str) -> ServerMessage {
     ServerMessage::Welcome {
         session_id,
         protocol_version: PROTOCOL_VERSION,
+        resume_token: Some(resume_token.to_string()),
+        capabilities: CAPABILITIES.to_vec(),
     }
 }
 
 /// Create an error message
 pub fn create_error_message(message: String) -> ServerMessage {
-    ServerMessage::Error { message }
+    ServerMessage::Error {
+        message,
+        request_id: None,
+    }
 }
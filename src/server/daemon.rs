@@ -0,0 +1,226 @@
+//! Daemonization, PID files and the startup lock
+//!
+//! Each server writes `<session>.pid` next to its socket. A PID file whose
+//! process is gone is stale and gets replaced; a live server whose socket has
+//! disappeared is an orphan that `nexus kill-server` can still find and stop.
+//!
+//! `<session>.lock` is held from before daemonizing until the socket is
+//! bound, so concurrent starts are serialized and clients can block on it to
+//! learn when the server is ready.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Detach from the terminal and the spawning process with the classic
+/// fork/setsid/fork sequence, and point stdio at `/dev/null`.
+///
+/// The original process exits once the daemon is forked, so the caller can
+/// wait on it. The working directory is kept, since channels without one of
+/// their own start there. Must run before any threads (or the tokio runtime)
+/// are started.
+pub fn daemonize() -> Result<()> {
+    // SAFETY: called while single-threaded; the parents exit immediately
+    // without running destructors
+    unsafe {
+        match libc::fork() {
+            -1 => return Err(std::io::Error::last_os_error()).context("fork failed"),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+        if libc::setsid() == -1 {
+            return Err(std::io::Error::last_os_error()).context("setsid failed");
+        }
+        // Fork again so the daemon isn't a session leader and can never
+        // reacquire a controlling terminal
+        match libc::fork() {
+            -1 => return Err(std::io::Error::last_os_error()).context("fork failed"),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+    }
+
+    let null = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in 0..=2 {
+        // SAFETY: `null` stays open for the duration of the dup2 calls
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(std::io::Error::last_os_error()).context("Failed to redirect stdio");
+        }
+    }
+    Ok(())
+}
+
+/// Startup lock path for the server listening on `socket_path`
+pub fn lock_path(socket_path: &Path) -> PathBuf {
+    socket_path.with_extension("lock")
+}
+
+/// Exclusive hold on a session's startup lock; released when dropped.
+///
+/// `flock` locks belong to the open file, so taking this before
+/// [`daemonize`] leaves the daemon holding it.
+pub struct StartupLock {
+    _file: File,
+}
+
+impl StartupLock {
+    /// Block until no other server for the session is starting up
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let file = open_lock_file(path)?;
+        flock(&file, libc::LOCK_EX)?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// Block until nobody holds the startup lock at `path`, i.e. until a server
+/// that was starting has bound its socket or given up
+pub fn wait_for_startup(path: &Path) -> Result<()> {
+    let file = open_lock_file(path)?;
+    flock(&file, libc::LOCK_SH)
+}
+
+fn open_lock_file(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+fn flock(file: &File, operation: libc::c_int) -> Result<()> {
+    loop {
+        // SAFETY: the descriptor is valid for the lifetime of `file`
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(error).context("Failed to lock the startup lock");
+        }
+    }
+}
+
+/// PID file path for the server listening on `socket_path`
+pub fn pid_path(socket_path: &Path) -> PathBuf {
+    socket_path.with_extension("pid")
+}
+
+/// PID recorded in a PID file, if it exists and parses
+pub fn read_pid(path: &Path) -> Option<u32> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Whether `pid` is a running nexus-server.
+///
+/// Where `/proc` is available the command line is checked too, so a PID
+/// reused by an unrelated process doesn't count.
+pub fn is_server_running(pid: u32) -> bool {
+    let Ok(raw) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks that the process exists
+    let exists = unsafe { libc::kill(raw, 0) } == 0
+        || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    if !exists {
+        return false;
+    }
+    match std::fs::read(format!("/proc/{}/cmdline", pid)) {
+        Ok(cmdline) => String::from_utf8_lossy(&cmdline).contains("nexus-server"),
+        Err(_) => true,
+    }
+}
+
+/// Ask a server process to shut down with SIGTERM
+pub fn terminate(pid: u32) -> Result<()> {
+    let raw = libc::pid_t::try_from(pid).map_err(|_| anyhow!("Invalid pid {}", pid))?;
+    // SAFETY: plain kill(2); the caller checked the process is a nexus-server
+    if unsafe { libc::kill(raw, libc::SIGTERM) } == -1 {
+        return Err(std::io::Error::last_os_error()).with_context(|| format!("kill {}", pid));
+    }
+    Ok(())
+}
+
+/// This process's PID file, removed again when dropped
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Record this process in `path`, replacing a stale file but refusing to
+    /// start alongside a live server
+    pub fn acquire(path: PathBuf) -> Result<Self> {
+        if let Some(pid) = read_pid(&path) {
+            if pid != std::process::id() && is_server_running(pid) {
+                return Err(anyhow!(
+                    "nexus-server is already running with pid {} ({})",
+                    pid,
+                    path.display()
+                ));
+            }
+            tracing::info!("Replacing stale PID file {:?} (pid {})", path, pid);
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // The session may have been renamed, taking the file with it
+        if read_pid(&self.path) == Some(std::process::id()) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn startup_waiters_block_until_the_lock_is_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.lock");
+        let lock = StartupLock::acquire(&path).unwrap();
+
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        let waiter_path = path.clone();
+        std::thread::spawn(move || {
+            wait_for_startup(&waiter_path).unwrap();
+            done_tx.send(()).unwrap();
+        });
+        assert!(done_rx
+            .recv_timeout(std::time::Duration::from_millis(200))
+            .is_err());
+
+        drop(lock);
+        done_rx
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .expect("waiter should proceed once the lock is released");
+    }
+
+    #[test]
+    fn replaces_stale_pid_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.pid");
+        // Nothing runs as the largest pid
+        std::fs::write(&path, format!("{}\n", i32::MAX)).unwrap();
+
+        let pid_file = PidFile::acquire(path.clone()).unwrap();
+        assert_eq!(read_pid(&path), Some(std::process::id()));
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,163 @@
+//! In-process server for embedding nexus in another tokio application
+
+use super::connection::ClientConnection;
+use super::listener::{add_client, process_message, shutdown_state, ServerState};
+use crate::protocol::{
+    ChannelInfo, ChannelStatus, ClientMessage, CreateChannelRequest, ServerMessage,
+};
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use tokio::sync::{mpsc, RwLock};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// A session server running in-process, driven through method calls instead
+/// of a Unix socket.
+///
+/// ```no_run
+/// use nexus::protocol::ServerMessage;
+/// use nexus::server::ServerListener;
+/// use std::path::PathBuf;
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let mut server = ServerListener::new("embedded".to_string(), PathBuf::new())
+///     .start_embedded()
+///     .await?;
+/// let mut events = server.take_events().unwrap();
+///
+/// server.create_channel("build", Some("cargo build")).await?;
+/// while let Some(ServerMessage::Output { data, .. }) = events.recv().await {
+///     print!("{}", String::from_utf8_lossy(&data));
+/// }
+/// server.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct EmbeddedServer {
+    state: Arc<RwLock<ServerState>>,
+    client_id: Uuid,
+    events: Option<mpsc::UnboundedReceiver<ServerMessage>>,
+    event_task: JoinHandle<()>,
+    forward_task: JoinHandle<()>,
+}
+
+impl EmbeddedServer {
+    pub(super) async fn new(state: Arc<RwLock<ServerState>>, event_task: JoinHandle<()>) -> Self {
+        let (tx, mut rx) = mpsc::channel::<ServerMessage>(256);
+        let client = ClientConnection::new(tx);
+        let client_id = client.id();
+        add_client(&state, client).await;
+
+        // Drain the bounded client queue so broadcasts never block the server,
+        // even when nobody is reading events
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let forward_task = tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                let _ = events_tx.send(msg);
+            }
+        });
+
+        Self {
+            state,
+            client_id,
+            events: Some(events_rx),
+            event_task,
+            forward_task,
+        }
+    }
+
+    /// Take the stream of output and events for the embedding application.
+    ///
+    /// Returns `None` if it was already taken.
+    pub fn take_events(&mut self) -> Option<mpsc::UnboundedReceiver<ServerMessage>> {
+        self.events.take()
+    }
+
+    /// Handle a protocol message as if it came from a connected client,
+    /// returning the server's reply (if any)
+    pub async fn request(&self, msg: ClientMessage) -> Option<ServerMessage> {
+        process_message(msg, self.client_id, &self.state).await
+    }
+
+    /// Create a channel running `command` (or the default shell) and subscribe to it
+    pub async fn create_channel(&self, name: &str, command: Option<&str>) -> Result<()> {
+        self.expect_ack(ClientMessage::CreateChannel(CreateChannelRequest {
+            name: name.to_string(),
+            command: command.map(str::to_string),
+            ..Default::default()
+        }))
+        .await
+    }
+
+    /// Kill a channel
+    pub async fn kill_channel(&self, name: &str) -> Result<()> {
+        self.expect_ack(ClientMessage::KillChannel {
+            name: name.to_string(),
+        })
+        .await
+    }
+
+    /// Write input to a channel
+    pub async fn send_input(&self, channel: &str, data: impl AsRef<[u8]>) -> Result<()> {
+        let msg = ClientMessage::InputTo {
+            channel: channel.to_string(),
+            data: data.as_ref().to_vec(),
+        };
+        match self.request(msg).await {
+            Some(ServerMessage::Error { message, .. }) => Err(anyhow!(message)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Subscribe to channel output (`"*"` for every channel)
+    pub async fn subscribe(&self, channels: &[&str]) -> Result<()> {
+        let msg = ClientMessage::Subscribe {
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+        };
+        match self.request(msg).await {
+            Some(ServerMessage::Error { message, .. }) => Err(anyhow!(message)),
+            _ => Ok(()),
+        }
+    }
+
+    /// List the session's channels
+    pub async fn list_channels(&self) -> Result<Vec<ChannelInfo>> {
+        match self.request(ClientMessage::ListChannels).await {
+            Some(ServerMessage::ChannelList { channels }) => Ok(channels),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Detailed status of one channel, or every channel when `None`
+    pub async fn status(&self, channel: Option<&str>) -> Result<Vec<ChannelStatus>> {
+        let msg = ClientMessage::GetStatus {
+            channel: channel.map(str::to_string),
+        };
+        match self.request(msg).await {
+            Some(ServerMessage::Status { channels }) => Ok(channels),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Kill every channel and stop the server's background tasks
+    pub async fn shutdown(self) {
+        self.event_task.abort();
+        shutdown_state(&self.state, self.client_id).await;
+        self.forward_task.abort();
+    }
+
+    async fn expect_ack(&self, msg: ClientMessage) -> Result<()> {
+        match self.request(msg).await {
+            Some(ServerMessage::Ack { .. }) => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+fn unexpected(reply: Option<ServerMessage>) -> anyhow::Error {
+    match reply {
+        Some(ServerMessage::Error { message, .. }) => anyhow!(message),
+        Some(other) => anyhow!("Unexpected reply: {:?}", other),
+        None => anyhow!("No reply from server"),
+    }
+}
@@ -0,0 +1,108 @@
+//! Command history shared by all clients of a session
+
+use crate::protocol::ChannelHistory;
+use std::collections::{HashMap, VecDeque};
+
+/// Maximum history entries kept per channel
+pub const MAX_HISTORY_ENTRIES: usize = 1000;
+
+/// Per-channel command history kept by the server
+pub struct HistoryStore {
+    entries: HashMap<String, VecDeque<String>>,
+    max_entries: usize,
+}
+
+impl HistoryStore {
+    /// Create an empty store keeping at most `max_entries` per channel
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_entries,
+        }
+    }
+
+    /// Append an entry; returns false if it was empty or repeats the last entry
+    pub fn append(&mut self, channel: &str, entry: &str) -> bool {
+        if entry.is_empty() {
+            return false;
+        }
+        let history = self.entries.entry(channel.to_string()).or_default();
+        if history.back().map(String::as_str) == Some(entry) {
+            return false;
+        }
+        history.push_back(entry.to_string());
+        while history.len() > self.max_entries {
+            history.pop_front();
+        }
+        true
+    }
+
+    /// History for one channel, oldest first
+    pub fn get(&self, channel: &str) -> Vec<String> {
+        self.entries
+            .get(channel)
+            .map(|h| h.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Histories for the given channel, or for every channel when `None`
+    pub fn snapshot(&self, channel: Option<&str>) -> Vec<ChannelHistory> {
+        match channel {
+            Some(channel) => vec![ChannelHistory {
+                channel: channel.to_string(),
+                entries: self.get(channel),
+            }],
+            None => {
+                let mut histories: Vec<ChannelHistory> = self
+                    .entries
+                    .keys()
+                    .map(|channel| ChannelHistory {
+                        channel: channel.clone(),
+                        entries: self.get(channel),
+                    })
+                    .collect();
+                histories.sort_by(|a, b| a.channel.cmp(&b.channel));
+                histories
+            }
+        }
+    }
+}
+
+impl Default for HistoryStore {
+    fn default() -> Self {
+        Self::new(MAX_HISTORY_ENTRIES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_empty_and_repeated_entries() {
+        let mut store = HistoryStore::default();
+        assert!(store.append("build", "make"));
+        assert!(!store.append("build", "make"));
+        assert!(!store.append("build", ""));
+        assert!(store.append("build", "make test"));
+        assert_eq!(store.get("build"), vec!["make", "make test"]);
+        assert!(store.get("other").is_empty());
+    }
+
+    #[test]
+    fn caps_entries_per_channel() {
+        let mut store = HistoryStore::new(2);
+        for cmd in ["a", "b", "c"] {
+            store.append("shell", cmd);
+        }
+        store.append("build", "make");
+
+        assert_eq!(store.get("shell"), vec!["b", "c"]);
+        let channels: Vec<_> = store
+            .snapshot(None)
+            .into_iter()
+            .map(|h| h.channel)
+            .collect();
+        assert_eq!(channels, vec!["build", "shell"]);
+    }
+}
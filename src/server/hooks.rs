@@ -0,0 +1,336 @@
+//! Event hooks - shell commands from `[hooks]` run by the server
+
+use crate::config::HooksConfig;
+use regex::Regex;
+use std::collections::HashMap;
+use tokio::process::Command;
+
+/// Event that can trigger hooks
+#[derive(Debug, Clone, Copy)]
+pub enum HookEvent<'a> {
+    ChannelCreated {
+        channel: &'a str,
+    },
+    ChannelExited {
+        channel: &'a str,
+        exit_code: Option<i32>,
+    },
+    Output {
+        channel: &'a str,
+        data: &'a [u8],
+    },
+    ClientAttached {
+        client_id: &'a str,
+    },
+}
+
+/// Longest unfinished line kept for `output_match`; longer ones are matched
+/// as they are
+const MAX_PARTIAL_LINE: usize = 64 * 1024;
+
+struct OutputHook {
+    pattern: Regex,
+    channel: Option<String>,
+    command: String,
+}
+
+/// Hooks compiled from config, ready to run
+#[derive(Default)]
+pub struct Hooks {
+    channel_created: Vec<String>,
+    channel_exited: Vec<String>,
+    client_attached: Vec<String>,
+    output_match: Vec<OutputHook>,
+    /// Unfinished last line of each channel's output, matched once it ends
+    partial_lines: HashMap<String, Vec<u8>>,
+}
+
+impl Hooks {
+    /// Compile hooks from config, returning errors for invalid patterns
+    pub fn from_config(config: &HooksConfig) -> (Self, Vec<String>) {
+        let mut errors = Vec::new();
+        let output_match = config
+            .output_match
+            .iter()
+            .filter_map(|hook| match Regex::new(&hook.pattern) {
+                Ok(pattern) => Some(OutputHook {
+                    pattern,
+                    channel: hook.channel.clone(),
+                    command: hook.command.clone(),
+                }),
+                Err(e) => {
+                    errors.push(format!(
+                        "Invalid output_match pattern '{}': {}",
+                        hook.pattern, e
+                    ));
+                    None
+                }
+            })
+            .collect();
+
+        let hooks = Self {
+            channel_created: config.channel_created.clone(),
+            channel_exited: config.channel_exited.clone(),
+            client_attached: config.client_attached.clone(),
+            output_match,
+            partial_lines: HashMap::new(),
+        };
+        (hooks, errors)
+    }
+
+    /// Recompile hooks from changed config, keeping output lines still in
+    /// progress
+    pub fn reconfigure(&mut self, config: &HooksConfig) -> Vec<String> {
+        let (hooks, errors) = Self::from_config(config);
+        let partial_lines = std::mem::take(&mut self.partial_lines);
+        *self = Self {
+            partial_lines,
+            ..hooks
+        };
+        errors
+    }
+
+    /// Start every hook for `event` in the background
+    pub fn run(&mut self, session: &str, event: HookEvent<'_>) {
+        for (command, env) in self.commands_for(event) {
+            spawn_hook(command, session, env);
+        }
+    }
+
+    /// Commands to run for an event, with their event-specific environment
+    fn commands_for(&mut self, event: HookEvent<'_>) -> Vec<(String, Vec<(&'static str, String)>)> {
+        let with_env = |commands: &[String], env: Vec<(&'static str, String)>| {
+            commands
+                .iter()
+                .map(|command| (command.clone(), env.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        match event {
+            HookEvent::ChannelCreated { channel } => with_env(
+                &self.channel_created,
+                vec![
+                    ("NEXUS_EVENT", "channel_created".to_string()),
+                    ("NEXUS_CHANNEL", channel.to_string()),
+                ],
+            ),
+            HookEvent::ChannelExited { channel, exit_code } => {
+                let mut env = vec![
+                    ("NEXUS_EVENT", "channel_exited".to_string()),
+                    ("NEXUS_CHANNEL", channel.to_string()),
+                ];
+                if let Some(code) = exit_code {
+                    env.push(("NEXUS_EXIT_CODE", code.to_string()));
+                }
+                let mut commands = with_env(&self.channel_exited, env);
+                // The last line may not have ended
+                if let Some(rest) = self.partial_lines.remove(channel) {
+                    let rest = String::from_utf8_lossy(&rest).to_string();
+                    commands.extend(self.output_commands(channel, &[rest]));
+                }
+                commands
+            }
+            HookEvent::ClientAttached { client_id } => with_env(
+                &self.client_attached,
+                vec![
+                    ("NEXUS_EVENT", "client_attached".to_string()),
+                    ("NEXUS_CLIENT_ID", client_id.to_string()),
+                ],
+            ),
+            HookEvent::Output { channel, data } => {
+                if self.output_match.is_empty() {
+                    return Vec::new();
+                }
+                let lines = self.complete_lines(channel, data);
+                self.output_commands(channel, &lines)
+            }
+        }
+    }
+
+    /// Add a chunk of `channel`'s output and return the lines it completes,
+    /// holding back an unfinished last line until the rest of it arrives
+    fn complete_lines(&mut self, channel: &str, data: &[u8]) -> Vec<String> {
+        let partial = self.partial_lines.entry(channel.to_string()).or_default();
+        partial.extend_from_slice(data);
+        let complete = match partial.iter().rposition(|&b| b == b'\n') {
+            Some(end) => {
+                let rest = partial.split_off(end + 1);
+                std::mem::replace(partial, rest)
+            }
+            None if partial.len() > MAX_PARTIAL_LINE => std::mem::take(partial),
+            None => return Vec::new(),
+        };
+        String::from_utf8_lossy(&complete)
+            .lines()
+            .map(|line| line.trim_end_matches('\r').to_string())
+            .collect()
+    }
+
+    /// `output_match` hooks for `channel` with a pattern matching one of
+    /// `lines`; at most one run per hook, for the first matching line
+    fn output_commands(
+        &self,
+        channel: &str,
+        lines: &[String],
+    ) -> Vec<(String, Vec<(&'static str, String)>)> {
+        self.output_match
+            .iter()
+            .filter(|hook| hook.channel.as_deref().is_none_or(|c| c == channel))
+            .filter_map(|hook| {
+                let line = lines.iter().find(|line| hook.pattern.is_match(line))?;
+                Some((
+                    hook.command.clone(),
+                    vec![
+                        ("NEXUS_EVENT", "output_match".to_string()),
+                        ("NEXUS_CHANNEL", channel.to_string()),
+                        ("NEXUS_MATCH", line.clone()),
+                    ],
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Run a hook command with `sh -c`, logging failures without blocking the caller
+fn spawn_hook(command: String, session: &str, env: Vec<(&'static str, String)>) {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(&command)
+        .env("NEXUS_SESSION", session)
+        .envs(env)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+
+    match cmd.spawn() {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                match child.wait().await {
+                    Ok(status) if !status.success() => {
+                        tracing::warn!("Hook '{}' exited with {}", command, status)
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("Failed to wait for hook '{}': {}", command, e),
+                }
+            });
+        }
+        Err(e) => tracing::warn!("Failed to run hook '{}': {}", command, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OutputMatchHook;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn output_hooks_match_lines_and_channels() {
+        let config = HooksConfig {
+            output_match: vec![
+                OutputMatchHook {
+                    pattern: "error".to_string(),
+                    channel: Some("build".to_string()),
+                    command: "echo build".to_string(),
+                },
+                OutputMatchHook {
+                    pattern: "(".to_string(),
+                    channel: None,
+                    command: "echo bad".to_string(),
+                },
+            ],
+            ..Default::default()
+        };
+        let (mut hooks, errors) = Hooks::from_config(&config);
+        assert_eq!(errors.len(), 1);
+
+        let commands = hooks.commands_for(HookEvent::Output {
+            channel: "build",
+            data: b"ok\r\nerror: oops\r\nerror: again\r\n",
+        });
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0]
+            .1
+            .contains(&("NEXUS_MATCH", "error: oops".to_string())));
+
+        assert!(hooks
+            .commands_for(HookEvent::Output {
+                channel: "web",
+                data: b"error",
+            })
+            .is_empty());
+    }
+
+    #[test]
+    fn output_hooks_match_whole_lines_across_chunks() {
+        let config = HooksConfig {
+            output_match: vec![OutputMatchHook {
+                pattern: "^error: .*failed$".to_string(),
+                channel: None,
+                command: "echo failed".to_string(),
+            }],
+            ..Default::default()
+        };
+        let (mut hooks, _) = Hooks::from_config(&config);
+        let output = |hooks: &mut Hooks, data: &'static [u8]| {
+            hooks.commands_for(HookEvent::Output {
+                channel: "build",
+                data,
+            })
+        };
+
+        assert!(output(&mut hooks, b"ok\nerror: bu").is_empty());
+        assert!(output(&mut hooks, b"ild ").is_empty());
+        let commands = output(&mut hooks, b"failed\r\nmore");
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0]
+            .1
+            .contains(&("NEXUS_MATCH", "error: build failed".to_string())));
+
+        // Lines in progress survive a config change, and the last one is
+        // matched when the channel exits without ending it
+        hooks.reconfigure(&config);
+        assert!(output(&mut hooks, b"\nerror: test failed").is_empty());
+        let commands = hooks.commands_for(HookEvent::ChannelExited {
+            channel: "build",
+            exit_code: Some(1),
+        });
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0]
+            .1
+            .contains(&("NEXUS_MATCH", "error: test failed".to_string())));
+        assert!(hooks.partial_lines.is_empty());
+    }
+
+    #[tokio::test]
+    async fn runs_hooks_with_event_environment() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("hook.out");
+        let config = HooksConfig {
+            channel_exited: vec![format!(
+                "echo \"$NEXUS_SESSION $NEXUS_EVENT $NEXUS_CHANNEL $NEXUS_EXIT_CODE\" > {}",
+                out.display()
+            )],
+            ..Default::default()
+        };
+        let (mut hooks, _) = Hooks::from_config(&config);
+        hooks.run(
+            "dev",
+            HookEvent::ChannelExited {
+                channel: "build",
+                exit_code: Some(3),
+            },
+        );
+
+        let mut content = String::new();
+        for _ in 0..50 {
+            content = std::fs::read_to_string(&out).unwrap_or_default();
+            if !content.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(content.trim(), "dev channel_exited build 3");
+    }
+}
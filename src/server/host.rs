@@ -0,0 +1,128 @@
+//! Session host - several sessions in one server process
+//!
+//! Every `nexus-server` is a host: besides the session it was started for,
+//! clients can ask it (`ClientMessage::StartSession`) to run more sessions
+//! alongside. Each session keeps its own socket, channels and PID file; only
+//! the process is shared. The process exits once its last session does.
+
+use super::daemon::{self, PidFile, StartupLock};
+use super::listener::ServerListener;
+use super::session::validate_name;
+use crate::config::Config;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::net::UnixStream;
+use tokio::sync::{mpsc, Notify};
+use uuid::Uuid;
+
+/// Runs sessions as tasks sharing one process
+pub struct SessionHost {
+    config: Config,
+    scripts_dir: Option<PathBuf>,
+    plugins_dir: Option<PathBuf>,
+    /// Shutdown senders of the running sessions
+    sessions: Mutex<HashMap<Uuid, mpsc::Sender<()>>>,
+    /// Signalled when the last session finishes
+    idle: Notify,
+}
+
+impl SessionHost {
+    /// Create a host whose sessions use `config`
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            scripts_dir: None,
+            plugins_dir: None,
+            sessions: Mutex::new(HashMap::new()),
+            idle: Notify::new(),
+        }
+    }
+
+    /// Load Lua scripts from this directory in every session
+    pub fn with_scripts_dir(mut self, dir: PathBuf) -> Self {
+        self.scripts_dir = Some(dir);
+        self
+    }
+
+    /// Load WASM plugins from this directory in every session
+    pub fn with_plugins_dir(mut self, dir: PathBuf) -> Self {
+        self.plugins_dir = Some(dir);
+        self
+    }
+
+    /// A listener for session `name` configured like the host's other sessions
+    pub fn listener(self: &Arc<Self>, name: String, socket_path: PathBuf) -> ServerListener {
+        let mut listener = ServerListener::new(name, socket_path)
+            .with_config(self.config.clone())
+            .with_host(Arc::clone(self));
+        if let Some(dir) = &self.scripts_dir {
+            listener = listener.with_scripts_dir(dir.clone());
+        }
+        if let Some(dir) = &self.plugins_dir {
+            listener = listener.with_plugins_dir(dir.clone());
+        }
+        listener
+    }
+
+    /// Run `listener` as one of this host's sessions until it shuts down,
+    /// holding `pid_file` for as long as it runs
+    pub fn spawn(self: &Arc<Self>, listener: ServerListener, pid_file: Option<PidFile>) {
+        let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+        let id = Uuid::new_v4();
+        self.sessions.lock().unwrap().insert(id, shutdown_tx);
+
+        let host = Arc::clone(self);
+        tokio::spawn(async move {
+            if let Err(e) = listener.run(shutdown_rx).await {
+                tracing::error!("Session error: {}", e);
+            }
+            drop(pid_file);
+
+            let mut sessions = host.sessions.lock().unwrap();
+            sessions.remove(&id);
+            if sessions.is_empty() {
+                host.idle.notify_one();
+            }
+        });
+    }
+
+    /// Start session `name` on its configured socket unless it's already
+    /// running. The session's startup lock is held until its socket is bound,
+    /// so callers can wait on the lock before connecting.
+    pub async fn start(self: &Arc<Self>, name: &str) -> Result<()> {
+        validate_name(name)?;
+        let socket_path = self.config.socket_path(name);
+        if UnixStream::connect(&socket_path).await.is_ok() {
+            return Ok(());
+        }
+
+        let lock_path = daemon::lock_path(&socket_path);
+        let lock = tokio::task::spawn_blocking(move || StartupLock::acquire(&lock_path)).await??;
+        let pid_file = PidFile::acquire(daemon::pid_path(&socket_path))?;
+        tracing::info!("Hosting session '{}' at {:?}", name, socket_path);
+        let listener = self
+            .listener(name.to_string(), socket_path)
+            .with_startup_lock(lock);
+        self.spawn(listener, Some(pid_file));
+        Ok(())
+    }
+
+    /// Ask every session to shut down
+    pub fn shutdown_all(&self) {
+        for shutdown_tx in self.sessions.lock().unwrap().values() {
+            let _ = shutdown_tx.try_send(());
+        }
+    }
+
+    /// Wait until every session has shut down
+    pub async fn wait(&self) {
+        loop {
+            if self.sessions.lock().unwrap().is_empty() {
+                return;
+            }
+            self.idle.notified().await;
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! HTTP control API - token-protected REST endpoints for scripts and editors
+//!
+//! Every request needs an `Authorization: Bearer <token>` header.
+//!
+//! | Method   | Path                                | Action                               |
+//! |----------|-------------------------------------|--------------------------------------|
+//! | `GET`    | `/sessions`                         | List sessions in the runtime dir     |
+//! | `GET`    | `/channels`                         | List this session's channels         |
+//! | `POST`   | `/channels`                         | Create a channel (`{"name", "command", "working_dir", "container", "mode"}`) |
+//! | `DELETE` | `/channels/{name}`                  | Kill a channel                       |
+//! | `POST`   | `/channels/{name}/input`            | Send input (`{"data": "ls\n"}`)      |
+//! | `GET`    | `/channels/{name}/output?lines=100` | Recent output as plain text          |
+
+use super::auth::{tokens_match, write_token};
+use super::connection::ClientConnection;
+use super::listener::{add_client, process_message, recent_output, ServerState};
+use crate::channel::{ChannelMode, ContainerTarget};
+use crate::config::HttpConfig;
+use crate::protocol::{ClientMessage, CreateChannelRequest, ServerMessage};
+use anyhow::{Context, Result};
+use axum::extract::{Path as UrlPath, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, RwLock};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Lines returned by `/output` when `lines` isn't given
+const DEFAULT_OUTPUT_LINES: usize = 100;
+
+#[derive(Clone)]
+struct ApiState {
+    server: Arc<RwLock<ServerState>>,
+    client_id: Uuid,
+    token: Arc<str>,
+    runtime_dir: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct CreateChannelBody {
+    name: String,
+    command: Option<String>,
+    working_dir: Option<String>,
+    container: Option<ContainerTarget>,
+    mode: Option<ChannelMode>,
+}
+
+#[derive(Deserialize)]
+struct InputBody {
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct OutputQuery {
+    lines: Option<usize>,
+}
+
+/// Start serving the API, returning the bound address and the server task.
+///
+/// `token_path` is where a generated token is written when none is configured.
+pub(super) async fn serve(
+    config: &HttpConfig,
+    server: Arc<RwLock<ServerState>>,
+    token_path: &Path,
+) -> Result<(std::net::SocketAddr, JoinHandle<()>)> {
+    let token = match &config.token {
+        Some(token) => token.clone(),
+        None => write_token(token_path)?,
+    };
+
+    // The API acts as one in-process client; nothing reads its pushed messages
+    let (tx, mut rx) = mpsc::channel::<ServerMessage>(256);
+    let client = ClientConnection::new(tx);
+    let client_id = client.id();
+    add_client(&server, client).await;
+    tokio::spawn(async move { while rx.recv().await.is_some() {} });
+
+    let api = ApiState {
+        server,
+        client_id,
+        token: token.into(),
+        runtime_dir: token_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+    };
+    let app = router(api);
+
+    let listener = TcpListener::bind(&config.bind)
+        .await
+        .with_context(|| format!("Failed to bind HTTP API to {}", config.bind))?;
+    let addr = listener.local_addr()?;
+    let task = tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("HTTP API stopped: {}", e);
+        }
+    });
+    Ok((addr, task))
+}
+
+fn router(api: ApiState) -> Router {
+    Router::new()
+        .route("/sessions", get(list_sessions))
+        .route("/session", get(session_info))
+        .route("/channels", get(list_channels).post(create_channel))
+        .route("/channels/:name", axum::routing::delete(kill_channel))
+        .route("/channels/:name/input", post(send_input))
+        .route("/channels/:name/output", get(channel_output))
+        .layer(middleware::from_fn_with_state(api.clone(), require_token))
+        .with_state(api)
+}
+
+async fn require_token(State(api): State<ApiState>, request: Request, next: Next) -> Response {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(token) if tokens_match(token, &api.token) => next.run(request).await,
+        _ => error_response(StatusCode::UNAUTHORIZED, "missing or invalid token"),
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// Run a protocol message as the API client and map the reply to a response
+async fn dispatch(api: &ApiState, msg: ClientMessage, success: StatusCode) -> Response {
+    match process_message(msg, api.client_id, &api.server).await {
+        Some(ServerMessage::Error { message, .. }) => {
+            error_response(StatusCode::BAD_REQUEST, &message)
+        }
+        _ => success.into_response(),
+    }
+}
+
+async fn list_sessions(State(api): State<ApiState>) -> Response {
+    let mut sessions: Vec<String> = std::fs::read_dir(&api.runtime_dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "sock"))
+                .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    sessions.sort();
+    Json(json!({ "sessions": sessions })).into_response()
+}
+
+async fn session_info(State(api): State<ApiState>) -> Response {
+    match process_message(ClientMessage::GetSessionInfo, api.client_id, &api.server).await {
+        Some(ServerMessage::SessionInfo { info, uptime_secs }) => {
+            Json(json!({ "session": info, "uptime_secs": uptime_secs })).into_response()
+        }
+        _ => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "failed to get session info",
+        ),
+    }
+}
+
+async fn list_channels(State(api): State<ApiState>) -> Response {
+    match process_message(ClientMessage::ListChannels, api.client_id, &api.server).await {
+        Some(ServerMessage::ChannelList { channels }) => {
+            Json(json!({ "channels": channels })).into_response()
+        }
+        _ => error_response(StatusCode::INTERNAL_SERVER_ERROR, "failed to list channels"),
+    }
+}
+
+async fn create_channel(
+    State(api): State<ApiState>,
+    Json(body): Json<CreateChannelBody>,
+) -> Response {
+    let msg = ClientMessage::CreateChannel(CreateChannelRequest {
+        name: body.name,
+        command: body.command,
+        working_dir: body.working_dir,
+        container: body.container,
+        mode: body.mode,
+        ..Default::default()
+    });
+    dispatch(&api, msg, StatusCode::CREATED).await
+}
+
+async fn kill_channel(State(api): State<ApiState>, UrlPath(name): UrlPath<String>) -> Response {
+    dispatch(
+        &api,
+        ClientMessage::KillChannel { name },
+        StatusCode::NO_CONTENT,
+    )
+    .await
+}
+
+async fn send_input(
+    State(api): State<ApiState>,
+    UrlPath(channel): UrlPath<String>,
+    Json(body): Json<InputBody>,
+) -> Response {
+    let msg = ClientMessage::InputTo {
+        channel,
+        data: body.data.into_bytes(),
+    };
+    dispatch(&api, msg, StatusCode::NO_CONTENT).await
+}
+
+async fn channel_output(
+    State(api): State<ApiState>,
+    UrlPath(channel): UrlPath<String>,
+    Query(query): Query<OutputQuery>,
+) -> Response {
+    let lines = query.lines.unwrap_or(DEFAULT_OUTPUT_LINES);
+    match recent_output(&api.server, &channel, lines).await {
+        Some(data) => (
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            String::from_utf8_lossy(&data).into_owned(),
+        )
+            .into_response(),
+        None => error_response(
+            StatusCode::NOT_FOUND,
+            &format!("channel '{}' not found", channel),
+        ),
+    }
+}
@@ -1,42 +1,236 @@
 //! Unix socket listener and server main loop
 
+use super::auth::{
+    apply_socket_permissions, certificate_common_name, is_own_uid, tokens_match, user_name,
+    write_token,
+};
+use super::coalesce::OutputCoalescer;
+use super::completion::complete_path;
 use super::connection::{
     client_writer_task, create_error_message, create_welcome_message, parse_client_message,
-    read_message, ClientConnection,
+    read_message, write_message, ClientConnection,
 };
-use super::session::Session;
+use super::daemon;
+use super::embedded::EmbeddedServer;
+use super::history::HistoryStore;
+use super::hooks::{HookEvent, Hooks};
+use super::host::SessionHost;
+use super::http;
+use super::plugins::PluginHost;
+use super::registry;
+use super::scripting::{QueuedEvent, ScriptAction, ScriptEngine, ScriptEvent};
+use super::scrollback::{spill_file_name, BufferedOutput, Limits, Scrollback};
+use super::session::{self, Session, SessionInfo};
+use super::tls;
 use crate::{
-    channel::{ChannelManager, ChannelManagerEvent},
-    protocol::{ChannelEvent, ClientMessage, ServerMessage, PROTOCOL_VERSION},
+    channel::{
+        process, ChannelConfig, ChannelManager, ChannelManagerEvent, ChannelMode, ContainerTarget,
+    },
+    config::{duration, ChannelAction, Config},
+    protocol::{
+        check_client_version, Capability, ChannelEvent, ClientInfo, ClientMessage, Codec,
+        CreateChannelRequest, NotificationKind, ProcessInfo, RunInfo, ScheduleInfo, ServerMessage,
+        SharedMark, StopSignal,
+    },
 };
 use anyhow::{anyhow, Result};
-use std::collections::{HashMap, HashSet, VecDeque};
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::net::{UnixListener, UnixStream};
-use tokio::sync::{mpsc, RwLock};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use tokio::sync::{mpsc, Notify, RwLock};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_rustls::TlsAcceptor;
 use uuid::Uuid;
 
-const MAX_BUFFERED_OUTPUTS: usize = 200;
+/// Delay before restarting a channel, so crash loops don't spin
+const RESTART_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
 
-#[derive(Clone)]
-struct BufferedOutput {
-    data: Vec<u8>,
-    timestamp: i64,
-}
+/// How long a detaching client's pending messages may take to send
+const DETACH_FLUSH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// How often the session's registry entry is refreshed
+const REGISTRY_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How often clients are checked for having stopped reading
+const STALL_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How long a client's queued messages may go unread before it's dropped
+const CLIENT_STALL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How often channels are checked for a new command in the foreground
+const FOREGROUND_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How often channels are checked for having run past their timeout
+const TIMEOUT_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How often scheduled channels are checked for being due to run
+const SCHEDULE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How often running channels' CPU and memory use is sampled for status
+const USAGE_SAMPLE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// How long a dropped client's view is kept for it to resume
+const RESUME_TTL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// Channel events that may wait for script and plugin handlers before newer
+/// ones are dropped
+const SCRIPT_QUEUE_CAPACITY: usize = 1024;
 
 /// Server state shared across connections
-struct ServerState {
+pub(super) struct ServerState {
     session: Session,
     clients: HashMap<Uuid, ClientConnection>,
     channel_manager: ChannelManager,
-    output_buffers: HashMap<String, VecDeque<BufferedOutput>>,
+    output_buffers: HashMap<String, Scrollback>,
+    config: Config,
+    history: HistoryStore,
+    scripts: Arc<ScriptEngine>,
+    plugins: Arc<PluginHost>,
+    /// Events for script and plugin handlers, which run off the state lock
+    script_events: mpsc::Sender<QueuedEvent>,
+    hooks: Hooks,
+    /// Signalled when a client asks the server to shut down
+    shutdown: Arc<Notify>,
+    /// Host that can start more sessions in this process
+    host: Option<Arc<SessionHost>>,
+    /// Views of clients that detached, by identity
+    detached: HashMap<String, DetachedView>,
+    /// Views of clients whose connection ended, by resume token, with when
+    /// they were saved
+    resumable: HashMap<String, (std::time::Instant, DetachedView)>,
+    /// Sequence number of each channel's latest output chunk. Kept when a
+    /// channel is removed, so a new channel by the same name counts on.
+    output_seqs: HashMap<String, u64>,
+    /// Pinned channels, in the order they were pinned
+    pinned: Vec<String>,
+    /// Scrollback marks shared by clients, by channel and name, with the
+    /// output chunk each sits at
+    marks: BTreeMap<(String, String), u64>,
+}
+
+/// What a client was looking at when it detached or its connection ended,
+/// restored when it comes back
+struct DetachedView {
+    subscriptions: Vec<String>,
+    active_channel: Option<String>,
+}
+
+impl ServerState {
+    /// A channel's scrollback, created on first use; with
+    /// `general.spill_scrollback` it spills into a directory next to the
+    /// session socket
+    fn scrollback(&mut self, channel: &str) -> &mut Scrollback {
+        let spill_dir = self
+            .config
+            .general
+            .spill_scrollback
+            .then(|| self.session.info.socket_path.with_extension("spill"));
+        self.output_buffers
+            .entry(channel.to_string())
+            .or_insert_with(|| match spill_dir {
+                Some(dir) => Scrollback::spilling_to(&dir.join(spill_file_name(channel))),
+                None => Scrollback::default(),
+            })
+    }
+
+    /// Current session metadata, with live client and channel counts
+    fn session_info(&mut self) -> SessionInfo {
+        self.session.info.channel_count = self.channel_manager.list_channels().len();
+        self.session.info.clone()
+    }
+
+    /// Deliver a channel event to hooks, and queue it for scripts and
+    /// plugins
+    fn emit_event(&mut self, event: QueuedEvent) {
+        let hook_event = match event.as_event() {
+            ScriptEvent::Output { channel, data } => HookEvent::Output { channel, data },
+            ScriptEvent::Exited { channel, exit_code } => {
+                HookEvent::ChannelExited { channel, exit_code }
+            }
+            ScriptEvent::Created { channel } => HookEvent::ChannelCreated { channel },
+        };
+        self.hooks.run(self.session.name(), hook_event);
+
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.script_events.try_send(event) {
+            tracing::warn!("Script handlers are falling behind, dropping an event");
+        }
+    }
+
+    /// Largest size every attached client can show in full: the smallest
+    /// width and height any of them reported, like tmux
+    fn negotiated_size(&self) -> Option<(u16, u16)> {
+        self.clients
+            .values()
+            .filter(|client| !client.is_detached() && !client.is_observer())
+            .filter_map(ClientConnection::size)
+            .reduce(|(cols, rows), (other_cols, other_rows)| {
+                (cols.min(other_cols), rows.min(other_rows))
+            })
+    }
+
+    /// Refuse `action` on `channel` unless the channel's ACL lets the client
+    /// take it, with an error naming who was refused what
+    fn check_acl(&self, client_id: Uuid, channel: &str, action: ChannelAction) -> Result<()> {
+        let Some(acl) = self.config.acl.get(channel) else {
+            return Ok(());
+        };
+        let principal = self
+            .clients
+            .get(&client_id)
+            .and_then(ClientConnection::principal);
+        if acl.allows(action, principal) {
+            return Ok(());
+        }
+        let who = match principal {
+            Some(principal) => format!("'{}'", principal),
+            None => "an unidentified client".to_string(),
+        };
+        let what = match action {
+            ChannelAction::Input => "send input to",
+            ChannelAction::Kill => "kill",
+        };
+        Err(anyhow!(
+            "Permission denied: {} may not {} channel '{}'",
+            who,
+            what,
+            channel
+        ))
+    }
+
+    /// Resize channels to the negotiated size, if that changed. Called
+    /// whenever a client reports a size or stops being attached.
+    async fn apply_negotiated_size(&mut self) -> Result<()> {
+        match self.negotiated_size() {
+            Some(size) if self.channel_manager.size() != Some(size) => {
+                self.channel_manager.resize_all(size.0, size.1).await
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// TCP listener with the token clients must present and optional TLS
+struct TcpTransport {
+    listener: TcpListener,
+    token: Arc<str>,
+    tls: Option<TlsAcceptor>,
 }
 
 /// Unix socket server listener
 pub struct ServerListener {
     socket_path: PathBuf,
     session_name: String,
+    config: Config,
+    scripts_dir: Option<PathBuf>,
+    plugins_dir: Option<PathBuf>,
+    /// Socket passed in by systemd, used instead of binding `socket_path`
+    activated: Option<std::os::unix::net::UnixListener>,
+    /// Released once the socket is bound, waking clients waiting to connect
+    startup_lock: std::sync::Mutex<Option<daemon::StartupLock>>,
+    host: Option<Arc<SessionHost>>,
 }
 
 impl ServerListener {
@@ -45,9 +239,52 @@ impl ServerListener {
         Self {
             socket_path,
             session_name,
+            config: Config::default(),
+            scripts_dir: None,
+            plugins_dir: None,
+            activated: None,
+            startup_lock: std::sync::Mutex::new(None),
+            host: None,
         }
     }
 
+    /// Let clients of this session start more sessions on `host`
+    pub fn with_host(mut self, host: Arc<SessionHost>) -> Self {
+        self.host = Some(host);
+        self
+    }
+
+    /// Hold the session's startup lock until the socket is bound
+    pub fn with_startup_lock(self, lock: daemon::StartupLock) -> Self {
+        *self.startup_lock.lock().unwrap() = Some(lock);
+        self
+    }
+
+    /// Accept on a socket that's already bound, e.g. one passed by systemd
+    /// socket activation. The socket file is left in place on shutdown.
+    pub fn with_activated_listener(mut self, listener: std::os::unix::net::UnixListener) -> Self {
+        self.activated = Some(listener);
+        self
+    }
+
+    /// Use the given configuration (templates, defaults) instead of the built-in defaults
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Load Lua scripts from this directory when the server starts
+    pub fn with_scripts_dir(mut self, dir: PathBuf) -> Self {
+        self.scripts_dir = Some(dir);
+        self
+    }
+
+    /// Load WASM plugins from this directory when the server starts
+    pub fn with_plugins_dir(mut self, dir: PathBuf) -> Self {
+        self.plugins_dir = Some(dir);
+        self
+    }
+
     /// Check if socket already exists (another server running)
     pub fn socket_exists(&self) -> bool {
         self.socket_path.exists()
@@ -60,52 +297,58 @@ impl ServerListener {
 
     /// Run the server
     pub async fn run(&self, mut shutdown_rx: mpsc::Receiver<()>) -> Result<()> {
-        // Ensure parent directory exists
-        if let Some(parent) = self.socket_path.parent() {
-            std::fs::create_dir_all(parent)?;
-        }
-
-        // Remove stale socket if it exists
-        if self.socket_path.exists() {
-            // Try to connect to check if it's alive
-            match UnixStream::connect(&self.socket_path).await {
-                Ok(_) => {
-                    return Err(anyhow!(
-                        "Server already running for session '{}'",
-                        self.session_name
-                    ));
+        let listener = match &self.activated {
+            Some(activated) => {
+                let listener = activated.try_clone()?;
+                listener.set_nonblocking(true)?;
+                tracing::info!("Server listening on socket passed by systemd");
+                UnixListener::from_std(listener)?
+            }
+            None => self.bind_socket().await?,
+        };
+        // Clients waiting on the lock can connect now
+        self.startup_lock.lock().unwrap().take();
+
+        let (state, event_task) = self.start_state().await?;
+        let shutdown_requested = Arc::clone(&state.read().await.shutdown);
+
+        let http_task = if self.config.http.enabled {
+            let token_path = self.socket_path.with_extension("http-token");
+            match http::serve(&self.config.http, Arc::clone(&state), &token_path).await {
+                Ok((addr, task)) => {
+                    tracing::info!("HTTP API listening on http://{}", addr);
+                    Some(task)
                 }
-                Err(_) => {
-                    // Stale socket, remove it
-                    tracing::info!("Removing stale socket: {:?}", self.socket_path);
-                    std::fs::remove_file(&self.socket_path)?;
+                Err(e) => {
+                    tracing::error!("Failed to start HTTP API: {:#}", e);
+                    None
                 }
             }
-        }
+        } else {
+            None
+        };
 
-        // Create Unix socket listener
-        let listener = UnixListener::bind(&self.socket_path)?;
-        tracing::info!("Server listening on {:?}", self.socket_path);
+        let tcp = if self.config.tcp.enabled {
+            match self.bind_tcp().await {
+                Ok(transport) => Some(transport),
+                Err(e) => {
+                    tracing::error!("Failed to start TCP listener: {:#}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
 
-        // Channel for manager -> server communication
-        let (event_tx, mut event_rx) = mpsc::channel::<ChannelManagerEvent>(256);
+        // Client tasks end with the session even when the process lives on to
+        // host others
+        let mut client_tasks = JoinSet::new();
 
-        // Initialize server state
-        let state = Arc::new(RwLock::new(ServerState {
-            session: Session::new(self.session_name.clone(), self.socket_path.clone()),
-            clients: HashMap::new(),
-            channel_manager: ChannelManager::new(event_tx),
-            output_buffers: HashMap::new(),
-        }));
+        // Keep this session's registry entry current for `nexus list`
+        let mut registry_refresh = tokio::time::interval(REGISTRY_REFRESH_INTERVAL);
+        let mut published: Option<SessionInfo> = None;
 
-        // Spawn the event handler task
-        let event_state = Arc::clone(&state);
-        tokio::spawn(async move {
-            while let Some(event) = event_rx.recv().await {
-                handle_channel_event(event, &event_state).await;
-            }
-            tracing::info!("Channel manager event loop finished");
-        });
+        let mut stall_check = tokio::time::interval(STALL_CHECK_INTERVAL);
 
         // Main server loop
         loop {
@@ -116,13 +359,36 @@ impl ServerListener {
                     break;
                 }
 
+                _ = shutdown_requested.notified() => {
+                    break;
+                }
+
+                // Reap finished client tasks
+                Some(_) = client_tasks.join_next(), if !client_tasks.is_empty() => {}
+
+                _ = registry_refresh.tick() => {
+                    let info = state.write().await.session_info();
+                    if published.as_ref() != Some(&info) {
+                        if let Err(e) = registry::publish(&info) {
+                            tracing::warn!("Failed to update session registry: {:#}", e);
+                        }
+                        published = Some(info);
+                    }
+                }
+
+                _ = stall_check.tick() => {
+                    disconnect_stalled_clients(&state).await;
+                }
+
                 // Accept new connections
                 accept_result = listener.accept() => {
                     match accept_result {
                         Ok((stream, _addr)) => {
+                            let peer = Peer::unix(&stream);
                             let state = Arc::clone(&state);
-                            tokio::spawn(async move {
-                                if let Err(e) = handle_client(stream, state).await {
+                            client_tasks.spawn(async move {
+                                let (reader, writer) = stream.into_split();
+                                if let Err(e) = handle_client(reader, writer, None, peer, state).await {
                                     tracing::error!("Client error: {}", e);
                                 }
                             });
@@ -132,165 +398,893 @@ impl ServerListener {
                         }
                     }
                 }
+
+                // Accept TCP connections, which must authenticate
+                accept_result = async {
+                    match &tcp {
+                        Some(transport) => transport.listener.accept().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    match accept_result {
+                        Ok((stream, addr)) => {
+                            tracing::info!("TCP connection from {}", addr);
+                            let Some(transport) = &tcp else { continue };
+                            let token = Some(Arc::clone(&transport.token));
+                            let tls = transport.tls.clone();
+                            let state = Arc::clone(&state);
+                            client_tasks.spawn(async move {
+                                let result = match tls {
+                                    Some(acceptor) => match acceptor.accept(stream).await {
+                                        Ok(stream) => {
+                                            let peer = Peer::tls(stream.get_ref().1);
+                                            let (reader, writer) = tokio::io::split(stream);
+                                            handle_client(reader, writer, token, peer, state).await
+                                        }
+                                        Err(e) => Err(anyhow!("TLS handshake failed: {}", e)),
+                                    },
+                                    None => {
+                                        let (reader, writer) = stream.into_split();
+                                        handle_client(reader, writer, token, Peer::default(), state)
+                                            .await
+                                    }
+                                };
+                                if let Err(e) = result {
+                                    tracing::warn!("TCP client {}: {}", addr, e);
+                                }
+                            });
+                        }
+                        Err(e) => {
+                            tracing::error!("Failed to accept TCP connection: {}", e);
+                        }
+                    }
+                }
             }
         }
 
         // Cleanup
-        self.cleanup().await;
+        if let Some(task) = http_task {
+            task.abort();
+        }
+        kill_all_channels(&state).await;
+        client_tasks.shutdown().await;
+        // Dropping the senders ends the writer tasks, closing the connections
+        state.write().await.clients.clear();
+        event_task.abort();
+        // The session may have been renamed since the socket was bound
+        let socket_path = state.read().await.session.socket_path().to_path_buf();
+        self.cleanup(&socket_path).await;
 
         Ok(())
     }
 
-    /// Clean up server resources
-    async fn cleanup(&self) {
-        tracing::info!("Cleaning up server resources");
+    /// Bind the session's Unix socket, replacing a stale one
+    async fn bind_socket(&self) -> Result<UnixListener> {
+        // Ensure parent directory exists
+        if let Some(parent) = self.socket_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
 
-        // Remove socket file
+        // Remove stale socket if it exists
         if self.socket_path.exists() {
-            if let Err(e) = std::fs::remove_file(&self.socket_path) {
-                tracing::error!("Failed to remove socket file: {}", e);
+            // Try to connect to check if it's alive
+            match UnixStream::connect(&self.socket_path).await {
+                Ok(_) => {
+                    return Err(anyhow!(
+                        "Server already running for session '{}'",
+                        self.session_name
+                    ));
+                }
+                Err(_) => {
+                    // Stale socket, remove it
+                    tracing::info!("Removing stale socket: {:?}", self.socket_path);
+                    std::fs::remove_file(&self.socket_path)?;
+                }
             }
         }
-    }
-}
-
-/// Handle a single client connection
-async fn handle_client(stream: UnixStream, state: Arc<RwLock<ServerState>>) -> Result<()> {
-    let (mut reader, writer) = stream.into_split();
 
-    // Create message channel for this client
-    let (tx, rx) = mpsc::channel::<ServerMessage>(256);
-    let mut client = ClientConnection::new(tx);
-    let client_id = client.id();
+        // Create Unix socket listener
+        let listener = UnixListener::bind(&self.socket_path)?;
+        apply_socket_permissions(&self.socket_path, &self.config.socket)?;
+        tracing::info!("Server listening on {:?}", self.socket_path);
+        Ok(listener)
+    }
 
-    tracing::info!("Client connected: {}", client_id);
+    /// Bind the TCP listener, returning it with the token clients must present
+    async fn bind_tcp(&self) -> Result<TcpTransport> {
+        let tcp = &self.config.tcp;
+        let tls = match (&tcp.cert, &tcp.key) {
+            (Some(cert), Some(key)) => Some(tls::acceptor(cert, key, tcp.client_ca.as_deref())?),
+            (None, None) if tcp.client_ca.is_none() => None,
+            _ => return Err(anyhow!("[tcp] TLS needs both cert and key")),
+        };
+        let token = match &tcp.token {
+            Some(token) => token.clone(),
+            None => write_token(&self.socket_path.with_extension("tcp-token"))?,
+        };
+        let listener = TcpListener::bind(&tcp.bind)
+            .await
+            .map_err(|e| anyhow!("Failed to bind {}: {}", tcp.bind, e))?;
+        tracing::info!(
+            "Accepting TCP clients on {}{}",
+            listener.local_addr()?,
+            if tls.is_some() { " (TLS)" } else { "" }
+        );
+        Ok(TcpTransport {
+            listener,
+            token: token.into(),
+            tls,
+        })
+    }
 
-    // New clients subscribe to the active channel (if any) by default to avoid overwhelming output.
-    let (session_id, initial_channels) = {
-        let state_guard = state.read().await;
-        let initial = state_guard
-            .channel_manager
-            .active_channel()
-            .map(|name| vec![name.to_string()])
-            .unwrap_or_default();
-        (state_guard.session.id(), initial)
-    };
-    client.subscribe(&initial_channels);
+    /// Build the server state, create startup channels and start the channel
+    /// event loop. Shared by the socket server and [`EmbeddedServer`].
+    pub(super) async fn start_state(&self) -> Result<(Arc<RwLock<ServerState>>, JoinHandle<()>)> {
+        // Channel for manager -> server communication
+        let (event_tx, mut event_rx) = mpsc::channel::<ChannelManagerEvent>(256);
+        let (script_tx, script_rx) = mpsc::channel(SCRIPT_QUEUE_CAPACITY);
 
-    // Add client to state
-    {
-        let mut state_guard = state.write().await;
-        state_guard.session.add_client(client_id);
-        state_guard.clients.insert(client_id, client);
-    }
+        let mut scripts = ScriptEngine::new()?;
+        if let Some(dir) = &self.scripts_dir {
+            for error in scripts.load_dir(dir) {
+                tracing::warn!("Failed to load script {}", error);
+            }
+        }
 
-    // Spawn writer task
-    let writer_handle = tokio::spawn(client_writer_task(writer, rx));
+        let mut plugins = PluginHost::new();
+        if let Some(dir) = &self.plugins_dir {
+            for error in plugins.load_dir(dir) {
+                tracing::warn!("Failed to load plugin {}", error);
+            }
+            if !plugins.names().is_empty() {
+                tracing::info!("Loaded plugins: {}", plugins.names().join(", "));
+            }
+        }
 
-    // Send welcome message
-    {
-        let state = state.read().await;
-        if let Some(client) = state.clients.get(&client_id) {
-            client.send(create_welcome_message(session_id)).await?;
+        let (hooks, hook_errors) = Hooks::from_config(&self.config.hooks);
+        for error in hook_errors {
+            tracing::warn!("{}", error);
         }
-    }
 
-    if !initial_channels.is_empty() {
-        send_buffered_output(client_id, &initial_channels, &state).await;
-    }
+        // Initialize server state
+        let state = Arc::new(RwLock::new(ServerState {
+            session: Session::new(self.session_name.clone(), self.socket_path.clone()),
+            clients: HashMap::new(),
+            channel_manager: ChannelManager::new(event_tx),
+            output_buffers: HashMap::new(),
+            config: self.config.clone(),
+            history: HistoryStore::default(),
+            scripts: Arc::new(scripts),
+            plugins: Arc::new(plugins),
+            script_events: script_tx,
+            hooks,
+            shutdown: Arc::new(Notify::new()),
+            host: self.host.clone(),
+            detached: HashMap::new(),
+            resumable: HashMap::new(),
+            output_seqs: HashMap::new(),
+            pinned: Vec::new(),
+            marks: BTreeMap::new(),
+        }));
 
-    // Read and process messages
-    loop {
-        match read_message(&mut reader).await {
-            Ok(Some(bytes)) => match parse_client_message(&bytes) {
-                Ok(msg) => {
-                    let response = process_message(msg, client_id, &state).await;
-                    if let Some(response) = response {
-                        let state = state.read().await;
-                        if let Some(client) = state.clients.get(&client_id) {
-                            if let Err(e) = client.send(response).await {
-                                tracing::error!("Failed to send response: {}", e);
-                                break;
+        spawn_script_handlers(&state, script_rx);
+        create_startup_channels(&state).await;
+
+        // Spawn the event handler task. Output is coalesced briefly so a
+        // flooding channel doesn't send clients a message per PTY read.
+        let event_state = Arc::clone(&state);
+        let event_task = tokio::spawn(async move {
+            let mut coalescer = OutputCoalescer::default();
+            let mut foreground_tick = tokio::time::interval(FOREGROUND_POLL_INTERVAL);
+            let mut timeout_tick = tokio::time::interval(TIMEOUT_CHECK_INTERVAL);
+            let mut schedule_tick = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+            let mut usage_tick = tokio::time::interval(USAGE_SAMPLE_INTERVAL);
+            loop {
+                let deadline = coalescer.deadline();
+                tokio::select! {
+                    event = event_rx.recv() => match event {
+                        Some(ChannelManagerEvent::Output { channel_name, data, stream }) => {
+                            coalescer.push(channel_name, data, stream);
+                        }
+                        Some(event) => {
+                            // Output that came first goes out first
+                            for output in coalescer.take() {
+                                handle_channel_event(output, &event_state).await;
                             }
+                            handle_channel_event(event, &event_state).await;
+                        }
+                        None => break,
+                    },
+                    _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)),
+                        if deadline.is_some() =>
+                    {
+                        for output in coalescer.take() {
+                            handle_channel_event(output, &event_state).await;
                         }
                     }
-                }
-                Err(e) => {
-                    tracing::error!("Failed to parse message: {}", e);
-                    let state = state.read().await;
-                    if let Some(client) = state.clients.get(&client_id) {
-                        let _ = client
-                            .send(create_error_message(format!("Invalid message: {}", e)))
+                    _ = foreground_tick.tick() => {
+                        let changed = event_state.write().await.channel_manager.refresh_foreground();
+                        for (name, command) in changed {
+                            broadcast_to_supporting(
+                                ServerMessage::Event(ChannelEvent::Foreground { name, command }),
+                                Capability::Foreground,
+                                None,
+                                &event_state,
+                            )
                             .await;
+                        }
+                    }
+                    _ = timeout_tick.tick() => {
+                        let timed_out = event_state.read().await.channel_manager.timed_out();
+                        for (name, timeout_secs) in timed_out {
+                            // Killing waits on this loop to take the Killed event
+                            tokio::spawn(time_out_channel(name, timeout_secs, Arc::clone(&event_state)));
+                        }
+                    }
+                    _ = schedule_tick.tick() => {
+                        let due = event_state.write().await.channel_manager.due_runs();
+                        for name in due {
+                            tokio::spawn(run_scheduled(name, Arc::clone(&event_state)));
+                        }
+                    }
+                    _ = usage_tick.tick() => {
+                        // Read every process off the state lock, and only
+                        // when there is a channel to measure
+                        if event_state.read().await.channel_manager.has_running_channels() {
+                            let processes = tokio::task::spawn_blocking(process::all_processes)
+                                .await
+                                .unwrap_or_default();
+                            event_state.write().await.channel_manager.sample_usage(&processes);
+                        }
                     }
                 }
-            },
-            Ok(None) => {
-                // Client disconnected
-                tracing::info!("Client disconnected: {}", client_id);
-                break;
             }
-            Err(e) => {
-                tracing::error!("Error reading from client: {}", e);
-                break;
+            for output in coalescer.take() {
+                handle_channel_event(output, &event_state).await;
+            }
+            tracing::info!("Channel manager event loop finished");
+        });
+
+        Ok((state, event_task))
+    }
+
+    /// Start the server in-process without binding a socket, for embedding
+    /// nexus's channel management in another tokio application
+    pub async fn start_embedded(&self) -> Result<EmbeddedServer> {
+        let (state, event_task) = self.start_state().await?;
+        Ok(EmbeddedServer::new(state, event_task).await)
+    }
+
+    /// Clean up server resources
+    async fn cleanup(&self, socket_path: &Path) {
+        tracing::info!("Cleaning up server resources");
+
+        // Remove socket file, unless systemd owns it
+        if self.activated.is_none() && socket_path.exists() {
+            if let Err(e) = std::fs::remove_file(socket_path) {
+                tracing::error!("Failed to remove socket file: {}", e);
             }
         }
+        let _ = std::fs::remove_file(socket_path.with_extension("http-token"));
+        let _ = std::fs::remove_file(socket_path.with_extension("tcp-token"));
+        let _ = std::fs::remove_file(registry::info_path(socket_path));
+        // A rename moves the PID file away from where the daemon created it
+        let pid_path = daemon::pid_path(socket_path);
+        if daemon::read_pid(&pid_path) == Some(std::process::id()) {
+            let _ = std::fs::remove_file(pid_path);
+        }
     }
+}
 
-    // Cleanup client
-    {
-        let mut state = state.write().await;
-        state.session.remove_client(&client_id);
-        state.clients.remove(&client_id);
+/// Add an in-process client to the server state
+pub(super) async fn add_client(state: &Arc<RwLock<ServerState>>, client: ClientConnection) {
+    let mut state_guard = state.write().await;
+    state_guard.session.add_client(client.id());
+    state_guard.clients.insert(client.id(), client);
+}
+
+/// The last `lines` lines of a channel's buffered output, or `None` for an
+/// unknown channel
+pub(super) async fn recent_output(
+    state: &Arc<RwLock<ServerState>>,
+    channel: &str,
+    lines: usize,
+) -> Option<Vec<u8>> {
+    let state_guard = state.read().await;
+    let buffer = state_guard.output_buffers.get(channel)?;
+    let mut data = Vec::new();
+    for entry in buffer.covering(lines) {
+        data.extend_from_slice(&entry.data);
     }
 
-    // Wait for writer task to finish
-    writer_handle.abort();
+    // Skip a trailing newline so it doesn't count as an empty last line
+    let body = data.strip_suffix(b"\n").unwrap_or(&data);
+    let start = body
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|&(_, &b)| b == b'\n')
+        .nth(lines.saturating_sub(1))
+        .map(|(i, _)| i + 1)
+        .unwrap_or(0);
+    if lines == 0 {
+        data.clear();
+    } else {
+        data.drain(..start);
+    }
+    Some(data)
+}
 
-    tracing::info!("Client handler finished: {}", client_id);
+/// Remove a client and kill every channel, leaving the state empty
+pub(super) async fn shutdown_state(state: &Arc<RwLock<ServerState>>, client_id: Uuid) {
+    let mut state_guard = state.write().await;
+    state_guard.session.remove_client(&client_id);
+    state_guard.clients.remove(&client_id);
+    drop(state_guard);
+    kill_all_channels(state).await;
+}
 
-    Ok(())
+/// Give a client that presented `identity` back the view it had when it last
+/// detached
+async fn restore_view(client_id: Uuid, identity: String, state: &Arc<RwLock<ServerState>>) {
+    let view = {
+        let mut state_guard = state.write().await;
+        let view = state_guard.detached.remove(&identity);
+        match state_guard.clients.get_mut(&client_id) {
+            Some(client) => client.set_identity(identity),
+            None => return,
+        }
+        view
+    };
+    if let Some(view) = view {
+        apply_view(client_id, view, &HashMap::new(), state).await;
+    }
 }
 
-/// Process a client message and return optional response
-async fn process_message(
-    msg: ClientMessage,
+/// Give a client the view saved under a resume token, replaying only output
+/// newer than `last_seen`. Returns false if the token is unknown or expired.
+async fn resume_view(
     client_id: Uuid,
+    token: &str,
+    last_seen: &HashMap<String, u64>,
     state: &Arc<RwLock<ServerState>>,
-) -> Option<ServerMessage> {
-    match msg {
-        ClientMessage::Hello { protocol_version } => {
-            if protocol_version != PROTOCOL_VERSION {
-                return Some(create_error_message(format!(
-                    "Protocol version mismatch: expected {}, got {}",
-                    PROTOCOL_VERSION, protocol_version
-                )));
+) -> bool {
+    let view = {
+        let mut state_guard = state.write().await;
+        state_guard
+            .resumable
+            .retain(|_, (saved_at, _)| saved_at.elapsed() < RESUME_TTL);
+        state_guard.resumable.remove(token)
+    };
+    match view {
+        Some((_, view)) => {
+            apply_view(client_id, view, last_seen, state).await;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Restore a saved view: its subscriptions (replaying their buffered output
+/// after `last_seen`) and, when nobody else is attached, the active channel
+async fn apply_view(
+    client_id: Uuid,
+    view: DetachedView,
+    last_seen: &HashMap<String, u64>,
+    state: &Arc<RwLock<ServerState>>,
+) {
+    let mut guard = state.write().await;
+    let state_guard = &mut *guard;
+    let others_attached = state_guard
+        .clients
+        .iter()
+        .any(|(id, client)| *id != client_id && !client.is_detached());
+    let Some(client) = state_guard.clients.get_mut(&client_id) else {
+        return;
+    };
+
+    let channels = state_guard.channel_manager.list_channels();
+    let previous = client.get_subscriptions();
+    client.unsubscribe(&previous);
+    let subscriptions: Vec<String> = view
+        .subscriptions
+        .into_iter()
+        .filter(|name| channels.contains(name))
+        .collect();
+    client.subscribe(&subscriptions);
+    let replay: Vec<String> = subscriptions
+        .iter()
+        .filter(|name| !previous.contains(name))
+        .cloned()
+        .collect();
+    let _ = client
+        .send(ServerMessage::Event(ChannelEvent::SubscriptionChanged {
+            subscribed: client.get_subscriptions(),
+        }))
+        .await;
+
+    let mut switched = None;
+    if let Some(name) = view.active_channel {
+        let manager = &mut state_guard.channel_manager;
+        if !others_attached
+            && channels.contains(&name)
+            && manager.active_channel() != Some(name.as_str())
+            && manager.switch_active(&name).is_ok()
+        {
+            switched = Some(name);
+        }
+    }
+    drop(guard);
+
+    if let Some(name) = switched {
+        broadcast_to_clients(
+            ServerMessage::Event(ChannelEvent::ActiveChanged { name }),
+            state,
+        )
+        .await;
+    }
+    if !replay.is_empty() {
+        send_buffered_output_since(client_id, &replay, last_seen, state).await;
+    }
+}
+
+/// Rename the session, moving its socket and token files alongside.
+///
+/// The listener keeps accepting on the moved socket, so attached clients are
+/// unaffected and new ones connect under the new name.
+async fn rename_session(state: &Arc<RwLock<ServerState>>, name: &str) -> Result<()> {
+    session::validate_name(name)?;
+
+    let mut state_guard = state.write().await;
+    let old_path = state_guard.session.socket_path().to_path_buf();
+    let new_path = old_path.with_file_name(format!("{}.sock", name));
+    if new_path.exists() {
+        return Err(anyhow!("session '{}' already exists", name));
+    }
+
+    // Embedded servers have no socket to move
+    if old_path.exists() {
+        std::fs::rename(&old_path, &new_path)?;
+        for extension in ["http-token", "tcp-token", "pid", "info"] {
+            let token_path = old_path.with_extension(extension);
+            if token_path.exists() {
+                std::fs::rename(&token_path, new_path.with_extension(extension))?;
+            }
+        }
+    }
+    state_guard.session.rename(name.to_string(), new_path);
+    Ok(())
+}
+
+/// Hang up on clients that stopped reading, so their queues don't pile up
+async fn disconnect_stalled_clients(state: &Arc<RwLock<ServerState>>) {
+    let mut state_guard = state.write().await;
+    for (client_id, client) in state_guard.clients.iter_mut() {
+        if client.is_stalled(CLIENT_STALL_TIMEOUT) {
+            tracing::warn!("Disconnecting client {}: not reading", client_id);
+            client.disconnect();
+        }
+    }
+}
+
+/// Kill every channel's process
+async fn kill_all_channels(state: &Arc<RwLock<ServerState>>) {
+    let mut state_guard = state.write().await;
+    for name in state_guard.channel_manager.list_channels() {
+        if let Err(e) = state_guard.channel_manager.kill_channel(&name).await {
+            tracing::warn!("Failed to kill channel '{}': {}", name, e);
+        }
+    }
+}
+
+/// Who is on the other end of a connection, as the transport established
+/// it rather than as the client claims
+#[derive(Debug, Default)]
+struct Peer {
+    /// Local user (Unix socket) or client certificate name (mutual TLS)
+    principal: Option<String>,
+    /// Connected over the Unix socket as the user running the server
+    owner: bool,
+}
+
+impl Peer {
+    /// The user at the other end of a Unix socket, from its credentials
+    fn unix(stream: &UnixStream) -> Self {
+        match stream.peer_cred() {
+            Ok(cred) => Self {
+                principal: Some(user_name(cred.uid())),
+                owner: is_own_uid(cred.uid()),
+            },
+            Err(e) => {
+                tracing::warn!("Failed to read Unix socket peer credentials: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    /// The common name of a mutual-TLS client's certificate
+    fn tls(connection: &tokio_rustls::rustls::ServerConnection) -> Self {
+        Self {
+            principal: connection
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| certificate_common_name(cert)),
+            owner: false,
+        }
+    }
+}
+
+/// What a client said about itself in its `Hello`
+#[derive(Default)]
+struct Hello {
+    protocol_version: Option<u32>,
+    identity: Option<String>,
+    capabilities: Vec<Capability>,
+    observe: bool,
+    name: Option<String>,
+}
+
+/// Check that a TCP client's first message is a `Hello` carrying the right
+/// token, replying with an error if not
+async fn authenticate<R, W>(reader: &mut R, writer: &mut W, token: &str) -> Result<Hello>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let result = match read_message(reader).await? {
+        Some(bytes) => match parse_client_message(&bytes)? {
+            ClientMessage::Hello {
+                protocol_version,
+                token: presented,
+                identity,
+                capabilities,
+                observe,
+                name,
+            } => {
+                if let Err(e) = check_client_version(protocol_version) {
+                    Err(e.to_string())
+                } else if presented.is_some_and(|presented| tokens_match(&presented, token)) {
+                    Ok(Hello {
+                        protocol_version: Some(protocol_version),
+                        identity,
+                        capabilities,
+                        observe,
+                        name,
+                    })
+                } else {
+                    Err("Authentication failed: invalid token".to_string())
+                }
+            }
+            _ => Err("Authentication required: expected Hello".to_string()),
+        },
+        None => return Err(anyhow!("Client disconnected before authenticating")),
+    };
+
+    match result {
+        Ok(hello) => Ok(hello),
+        Err(message) => {
+            // The client's version isn't settled; every version decodes this
+            let reply = Codec::oldest().encode(&create_error_message(message.clone()))?;
+            let _ = write_message(writer, &reply).await;
+            Err(anyhow!(message))
+        }
+    }
+}
+
+/// Handle a single client connection.
+///
+/// When `token` is set (TCP connections) the client must authenticate with
+/// its `Hello` before it's registered or told anything about the session.
+async fn handle_client<R, W>(
+    mut reader: R,
+    mut writer: W,
+    token: Option<Arc<str>>,
+    peer: Peer,
+    state: Arc<RwLock<ServerState>>,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let hello = match &token {
+        Some(token) => authenticate(&mut reader, &mut writer, token).await?,
+        None => Hello::default(),
+    };
+    // Observers don't take over a detached view saved under their identity
+    let identity = hello.identity.filter(|_| !hello.observe);
+
+    // Create message channel for this client
+    let (tx, rx) = mpsc::channel::<ServerMessage>(256);
+    let mut client = ClientConnection::new(tx);
+    client.set_capabilities(&hello.capabilities);
+    if hello.observe {
+        client.set_observer();
+    }
+    if let Some(principal) = peer.principal {
+        client.set_principal(principal);
+    }
+    if peer.owner {
+        client.set_owner();
+    }
+    if let Some(name) = hello.name {
+        client.set_name(name);
+    }
+    if let Some(version) = hello.protocol_version {
+        client.set_protocol_version(version);
+    }
+    let protocol_version = client.protocol_version_handle();
+    let client_id = client.id();
+    let written = client.track_writes();
+    let disconnect = client.disconnect_signal();
+
+    tracing::info!("Client connected: {}", client_id);
+
+    // New clients subscribe to the active channel (if any) by default to avoid overwhelming output.
+    let (session_id, initial_channels) = {
+        let state_guard = state.read().await;
+        let initial = state_guard
+            .channel_manager
+            .active_channel()
+            .map(|name| vec![name.to_string()])
+            .unwrap_or_default();
+        (state_guard.session.id(), initial)
+    };
+    client.subscribe(&initial_channels);
+
+    // Add client to state
+    {
+        let mut state_guard = state.write().await;
+        let state_guard = &mut *state_guard;
+        state_guard.session.add_client(client_id);
+        state_guard.clients.insert(client_id, client);
+        state_guard.hooks.run(
+            state_guard.session.name(),
+            HookEvent::ClientAttached {
+                client_id: &client_id.to_string(),
+            },
+        );
+    }
+
+    // Spawn writer task
+    let writer_handle = tokio::spawn(client_writer_task(writer, rx, written, protocol_version));
+
+    // Send welcome message
+    {
+        let state = state.read().await;
+        if let Some(client) = state.clients.get(&client_id) {
+            client
+                .send(create_welcome_message(session_id, client.resume_token()))
+                .await?;
+            // The Hello was consumed by authentication; answer it as usual
+            if token.is_some() {
+                client
+                    .send(ServerMessage::Ack {
+                        for_command: "Hello".to_string(),
+                        request_id: None,
+                    })
+                    .await?;
+            }
+        }
+    }
+    if token.is_some() {
+        announce_client(client_id, &state).await;
+    }
+
+    if !initial_channels.is_empty() {
+        send_buffered_output(client_id, &initial_channels, &state).await;
+    }
+    if let Some(identity) = identity {
+        restore_view(client_id, identity, &state).await;
+    }
+
+    // Read and process messages
+    let mut detached = false;
+    loop {
+        let read = tokio::select! {
+            read = read_message(&mut reader) => read,
+            _ = disconnect.notified() => break,
+        };
+        match read {
+            Ok(Some(bytes)) => match parse_client_message(&bytes) {
+                Ok(msg) => {
+                    let response = process_message(msg, client_id, &state).await;
+                    if let Some(response) = response {
+                        let state = state.read().await;
+                        if let Some(client) = state.clients.get(&client_id) {
+                            if let Err(e) = client.send(response).await {
+                                tracing::error!("Failed to send response: {}", e);
+                                break;
+                            }
+                            if client.is_detached() {
+                                tracing::info!("Client detached: {}", client_id);
+                                detached = true;
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("Failed to parse message: {}", e);
+                    let state = state.read().await;
+                    if let Some(client) = state.clients.get(&client_id) {
+                        let _ = client
+                            .send(create_error_message(format!("Invalid message: {}", e)))
+                            .await;
+                    }
+                }
+            },
+            Ok(None) => {
+                // Client disconnected
+                tracing::info!("Client disconnected: {}", client_id);
+                break;
+            }
+            Err(e) => {
+                tracing::error!("Error reading from client: {}", e);
+                break;
+            }
+        }
+    }
+
+    // Cleanup client, keeping its view for it to resume
+    let left = {
+        let mut state = state.write().await;
+        state.session.remove_client(&client_id);
+        let client = state.clients.remove(&client_id);
+        let left = client
+            .as_ref()
+            .filter(|client| client.is_announced())
+            .map(ClientConnection::info);
+        if let Some(mut client) = client.filter(|client| !client.is_detached()) {
+            let view = DetachedView {
+                subscriptions: client.detach(),
+                active_channel: state.channel_manager.active_channel().map(str::to_string),
+            };
+            state
+                .resumable
+                .retain(|_, (saved_at, _)| saved_at.elapsed() < RESUME_TTL);
+            state.resumable.insert(
+                client.resume_token().to_string(),
+                (std::time::Instant::now(), view),
+            );
+        }
+        if let Err(e) = state.apply_negotiated_size().await {
+            tracing::warn!("Failed to resize channels: {}", e);
+        }
+        left
+    };
+    if let Some(client) = left {
+        let about = Some(client.id);
+        let msg = ServerMessage::ClientLeft { client };
+        broadcast_to_supporting(msg, Capability::Presence, about, &state).await;
+    }
+
+    // A detaching client is still reading; let the writer deliver the Ack
+    // (it ends now that the client's sender is gone). Otherwise stop it.
+    if detached {
+        let _ = tokio::time::timeout(DETACH_FLUSH_TIMEOUT, writer_handle).await;
+    } else {
+        writer_handle.abort();
+    }
+
+    tracing::info!("Client handler finished: {}", client_id);
+
+    Ok(())
+}
+
+/// Process a client message and return optional response
+pub(super) async fn process_message(
+    msg: ClientMessage,
+    client_id: Uuid,
+    state: &Arc<RwLock<ServerState>>,
+) -> Option<ServerMessage> {
+    if !allowed_for_observers(&msg) {
+        let observing = state
+            .read()
+            .await
+            .clients
+            .get(&client_id)
+            .is_some_and(ClientConnection::is_observer);
+        if observing {
+            return Some(create_error_message(
+                "Read-only observer: this client can't change the session".to_string(),
+            ));
+        }
+    }
+
+    match msg {
+        ClientMessage::Hello {
+            protocol_version,
+            identity,
+            capabilities,
+            observe,
+            name,
+            ..
+        } => {
+            if let Err(e) = check_client_version(protocol_version) {
+                return Some(create_error_message(e.to_string()));
+            }
+            let observing = match state.write().await.clients.get_mut(&client_id) {
+                Some(client) => {
+                    client.set_protocol_version(protocol_version);
+                    client.set_capabilities(&capabilities);
+                    if observe {
+                        client.set_observer();
+                    }
+                    if let Some(name) = name {
+                        client.set_name(name);
+                    }
+                    client.is_observer()
+                }
+                None => observe,
+            };
+            // Observers don't take over a detached view saved under their identity
+            if let Some(identity) = identity.filter(|_| !observing) {
+                restore_view(client_id, identity, state).await;
             }
+            announce_client(client_id, state).await;
             // Already sent welcome, just acknowledge
             Some(ServerMessage::Ack {
                 for_command: "Hello".to_string(),
+                request_id: None,
             })
         }
 
-        ClientMessage::CreateChannel {
+        ClientMessage::CreateChannel(CreateChannelRequest {
             name,
             command,
             working_dir,
-        } => {
+            env,
+            template,
+            container,
+            mode,
+            ephemeral,
+            keep_on_failure,
+            size,
+            timeout_secs,
+            schedule,
+        }) => {
             let mut state_guard = state.write().await;
-            let config = crate::channel::ChannelConfig {
-                name: name.clone(),
+            let config = match build_channel_config(
+                &state_guard.config,
+                &name,
                 command,
-                working_dir: working_dir.map(std::path::PathBuf::from),
-                env: None,
-                size: None, // TODO: Get from client
+                working_dir,
+                env,
+                container,
+                mode,
+                template.as_deref(),
+            ) {
+                Ok(mut config) => {
+                    // Given explicitly, it overrides the template's
+                    if timeout_secs.is_some() {
+                        config.timeout_secs = timeout_secs;
+                    }
+                    if schedule.is_some() {
+                        config.schedule = schedule;
+                    }
+                    config.keep_on_failure = keep_on_failure;
+                    match size {
+                        Some((cols, rows)) => {
+                            config.with_ephemeral(ephemeral).with_fixed_size(cols, rows)
+                        }
+                        None => config.with_ephemeral(ephemeral),
+                    }
+                }
+                Err(e) => {
+                    return Some(create_error_message(format!(
+                        "Failed to create channel: {}",
+                        e
+                    )))
+                }
             };
+            let config_container = config.container.as_ref().map(ContainerTarget::label);
             match state_guard.channel_manager.create_channel(config).await {
                 Ok(()) => {
-                    state_guard
-                        .output_buffers
-                        .entry(name.clone())
-                        .or_insert_with(VecDeque::new);
+                    state_guard.scrollback(&name);
+
+                    state_guard.emit_event(QueuedEvent::Created {
+                        channel: name.clone(),
+                    });
 
                     // Auto-subscribe the creating client to the new channel
                     let subscription_event =
@@ -304,8 +1298,10 @@ async fn process_message(
                             None
                         };
 
-                    let created_event =
-                        ServerMessage::Event(ChannelEvent::Created { name: name.clone() });
+                    let created_event = ServerMessage::Event(ChannelEvent::Created {
+                        name: name.clone(),
+                        container: config_container,
+                    });
                     drop(state_guard); // Release write lock before broadcasting
 
                     broadcast_to_clients(created_event, state).await;
@@ -320,6 +1316,7 @@ async fn process_message(
 
                     Some(ServerMessage::Ack {
                         for_command: "CreateChannel".to_string(),
+                        request_id: None,
                     })
                 }
                 Err(e) => Some(create_error_message(format!(
@@ -331,10 +1328,18 @@ async fn process_message(
 
         ClientMessage::KillChannel { name } => {
             let mut state_guard = state.write().await;
+            if let Err(e) = state_guard.check_acl(client_id, &name, ChannelAction::Kill) {
+                return Some(create_error_message(e.to_string()));
+            }
             match state_guard.channel_manager.kill_channel(&name).await {
-                Ok(()) => Some(ServerMessage::Ack {
-                    for_command: "KillChannel".to_string(),
-                }),
+                Ok(()) => {
+                    // Killed by hand, a scheduled channel stays stopped
+                    state_guard.channel_manager.cancel_schedule(&name);
+                    Some(ServerMessage::Ack {
+                        for_command: "KillChannel".to_string(),
+                        request_id: None,
+                    })
+                }
                 Err(e) => Some(create_error_message(format!(
                     "Failed to kill channel: {}",
                     e
@@ -351,9 +1356,14 @@ async fn process_message(
                 .into_iter()
                 .map(|info| crate::protocol::ChannelInfo {
                     is_subscribed: client.is_subscribed(&info.name),
+                    is_pinned: state_guard.pinned.contains(&info.name),
                     is_active: info.is_active,
                     name: info.name,
                     running: info.running,
+                    is_starting: info.starting,
+                    container: info.container,
+                    started_at: info.started_at,
+                    foreground: info.foreground,
                 })
                 .collect();
             Some(ServerMessage::ChannelList { channels: infos })
@@ -375,31 +1385,334 @@ async fn process_message(
                     command: status.command,
                     created_at: 0,
                     output_lines: status.output_lines,
+                    cpu_percent: status.cpu_percent,
+                    rss_bytes: status.rss_bytes,
+                    schedule: status.schedule.map(|schedule| ScheduleInfo {
+                        description: schedule.schedule.describe(),
+                        next_run: schedule.next_run.map(|time| time.timestamp_millis()),
+                        runs: schedule
+                            .runs
+                            .iter()
+                            .map(|run| RunInfo {
+                                started_at: run.started_at.timestamp_millis(),
+                                finished_at: run.finished_at.map(|time| time.timestamp_millis()),
+                                exit_code: run.exit_code,
+                            })
+                            .collect(),
+                    }),
                 })
                 .collect();
             Some(ServerMessage::Status { channels: statuses })
         }
 
-        ClientMessage::Detach => {
-            tracing::info!("Client {} requested detach", client_id);
-            // Client will disconnect after receiving ack
+        ClientMessage::GetProcesses { channel } => {
+            let state_guard = state.read().await;
+            let Some(tree) = state_guard.channel_manager.process_tree(&channel) else {
+                return Some(create_error_message(format!(
+                    "Channel '{}' has no running process",
+                    channel
+                )));
+            };
+            let processes = tree
+                .into_iter()
+                .map(|(depth, process)| ProcessInfo {
+                    pid: process.pid,
+                    depth,
+                    state: process.state,
+                    command: process::command_line(process.pid).unwrap_or(process.name),
+                })
+                .collect();
+            Some(ServerMessage::Processes { channel, processes })
+        }
+
+        ClientMessage::SignalForeground { channel, signal } => {
+            let state_guard = state.read().await;
+            // Interrupting is as good as typing Ctrl+C; the others end the
+            // command whether it listens or not
+            let action = match signal {
+                StopSignal::Interrupt => ChannelAction::Input,
+                StopSignal::Terminate | StopSignal::Kill => ChannelAction::Kill,
+            };
+            if let Err(e) = state_guard.check_acl(client_id, &channel, action) {
+                return Some(create_error_message(e.to_string()));
+            }
+            let number = match signal {
+                StopSignal::Interrupt => libc::SIGINT,
+                StopSignal::Terminate => libc::SIGTERM,
+                StopSignal::Kill => libc::SIGKILL,
+            };
+            match state_guard
+                .channel_manager
+                .signal_foreground(&channel, number)
+            {
+                Ok(command) => {
+                    tracing::info!("Sent {} to '{}' in '{}'", signal.name(), command, channel);
+                    Some(ServerMessage::Ack {
+                        for_command: "SignalForeground".to_string(),
+                        request_id: None,
+                    })
+                }
+                Err(e) => Some(create_error_message(format!(
+                    "Failed to signal the foreground command: {}",
+                    e
+                ))),
+            }
+        }
+
+        ClientMessage::GetSessionInfo => {
+            let info = state.write().await.session_info();
+            let uptime_secs = (chrono::Utc::now() - info.created_at).num_seconds().max(0) as u64;
+            Some(ServerMessage::SessionInfo { info, uptime_secs })
+        }
+
+        ClientMessage::ListClients => {
+            let state_guard = state.read().await;
+            let mut clients: Vec<&ClientConnection> = state_guard
+                .clients
+                .values()
+                .filter(|client| !client.is_detached())
+                .collect();
+            clients.sort_by_key(|client| client.attached_at());
+            let clients = clients
+                .into_iter()
+                .map(|client| ClientInfo {
+                    is_self: client.id() == client_id,
+                    ..client.info()
+                })
+                .collect();
+            Some(ServerMessage::ClientList { clients })
+        }
+
+        ClientMessage::Wall { message } => {
+            let message = message.trim().to_string();
+            if message.is_empty() {
+                return Some(create_error_message("Nothing to send".to_string()));
+            }
+            let from = match state.read().await.clients.get(&client_id) {
+                Some(client) => client.info().label(),
+                None => "server".to_string(),
+            };
+            let wall = ServerMessage::Wall { from, message };
+            broadcast_to_supporting(wall, Capability::Wall, None, state).await;
             Some(ServerMessage::Ack {
-                for_command: "Detach".to_string(),
+                for_command: "Wall".to_string(),
+                request_id: None,
             })
         }
 
-        ClientMessage::Shutdown => {
-            tracing::info!("Client {} requested shutdown", client_id);
-            // TODO: Trigger server shutdown
+        ClientMessage::SetNotifications { prefs } => {
+            if let Some(client) = state.write().await.clients.get_mut(&client_id) {
+                client.set_notifications(prefs);
+            }
             Some(ServerMessage::Ack {
-                for_command: "Shutdown".to_string(),
+                for_command: "SetNotifications".to_string(),
+                request_id: None,
             })
         }
 
-        ClientMessage::Subscribe { channels } => {
-            let target_channels = {
-                let state_guard = state.read().await;
-                let known_channels: HashSet<_> = state_guard
+        ClientMessage::SetPinned { channel, pinned } => {
+            let channels = {
+                let mut state_guard = state.write().await;
+                if pinned {
+                    if !state_guard
+                        .channel_manager
+                        .list_channels()
+                        .contains(&channel)
+                    {
+                        return Some(create_error_message(format!(
+                            "Channel '{}' not found",
+                            channel
+                        )));
+                    }
+                    if !state_guard.pinned.contains(&channel) {
+                        state_guard.pinned.push(channel);
+                    }
+                } else {
+                    state_guard.pinned.retain(|name| name != &channel);
+                }
+                state_guard.pinned.clone()
+            };
+            broadcast_to_supporting(
+                ServerMessage::PinnedChanged { channels },
+                Capability::Pins,
+                None,
+                state,
+            )
+            .await;
+            Some(ServerMessage::Ack {
+                for_command: "SetPinned".to_string(),
+                request_id: None,
+            })
+        }
+
+        ClientMessage::SetMark { channel, name, seq } => {
+            {
+                let mut state_guard = state.write().await;
+                match seq {
+                    Some(seq) => {
+                        if !state_guard
+                            .channel_manager
+                            .list_channels()
+                            .contains(&channel)
+                        {
+                            return Some(create_error_message(format!(
+                                "Channel '{}' not found",
+                                channel
+                            )));
+                        }
+                        state_guard
+                            .marks
+                            .insert((channel.clone(), name.clone()), seq);
+                    }
+                    None => {
+                        state_guard.marks.remove(&(channel.clone(), name.clone()));
+                    }
+                }
+            }
+            broadcast_to_supporting(
+                ServerMessage::MarkChanged { channel, name, seq },
+                Capability::Marks,
+                None,
+                state,
+            )
+            .await;
+            Some(ServerMessage::Ack {
+                for_command: "SetMark".to_string(),
+                request_id: None,
+            })
+        }
+
+        ClientMessage::ListMarks => {
+            let state_guard = state.read().await;
+            let marks = state_guard
+                .marks
+                .iter()
+                .map(|((channel, name), &seq)| SharedMark {
+                    channel: channel.clone(),
+                    name: name.clone(),
+                    seq,
+                })
+                .collect();
+            Some(ServerMessage::Marks { marks })
+        }
+
+        ClientMessage::CompletePath { channel, partial } => {
+            let state_guard = state.read().await;
+            let manager = &state_guard.channel_manager;
+            let base = channel
+                .as_deref()
+                .or(manager.active_channel())
+                .and_then(|name| manager.current_dir(name))
+                .or_else(|| std::env::current_dir().ok())
+                .unwrap_or_else(|| PathBuf::from("."));
+            let candidates = complete_path(&base, &partial);
+            Some(ServerMessage::PathCompletions {
+                partial,
+                candidates,
+            })
+        }
+
+        ClientMessage::Detach => {
+            tracing::info!("Client {} requested detach", client_id);
+            let mut state_guard = state.write().await;
+            let state_guard = &mut *state_guard;
+            let active_channel = state_guard
+                .channel_manager
+                .active_channel()
+                .map(str::to_string);
+            if let Some(client) = state_guard.clients.get_mut(&client_id) {
+                let subscriptions = client.detach();
+                if let Some(identity) = client.identity() {
+                    state_guard.detached.insert(
+                        identity.to_string(),
+                        DetachedView {
+                            subscriptions,
+                            active_channel,
+                        },
+                    );
+                }
+            }
+            if let Err(e) = state_guard.apply_negotiated_size().await {
+                tracing::warn!("Failed to resize channels: {}", e);
+            }
+            // The connection is closed once this is sent
+            Some(ServerMessage::Ack {
+                for_command: "Detach".to_string(),
+                request_id: None,
+            })
+        }
+
+        ClientMessage::Shutdown => {
+            let state_guard = state.read().await;
+            if !state_guard
+                .clients
+                .get(&client_id)
+                .is_some_and(ClientConnection::is_owner)
+            {
+                tracing::warn!("Refused shutdown requested by client {}", client_id);
+                return Some(create_error_message(
+                    "Permission denied: only the user running the server may shut it down"
+                        .to_string(),
+                ));
+            }
+            tracing::info!("Client {} requested shutdown", client_id);
+            state_guard.shutdown.notify_one();
+            Some(ServerMessage::Ack {
+                for_command: "Shutdown".to_string(),
+                request_id: None,
+            })
+        }
+
+        ClientMessage::Ping { nonce } => Some(ServerMessage::Pong { nonce }),
+
+        ClientMessage::Request { id, message } => {
+            Box::pin(process_message(*message, client_id, state))
+                .await
+                .map(|response| response.with_request_id(id))
+        }
+
+        ClientMessage::Resume { token, last_seen } => {
+            let restored = resume_view(client_id, &token, &last_seen, state).await;
+            Some(ServerMessage::Resumed { restored })
+        }
+
+        ClientMessage::StartSession { name } => {
+            let host = state.read().await.host.clone();
+            let result = match host {
+                Some(host) => host.start(&name).await,
+                None => Err(anyhow!("this server can't host other sessions")),
+            };
+            match result {
+                Ok(()) => Some(ServerMessage::Ack {
+                    for_command: "StartSession".to_string(),
+                    request_id: None,
+                }),
+                Err(e) => Some(create_error_message(format!(
+                    "Failed to start session '{}': {}",
+                    name, e
+                ))),
+            }
+        }
+
+        ClientMessage::RenameSession { name } => match rename_session(state, &name).await {
+            Ok(()) => {
+                tracing::info!("Session renamed to '{}'", name);
+                broadcast_to_clients(ServerMessage::SessionRenamed { name }, state).await;
+                Some(ServerMessage::Ack {
+                    for_command: "RenameSession".to_string(),
+                    request_id: None,
+                })
+            }
+            Err(e) => Some(create_error_message(format!(
+                "Failed to rename session: {}",
+                e
+            ))),
+        },
+
+        ClientMessage::Subscribe { channels } => {
+            let target_channels = {
+                let state_guard = state.read().await;
+                let known_channels: HashSet<_> = state_guard
                     .channel_manager
                     .list_channels()
                     .into_iter()
@@ -464,6 +1777,11 @@ async fn process_message(
 
         ClientMessage::Input { data } => {
             let mut state_guard = state.write().await;
+            if let Some(active) = state_guard.channel_manager.active_channel() {
+                if let Err(e) = state_guard.check_acl(client_id, active, ChannelAction::Input) {
+                    return Some(create_error_message(e.to_string()));
+                }
+            }
             match state_guard.channel_manager.send_input(&data).await {
                 Ok(()) => None, // No response needed for input
                 Err(e) => Some(create_error_message(format!("Failed to send input: {}", e))),
@@ -472,6 +1790,9 @@ async fn process_message(
 
         ClientMessage::InputTo { channel, data } => {
             let mut state_guard = state.write().await;
+            if let Err(e) = state_guard.check_acl(client_id, &channel, ChannelAction::Input) {
+                return Some(create_error_message(e.to_string()));
+            }
             match state_guard
                 .channel_manager
                 .send_input_to(&channel, &data)
@@ -495,6 +1816,7 @@ async fn process_message(
                     broadcast_to_clients(event, state).await;
                     Some(ServerMessage::Ack {
                         for_command: "SwitchChannel".to_string(),
+                        request_id: None,
                     })
                 }
                 Err(e) => Some(create_error_message(format!(
@@ -504,11 +1826,121 @@ async fn process_message(
             }
         }
 
+        ClientMessage::ReloadConfig => match Config::load() {
+            Ok(config) => {
+                let mut state_guard = state.write().await;
+                let limits = Limits::from(&config.general);
+                let mut script_errors = state_guard.hooks.reconfigure(&config.hooks);
+                state_guard.config = config;
+                for buffer in state_guard.output_buffers.values_mut() {
+                    buffer.trim(limits);
+                }
+                tracing::info!("Configuration reloaded by client {}", client_id);
+
+                match state_guard.scripts.reload() {
+                    Ok(errors) => script_errors.extend(errors),
+                    Err(e) => script_errors.push(e.to_string()),
+                }
+                if script_errors.is_empty() {
+                    Some(ServerMessage::Ack {
+                        for_command: "ReloadConfig".to_string(),
+                        request_id: None,
+                    })
+                } else {
+                    Some(create_error_message(format!(
+                        "Configuration reloaded with errors: {}",
+                        script_errors.join("; ")
+                    )))
+                }
+            }
+            Err(e) => Some(create_error_message(format!(
+                "Failed to reload configuration: {}",
+                e
+            ))),
+        },
+
+        ClientMessage::SetConfig { key, value } => {
+            let mut state_guard = state.write().await;
+            let state_guard = &mut *state_guard;
+            match state_guard.config.set(&key, &value) {
+                Ok(()) => {
+                    let limits = Limits::from(&state_guard.config.general);
+                    for buffer in state_guard.output_buffers.values_mut() {
+                        buffer.trim(limits);
+                    }
+                    let hook_errors = state_guard.hooks.reconfigure(&state_guard.config.hooks);
+                    if hook_errors.is_empty() {
+                        Some(ServerMessage::Ack {
+                            for_command: "SetConfig".to_string(),
+                            request_id: None,
+                        })
+                    } else {
+                        Some(create_error_message(format!(
+                            "Set {} with errors: {}",
+                            key,
+                            hook_errors.join("; ")
+                        )))
+                    }
+                }
+                Err(e) => Some(create_error_message(format!(
+                    "Failed to set {}: {}",
+                    key, e
+                ))),
+            }
+        }
+
+        ClientMessage::GetHistory { channel } => {
+            let state_guard = state.read().await;
+            Some(ServerMessage::History {
+                histories: state_guard.history.snapshot(channel.as_deref()),
+            })
+        }
+
+        ClientMessage::AppendHistory { channel, entry } => {
+            let mut state_guard = state.write().await;
+            if state_guard.history.append(&channel, &entry) {
+                let msg = ServerMessage::HistoryAppended { channel, entry };
+                for (id, client) in state_guard.clients.iter() {
+                    if *id == client_id || client.is_detached() {
+                        continue;
+                    }
+                    if let Err(e) = client.send(msg.clone()).await {
+                        tracing::warn!("Failed to send history to client {}: {}", id, e);
+                    }
+                }
+            }
+            None
+        }
+
+        ClientMessage::ScriptCommand { name, args } => {
+            // Commands have an instruction budget to spend, so run them off
+            // the state lock
+            let scripts = Arc::clone(&state.read().await.scripts);
+            let command = name.clone();
+            let result = tokio::task::spawn_blocking(move || scripts.run_command(&command, &args))
+                .await
+                .unwrap_or_else(|e| Err(anyhow!("Script command failed: {}", e)));
+            match result {
+                Ok((message, actions)) => {
+                    run_script_actions(&mut *state.write().await, actions).await;
+                    Some(ServerMessage::ScriptResult {
+                        command: name,
+                        message,
+                    })
+                }
+                Err(e) => Some(create_error_message(e.to_string())),
+            }
+        }
+
         ClientMessage::Resize { cols, rows } => {
             let mut state_guard = state.write().await;
-            match state_guard.channel_manager.resize_all(cols, rows).await {
+            if let Some(client) = state_guard.clients.get_mut(&client_id) {
+                client.set_size(cols, rows);
+            }
+            match state_guard.apply_negotiated_size().await {
                 Ok(()) => Some(ServerMessage::Ack {
                     for_command: "Resize".to_string(),
+                    request_id: None,
                 }),
                 Err(e) => Some(create_error_message(format!(
                     "Failed to resize channels: {}",
@@ -519,10 +1951,287 @@ async fn process_message(
     }
 }
 
+/// Whether a read-only observer may send `msg`: anything that only reads
+/// the session or changes what this client itself sees
+fn allowed_for_observers(msg: &ClientMessage) -> bool {
+    match msg {
+        ClientMessage::Hello { .. }
+        | ClientMessage::Subscribe { .. }
+        | ClientMessage::Unsubscribe { .. }
+        | ClientMessage::ListChannels
+        | ClientMessage::GetStatus { .. }
+        | ClientMessage::GetSessionInfo
+        | ClientMessage::ListClients
+        | ClientMessage::ListMarks
+        | ClientMessage::GetProcesses { .. }
+        | ClientMessage::Wall { .. }
+        | ClientMessage::SetNotifications { .. }
+        | ClientMessage::GetHistory { .. }
+        | ClientMessage::Resize { .. }
+        | ClientMessage::Detach
+        | ClientMessage::Ping { .. }
+        | ClientMessage::Resume { .. } => true,
+        // Checked again when the wrapped message is processed
+        ClientMessage::Request { .. } => true,
+        ClientMessage::Input { .. }
+        | ClientMessage::InputTo { .. }
+        | ClientMessage::CreateChannel(_)
+        | ClientMessage::KillChannel { .. }
+        | ClientMessage::SignalForeground { .. }
+        | ClientMessage::SwitchChannel { .. }
+        | ClientMessage::SetPinned { .. }
+        | ClientMessage::SetMark { .. }
+        | ClientMessage::ReloadConfig
+        | ClientMessage::SetConfig { .. }
+        | ClientMessage::AppendHistory { .. }
+        | ClientMessage::CompletePath { .. }
+        | ClientMessage::ScriptCommand { .. }
+        | ClientMessage::Shutdown
+        | ClientMessage::RenameSession { .. }
+        | ClientMessage::StartSession { .. } => false,
+    }
+}
+
+/// Create the channels listed in the config's `[startup]` section.
+///
+/// Failures are logged and skipped so one bad entry doesn't keep the server down.
+async fn create_startup_channels(state: &Arc<RwLock<ServerState>>) {
+    let mut state_guard = state.write().await;
+    let startup = state_guard.config.startup.clone();
+
+    for channel in &startup.channels {
+        let config = build_channel_config(
+            &state_guard.config,
+            &channel.name,
+            channel.command.clone(),
+            channel
+                .cwd
+                .as_ref()
+                .map(|dir| dir.to_string_lossy().to_string()),
+            channel
+                .env
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            None,
+            None,
+            channel.template.as_deref(),
+        );
+        let result = match config {
+            Ok(mut config) => {
+                if channel.timeout.is_some() {
+                    config.timeout_secs = channel.timeout;
+                }
+                if channel.schedule.is_some() {
+                    config.schedule = channel.schedule.clone();
+                }
+                state_guard.channel_manager.create_channel(config).await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {
+                state_guard.scrollback(&channel.name);
+                tracing::info!("Started startup channel '{}'", channel.name);
+                state_guard.emit_event(QueuedEvent::Created {
+                    channel: channel.name.clone(),
+                });
+            }
+            Err(e) => tracing::warn!("Failed to start startup channel '{}': {}", channel.name, e),
+        }
+    }
+
+    if let Some(focus) = &startup.focus {
+        if let Err(e) = state_guard.channel_manager.switch_active(focus) {
+            tracing::warn!("Failed to focus startup channel '{}': {}", focus, e);
+        }
+    }
+}
+
+/// Resolve a `CreateChannel` request into a channel config.
+///
+/// When a template is named, its settings are used as the base and any values
+/// given explicitly in the request take precedence.
+#[allow(clippy::too_many_arguments)]
+fn build_channel_config(
+    config: &Config,
+    name: &str,
+    command: Option<String>,
+    working_dir: Option<String>,
+    env: Vec<(String, String)>,
+    container: Option<ContainerTarget>,
+    mode: Option<ChannelMode>,
+    template: Option<&str>,
+) -> Result<ChannelConfig> {
+    let mut channel_config = match template {
+        Some(template_name) => config
+            .templates
+            .get(template_name)
+            .ok_or_else(|| anyhow!("Unknown template '{}'", template_name))?
+            .to_channel_config(name),
+        None => ChannelConfig::new(name),
+    };
+
+    if command.is_some() {
+        channel_config.command = command;
+    }
+    if container.is_some() {
+        channel_config.container = container;
+    }
+    if let Some(mode) = mode {
+        channel_config.mode = mode;
+    }
+    // The host's shell may not exist in a container; exec falls back to `sh`
+    if channel_config.command.is_none() && channel_config.container.is_none() {
+        channel_config.command = Some(config.general.default_shell.clone());
+    }
+    if let Some(dir) = working_dir {
+        channel_config.working_dir = Some(PathBuf::from(dir));
+    } else if channel_config.container.is_some() {
+        // A template's directory is on the host and likely missing in the
+        // container; without one, exec starts in the container's own
+        channel_config.working_dir = None;
+    }
+    for (key, value) in env {
+        channel_config = channel_config.with_env(key, value);
+    }
+
+    Ok(channel_config)
+}
+
+/// Run script and plugin handlers for queued channel events, in order and
+/// off the state lock, then carry out the actions they queued
+fn spawn_script_handlers(
+    state: &Arc<RwLock<ServerState>>,
+    mut events: mpsc::Receiver<QueuedEvent>,
+) {
+    let state = Arc::downgrade(state);
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            let Some(state) = state.upgrade() else {
+                break;
+            };
+            let (scripts, plugins) = {
+                let state_guard = state.read().await;
+                (
+                    Arc::clone(&state_guard.scripts),
+                    Arc::clone(&state_guard.plugins),
+                )
+            };
+            let handled = tokio::task::spawn_blocking(move || {
+                let mut actions = scripts.emit(event.as_event());
+                actions.extend(plugins.emit(event.as_event()));
+                actions
+            })
+            .await;
+            match handled {
+                Ok(actions) if !actions.is_empty() => {
+                    run_script_actions(&mut *state.write().await, actions).await;
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Script handlers failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Carry out actions queued by script handlers
+async fn run_script_actions(state: &mut ServerState, actions: Vec<ScriptAction>) {
+    for action in actions {
+        match action {
+            ScriptAction::SendInput { channel, data } => {
+                if let Err(e) = state.channel_manager.send_input_to(&channel, &data).await {
+                    tracing::warn!("Script failed to send input to '{}': {}", channel, e);
+                }
+            }
+        }
+    }
+}
+
+/// Notify the clients that asked about `channel` exiting: as a failure when
+/// it didn't exit cleanly and they asked about failures, otherwise as an exit
+async fn notify_exit(channel: &str, exit_code: Option<i32>, state: &Arc<RwLock<ServerState>>) {
+    let message = match exit_code {
+        Some(0) => format!("#{} exited", channel),
+        Some(code) => format!("#{} exited with status {}", channel, code),
+        None => format!("#{} was killed by a signal", channel),
+    };
+    let kinds: &[NotificationKind] = if exit_code == Some(0) {
+        &[NotificationKind::Exit]
+    } else {
+        &[NotificationKind::Failure, NotificationKind::Exit]
+    };
+    notify_finished(channel, message, kinds, state).await;
+}
+
+/// Send `message` to each client that asked about the first of `kinds` it
+/// wants for `channel`
+async fn notify_finished(
+    channel: &str,
+    message: String,
+    kinds: &[NotificationKind],
+    state: &Arc<RwLock<ServerState>>,
+) {
+    let mut state_guard = state.write().await;
+    for client in state_guard.clients.values_mut() {
+        let Some(&kind) = kinds
+            .iter()
+            .find(|&&kind| client.take_notification(channel, kind))
+        else {
+            continue;
+        };
+        let notification = ServerMessage::Notification {
+            channel: channel.to_string(),
+            kind,
+            message: message.clone(),
+        };
+        if let Err(e) = client.send(notification).await {
+            tracing::warn!("Failed to notify client {}: {}", client.id(), e);
+        }
+    }
+}
+
+/// Tell the other clients that a client attached, once it has said who it
+/// is; a repeated Hello isn't announced again
+async fn announce_client(client_id: Uuid, state: &Arc<RwLock<ServerState>>) {
+    let joined = match state.write().await.clients.get_mut(&client_id) {
+        Some(client) if !client.is_announced() => {
+            client.set_announced();
+            client.info()
+        }
+        _ => return,
+    };
+    let about = Some(joined.id);
+    let msg = ServerMessage::ClientJoined { client: joined };
+    broadcast_to_supporting(msg, Capability::Presence, about, state).await;
+}
+
+/// Send a message to every attached client that advertised `capability`,
+/// except `skip`
+async fn broadcast_to_supporting(
+    msg: ServerMessage,
+    capability: Capability,
+    skip: Option<Uuid>,
+    state: &Arc<RwLock<ServerState>>,
+) {
+    let state = state.read().await;
+    for client in state.clients.values().filter(|client| {
+        Some(client.id()) != skip && !client.is_detached() && client.supports(capability)
+    }) {
+        if let Err(e) = client.send(msg.clone()).await {
+            tracing::warn!("Failed to notify client {}: {}", client.id(), e);
+        }
+    }
+}
+
 /// Broadcasts a server message to all connected clients.
 async fn broadcast_to_clients(msg: ServerMessage, state: &Arc<RwLock<ServerState>>) {
     let state = state.read().await;
-    for client in state.clients.values() {
+    for client in state
+        .clients
+        .values()
+        .filter(|client| !client.is_detached())
+    {
         if let Err(e) = client.send(msg.clone()).await {
             tracing::warn!(
                 "Failed to broadcast message to client {}: {}",
@@ -538,16 +2247,28 @@ async fn send_buffered_output(
     client_id: Uuid,
     channels: &[String],
     state: &Arc<RwLock<ServerState>>,
+) {
+    send_buffered_output_since(client_id, channels, &HashMap::new(), state).await;
+}
+
+/// Send buffered output for specified channels to the given client, skipping
+/// chunks up to the sequence number it has already seen for each channel.
+async fn send_buffered_output_since(
+    client_id: Uuid,
+    channels: &[String],
+    last_seen: &HashMap<String, u64>,
+    state: &Arc<RwLock<ServerState>>,
 ) {
     let buffers: Vec<(String, Vec<BufferedOutput>)> = {
         let state_guard = state.read().await;
         channels
             .iter()
             .filter_map(|channel| {
+                let seen = last_seen.get(channel).copied().unwrap_or(0);
                 state_guard
                     .output_buffers
                     .get(channel)
-                    .map(|buf| (channel.clone(), buf.iter().cloned().collect()))
+                    .map(|buf| (channel.clone(), buf.since(seen)))
             })
             .collect()
     };
@@ -565,6 +2286,8 @@ async fn send_buffered_output(
                         channel: channel.clone(),
                         data: entry.data.clone(),
                         timestamp: entry.timestamp,
+                        stream: entry.stream,
+                        seq: entry.seq,
                     })
                     .await
                 {
@@ -582,27 +2305,49 @@ async fn send_buffered_output(
 /// Handles events coming from the ChannelManager.
 async fn handle_channel_event(event: ChannelManagerEvent, state: &Arc<RwLock<ServerState>>) {
     match event {
-        ChannelManagerEvent::Output { channel_name, data } => {
+        ChannelManagerEvent::Output {
+            channel_name,
+            data,
+            stream,
+        } => {
+            // Shared by the buffer and every recipient from here on
+            let data = Bytes::from(data);
             let timestamp = chrono::Utc::now().timestamp_millis();
+            let seq;
             let mut recipients = Vec::new();
+            let mut notified = Vec::new();
             {
                 let mut state_guard = state.write().await;
-                let buffer = state_guard
-                    .output_buffers
+                let limits = Limits::from(&state_guard.config.general);
+                let last_seq = state_guard
+                    .output_seqs
                     .entry(channel_name.clone())
-                    .or_insert_with(VecDeque::new);
-                buffer.push_back(BufferedOutput {
+                    .or_insert(0);
+                *last_seq += 1;
+                seq = *last_seq;
+                let buffer = state_guard.scrollback(&channel_name);
+                buffer.push(
+                    BufferedOutput {
+                        data: data.clone(),
+                        timestamp,
+                        stream,
+                        seq,
+                    },
+                    limits,
+                );
+
+                state_guard.emit_event(QueuedEvent::Output {
+                    channel: channel_name.clone(),
                     data: data.clone(),
-                    timestamp,
                 });
-                while buffer.len() > MAX_BUFFERED_OUTPUTS {
-                    buffer.pop_front();
-                }
 
-                for (client_id, client) in state_guard.clients.iter() {
+                for (client_id, client) in state_guard.clients.iter_mut() {
                     if client.is_subscribed(&channel_name) {
                         recipients.push(*client_id);
                     }
+                    if client.take_notification(&channel_name, NotificationKind::Output) {
+                        notified.push(*client_id);
+                    }
                 }
             }
 
@@ -611,6 +2356,8 @@ async fn handle_channel_event(event: ChannelManagerEvent, state: &Arc<RwLock<Ser
                 channel: channel_name.clone(),
                 data,
                 timestamp,
+                stream,
+                seq,
             };
             let state_read = state.read().await;
             for client_id in recipients {
@@ -620,16 +2367,70 @@ async fn handle_channel_event(event: ChannelManagerEvent, state: &Arc<RwLock<Ser
                     }
                 }
             }
+            let notification = ServerMessage::Notification {
+                message: format!("New output in #{}", channel_name),
+                channel: channel_name,
+                kind: NotificationKind::Output,
+            };
+            for client_id in notified {
+                if let Some(client) = state_read.clients.get(&client_id) {
+                    let _ = client.send(notification.clone()).await;
+                }
+            }
         }
         ChannelManagerEvent::StateChanged {
             channel_name,
             state: channel_state,
         } => {
+            let will_restart = matches!(channel_state, crate::channel::ChannelState::Exited(_))
+                && state
+                    .read()
+                    .await
+                    .channel_manager
+                    .should_restart(&channel_name);
+
+            if will_restart {
+                schedule_restart(channel_name.clone(), Arc::clone(state));
+            }
+
+            match channel_state {
+                crate::channel::ChannelState::Exited(exit_code) => state
+                    .write()
+                    .await
+                    .channel_manager
+                    .record_exit(&channel_name, exit_code),
+                crate::channel::ChannelState::Killed => state
+                    .write()
+                    .await
+                    .channel_manager
+                    .record_exit(&channel_name, None),
+                _ => {}
+            }
+            // Subscribers keep a scheduled channel between runs
+            let will_rerun = will_restart
+                || state
+                    .read()
+                    .await
+                    .channel_manager
+                    .is_scheduled(&channel_name);
+
+            if let crate::channel::ChannelState::Exited(exit_code) = channel_state {
+                let mut state_guard = state.write().await;
+                state_guard.emit_event(QueuedEvent::Exited {
+                    channel: channel_name.clone(),
+                    exit_code,
+                });
+                drop(state_guard);
+                notify_exit(&channel_name, exit_code, state).await;
+            }
+
             let mut subscription_updates = Vec::new();
-            if matches!(
-                channel_state,
-                crate::channel::ChannelState::Killed | crate::channel::ChannelState::Exited(_)
-            ) {
+            if !will_rerun
+                && matches!(
+                    channel_state,
+                    crate::channel::ChannelState::Killed | crate::channel::ChannelState::Exited(_)
+                )
+            {
                 let mut state_guard = state.write().await;
                 for (client_id, client) in state_guard.clients.iter_mut() {
                     if client.is_subscribed(&channel_name) {
@@ -661,38 +2462,166 @@ async fn handle_channel_event(event: ChannelManagerEvent, state: &Arc<RwLock<Ser
 
             let server_event = match channel_state {
                 // We broadcast Created events from the message handler to get an Ack.
-                crate::channel::ChannelState::Running => None,
+                crate::channel::ChannelState::Starting => None,
+                crate::channel::ChannelState::Running => Some(ChannelEvent::Ready {
+                    name: channel_name.clone(),
+                }),
                 crate::channel::ChannelState::Exited(code) => Some(ChannelEvent::Exited {
-                    name: channel_name,
+                    name: channel_name.clone(),
                     exit_code: code,
                 }),
-                crate::channel::ChannelState::Killed => {
-                    Some(ChannelEvent::Killed { name: channel_name })
-                }
-                crate::channel::ChannelState::Starting => None,
+                crate::channel::ChannelState::Killed => Some(ChannelEvent::Killed {
+                    name: channel_name.clone(),
+                }),
             };
+            let is_over = matches!(
+                server_event,
+                Some(ChannelEvent::Exited { .. } | ChannelEvent::Killed { .. })
+            );
             if let Some(event) = server_event {
                 broadcast_to_clients(ServerMessage::Event(event), state).await;
             }
+
+            // Ephemeral channels go away once clients have seen them finish
+            if is_over {
+                let mut state_guard = state.write().await;
+                if state_guard.channel_manager.is_disposable(&channel_name)
+                    && state_guard.channel_manager.remove_channel(&channel_name)
+                {
+                    state_guard.output_buffers.remove(&channel_name);
+                    tracing::info!("Removed ephemeral channel '{}'", channel_name);
+                }
+            }
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::channel::ChannelConfig;
-    use tempfile::tempdir;
-    use tokio::sync::mpsc;
-
-    #[tokio::test]
-    async fn sends_output_only_to_subscribers() {
-        let temp_dir = tempdir().unwrap();
-        let (event_tx, _event_rx) = mpsc::channel(8);
-        let (client1_tx, mut client1_rx) = mpsc::channel(8);
-        let (client2_tx, mut client2_rx) = mpsc::channel(8);
+/// Kill a channel that ran past its timeout. Clients that know about
+/// timeouts hear why before the Killed event reaches everyone.
+async fn time_out_channel(name: String, timeout_secs: u64, state: Arc<RwLock<ServerState>>) {
+    let killed = state
+        .write()
+        .await
+        .channel_manager
+        .kill_channel(&name)
+        .await;
+    if let Err(e) = killed {
+        tracing::warn!("Failed to kill timed out channel '{}': {}", name, e);
+        return;
+    }
+    let limit = duration::format(timeout_secs);
+    tracing::info!("Killed channel '{}' after its {} timeout", name, limit);
+    broadcast_to_supporting(
+        ServerMessage::Event(ChannelEvent::TimedOut {
+            name: name.clone(),
+            timeout_secs,
+        }),
+        Capability::Timeouts,
+        None,
+        &state,
+    )
+    .await;
+    notify_finished(
+        &name,
+        format!("#{} timed out after {}", name, limit),
+        &[NotificationKind::Failure, NotificationKind::Exit],
+        &state,
+    )
+    .await;
+}
 
-        let mut client1 = ClientConnection::new(client1_tx);
+/// Start the next run of a scheduled channel and tell clients about it
+async fn run_scheduled(channel_name: String, state: Arc<RwLock<ServerState>>) {
+    let result = state
+        .write()
+        .await
+        .channel_manager
+        .restart_channel(&channel_name)
+        .await;
+    match result {
+        Ok(()) => {
+            tracing::info!("Started scheduled run of '{}'", channel_name);
+            broadcast_to_clients(
+                ServerMessage::Event(ChannelEvent::Restarted { name: channel_name }),
+                &state,
+            )
+            .await;
+        }
+        Err(e) => tracing::warn!("Failed to start scheduled run of '{}': {}", channel_name, e),
+    }
+}
+
+/// Restart an exited channel after `RESTART_DELAY` and tell clients about it.
+fn schedule_restart(channel_name: String, state: Arc<RwLock<ServerState>>) {
+    tokio::spawn(async move {
+        tokio::time::sleep(RESTART_DELAY).await;
+        let result = {
+            let mut state_guard = state.write().await;
+            // The channel may have been killed while we were waiting
+            if !state_guard.channel_manager.should_restart(&channel_name) {
+                return;
+            }
+            state_guard
+                .channel_manager
+                .restart_channel(&channel_name)
+                .await
+        };
+        match result {
+            Ok(()) => {
+                tracing::info!("Restarted channel '{}'", channel_name);
+                broadcast_to_clients(
+                    ServerMessage::Event(ChannelEvent::Restarted { name: channel_name }),
+                    &state,
+                )
+                .await;
+            }
+            Err(e) => tracing::warn!("Failed to restart channel '{}': {}", channel_name, e),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{ChannelConfig, OutputStream};
+    use crate::protocol::NotificationPrefs;
+    use tempfile::tempdir;
+    use tokio::sync::mpsc;
+
+    /// A server state with no clients or channels, for tests to override
+    /// what they need
+    fn test_state(event_tx: mpsc::Sender<ChannelManagerEvent>) -> ServerState {
+        ServerState {
+            session: Session::new(
+                "test".to_string(),
+                std::env::temp_dir().join("nexus-test.sock"),
+            ),
+            clients: HashMap::new(),
+            channel_manager: ChannelManager::new(event_tx),
+            output_buffers: HashMap::new(),
+            config: Config::default(),
+            history: HistoryStore::default(),
+            scripts: Arc::new(ScriptEngine::new().unwrap()),
+            plugins: Arc::new(PluginHost::new()),
+            script_events: mpsc::channel(1).0,
+            hooks: Hooks::default(),
+            shutdown: Arc::new(Notify::new()),
+            host: None,
+            detached: HashMap::new(),
+            resumable: HashMap::new(),
+            output_seqs: HashMap::new(),
+            pinned: Vec::new(),
+            marks: BTreeMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_output_only_to_subscribers() {
+        let (event_tx, _event_rx) = mpsc::channel(8);
+        let (client1_tx, mut client1_rx) = mpsc::channel(8);
+        let (client2_tx, mut client2_rx) = mpsc::channel(8);
+
+        let mut client1 = ClientConnection::new(client1_tx);
         let client1_id = client1.id();
         client1.subscribe(&["chan".to_string()]);
 
@@ -700,16 +2629,15 @@ mod tests {
         let client2_id = client2.id();
 
         let state = Arc::new(RwLock::new(ServerState {
-            session: Session::new("test".to_string(), temp_dir.path().join("sock")),
             clients: HashMap::from([(client1_id, client1), (client2_id, client2)]),
-            channel_manager: ChannelManager::new(event_tx),
-            output_buffers: HashMap::new(),
+            ..test_state(event_tx)
         }));
 
         handle_channel_event(
             ChannelManagerEvent::Output {
                 channel_name: "chan".to_string(),
                 data: b"hello".to_vec(),
+                stream: OutputStream::Stdout,
             },
             &state,
         )
@@ -722,7 +2650,7 @@ mod tests {
         match msg {
             ServerMessage::Output { channel, data, .. } => {
                 assert_eq!(channel, "chan");
-                assert_eq!(data, b"hello");
+                assert_eq!(data, &b"hello"[..]);
             }
             other => panic!("unexpected message for subscriber: {:?}", other),
         }
@@ -735,17 +2663,14 @@ mod tests {
 
     #[tokio::test]
     async fn replays_buffer_on_subscribe() {
-        let temp_dir = tempdir().unwrap();
         let (event_tx, _event_rx) = mpsc::channel(8);
         let (client_tx, mut client_rx) = mpsc::channel(16);
         let client = ClientConnection::new(client_tx);
         let client_id = client.id();
 
         let state = Arc::new(RwLock::new(ServerState {
-            session: Session::new("test".to_string(), temp_dir.path().join("sock")),
             clients: HashMap::from([(client_id, client)]),
-            channel_manager: ChannelManager::new(event_tx),
-            output_buffers: HashMap::new(),
+            ..test_state(event_tx)
         }));
 
         {
@@ -755,16 +2680,14 @@ mod tests {
                 .create_channel(ChannelConfig::new("chan").with_command("/bin/echo"))
                 .await
                 .unwrap();
-            guard
-                .output_buffers
-                .entry("chan".to_string())
-                .or_insert_with(VecDeque::new);
+            guard.scrollback("chan");
         }
 
         handle_channel_event(
             ChannelManagerEvent::Output {
                 channel_name: "chan".to_string(),
                 data: b"missed".to_vec(),
+                stream: OutputStream::Stdout,
             },
             &state,
         )
@@ -785,7 +2708,7 @@ mod tests {
         match output_msg {
             ServerMessage::Output { channel, data, .. } => {
                 assert_eq!(channel, "chan");
-                assert_eq!(data, b"missed");
+                assert_eq!(data, &b"missed"[..]);
             }
             other => panic!("unexpected message: {:?}", other),
         }
@@ -797,4 +2720,875 @@ mod tests {
             other => panic!("unexpected response: {:?}", other),
         }
     }
+
+    #[test]
+    fn template_values_are_overridden_by_request() {
+        let config: Config = toml::from_str(
+            r#"
+            [templates.node-dev]
+            command = "npm run dev"
+            cwd = "/srv/app"
+            env = { NODE_ENV = "development", PORT = "3000" }
+            restart = "on-failure"
+            "#,
+        )
+        .unwrap();
+
+        let channel_config = build_channel_config(
+            &config,
+            "frontend",
+            None,
+            Some("/tmp".to_string()),
+            vec![("PORT".to_string(), "4000".to_string())],
+            None,
+            None,
+            Some("node-dev"),
+        )
+        .unwrap();
+
+        assert_eq!(channel_config.name, "frontend");
+        assert_eq!(channel_config.command.as_deref(), Some("npm run dev"));
+        assert_eq!(channel_config.working_dir, Some(PathBuf::from("/tmp")));
+        assert_eq!(
+            channel_config.restart,
+            crate::channel::RestartPolicy::OnFailure
+        );
+        // Request env is applied after the template's, so it wins
+        assert_eq!(
+            channel_config.env.unwrap().last(),
+            Some(&("PORT".to_string(), "4000".to_string()))
+        );
+    }
+
+    #[test]
+    fn unknown_template_is_an_error() {
+        let result = build_channel_config(
+            &Config::default(),
+            "frontend",
+            None,
+            None,
+            Vec::new(),
+            None,
+            None,
+            Some("missing"),
+        );
+        assert!(result.unwrap_err().to_string().contains("Unknown template"));
+    }
+
+    #[tokio::test]
+    async fn clients_that_stop_reading_are_stalled() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let mut client = ClientConnection::new(tx);
+        let written = client.track_writes();
+        assert!(!client.is_stalled(std::time::Duration::ZERO));
+
+        // Queued but never written out
+        client
+            .send(create_error_message("one".to_string()))
+            .await
+            .unwrap();
+        assert!(client.is_stalled(std::time::Duration::ZERO));
+        assert!(!client.is_stalled(std::time::Duration::from_secs(60)));
+        // A full queue fails the send instead of blocking the caller
+        let started = std::time::Instant::now();
+        assert!(client
+            .send(create_error_message("two".to_string()))
+            .await
+            .is_err());
+        assert!(client
+            .send(create_error_message("three".to_string()))
+            .await
+            .is_err());
+        assert!(started.elapsed() < std::time::Duration::from_secs(3));
+
+        // Progress resets the clock
+        rx.recv().await.unwrap();
+        written.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        client
+            .send(create_error_message("four".to_string()))
+            .await
+            .unwrap();
+        assert!(!client.is_stalled(std::time::Duration::from_millis(50)));
+    }
+
+    /// Writer that counts flushes, to see how writes were batched
+    #[derive(Default)]
+    struct FlushCounter {
+        data: Vec<u8>,
+        flushes: usize,
+    }
+
+    impl tokio::io::AsyncWrite for FlushCounter {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            self.data.extend_from_slice(buf);
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            self.flushes += 1;
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_messages_are_written_in_one_batch() {
+        let (tx, rx) = mpsc::channel(16);
+        let client = ClientConnection::new(tx);
+        for i in 0..10 {
+            client
+                .send(create_error_message(format!("message {}", i)))
+                .await
+                .unwrap();
+        }
+        let protocol_version = client.protocol_version_handle();
+        drop(client);
+
+        let mut writer = FlushCounter::default();
+        let written = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        client_writer_task(&mut writer, rx, Arc::clone(&written), protocol_version).await;
+
+        assert_eq!(writer.flushes, 1);
+        assert_eq!(written.load(std::sync::atomic::Ordering::Relaxed), 10);
+        // Frames still arrive back to back, in order
+        let mut rest = writer.data.as_slice();
+        for i in 0..10 {
+            let (payload, remaining) = crate::protocol::unframe_message(rest).unwrap().unwrap();
+            match crate::protocol::deserialize(&payload).unwrap() {
+                ServerMessage::Error { message, .. } => {
+                    assert_eq!(message, format!("message {}", i))
+                }
+                other => panic!("unexpected message: {:?}", other),
+            }
+            rest = remaining;
+        }
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn untemplated_channels_use_default_shell() {
+        let mut config = Config::default();
+        config.general.default_shell = "/bin/zsh".to_string();
+
+        let channel_config =
+            build_channel_config(&config, "shell", None, None, Vec::new(), None, None, None)
+                .unwrap();
+        assert_eq!(channel_config.command.as_deref(), Some("/bin/zsh"));
+    }
+
+    #[test]
+    fn container_channels_skip_the_host_shell() {
+        let mut config = Config::default();
+        config.general.default_shell = "/bin/zsh".to_string();
+        let target = ContainerTarget::new(crate::channel::ContainerRuntime::Docker, "web");
+
+        let channel_config = build_channel_config(
+            &config,
+            "appshell",
+            None,
+            None,
+            Vec::new(),
+            Some(target.clone()),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(channel_config.command, None);
+        assert_eq!(channel_config.container, Some(target));
+    }
+
+    #[test]
+    fn container_channels_only_get_an_explicit_working_dir() {
+        let config: Config = toml::from_str(
+            r#"
+            [templates.app]
+            command = "npm start"
+            cwd = "/home/me/app"
+            "#,
+        )
+        .unwrap();
+        let target = ContainerTarget::new(crate::channel::ContainerRuntime::Podman, "web");
+        let build = |working_dir: Option<&str>| {
+            build_channel_config(
+                &config,
+                "app",
+                None,
+                working_dir.map(str::to_string),
+                Vec::new(),
+                Some(target.clone()),
+                None,
+                Some("app"),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(build(None).working_dir, None);
+        assert_eq!(build(None).command.as_deref(), Some("npm start"));
+        assert_eq!(
+            build(Some("/srv/app")).working_dir,
+            Some(PathBuf::from("/srv/app"))
+        );
+    }
+
+    #[tokio::test]
+    async fn channels_fit_the_smallest_attached_client() {
+        let (event_tx, _event_rx) = mpsc::channel(8);
+        let (client1_tx, _client1_rx) = mpsc::channel(8);
+        let (client2_tx, _client2_rx) = mpsc::channel(8);
+        let client1 = ClientConnection::new(client1_tx);
+        let client1_id = client1.id();
+        let client2 = ClientConnection::new(client2_tx);
+        let client2_id = client2.id();
+
+        let state = Arc::new(RwLock::new(ServerState {
+            clients: HashMap::from([(client1_id, client1), (client2_id, client2)]),
+            ..test_state(event_tx)
+        }));
+        state
+            .write()
+            .await
+            .channel_manager
+            .create_channel(ChannelConfig::new("chan").with_command("/bin/cat"))
+            .await
+            .unwrap();
+
+        let size = || async { state.read().await.channel_manager.size() };
+        let resize = |cols, rows| ClientMessage::Resize { cols, rows };
+
+        process_message(resize(120, 40), client1_id, &state).await;
+        assert_eq!(size().await, Some((120, 40)));
+
+        // A second, differently shaped terminal doesn't take over; both fit
+        process_message(resize(80, 50), client2_id, &state).await;
+        assert_eq!(size().await, Some((80, 40)));
+        process_message(resize(120, 40), client1_id, &state).await;
+        assert_eq!(size().await, Some((80, 40)));
+
+        // Once the smaller client detaches, channels grow back
+        process_message(ClientMessage::Detach, client2_id, &state).await;
+        assert_eq!(size().await, Some((120, 40)));
+
+        state
+            .write()
+            .await
+            .channel_manager
+            .kill_channel("chan")
+            .await
+            .ok();
+    }
+
+    #[tokio::test]
+    async fn observers_watch_but_cannot_change_the_session() {
+        let (event_tx, _event_rx) = mpsc::channel(8);
+        let (worker_tx, _worker_rx) = mpsc::channel(8);
+        let (observer_tx, mut observer_rx) = mpsc::channel(8);
+        let worker = ClientConnection::new(worker_tx);
+        let worker_id = worker.id();
+        let mut observer = ClientConnection::new(observer_tx);
+        let observer_id = observer.id();
+        observer.subscribe(&["chan".to_string()]);
+
+        let state = Arc::new(RwLock::new(ServerState {
+            clients: HashMap::from([(worker_id, worker), (observer_id, observer)]),
+            ..test_state(event_tx)
+        }));
+        state
+            .write()
+            .await
+            .channel_manager
+            .create_channel(ChannelConfig::new("chan").with_command("/bin/cat"))
+            .await
+            .unwrap();
+
+        let hello = |observe| ClientMessage::Hello {
+            protocol_version: crate::protocol::PROTOCOL_VERSION,
+            token: None,
+            identity: None,
+            capabilities: Vec::new(),
+            observe,
+            name: None,
+        };
+        process_message(hello(true), observer_id, &state).await;
+        // A later Hello can't lift the restriction
+        process_message(hello(false), observer_id, &state).await;
+
+        let refused = [
+            ClientMessage::Input {
+                data: b"rm -rf ~\n".to_vec(),
+            },
+            ClientMessage::CreateChannel(CreateChannelRequest {
+                name: "other".to_string(),
+                ..Default::default()
+            }),
+            ClientMessage::KillChannel {
+                name: "chan".to_string(),
+            },
+            ClientMessage::Request {
+                id: 1,
+                message: Box::new(ClientMessage::KillChannel {
+                    name: "chan".to_string(),
+                }),
+            },
+        ];
+        for msg in refused {
+            match process_message(msg, observer_id, &state).await {
+                Some(ServerMessage::Error { message, .. }) => {
+                    assert!(message.contains("observer"), "{}", message)
+                }
+                other => panic!("expected the observer to be refused, got {:?}", other),
+            }
+        }
+        assert_eq!(
+            state.read().await.channel_manager.list_channels(),
+            vec!["chan"]
+        );
+
+        // Observers still get output, and their terminal doesn't shrink channels
+        handle_channel_event(
+            ChannelManagerEvent::Output {
+                channel_name: "chan".to_string(),
+                data: b"hello".to_vec(),
+                stream: OutputStream::Stdout,
+            },
+            &state,
+        )
+        .await;
+        assert!(matches!(
+            observer_rx.try_recv(),
+            Ok(ServerMessage::Output { .. })
+        ));
+        process_message(
+            ClientMessage::Resize {
+                cols: 120,
+                rows: 40,
+            },
+            worker_id,
+            &state,
+        )
+        .await;
+        process_message(
+            ClientMessage::Resize { cols: 40, rows: 10 },
+            observer_id,
+            &state,
+        )
+        .await;
+        assert_eq!(state.read().await.channel_manager.size(), Some((120, 40)));
+
+        // The worker is unaffected
+        assert!(process_message(
+            ClientMessage::KillChannel {
+                name: "chan".to_string()
+            },
+            worker_id,
+            &state
+        )
+        .await
+        .is_some_and(|msg| !matches!(msg, ServerMessage::Error { .. })));
+    }
+
+    #[tokio::test]
+    async fn channel_acls_limit_input_and_kill() {
+        let (event_tx, _event_rx) = mpsc::channel(8);
+        let (alice_tx, _alice_rx) = mpsc::channel(8);
+        let (bob_tx, _bob_rx) = mpsc::channel(8);
+        let mut alice = ClientConnection::new(alice_tx);
+        alice.set_principal("alice".to_string());
+        let alice_id = alice.id();
+        let mut bob = ClientConnection::new(bob_tx);
+        // Claiming to be alice doesn't get past the ACL
+        bob.set_identity("alice".to_string());
+        bob.set_principal("bob".to_string());
+        let bob_id = bob.id();
+
+        let mut config = Config::default();
+        config.acl.insert(
+            "prod".to_string(),
+            toml::from_str(
+                r#"input = ["alice"]
+kill = ["alice"]"#,
+            )
+            .unwrap(),
+        );
+
+        let state = Arc::new(RwLock::new(ServerState {
+            clients: HashMap::from([(alice_id, alice), (bob_id, bob)]),
+            config,
+            ..test_state(event_tx)
+        }));
+        for name in ["prod", "dev"] {
+            state
+                .write()
+                .await
+                .channel_manager
+                .create_channel(ChannelConfig::new(name).with_command("/bin/cat"))
+                .await
+                .unwrap();
+        }
+
+        let input_to = |channel: &str| ClientMessage::InputTo {
+            channel: channel.to_string(),
+            data: b"ls\n".to_vec(),
+        };
+        let kill = |channel: &str| ClientMessage::KillChannel {
+            name: channel.to_string(),
+        };
+        let error = |response: Option<ServerMessage>| match response {
+            Some(ServerMessage::Error { message, .. }) => Some(message),
+            _ => None,
+        };
+
+        // "prod" is the active channel, so plain Input is checked against it too
+        let denied = error(process_message(input_to("prod"), bob_id, &state).await).unwrap();
+        assert_eq!(
+            denied,
+            "Permission denied: 'bob' may not send input to channel 'prod'"
+        );
+        let input = ClientMessage::Input {
+            data: b"ls\n".to_vec(),
+        };
+        assert!(error(process_message(input, bob_id, &state).await).is_some());
+        let denied = error(process_message(kill("prod"), bob_id, &state).await).unwrap();
+        assert_eq!(
+            denied,
+            "Permission denied: 'bob' may not kill channel 'prod'"
+        );
+
+        // Channels without an ACL stay open; listed clients get through
+        assert!(error(process_message(input_to("dev"), bob_id, &state).await).is_none());
+        assert!(error(process_message(input_to("prod"), alice_id, &state).await).is_none());
+        assert!(error(process_message(kill("prod"), alice_id, &state).await).is_none());
+        assert!(error(process_message(kill("dev"), bob_id, &state).await).is_none());
+
+        // Only the user running the server may stop it
+        let denied = error(process_message(ClientMessage::Shutdown, bob_id, &state).await);
+        assert!(denied.is_some_and(|message| message.starts_with("Permission denied")));
+        state
+            .write()
+            .await
+            .clients
+            .get_mut(&alice_id)
+            .unwrap()
+            .set_owner();
+        assert!(error(process_message(ClientMessage::Shutdown, alice_id, &state).await).is_none());
+    }
+
+    #[tokio::test]
+    async fn shares_history_with_other_clients() {
+        let (event_tx, _event_rx) = mpsc::channel(8);
+        let (client1_tx, mut client1_rx) = mpsc::channel(8);
+        let (client2_tx, mut client2_rx) = mpsc::channel(8);
+        let client1 = ClientConnection::new(client1_tx);
+        let client1_id = client1.id();
+        let client2 = ClientConnection::new(client2_tx);
+        let client2_id = client2.id();
+
+        let state = Arc::new(RwLock::new(ServerState {
+            clients: HashMap::from([(client1_id, client1), (client2_id, client2)]),
+            ..test_state(event_tx)
+        }));
+
+        let append = ClientMessage::AppendHistory {
+            channel: "build".to_string(),
+            entry: "make".to_string(),
+        };
+        assert!(process_message(append, client1_id, &state).await.is_none());
+
+        match client2_rx.try_recv() {
+            Ok(ServerMessage::HistoryAppended { channel, entry }) => {
+                assert_eq!(channel, "build");
+                assert_eq!(entry, "make");
+            }
+            other => panic!("expected HistoryAppended, got {:?}", other),
+        }
+        assert!(
+            client1_rx.try_recv().is_err(),
+            "sender should not get its own entry back"
+        );
+
+        let response = process_message(
+            ClientMessage::GetHistory { channel: None },
+            client2_id,
+            &state,
+        )
+        .await;
+        match response {
+            Some(ServerMessage::History { histories }) => {
+                assert_eq!(histories.len(), 1);
+                assert_eq!(histories[0].channel, "build");
+                assert_eq!(histories[0].entries, vec!["make"]);
+            }
+            other => panic!("expected History, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn wall_reaches_every_client_that_understands_it() {
+        let (event_tx, _event_rx) = mpsc::channel(8);
+        let (sender_tx, mut sender_rx) = mpsc::channel(8);
+        let (other_tx, mut other_rx) = mpsc::channel(8);
+        let (old_tx, mut old_rx) = mpsc::channel(8);
+        let mut sender = ClientConnection::new(sender_tx);
+        sender.set_name("laptop".to_string());
+        sender.set_capabilities(&[Capability::Wall]);
+        let sender_id = sender.id();
+        let mut other = ClientConnection::new(other_tx);
+        other.set_capabilities(&[Capability::Wall]);
+        let other_id = other.id();
+        let old = ClientConnection::new(old_tx);
+        let old_id = old.id();
+
+        let state = Arc::new(RwLock::new(ServerState {
+            clients: HashMap::from([(sender_id, sender), (other_id, other), (old_id, old)]),
+            ..test_state(event_tx)
+        }));
+
+        let wall = |message: &str| ClientMessage::Wall {
+            message: message.to_string(),
+        };
+        assert!(matches!(
+            process_message(wall("  "), sender_id, &state).await,
+            Some(ServerMessage::Error { .. })
+        ));
+        assert!(matches!(
+            process_message(wall("restarting db in 1 min\n"), sender_id, &state).await,
+            Some(ServerMessage::Ack { .. })
+        ));
+
+        for rx in [&mut sender_rx, &mut other_rx] {
+            match rx.try_recv() {
+                Ok(ServerMessage::Wall { from, message }) => {
+                    assert_eq!(from, "laptop");
+                    assert_eq!(message, "restarting db in 1 min");
+                }
+                other => panic!("expected Wall, got {:?}", other),
+            }
+        }
+        assert!(
+            old_rx.try_recv().is_err(),
+            "clients that can't decode it should be skipped"
+        );
+    }
+
+    #[tokio::test]
+    async fn pins_are_shared_by_every_client() {
+        let (event_tx, _event_rx) = mpsc::channel(8);
+        let (pinner_tx, _pinner_rx) = mpsc::channel(8);
+        let (other_tx, mut other_rx) = mpsc::channel(8);
+        let pinner = ClientConnection::new(pinner_tx);
+        let pinner_id = pinner.id();
+        let mut other = ClientConnection::new(other_tx);
+        other.set_capabilities(&[Capability::Pins]);
+        let other_id = other.id();
+
+        let state = Arc::new(RwLock::new(ServerState {
+            clients: HashMap::from([(pinner_id, pinner), (other_id, other)]),
+            ..test_state(event_tx)
+        }));
+        for name in ["build", "logs"] {
+            state
+                .write()
+                .await
+                .channel_manager
+                .create_channel(ChannelConfig::new(name).with_command("/bin/cat"))
+                .await
+                .unwrap();
+        }
+
+        let pin = |channel: &str, pinned| ClientMessage::SetPinned {
+            channel: channel.to_string(),
+            pinned,
+        };
+        assert!(matches!(
+            process_message(pin("nope", true), pinner_id, &state).await,
+            Some(ServerMessage::Error { .. })
+        ));
+        for (channel, pinned) in [("logs", true), ("build", true), ("logs", false)] {
+            assert!(matches!(
+                process_message(pin(channel, pinned), pinner_id, &state).await,
+                Some(ServerMessage::Ack { .. })
+            ));
+        }
+
+        let mut changes = Vec::new();
+        while let Ok(msg) = other_rx.try_recv() {
+            if let ServerMessage::PinnedChanged { channels } = msg {
+                changes.push(channels);
+            }
+        }
+        assert_eq!(
+            changes,
+            vec![
+                vec!["logs".to_string()],
+                vec!["logs".to_string(), "build".to_string()],
+                vec!["build".to_string()],
+            ]
+        );
+
+        match process_message(ClientMessage::ListChannels, other_id, &state).await {
+            Some(ServerMessage::ChannelList { channels }) => {
+                let pinned: Vec<_> = channels
+                    .iter()
+                    .filter(|info| info.is_pinned)
+                    .map(|info| info.name.as_str())
+                    .collect();
+                assert_eq!(pinned, vec!["build"]);
+            }
+            other => panic!("expected ChannelList, got {:?}", other),
+        }
+
+        for name in ["build", "logs"] {
+            state
+                .write()
+                .await
+                .channel_manager
+                .kill_channel(name)
+                .await
+                .ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn marks_are_shared_with_supporting_clients() {
+        let (event_tx, _event_rx) = mpsc::channel(8);
+        let (marker_tx, _marker_rx) = mpsc::channel(8);
+        let (other_tx, mut other_rx) = mpsc::channel(8);
+        let marker = ClientConnection::new(marker_tx);
+        let marker_id = marker.id();
+        let mut other = ClientConnection::new(other_tx);
+        other.set_capabilities(&[Capability::Marks]);
+        let other_id = other.id();
+
+        let state = Arc::new(RwLock::new(ServerState {
+            clients: HashMap::from([(marker_id, marker), (other_id, other)]),
+            ..test_state(event_tx)
+        }));
+        state
+            .write()
+            .await
+            .channel_manager
+            .create_channel(ChannelConfig::new("deploy").with_command("/bin/cat"))
+            .await
+            .unwrap();
+
+        let mark = |channel: &str, name: &str, seq| ClientMessage::SetMark {
+            channel: channel.to_string(),
+            name: name.to_string(),
+            seq,
+        };
+        assert!(matches!(
+            process_message(mark("nope", "start", Some(1)), marker_id, &state).await,
+            Some(ServerMessage::Error { .. })
+        ));
+        for (name, seq) in [("start", Some(3)), ("end", Some(9)), ("end", None)] {
+            assert!(matches!(
+                process_message(mark("deploy", name, seq), marker_id, &state).await,
+                Some(ServerMessage::Ack { .. })
+            ));
+        }
+
+        let mut changes = Vec::new();
+        while let Ok(msg) = other_rx.try_recv() {
+            if let ServerMessage::MarkChanged { name, seq, .. } = msg {
+                changes.push((name, seq));
+            }
+        }
+        assert_eq!(
+            changes,
+            vec![
+                ("start".to_string(), Some(3)),
+                ("end".to_string(), Some(9)),
+                ("end".to_string(), None),
+            ]
+        );
+
+        match process_message(ClientMessage::ListMarks, other_id, &state).await {
+            Some(ServerMessage::Marks { marks }) => assert_eq!(
+                marks,
+                vec![SharedMark {
+                    channel: "deploy".to_string(),
+                    name: "start".to_string(),
+                    seq: 3,
+                }]
+            ),
+            other => panic!("expected Marks, got {:?}", other),
+        }
+
+        state
+            .write()
+            .await
+            .channel_manager
+            .kill_channel("deploy")
+            .await
+            .ok();
+    }
+
+    #[tokio::test]
+    async fn notifications_follow_each_clients_preferences() {
+        let (event_tx, _event_rx) = mpsc::channel(8);
+        let (dashboard_tx, mut dashboard_rx) = mpsc::channel(8);
+        let (other_tx, mut other_rx) = mpsc::channel(8);
+        let dashboard = ClientConnection::new(dashboard_tx);
+        let dashboard_id = dashboard.id();
+        let other = ClientConnection::new(other_tx);
+        let other_id = other.id();
+
+        let state = Arc::new(RwLock::new(ServerState {
+            clients: HashMap::from([(dashboard_id, dashboard), (other_id, other)]),
+            ..test_state(event_tx)
+        }));
+
+        let prefs = NotificationPrefs {
+            channels: vec!["build".to_string()],
+            kinds: vec![
+                NotificationKind::Output,
+                NotificationKind::Exit,
+                NotificationKind::Failure,
+            ],
+        };
+        let set = ClientMessage::SetNotifications { prefs };
+        assert!(matches!(
+            process_message(set, dashboard_id, &state).await,
+            Some(ServerMessage::Ack { .. })
+        ));
+
+        let output = |channel: &str| ChannelManagerEvent::Output {
+            channel_name: channel.to_string(),
+            data: b"line\n".to_vec(),
+            stream: OutputStream::Stdout,
+        };
+        // Output notifications are rate-limited, and only for watched channels
+        handle_channel_event(output("build"), &state).await;
+        handle_channel_event(output("build"), &state).await;
+        handle_channel_event(output("docs"), &state).await;
+        handle_channel_event(
+            ChannelManagerEvent::StateChanged {
+                channel_name: "build".to_string(),
+                state: crate::channel::ChannelState::Exited(Some(2)),
+            },
+            &state,
+        )
+        .await;
+
+        let mut kinds = Vec::new();
+        while let Ok(msg) = dashboard_rx.try_recv() {
+            if let ServerMessage::Notification { channel, kind, .. } = msg {
+                assert_eq!(channel, "build");
+                kinds.push(kind);
+            }
+        }
+        assert_eq!(
+            kinds,
+            vec![NotificationKind::Output, NotificationKind::Failure]
+        );
+        while let Ok(msg) = other_rx.try_recv() {
+            assert!(
+                !matches!(msg, ServerMessage::Notification { .. }),
+                "client without preferences got {:?}",
+                msg
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn renaming_moves_the_socket_and_notifies_clients() {
+        let temp_dir = tempdir().unwrap();
+        let socket_path = temp_dir.path().join("old.sock");
+        std::fs::write(&socket_path, "").unwrap();
+        std::fs::write(temp_dir.path().join("taken.sock"), "").unwrap();
+        let (event_tx, _event_rx) = mpsc::channel(8);
+        let (client_tx, mut client_rx) = mpsc::channel(16);
+        let client = ClientConnection::new(client_tx);
+        let client_id = client.id();
+
+        let state = Arc::new(RwLock::new(ServerState {
+            session: Session::new("old".to_string(), socket_path.clone()),
+            clients: HashMap::from([(client_id, client)]),
+            ..test_state(event_tx)
+        }));
+
+        let rename = |name: &str| ClientMessage::RenameSession {
+            name: name.to_string(),
+        };
+        assert!(matches!(
+            process_message(rename("taken"), client_id, &state).await,
+            Some(ServerMessage::Error { .. })
+        ));
+        assert!(matches!(
+            process_message(rename("new"), client_id, &state).await,
+            Some(ServerMessage::Ack { .. })
+        ));
+
+        assert!(!socket_path.exists());
+        assert!(temp_dir.path().join("new.sock").exists());
+        assert_eq!(state.read().await.session.name(), "new");
+        match client_rx.try_recv() {
+            Ok(ServerMessage::SessionRenamed { name }) => assert_eq!(name, "new"),
+            other => panic!("expected SessionRenamed, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn script_handlers_run_off_the_state_lock() {
+        let temp_dir = tempdir().unwrap();
+        let flag = temp_dir.path().join("release");
+        let scripts = ScriptEngine::new().unwrap();
+        scripts
+            .load_str(
+                "slow",
+                &format!(
+                    r#"nexus.on("output", function(channel)
+                         while io.open({:?}) == nil do end
+                       end)"#,
+                    flag.to_str().unwrap()
+                ),
+            )
+            .unwrap();
+
+        let (event_tx, _event_rx) = mpsc::channel(8);
+        let (script_tx, script_rx) = mpsc::channel(8);
+        let (client_tx, _client_rx) = mpsc::channel(8);
+        let client = ClientConnection::new(client_tx);
+        let client_id = client.id();
+        let state = Arc::new(RwLock::new(ServerState {
+            clients: HashMap::from([(client_id, client)]),
+            scripts: Arc::new(scripts),
+            script_events: script_tx,
+            ..test_state(event_tx)
+        }));
+        spawn_script_handlers(&state, script_rx);
+
+        // The handler spins until the flag file exists, and the server keeps
+        // answering meanwhile
+        let answered = tokio::time::timeout(std::time::Duration::from_secs(2), async {
+            handle_channel_event(
+                ChannelManagerEvent::Output {
+                    channel_name: "build".to_string(),
+                    data: b"hi\n".to_vec(),
+                    stream: OutputStream::Stdout,
+                },
+                &state,
+            )
+            .await;
+            process_message(ClientMessage::ListChannels, client_id, &state).await
+        })
+        .await;
+        std::fs::write(&flag, "").unwrap();
+        assert!(matches!(
+            answered,
+            Ok(Some(ServerMessage::ChannelList { .. }))
+        ));
+    }
 }
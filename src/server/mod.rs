@@ -1,9 +1,27 @@
 //! Server module - Unix socket listener and client connection handling
 
+mod auth;
+mod coalesce;
+mod completion;
 pub mod connection;
+pub mod daemon;
+mod embedded;
+mod history;
+mod hooks;
+mod host;
+mod http;
 mod listener;
+pub mod plugins;
+pub mod registry;
+pub mod scripting;
+mod scrollback;
 mod session;
+pub mod systemd;
+pub mod tls;
 
 pub use connection::ClientConnection;
+pub use embedded::EmbeddedServer;
+pub use history::{HistoryStore, MAX_HISTORY_ENTRIES};
+pub use host::SessionHost;
 pub use listener::ServerListener;
 pub use session::{Session, SessionInfo};
@@ -0,0 +1,419 @@
+//! WASM plugin host - sandboxed modules that react to channel events
+//!
+//! Plugins are loaded from `~/.config/nexus/plugins/*.wasm` and run in an
+//! interpreter with no WASI, a fuel limit per call and a memory cap, so a
+//! plugin can only see what the host hands it and can't hang the server or
+//! exhaust its memory.
+//!
+//! # ABI (version 1)
+//!
+//! A plugin module must export:
+//!
+//! - `memory`
+//! - `nexus_abi_version() -> i32`, returning [`ABI_VERSION`]
+//! - `nexus_alloc(len: i32) -> i32`, returning a buffer the host may write `len` bytes to
+//!
+//! Plugins subscribe to events by exporting any of:
+//!
+//! - `nexus_on_output(channel_ptr, channel_len, data_ptr, data_len)`
+//! - `nexus_on_exit(channel_ptr, channel_len, has_code, code)`
+//! - `nexus_on_created(channel_ptr, channel_len)`
+//!
+//! and may import from the `nexus` module:
+//!
+//! - `send_input(channel_ptr, channel_len, data_ptr, data_len)` - write to a channel
+//! - `log(ptr, len)` - write a line to the server log
+//!
+//! All pointers and lengths are `i32` offsets into the plugin's own memory;
+//! strings are UTF-8.
+
+use super::scripting::{ScriptAction, ScriptEvent};
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use wasmi::{
+    Caller, Engine, Extern, Instance, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
+
+/// Plugin ABI version implemented by this host
+pub const ABI_VERSION: i32 = 1;
+
+/// Instructions a plugin may execute per call
+const FUEL_PER_CALL: u64 = 10_000_000;
+
+/// Largest linear memory a plugin may grow to
+const MAX_MEMORY: usize = 64 * 1024 * 1024;
+
+/// Largest buffer a plugin may hand the host in one import call
+const MAX_READ: usize = 1024 * 1024;
+
+/// Per-plugin host state
+struct HostState {
+    name: String,
+    actions: Vec<ScriptAction>,
+    limits: StoreLimits,
+}
+
+/// A loaded plugin instance
+struct Plugin {
+    store: Store<HostState>,
+    instance: Instance,
+    memory: Memory,
+}
+
+impl Plugin {
+    fn load(engine: &Engine, name: &str, wasm: &[u8]) -> Result<Self> {
+        let module = Module::new(engine, wasm).map_err(|e| anyhow!("{}", e))?;
+        let mut store = Store::new(
+            engine,
+            HostState {
+                name: name.to_string(),
+                actions: Vec::new(),
+                limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build(),
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+
+        let mut linker = Linker::<HostState>::new(engine);
+        linker
+            .func_wrap(
+                "nexus",
+                "send_input",
+                |mut caller: Caller<'_, HostState>,
+                 channel_ptr: i32,
+                 channel_len: i32,
+                 data_ptr: i32,
+                 data_len: i32|
+                 -> Result<(), wasmi::Error> {
+                    let channel = read_string(&caller, channel_ptr, channel_len)?;
+                    let data = read_bytes(&caller, data_ptr, data_len)?;
+                    caller
+                        .data_mut()
+                        .actions
+                        .push(ScriptAction::SendInput { channel, data });
+                    Ok(())
+                },
+            )
+            .map_err(|e| anyhow!("{}", e))?;
+        linker
+            .func_wrap(
+                "nexus",
+                "log",
+                |caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<(), wasmi::Error> {
+                    let message = read_string(&caller, ptr, len)?;
+                    tracing::info!("[plugin {}] {}", caller.data().name, message);
+                    Ok(())
+                },
+            )
+            .map_err(|e| anyhow!("{}", e))?;
+
+        store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(|e| anyhow!("{}", e))?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|e| anyhow!("{}", e))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| anyhow!("plugin does not export 'memory'"))?;
+
+        let mut plugin = Self {
+            store,
+            instance,
+            memory,
+        };
+        let version: i32 = plugin.call("nexus_abi_version", ())?;
+        if version != ABI_VERSION {
+            bail!(
+                "plugin ABI version {} is not supported (expected {})",
+                version,
+                ABI_VERSION
+            );
+        }
+        Ok(plugin)
+    }
+
+    fn has_export(&self, name: &str) -> bool {
+        self.instance.get_func(&self.store, name).is_some()
+    }
+
+    /// Call an export with a fresh fuel allowance
+    fn call<Params, Results>(&mut self, name: &str, params: Params) -> Result<Results>
+    where
+        Params: wasmi::WasmParams,
+        Results: wasmi::WasmResults,
+    {
+        self.store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(|e| anyhow!("{}", e))?;
+        let func = self
+            .instance
+            .get_typed_func::<Params, Results>(&self.store, name)
+            .map_err(|e| anyhow!("{}: {}", name, e))?;
+        func.call(&mut self.store, params)
+            .map_err(|e| anyhow!("{}: {}", name, e))
+    }
+
+    /// Copy bytes into plugin memory via its allocator
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(i32, i32)> {
+        let len = i32::try_from(bytes.len()).context("buffer too large for plugin")?;
+        let ptr: i32 = self.call("nexus_alloc", len)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, bytes)
+            .map_err(|e| anyhow!("nexus_alloc returned an invalid buffer: {}", e))?;
+        Ok((ptr, len))
+    }
+
+    fn handle(&mut self, event: ScriptEvent<'_>) -> Result<Vec<ScriptAction>> {
+        let export = match event {
+            ScriptEvent::Output { .. } => "nexus_on_output",
+            ScriptEvent::Exited { .. } => "nexus_on_exit",
+            ScriptEvent::Created { .. } => "nexus_on_created",
+        };
+        if !self.has_export(export) {
+            return Ok(Vec::new());
+        }
+        // Drop anything left behind by a call that trapped
+        self.store.data_mut().actions.clear();
+
+        match event {
+            ScriptEvent::Output { channel, data } => {
+                let (channel_ptr, channel_len) = self.write_bytes(channel.as_bytes())?;
+                let (data_ptr, data_len) = self.write_bytes(data)?;
+                self.call::<_, ()>(export, (channel_ptr, channel_len, data_ptr, data_len))?;
+            }
+            ScriptEvent::Exited { channel, exit_code } => {
+                let (channel_ptr, channel_len) = self.write_bytes(channel.as_bytes())?;
+                let has_code = i32::from(exit_code.is_some());
+                self.call::<_, ()>(
+                    export,
+                    (channel_ptr, channel_len, has_code, exit_code.unwrap_or(0)),
+                )?;
+            }
+            ScriptEvent::Created { channel } => {
+                let (channel_ptr, channel_len) = self.write_bytes(channel.as_bytes())?;
+                self.call::<_, ()>(export, (channel_ptr, channel_len))?;
+            }
+        }
+        Ok(std::mem::take(&mut self.store.data_mut().actions))
+    }
+}
+
+fn read_bytes(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<Vec<u8>, wasmi::Error> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("plugin does not export 'memory'"))?;
+    let len = usize::try_from(len).map_err(|_| wasmi::Error::new("negative buffer length"))?;
+    if len > MAX_READ {
+        return Err(wasmi::Error::new(format!(
+            "buffer of {} bytes exceeds the {} byte limit",
+            len, MAX_READ
+        )));
+    }
+    // Bounds-check against the memory before copying anything
+    let start = ptr as u32 as usize;
+    start
+        .checked_add(len)
+        .and_then(|end| memory.data(caller).get(start..end))
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| wasmi::Error::new("buffer is outside plugin memory"))
+}
+
+fn read_string(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<String, wasmi::Error> {
+    String::from_utf8(read_bytes(caller, ptr, len)?)
+        .map_err(|_| wasmi::Error::new("string is not valid UTF-8"))
+}
+
+/// All loaded plugins
+pub struct PluginHost {
+    engine: Engine,
+    plugins: Vec<(String, Mutex<Plugin>)>,
+}
+
+impl PluginHost {
+    pub fn new() -> Self {
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        Self {
+            engine: Engine::new(&config),
+            plugins: Vec::new(),
+        }
+    }
+
+    /// Load every `*.wasm` file in `dir`, returning per-plugin errors
+    pub fn load_dir(&mut self, dir: &Path) -> Vec<String> {
+        let mut paths: Vec<PathBuf> = match std::fs::read_dir(dir) {
+            Ok(entries) => entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "wasm"))
+                .collect(),
+            Err(_) => return Vec::new(),
+        };
+        paths.sort();
+
+        paths
+            .iter()
+            .filter_map(|path| {
+                let name = path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let result = std::fs::read(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))
+                    .and_then(|wasm| self.load(&name, &wasm));
+                result.err().map(|e| format!("{}: {}", path.display(), e))
+            })
+            .collect()
+    }
+
+    /// Load a plugin from module bytes
+    pub fn load(&mut self, name: &str, wasm: &[u8]) -> Result<()> {
+        let plugin = Plugin::load(&self.engine, name, wasm)?;
+        self.plugins.push((name.to_string(), Mutex::new(plugin)));
+        Ok(())
+    }
+
+    /// Names of loaded plugins
+    pub fn names(&self) -> Vec<&str> {
+        self.plugins.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Deliver an event to every subscribed plugin, returning the actions they queued.
+    ///
+    /// A plugin that traps or runs out of fuel is logged and skipped.
+    pub fn emit(&self, event: ScriptEvent<'_>) -> Vec<ScriptAction> {
+        let mut actions = Vec::new();
+        for (name, plugin) in &self.plugins {
+            match plugin.lock().unwrap().handle(event) {
+                Ok(queued) => actions.extend(queued),
+                Err(e) => tracing::warn!("Plugin {} failed: {}", name, e),
+            }
+        }
+        actions
+    }
+}
+
+impl Default for PluginHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Echoes each created channel's name back into it and loops forever on exit
+    const PLUGIN: &str = r#"
+        (module
+          (import "nexus" "send_input" (func $send_input (param i32 i32 i32 i32)))
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (func (export "nexus_abi_version") (result i32) (i32.const 1))
+          (func (export "nexus_alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "nexus_on_created") (param $ptr i32) (param $len i32)
+            (call $send_input (local.get $ptr) (local.get $len) (local.get $ptr) (local.get $len)))
+          (func (export "nexus_on_exit") (param i32 i32 i32 i32)
+            (loop $forever (br $forever))))
+    "#;
+
+    #[test]
+    fn delivers_events_and_collects_actions() {
+        let mut host = PluginHost::new();
+        host.load("echo", &wat::parse_str(PLUGIN).unwrap()).unwrap();
+        assert_eq!(host.names(), vec!["echo"]);
+
+        let actions = host.emit(ScriptEvent::Created { channel: "build" });
+        assert_eq!(
+            actions,
+            vec![ScriptAction::SendInput {
+                channel: "build".to_string(),
+                data: b"build".to_vec(),
+            }]
+        );
+
+        // Not subscribed to output
+        assert!(host
+            .emit(ScriptEvent::Output {
+                channel: "build",
+                data: b"hi",
+            })
+            .is_empty());
+
+        // Runs out of fuel instead of hanging; the plugin stays usable
+        assert!(host
+            .emit(ScriptEvent::Exited {
+                channel: "build",
+                exit_code: Some(1),
+            })
+            .is_empty());
+        assert_eq!(host.emit(ScriptEvent::Created { channel: "x" }).len(), 1);
+    }
+
+    #[test]
+    fn rejects_unsupported_abi_versions() {
+        let module = wat::parse_str(
+            r#"(module
+                 (memory (export "memory") 1)
+                 (func (export "nexus_abi_version") (result i32) (i32.const 99)))"#,
+        )
+        .unwrap();
+        let mut host = PluginHost::new();
+        let error = host.load("future", &module).unwrap_err();
+        assert!(error.to_string().contains("ABI version 99"));
+    }
+
+    #[test]
+    fn rejects_oversized_buffers() {
+        let module = wat::parse_str(
+            r#"(module
+                 (import "nexus" "send_input" (func $send_input (param i32 i32 i32 i32)))
+                 (memory (export "memory") 1)
+                 (func (export "nexus_abi_version") (result i32) (i32.const 1))
+                 (func (export "nexus_alloc") (param i32) (result i32) (i32.const 1024))
+                 (func (export "nexus_on_created") (param $ptr i32) (param $len i32)
+                   (call $send_input (local.get $ptr) (local.get $len)
+                     (i32.const 0) (i32.const 0x7fffffff))))"#,
+        )
+        .unwrap();
+        let mut host = PluginHost::new();
+        host.load("greedy", &module).unwrap();
+
+        // The call fails without the host allocating what the plugin asked for
+        assert!(host
+            .emit(ScriptEvent::Created { channel: "build" })
+            .is_empty());
+    }
+
+    #[test]
+    fn caps_memory_growth() {
+        let module = wat::parse_str(
+            r#"(module
+                 (import "nexus" "send_input" (func $send_input (param i32 i32 i32 i32)))
+                 (memory (export "memory") 1)
+                 (func (export "nexus_abi_version") (result i32) (i32.const 1))
+                 (func (export "nexus_alloc") (param i32) (result i32) (i32.const 1024))
+                 (func (export "nexus_on_created") (param $ptr i32) (param $len i32)
+                   ;; 2048 pages is 128 MiB
+                   (if (i32.eq (memory.grow (i32.const 2048)) (i32.const -1))
+                     (then (call $send_input (local.get $ptr) (local.get $len)
+                       (local.get $ptr) (local.get $len))))))"#,
+        )
+        .unwrap();
+        let mut host = PluginHost::new();
+        host.load("grower", &module).unwrap();
+
+        assert_eq!(
+            host.emit(ScriptEvent::Created { channel: "build" }).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,64 @@
+//! Session registry - live session metadata next to each socket
+//!
+//! Every server keeps `<session>.info` in the runtime directory up to date
+//! with its [`SessionInfo`], so `nexus list` can describe sessions without
+//! connecting to each one. The file goes away with the server; one left by a
+//! crashed server is ignored once its PID file shows the server is gone.
+
+use super::daemon;
+use super::session::SessionInfo;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Registry entry path for the server listening on `socket_path`
+pub fn info_path(socket_path: &Path) -> PathBuf {
+    socket_path.with_extension("info")
+}
+
+/// Write `info` to its registry entry, replacing it atomically so readers
+/// never see a partial file
+pub fn publish(info: &SessionInfo) -> Result<()> {
+    let path = info_path(&info.socket_path);
+    let staging = path.with_extension("info.tmp");
+    std::fs::write(&staging, serde_json::to_vec(info)?)
+        .with_context(|| format!("Failed to write {}", staging.display()))?;
+    std::fs::rename(&staging, &path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Registered info for the session at `socket_path`, if a live server
+/// published it
+pub fn lookup(socket_path: &Path) -> Option<SessionInfo> {
+    let pid = daemon::read_pid(&daemon::pid_path(socket_path))?;
+    if !daemon::is_server_running(pid) {
+        return None;
+    }
+    read(&info_path(socket_path))
+}
+
+/// Parse a registry entry
+fn read(path: &Path) -> Option<SessionInfo> {
+    serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::session::Session;
+
+    #[test]
+    fn publishes_entries_for_live_servers() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("work.sock");
+        let mut session = Session::new("work".to_string(), socket_path.clone());
+        session.add_client(uuid::Uuid::new_v4());
+        publish(&session.info).unwrap();
+
+        let info = read(&info_path(&socket_path)).unwrap();
+        assert_eq!(info.name, "work");
+        assert_eq!(info.client_count, 1);
+        assert_eq!(info.created_at, session.info.created_at);
+
+        // Without a PID file nothing vouches for the entry
+        assert!(lookup(&socket_path).is_none());
+    }
+}
@@ -0,0 +1,450 @@
+//! Lua scripting - user scripts that react to channel events and add commands
+//!
+//! Scripts are loaded from `~/.config/nexus/scripts/*.lua` and use a global
+//! `nexus` table:
+//!
+//! ```lua
+//! nexus.on("exit", function(channel, code)
+//!   if code ~= 0 then nexus.send("shell", "echo " .. channel .. " failed\n") end
+//! end)
+//!
+//! nexus.command("greet", function(args)
+//!   return "hello " .. (args[1] or "world")
+//! end)
+//! ```
+//!
+//! Lua callbacks never touch server state directly; `nexus.send` queues a
+//! [`ScriptAction`] that the server carries out after the handler returns.
+//! Each call into Lua gets an instruction budget, so a runaway handler is
+//! stopped with an error instead of hanging the server.
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use mlua::{Function, HookTriggers, Lua, Table, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Instructions a script may execute per call
+const INSTRUCTIONS_PER_CALL: u32 = 10_000_000;
+
+/// How often (in instructions) the budget is checked
+const BUDGET_CHECK_INTERVAL: u32 = 10_000;
+
+/// Lua half of the `nexus` API: handler and command registries
+const PRELUDE: &str = r#"
+nexus._handlers = { output = {}, exit = {}, created = {} }
+nexus._commands = {}
+
+function nexus.on(event, handler)
+  local handlers = nexus._handlers[event]
+  if handlers == nil then
+    error("unknown event '" .. tostring(event) .. "' (expected output, exit or created)")
+  end
+  table.insert(handlers, handler)
+end
+
+function nexus.command(name, handler)
+  nexus._commands[name] = handler
+end
+"#;
+
+/// Something a script asked the server to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptAction {
+    /// Write input to a channel
+    SendInput { channel: String, data: Vec<u8> },
+}
+
+/// Channel events delivered to script handlers
+#[derive(Debug, Clone, Copy)]
+pub enum ScriptEvent<'a> {
+    Output {
+        channel: &'a str,
+        data: &'a [u8],
+    },
+    Exited {
+        channel: &'a str,
+        exit_code: Option<i32>,
+    },
+    Created {
+        channel: &'a str,
+    },
+}
+
+/// A [`ScriptEvent`] that owns its data, for queueing until handlers run
+#[derive(Debug, Clone)]
+pub enum QueuedEvent {
+    Output {
+        channel: String,
+        data: Bytes,
+    },
+    Exited {
+        channel: String,
+        exit_code: Option<i32>,
+    },
+    Created {
+        channel: String,
+    },
+}
+
+impl QueuedEvent {
+    pub fn as_event(&self) -> ScriptEvent<'_> {
+        match self {
+            Self::Output { channel, data } => ScriptEvent::Output { channel, data },
+            Self::Exited { channel, exit_code } => ScriptEvent::Exited {
+                channel,
+                exit_code: *exit_code,
+            },
+            Self::Created { channel } => ScriptEvent::Created { channel },
+        }
+    }
+}
+
+/// Embedded Lua runtime holding all loaded scripts
+pub struct ScriptEngine {
+    lua: Mutex<Lua>,
+    actions: Arc<Mutex<Vec<ScriptAction>>>,
+    /// Budget checks left for the current call
+    budget: Arc<AtomicU32>,
+    scripts_dir: Option<PathBuf>,
+}
+
+impl ScriptEngine {
+    /// Create an engine with no scripts loaded
+    pub fn new() -> Result<Self> {
+        let actions = Arc::new(Mutex::new(Vec::new()));
+        let budget = Arc::new(AtomicU32::new(0));
+        let lua = Self::create_runtime(Arc::clone(&actions), Arc::clone(&budget))?;
+        Ok(Self {
+            lua: Mutex::new(lua),
+            actions,
+            budget,
+            scripts_dir: None,
+        })
+    }
+
+    fn create_runtime(
+        actions: Arc<Mutex<Vec<ScriptAction>>>,
+        budget: Arc<AtomicU32>,
+    ) -> Result<Lua> {
+        let lua = Lua::new();
+        // Fails every check once the budget is spent, until the next call
+        // refills it
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(BUDGET_CHECK_INTERVAL),
+            move |_, _| match budget.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                left.checked_sub(1)
+            }) {
+                Ok(_) => Ok(()),
+                Err(_) => Err(mlua::Error::RuntimeError(format!(
+                    "script ran past {} instructions",
+                    INSTRUCTIONS_PER_CALL
+                ))),
+            },
+        );
+        {
+            let nexus = lua.create_table().map_err(lua_error)?;
+
+            let send = lua
+                .create_function(move |_, (channel, data): (String, mlua::String)| {
+                    actions.lock().unwrap().push(ScriptAction::SendInput {
+                        channel,
+                        data: data.as_bytes().to_vec(),
+                    });
+                    Ok(())
+                })
+                .map_err(lua_error)?;
+            nexus.set("send", send).map_err(lua_error)?;
+
+            let log = lua
+                .create_function(|_, message: String| {
+                    tracing::info!("[script] {}", message);
+                    Ok(())
+                })
+                .map_err(lua_error)?;
+            nexus.set("log", log).map_err(lua_error)?;
+
+            lua.globals().set("nexus", nexus).map_err(lua_error)?;
+            lua.load(PRELUDE)
+                .set_name("prelude")
+                .exec()
+                .map_err(lua_error)?;
+        }
+        Ok(lua)
+    }
+
+    /// Load every `*.lua` file in `dir` (in name order), remembering the
+    /// directory for [`reload`](Self::reload). Returns per-script errors;
+    /// a broken script doesn't stop the others from loading.
+    pub fn load_dir(&mut self, dir: &Path) -> Vec<String> {
+        self.scripts_dir = Some(dir.to_path_buf());
+        self.load_files(dir)
+    }
+
+    fn load_files(&self, dir: &Path) -> Vec<String> {
+        let mut paths: Vec<PathBuf> = match std::fs::read_dir(dir) {
+            Ok(entries) => entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "lua"))
+                .collect(),
+            Err(_) => return Vec::new(),
+        };
+        paths.sort();
+
+        paths
+            .iter()
+            .filter_map(|path| {
+                let result = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))
+                    .and_then(|source| self.load_str(&path.to_string_lossy(), &source));
+                result.err().map(|e| format!("{}: {}", path.display(), e))
+            })
+            .collect()
+    }
+
+    /// Run a script from source
+    pub fn load_str(&self, name: &str, source: &str) -> Result<()> {
+        let lua = self.lua.lock().unwrap();
+        self.refill_budget();
+        lua.load(source).set_name(name).exec().map_err(lua_error)
+    }
+
+    /// Give the next call into Lua a fresh instruction budget
+    fn refill_budget(&self) {
+        self.budget.store(
+            INSTRUCTIONS_PER_CALL / BUDGET_CHECK_INTERVAL,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Start over with a fresh runtime and reload the scripts directory
+    pub fn reload(&self) -> Result<Vec<String>> {
+        *self.lua.lock().unwrap() =
+            Self::create_runtime(Arc::clone(&self.actions), Arc::clone(&self.budget))?;
+        self.actions.lock().unwrap().clear();
+        Ok(match &self.scripts_dir {
+            Some(dir) => self.load_files(dir),
+            None => Vec::new(),
+        })
+    }
+
+    /// Call every handler registered for `event`, returning the actions they queued.
+    ///
+    /// Handler errors are logged and don't stop later handlers.
+    pub fn emit(&self, event: ScriptEvent<'_>) -> Vec<ScriptAction> {
+        {
+            let lua = self.lua.lock().unwrap();
+            let result = (|| -> mlua::Result<()> {
+                let (name, args) = match event {
+                    ScriptEvent::Output { channel, data } => {
+                        ("output", (channel, Value::String(lua.create_string(data)?)))
+                    }
+                    ScriptEvent::Exited { channel, exit_code } => (
+                        "exit",
+                        (
+                            channel,
+                            exit_code
+                                .map(|code| Value::Integer(code.into()))
+                                .unwrap_or(Value::Nil),
+                        ),
+                    ),
+                    ScriptEvent::Created { channel } => ("created", (channel, Value::Nil)),
+                };
+                let handlers: Table = handlers_table(&lua)?.get(name)?;
+                for handler in handlers.sequence_values::<Function>() {
+                    self.refill_budget();
+                    if let Err(e) = handler?.call::<_, ()>(args.clone()) {
+                        tracing::warn!("Script {} handler failed: {}", name, e);
+                    }
+                }
+                Ok(())
+            })();
+            if let Err(e) = result {
+                tracing::warn!("Failed to dispatch script event: {}", e);
+            }
+        }
+        self.take_actions()
+    }
+
+    /// Whether a script registered a command with this name
+    pub fn has_command(&self, name: &str) -> bool {
+        let lua = self.lua.lock().unwrap();
+        commands_table(&lua)
+            .and_then(|commands| commands.contains_key(name))
+            .unwrap_or(false)
+    }
+
+    /// Run a script command, returning its message (if it returned one) and
+    /// the actions it queued
+    pub fn run_command(
+        &self,
+        name: &str,
+        args: &[String],
+    ) -> Result<(Option<String>, Vec<ScriptAction>)> {
+        let message = {
+            let lua = self.lua.lock().unwrap();
+            let handler: Option<Function> = commands_table(&lua)
+                .and_then(|commands| commands.get(name))
+                .map_err(lua_error)?;
+            let handler = handler.ok_or_else(|| anyhow!("Unknown command: {}", name))?;
+            self.refill_budget();
+            let result: Value = handler.call(args.to_vec()).map_err(lua_error)?;
+            match result {
+                Value::Nil => None,
+                other => Some(
+                    lua.coerce_string(other)
+                        .map_err(lua_error)?
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                ),
+            }
+        };
+        Ok((message, self.take_actions()))
+    }
+
+    fn take_actions(&self) -> Vec<ScriptAction> {
+        std::mem::take(&mut *self.actions.lock().unwrap())
+    }
+}
+
+fn nexus_table(lua: &Lua) -> mlua::Result<Table<'_>> {
+    lua.globals().get("nexus")
+}
+
+fn handlers_table(lua: &Lua) -> mlua::Result<Table<'_>> {
+    nexus_table(lua)?.get("_handlers")
+}
+
+fn commands_table(lua: &Lua) -> mlua::Result<Table<'_>> {
+    nexus_table(lua)?.get("_commands")
+}
+
+/// mlua errors aren't `Sync`, so carry them over as text
+fn lua_error(e: mlua::Error) -> anyhow::Error {
+    anyhow!("{}", e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn handlers_queue_actions() {
+        let engine = ScriptEngine::new().unwrap();
+        engine
+            .load_str(
+                "test",
+                r#"
+                nexus.on("output", function(channel, data)
+                  if data:find("error") then nexus.send("alerts", channel .. " failed\n") end
+                end)
+                nexus.on("exit", function(channel, code)
+                  nexus.send(channel, "exit " .. tostring(code))
+                end)
+                "#,
+            )
+            .unwrap();
+
+        let actions = engine.emit(ScriptEvent::Output {
+            channel: "build",
+            data: b"compile error: oops",
+        });
+        assert_eq!(
+            actions,
+            vec![ScriptAction::SendInput {
+                channel: "alerts".to_string(),
+                data: b"build failed\n".to_vec(),
+            }]
+        );
+        assert!(engine
+            .emit(ScriptEvent::Output {
+                channel: "build",
+                data: b"ok",
+            })
+            .is_empty());
+
+        let actions = engine.emit(ScriptEvent::Exited {
+            channel: "build",
+            exit_code: Some(2),
+        });
+        assert_eq!(
+            actions,
+            vec![ScriptAction::SendInput {
+                channel: "build".to_string(),
+                data: b"exit 2".to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn runs_registered_commands() {
+        let engine = ScriptEngine::new().unwrap();
+        engine
+            .load_str(
+                "test",
+                r#"nexus.command("greet", function(args) return "hello " .. (args[1] or "world") end)"#,
+            )
+            .unwrap();
+
+        assert!(engine.has_command("greet"));
+        assert!(!engine.has_command("missing"));
+
+        let (message, actions) = engine.run_command("greet", &["nexus".to_string()]).unwrap();
+        assert_eq!(message.as_deref(), Some("hello nexus"));
+        assert!(actions.is_empty());
+        assert!(engine.run_command("missing", &[]).is_err());
+        assert!(engine.load_str("bad", "nexus.on('nope', print)").is_err());
+    }
+
+    #[test]
+    fn runaway_handlers_are_interrupted() {
+        let engine = ScriptEngine::new().unwrap();
+        engine
+            .load_str(
+                "test",
+                r#"
+                nexus.on("created", function(channel)
+                  while true do end
+                end)
+                nexus.on("created", function(channel) nexus.send(channel, "hi") end)
+                nexus.command("spin", function() while true do end end)
+                "#,
+            )
+            .unwrap();
+
+        // The looping handler is stopped and the next one still runs
+        assert_eq!(
+            engine.emit(ScriptEvent::Created { channel: "x" }),
+            vec![ScriptAction::SendInput {
+                channel: "x".to_string(),
+                data: b"hi".to_vec(),
+            }]
+        );
+        let error = engine.run_command("spin", &[]).unwrap_err();
+        assert!(error.to_string().contains("instructions"), "{}", error);
+        assert!(engine.load_str("spin", "while true do end").is_err());
+    }
+
+    #[test]
+    fn loads_and_reloads_scripts_dir() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("a.lua"),
+            "nexus.command('a', function() end)",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("broken.lua"), "this is not lua").unwrap();
+
+        let mut engine = ScriptEngine::new().unwrap();
+        let errors = engine.load_dir(dir.path());
+        assert_eq!(errors.len(), 1);
+        assert!(engine.has_command("a"));
+
+        std::fs::remove_file(dir.path().join("a.lua")).unwrap();
+        engine.reload().unwrap();
+        assert!(!engine.has_command("a"));
+    }
+}
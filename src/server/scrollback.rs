@@ -0,0 +1,528 @@
+//! Each channel's output kept for replaying to clients. Older output is
+//! compressed in blocks, so channels that log for days stay small, and with
+//! `general.spill_scrollback` what no longer fits in memory is appended to a
+//! file instead of dropped.
+
+use crate::channel::OutputStream;
+use crate::config::GeneralConfig;
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Chunks kept as they are; past this the oldest are compressed, or
+/// dropped when compression is off
+const MAX_RECENT_CHUNKS: usize = 200;
+
+/// Chunks compressed together into one block
+const BLOCK_CHUNKS: usize = 100;
+
+/// zstd level: fast, and still shrinks logs several times over
+const COMPRESSION_LEVEL: i32 = 3;
+
+#[derive(Clone)]
+pub(super) struct BufferedOutput {
+    pub(super) data: Bytes,
+    pub(super) timestamp: i64,
+    pub(super) stream: OutputStream,
+    pub(super) seq: u64,
+}
+
+impl BufferedOutput {
+    fn lines(&self) -> usize {
+        self.data.iter().filter(|&&b| b == b'\n').count()
+    }
+}
+
+/// How much scrollback to keep, from `[general]`
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Limits {
+    pub(super) lines: usize,
+    /// Bytes held in memory, counting compressed blocks at their
+    /// compressed size
+    pub(super) bytes: usize,
+    pub(super) compress: bool,
+}
+
+impl From<&GeneralConfig> for Limits {
+    fn from(general: &GeneralConfig) -> Self {
+        Self {
+            lines: general.history_limit,
+            bytes: general.history_bytes,
+            compress: general.compress_scrollback,
+        }
+    }
+}
+
+/// Chunks compressed together, with what's needed to split them apart again
+struct Block {
+    /// The chunks' data back to back, compressed
+    data: Vec<u8>,
+    /// Length, timestamp, stream and sequence number of each chunk
+    chunks: Vec<(usize, i64, OutputStream, u64)>,
+    lines: usize,
+}
+
+impl Block {
+    fn compress(entries: &[BufferedOutput]) -> std::io::Result<Self> {
+        let raw: Vec<u8> = entries
+            .iter()
+            .flat_map(|entry| entry.data.iter().copied())
+            .collect();
+        Ok(Self {
+            data: zstd::bulk::compress(&raw, COMPRESSION_LEVEL)?,
+            chunks: entries
+                .iter()
+                .map(|e| (e.data.len(), e.timestamp, e.stream, e.seq))
+                .collect(),
+            lines: entries.iter().map(BufferedOutput::lines).sum(),
+        })
+    }
+
+    fn decompress(&self) -> Vec<BufferedOutput> {
+        let size = self.chunks.iter().map(|(len, ..)| len).sum();
+        let raw = match zstd::bulk::decompress(&self.data, size) {
+            Ok(raw) => Bytes::from(raw),
+            Err(e) => {
+                tracing::warn!("Failed to decompress scrollback: {}", e);
+                return Vec::new();
+            }
+        };
+        let mut offset = 0;
+        self.chunks
+            .iter()
+            .map(|&(len, timestamp, stream, seq)| {
+                offset += len;
+                BufferedOutput {
+                    data: raw.slice(offset - len..offset),
+                    timestamp,
+                    stream,
+                    seq,
+                }
+            })
+            .collect()
+    }
+
+    fn last_seq(&self) -> u64 {
+        self.chunks.last().map_or(0, |&(.., seq)| seq)
+    }
+}
+
+/// Bytes before each chunk in a spill file: sequence number, timestamp,
+/// stream and data length
+const SPILL_HEADER: usize = 8 + 8 + 1 + 4;
+
+/// Append-only file of a channel's output evicted from memory
+struct Spill {
+    path: PathBuf,
+    file: File,
+}
+
+impl Spill {
+    /// Start an empty spill file at `path`, replacing any left behind
+    fn create(path: PathBuf) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let file = File::options()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .mode(0o600)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&path)?;
+        Ok(Self { path, file })
+    }
+
+    fn append(&mut self, entries: &[BufferedOutput]) -> io::Result<()> {
+        let mut out = Vec::new();
+        for entry in entries {
+            out.extend_from_slice(&entry.seq.to_le_bytes());
+            out.extend_from_slice(&entry.timestamp.to_le_bytes());
+            out.push(match entry.stream {
+                OutputStream::Stdout => 0,
+                OutputStream::Stderr => 1,
+            });
+            out.extend_from_slice(&(entry.data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&entry.data);
+        }
+        self.file.write_all(&out)
+    }
+
+    /// Map the file, or `None` while it is empty
+    fn map(&self) -> io::Result<Option<memmap2::Mmap>> {
+        if self.file.metadata()?.len() == 0 {
+            return Ok(None);
+        }
+        // SAFETY: the file is only ever appended to, by this process, so
+        // the mapped bytes don't change under us
+        unsafe { memmap2::Mmap::map(&self.file) }.map(Some)
+    }
+
+    /// Chunks after sequence number `seq`
+    fn since(&self, seq: u64) -> io::Result<Vec<BufferedOutput>> {
+        let Some(map) = self.map()? else {
+            return Ok(Vec::new());
+        };
+        Ok(spilled_chunks(&map)
+            .into_iter()
+            .filter(|chunk| chunk.seq > seq)
+            .map(|chunk| chunk.read(&map))
+            .collect())
+    }
+
+    /// The newest chunks holding at least `lines` lines, copying nothing
+    /// older
+    fn covering(&self, lines: usize) -> io::Result<Vec<BufferedOutput>> {
+        let Some(map) = self.map()? else {
+            return Ok(Vec::new());
+        };
+        let chunks = spilled_chunks(&map);
+        let mut counted = 0;
+        let first = chunks
+            .iter()
+            .rposition(|chunk| {
+                counted += map[chunk.data.clone()]
+                    .iter()
+                    .filter(|&&b| b == b'\n')
+                    .count();
+                counted >= lines
+            })
+            .unwrap_or(0);
+        Ok(chunks[first..]
+            .iter()
+            .map(|chunk| chunk.read(&map))
+            .collect())
+    }
+}
+
+/// Where a chunk sits in a mapped spill file
+struct SpilledChunk {
+    seq: u64,
+    timestamp: i64,
+    stream: OutputStream,
+    data: Range<usize>,
+}
+
+impl SpilledChunk {
+    fn read(&self, map: &[u8]) -> BufferedOutput {
+        BufferedOutput {
+            data: Bytes::copy_from_slice(&map[self.data.clone()]),
+            timestamp: self.timestamp,
+            stream: self.stream,
+            seq: self.seq,
+        }
+    }
+}
+
+/// Every whole chunk in a mapped spill file, without copying their data
+fn spilled_chunks(map: &[u8]) -> Vec<SpilledChunk> {
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+    while map.len() - offset >= SPILL_HEADER {
+        let header = &map[offset..offset + SPILL_HEADER];
+        let number =
+            |range: Range<usize>| -> [u8; 8] { header[range].try_into().expect("eight bytes") };
+        let len = u32::from_le_bytes(header[17..21].try_into().expect("four bytes")) as usize;
+        let data = offset + SPILL_HEADER..offset + SPILL_HEADER + len;
+        if data.end > map.len() {
+            break;
+        }
+        chunks.push(SpilledChunk {
+            seq: u64::from_le_bytes(number(0..8)),
+            timestamp: i64::from_le_bytes(number(8..16)),
+            stream: match header[16] {
+                0 => OutputStream::Stdout,
+                _ => OutputStream::Stderr,
+            },
+            data: data.clone(),
+        });
+        offset = data.end;
+    }
+    chunks
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// File name for a channel's spill file, with anything but letters, digits,
+/// `-` and `_` escaped
+pub(super) fn spill_file_name(channel: &str) -> String {
+    let mut name = String::new();
+    for byte in channel.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => name.push(byte as char),
+            _ => name.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    name + ".spill"
+}
+
+/// A channel's output, oldest first
+#[derive(Default)]
+pub(super) struct Scrollback {
+    /// Older output, compressed
+    blocks: VecDeque<Block>,
+    /// Newer output, as it came
+    recent: VecDeque<BufferedOutput>,
+    lines: usize,
+    /// Bytes held, counting blocks at their compressed size
+    bytes: usize,
+    /// Where output evicted from memory goes, if it is kept
+    spill: Option<Spill>,
+}
+
+impl Scrollback {
+    /// Scrollback that appends what it evicts to a file at `path`
+    pub(super) fn spilling_to(path: &Path) -> Self {
+        let spill = Spill::create(path.to_path_buf())
+            .map_err(|e| tracing::warn!("Failed to create {}: {}", path.display(), e))
+            .ok();
+        Self {
+            spill,
+            ..Self::default()
+        }
+    }
+
+    /// Keep evicted chunks in the spill file, if there is one
+    fn evict(&mut self, entries: &[BufferedOutput]) {
+        if let Some(spill) = &mut self.spill {
+            if let Err(e) = spill.append(entries) {
+                tracing::warn!("Failed to spill scrollback, dropping it: {}", e);
+                self.spill = None;
+            }
+        }
+    }
+
+    /// Add a chunk and trim to `limits`
+    pub(super) fn push(&mut self, entry: BufferedOutput, limits: Limits) {
+        self.lines += entry.lines();
+        self.bytes += entry.data.len();
+        self.recent.push_back(entry);
+        self.trim(limits);
+    }
+
+    /// Compress or drop the oldest chunks past the uncompressed cap, then
+    /// drop the oldest output while over the line or byte limit, keeping at
+    /// least the newest chunk
+    pub(super) fn trim(&mut self, limits: Limits) {
+        if limits.compress && self.recent.len() > MAX_RECENT_CHUNKS {
+            let old: Vec<BufferedOutput> = self.recent.drain(..BLOCK_CHUNKS).collect();
+            let raw: usize = old.iter().map(|entry| entry.data.len()).sum();
+            match Block::compress(&old) {
+                Ok(block) => {
+                    self.bytes = self.bytes - raw + block.data.len();
+                    self.blocks.push_back(block);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to compress scrollback: {}", e);
+                    self.lines -= old.iter().map(BufferedOutput::lines).sum::<usize>();
+                    self.bytes -= raw;
+                    self.evict(&old);
+                }
+            }
+        }
+        while self.recent.len() > MAX_RECENT_CHUNKS
+            || ((self.lines > limits.lines || self.bytes > limits.bytes)
+                && self.blocks.len() + self.recent.len() > 1)
+        {
+            if let Some(block) = self.blocks.pop_front() {
+                self.lines -= block.lines;
+                self.bytes -= block.data.len();
+                if self.spill.is_some() {
+                    self.evict(&block.decompress());
+                }
+            } else if let Some(entry) = self.recent.pop_front() {
+                self.lines -= entry.lines();
+                self.bytes -= entry.data.len();
+                self.evict(std::slice::from_ref(&entry));
+            }
+        }
+    }
+
+    /// Every chunk, oldest first, decompressing older output as it goes
+    pub(super) fn entries(&self) -> impl Iterator<Item = BufferedOutput> + '_ {
+        self.blocks
+            .iter()
+            .flat_map(Block::decompress)
+            .chain(self.recent.iter().cloned())
+    }
+
+    /// Every chunk of at least the last `lines` lines, reading the spill
+    /// file when memory holds fewer
+    pub(super) fn covering(&self, lines: usize) -> Vec<BufferedOutput> {
+        let spilled = match &self.spill {
+            Some(spill) if self.lines < lines => {
+                spill.covering(lines - self.lines).unwrap_or_else(|e| {
+                    tracing::warn!("Failed to read spilled scrollback: {}", e);
+                    Vec::new()
+                })
+            }
+            _ => Vec::new(),
+        };
+        spilled.into_iter().chain(self.entries()).collect()
+    }
+
+    /// Chunks after sequence number `seq`; blocks with none aren't
+    /// decompressed. A client that has seen output older than what is in
+    /// memory gets the rest from the spill file; one that has seen nothing
+    /// (`seq` 0) gets what is in memory.
+    pub(super) fn since(&self, seq: u64) -> Vec<BufferedOutput> {
+        let in_memory = self.blocks.front().map_or_else(
+            || self.recent.front().map(|entry| entry.seq),
+            |block| block.chunks.first().map(|&(.., seq)| seq),
+        );
+        let spilled = match &self.spill {
+            Some(spill) if seq > 0 && in_memory.is_none_or(|first| seq + 1 < first) => {
+                spill.since(seq).unwrap_or_else(|e| {
+                    tracing::warn!("Failed to read spilled scrollback: {}", e);
+                    Vec::new()
+                })
+            }
+            _ => Vec::new(),
+        };
+        let first = self.blocks.partition_point(|block| block.last_seq() <= seq);
+        let held = self
+            .blocks
+            .iter()
+            .skip(first)
+            .flat_map(Block::decompress)
+            .chain(self.recent.iter().cloned())
+            .filter(|entry| entry.seq > seq);
+        spilled.into_iter().chain(held).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNLIMITED: Limits = Limits {
+        lines: usize::MAX,
+        bytes: usize::MAX,
+        compress: true,
+    };
+
+    fn chunk(i: usize) -> BufferedOutput {
+        BufferedOutput {
+            data: format!("line {}\n", i).into(),
+            timestamp: i as i64,
+            stream: OutputStream::Stdout,
+            seq: i as u64 + 1,
+        }
+    }
+
+    fn contents(entries: impl IntoIterator<Item = BufferedOutput>) -> Vec<Bytes> {
+        entries.into_iter().map(|entry| entry.data).collect()
+    }
+
+    #[test]
+    fn trims_buffer_to_history_limit() {
+        let mut buffer = Scrollback::default();
+        let limits = Limits {
+            lines: 3,
+            ..UNLIMITED
+        };
+        for i in 0..5 {
+            buffer.push(chunk(i), limits);
+        }
+
+        assert_eq!(buffer.recent.len(), 3);
+        assert_eq!(buffer.recent.front().unwrap().data, &b"line 2\n"[..]);
+
+        // Each chunk is 7 bytes
+        buffer.trim(Limits {
+            bytes: 15,
+            ..UNLIMITED
+        });
+        assert_eq!(buffer.recent.len(), 2);
+        buffer.trim(Limits {
+            bytes: 1,
+            ..UNLIMITED
+        });
+        assert_eq!(buffer.recent.len(), 1);
+        assert_eq!(buffer.recent.front().unwrap().data, &b"line 4\n"[..]);
+    }
+
+    #[test]
+    fn older_output_is_compressed_and_read_back() {
+        let mut buffer = Scrollback::default();
+        let count = MAX_RECENT_CHUNKS + BLOCK_CHUNKS;
+        for i in 0..count {
+            buffer.push(chunk(i), UNLIMITED);
+        }
+        assert_eq!(buffer.blocks.len(), 1);
+        assert_eq!(buffer.recent.len(), MAX_RECENT_CHUNKS);
+        assert_eq!(buffer.lines, count);
+        let raw: usize = (0..count).map(|i| chunk(i).data.len()).sum();
+        assert!(buffer.bytes < raw);
+
+        assert_eq!(contents(buffer.entries()), contents((0..count).map(chunk)));
+        let since = buffer.since(250);
+        assert_eq!(since.len(), count - 250);
+        assert_eq!(since[0].seq, 251);
+        assert_eq!(since[0].timestamp, 250);
+    }
+
+    #[test]
+    fn evicted_output_is_read_back_from_the_spill_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spill").join(spill_file_name("api/v2"));
+        assert!(path.ends_with("api%2Fv2.spill"));
+        let mut buffer = Scrollback::spilling_to(&path);
+        let limits = Limits {
+            lines: 3,
+            ..UNLIMITED
+        };
+        for i in 0..10 {
+            buffer.push(chunk(i), limits);
+        }
+        assert_eq!(buffer.recent.len(), 3);
+
+        // A client that saw chunk 4 gets 5 onwards, from disk and memory
+        let since = buffer.since(4);
+        assert_eq!(
+            since.iter().map(|e| e.seq).collect::<Vec<_>>(),
+            vec![5, 6, 7, 8, 9, 10]
+        );
+        assert_eq!(since[0].data, &b"line 4\n"[..]);
+        // A new client only gets what's in memory
+        assert_eq!(buffer.since(0).len(), 3);
+        assert_eq!(contents(buffer.covering(5)), contents((5..10).map(chunk)));
+        assert_eq!(contents(buffer.covering(50)), contents((0..10).map(chunk)));
+        assert_eq!(buffer.covering(2).len(), 3);
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        drop(buffer);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn without_compression_old_chunks_are_dropped() {
+        let mut buffer = Scrollback::default();
+        let limits = Limits {
+            compress: false,
+            ..UNLIMITED
+        };
+        for i in 0..MAX_RECENT_CHUNKS + 5 {
+            buffer.push(chunk(i), limits);
+        }
+        assert!(buffer.blocks.is_empty());
+        assert_eq!(buffer.recent.len(), MAX_RECENT_CHUNKS);
+        assert_eq!(buffer.since(0)[0].seq, 6);
+    }
+}
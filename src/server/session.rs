@@ -1,30 +1,17 @@
 //! Session management - tracks session state and metadata
 
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+pub use crate::protocol::SessionInfo;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
-/// Session metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SessionInfo {
-    /// Unique session identifier
-    pub id: Uuid,
-
-    /// Human-readable session name
-    pub name: String,
-
-    /// When the session was created
-    pub created_at: DateTime<Utc>,
-
-    /// Socket path for this session
-    pub socket_path: PathBuf,
-
-    /// Number of connected clients
-    pub client_count: usize,
-
-    /// Number of active channels
-    pub channel_count: usize,
+/// Check that `name` can name a session (and its socket file)
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\0']) {
+        return Err(anyhow!("invalid session name '{}'", name));
+    }
+    Ok(())
 }
 
 /// Active session state
@@ -47,6 +34,7 @@ impl Session {
                 socket_path,
                 client_count: 0,
                 channel_count: 0,
+                server_version: env!("CARGO_PKG_VERSION").to_string(),
             },
             client_ids: Vec::new(),
         }
@@ -74,6 +62,17 @@ impl Session {
         self.info.id
     }
 
+    /// Socket path clients connect to
+    pub fn socket_path(&self) -> &Path {
+        &self.info.socket_path
+    }
+
+    /// Record a new name and the socket path that goes with it
+    pub fn rename(&mut self, name: String, socket_path: PathBuf) {
+        self.info.name = name;
+        self.info.socket_path = socket_path;
+    }
+
     /// Check if any clients are connected
     pub fn has_clients(&self) -> bool {
         !self.client_ids.is_empty()
@@ -0,0 +1,135 @@
+//! systemd integration - socket activation and user-service units
+//!
+//! With the units from [`install_user_service`], systemd owns each session's
+//! socket and starts `nexus-server` on the first connection, so sessions come
+//! up on demand and (with lingering enabled) outlive the login session.
+
+use anyhow::{anyhow, Context, Result};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+
+/// First descriptor systemd passes (`SD_LISTEN_FDS_START`)
+const LISTEN_FDS_START: i32 = 3;
+
+/// Take the listening socket passed by systemd socket activation, if any.
+///
+/// Follows the `sd_listen_fds` protocol: the socket is only ours when
+/// `LISTEN_PID` names this process. The variables are cleared so channel
+/// processes don't see them.
+pub fn activated_listener() -> Result<Option<UnixListener>> {
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<u32>().ok())
+        .unwrap_or(0);
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(name);
+    }
+
+    if !for_us || count == 0 {
+        return Ok(None);
+    }
+    if count > 1 {
+        return Err(anyhow!("Expected one socket from systemd, got {}", count));
+    }
+
+    // SAFETY: systemd hands us this descriptor and nothing else owns it
+    let inherited = unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) };
+    // The passed descriptor isn't close-on-exec; keep a duplicate that is so
+    // channel processes don't inherit the listening socket
+    let listener = inherited
+        .try_clone()
+        .context("Failed to take the socket passed by systemd")?;
+    drop(inherited);
+    Ok(Some(listener))
+}
+
+/// `nexus-server@.socket`: one socket per session, named by the instance
+fn socket_unit(runtime_dir: &Path) -> String {
+    format!(
+        "[Unit]\n\
+         Description=nexus session %i socket\n\
+         \n\
+         [Socket]\n\
+         ListenStream={}/%i.sock\n\
+         SocketMode=0600\n\
+         DirectoryMode=0700\n\
+         \n\
+         [Install]\n\
+         WantedBy=sockets.target\n",
+        runtime_dir.display()
+    )
+}
+
+/// `nexus-server@.service`: the server started for a session's socket
+fn service_unit(server: &Path) -> String {
+    format!(
+        "[Unit]\n\
+         Description=nexus session %i\n\
+         Requires=nexus-server@%i.socket\n\
+         \n\
+         [Service]\n\
+         ExecStart={} --session %i --foreground\n\
+         KillMode=mixed\n\
+         Restart=on-failure\n",
+        server.display()
+    )
+}
+
+/// Write the user units for `server` into `unit_dir` (normally
+/// `~/.config/systemd/user`), returning the paths written
+pub fn install_user_service(
+    unit_dir: &Path,
+    server: &Path,
+    runtime_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(unit_dir)
+        .with_context(|| format!("Failed to create {}", unit_dir.display()))?;
+
+    let units = [
+        ("nexus-server@.socket", socket_unit(runtime_dir)),
+        ("nexus-server@.service", service_unit(server)),
+    ];
+    let mut written = Vec::new();
+    for (name, contents) in units {
+        let path = unit_dir.join(name);
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Default directory for user units
+pub fn user_unit_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("systemd")
+        .join("user")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_template_units() {
+        let dir = tempfile::tempdir().unwrap();
+        let written = install_user_service(
+            dir.path(),
+            Path::new("/usr/bin/nexus-server"),
+            Path::new("/run/user/1000/nexus"),
+        )
+        .unwrap();
+        assert_eq!(written.len(), 2);
+
+        let socket = std::fs::read_to_string(dir.path().join("nexus-server@.socket")).unwrap();
+        assert!(socket.contains("ListenStream=/run/user/1000/nexus/%i.sock"));
+        let service = std::fs::read_to_string(dir.path().join("nexus-server@.service")).unwrap();
+        assert!(service.contains("ExecStart=/usr/bin/nexus-server --session %i --foreground"));
+    }
+}
@@ -0,0 +1,125 @@
+//! TLS for the TCP transport, built on rustls
+//!
+//! The server presents `[tcp] cert`/`key`; setting `client_ca` also requires
+//! clients to present a certificate signed by that CA (mutual TLS). The
+//! protocol token is still checked inside the encrypted stream.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::crypto::ring::default_provider;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let pem = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificate in {}", path.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let pem = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    rustls_pemfile::private_key(&mut pem.as_slice())
+        .with_context(|| format!("Invalid private key in {}", path.display()))?
+        .ok_or_else(|| anyhow!("No private key found in {}", path.display()))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+    }
+    Ok(roots)
+}
+
+/// Build the server side, requiring client certificates when `client_ca` is set
+pub fn acceptor(cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<TlsAcceptor> {
+    let provider = Arc::new(default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()?;
+    let builder = match client_ca {
+        Some(ca) => {
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(load_roots(ca)?), provider)
+                    .build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(load_certs(cert)?, load_key(key)?)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Client-side TLS settings for `nexus connect`
+#[derive(Debug, Clone, Default)]
+pub struct ClientTls {
+    /// CA bundle the server's certificate must chain to
+    pub ca: PathBuf,
+
+    /// Client certificate and key, for servers that require mutual TLS
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+
+    /// Name to verify the server certificate against (defaults to the host in the address)
+    pub server_name: Option<String>,
+}
+
+impl ClientTls {
+    /// Open a TCP connection to `addr` and complete the TLS handshake
+    pub async fn connect(&self, addr: &str) -> Result<TlsStream<TcpStream>> {
+        let builder = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(load_roots(&self.ca)?);
+        let config = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => {
+                builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => return Err(anyhow!("A client certificate needs both --cert and --key")),
+        };
+
+        let host = match &self.server_name {
+            Some(name) => name.clone(),
+            None => host_of(addr).to_string(),
+        };
+        let server_name = ServerName::try_from(host.clone())
+            .map_err(|_| anyhow!("Invalid server name '{}'", host))?;
+
+        let stream = TcpStream::connect(addr)
+            .await
+            .with_context(|| format!("Failed to connect to {}", addr))?;
+        TlsConnector::from(Arc::new(config))
+            .connect(server_name, stream)
+            .await
+            .with_context(|| format!("TLS handshake with {} failed", addr))
+    }
+}
+
+/// Host part of `host:port` or `[v6]:port`
+fn host_of(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_host_from_address() {
+        assert_eq!(host_of("devbox:7421"), "devbox");
+        assert_eq!(host_of("[::1]:7421"), "::1");
+        assert_eq!(host_of("devbox"), "devbox");
+    }
+}
@@ -1,9 +1,12 @@
 //! Integration tests for protocol serialization
 
+use bytes::Bytes;
+use nexus::channel::{ChannelMode, ContainerRuntime, ContainerTarget, OutputStream};
 use nexus::protocol::{
-    check_version_compatibility, deserialize, frame_message, serialize, serialize_and_frame,
-    unframe_and_deserialize, unframe_message, ClientMessage, ServerMessage, MAX_MESSAGE_SIZE,
-    PROTOCOL_VERSION,
+    check_client_version, check_version_compatibility, deserialize, frame_message, serialize,
+    serialize_and_frame, unframe_and_deserialize, unframe_message, Capability, ClientMessage,
+    Codec, CreateChannelRequest, ServerMessage, CAPABILITIES, MAX_MESSAGE_SIZE,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
 };
 
 #[test]
@@ -11,19 +14,35 @@ fn test_client_message_roundtrip() {
     let messages = vec![
         ClientMessage::Hello {
             protocol_version: 1,
+            token: Some("secret".to_string()),
+            identity: Some("alice".to_string()),
+            capabilities: vec![Capability::Resume, Capability::Heartbeat],
+            observe: false,
+            name: None,
         },
         ClientMessage::Input {
             data: b"hello".to_vec(),
         },
-        ClientMessage::CreateChannel {
+        ClientMessage::CreateChannel(CreateChannelRequest {
             name: "test".to_string(),
             command: Some("bash".to_string()),
-            working_dir: None,
-        },
+            env: vec![("RUST_LOG".to_string(), "debug".to_string())],
+            template: Some("rust-dev".to_string()),
+            container: Some(ContainerTarget::new(ContainerRuntime::Podman, "web")),
+            mode: Some(ChannelMode::Pipe),
+            ephemeral: true,
+            ..Default::default()
+        }),
         ClientMessage::SwitchChannel {
             name: "test".to_string(),
         },
         ClientMessage::ListChannels,
+        ClientMessage::Request {
+            id: 3,
+            message: Box::new(ClientMessage::KillChannel {
+                name: "test".to_string(),
+            }),
+        },
     ];
 
     for msg in messages {
@@ -39,8 +58,10 @@ fn test_client_message_roundtrip() {
 fn test_server_message_roundtrip() {
     let msg = ServerMessage::Output {
         channel: "test".to_string(),
-        data: b"output data".to_vec(),
+        data: Bytes::from_static(b"output data"),
         timestamp: 1234567890,
+        stream: OutputStream::Stderr,
+        seq: 42,
     };
 
     let encoded = serialize(&msg).expect("serialize failed");
@@ -49,6 +70,68 @@ fn test_server_message_roundtrip() {
     assert_eq!(format!("{:?}", msg), format!("{:?}", decoded));
 }
 
+#[test]
+fn test_output_payload_decodes_as_a_vec() {
+    // How peers that predate shared buffers decode output
+    #[derive(serde::Deserialize)]
+    #[allow(dead_code)]
+    enum OldServerMessage {
+        Output {
+            channel: String,
+            data: Vec<u8>,
+            timestamp: i64,
+            stream: OutputStream,
+            seq: u64,
+        },
+    }
+
+    let msg = ServerMessage::Output {
+        channel: "test".to_string(),
+        data: Bytes::from_static(b"output data"),
+        timestamp: 1,
+        stream: OutputStream::Stdout,
+        seq: 1,
+    };
+    for codec in [Codec::V1, Codec::V2] {
+        let encoded = codec.encode(&msg).expect("serialize failed");
+        let OldServerMessage::Output { data, .. } =
+            deserialize(&encoded).expect("deserialize failed");
+        assert_eq!(data, b"output data");
+    }
+}
+
+#[test]
+fn test_request_ids_tag_acks_and_errors() {
+    let ack = ServerMessage::Ack {
+        for_command: "KillChannel".to_string(),
+        request_id: None,
+    }
+    .with_request_id(3);
+    assert!(matches!(
+        ack,
+        ServerMessage::Ack {
+            request_id: Some(3),
+            ..
+        }
+    ));
+
+    let error = ServerMessage::Error {
+        message: "nope".to_string(),
+        request_id: None,
+    }
+    .with_request_id(4);
+    assert!(matches!(
+        error,
+        ServerMessage::Error {
+            request_id: Some(4),
+            ..
+        }
+    ));
+
+    let other = ServerMessage::Pong { nonce: 1 }.with_request_id(5);
+    assert!(matches!(other, ServerMessage::Pong { nonce: 1 }));
+}
+
 #[test]
 fn test_acceptance_criteria() {
     // This is the exact acceptance criteria from the issue
@@ -179,6 +262,69 @@ fn test_version_compatibility() {
     assert!(err_msg.contains("server=2"));
 }
 
+#[test]
+fn test_server_accepts_older_clients() {
+    for version in MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION {
+        assert!(check_client_version(version).is_ok());
+    }
+
+    let err = check_client_version(PROTOCOL_VERSION + 1).unwrap_err();
+    assert!(err.to_string().contains("Protocol version mismatch"));
+    assert!(check_client_version(0).is_err());
+}
+
+#[test]
+fn test_codec_per_version() {
+    let ack = ServerMessage::Ack {
+        for_command: "Hello".to_string(),
+        request_id: Some(7),
+    };
+    let v1 = Codec::for_version(1).unwrap().encode(&ack).unwrap();
+    let v2 = Codec::for_version(2).unwrap().encode(&ack).unwrap();
+
+    // v2 names struct fields, v1 lays them out by position
+    let names_fields = |bytes: &[u8]| bytes.windows(11).any(|w| w == b"for_command");
+    assert!(!names_fields(&v1));
+    assert!(names_fields(&v2));
+
+    // Either encoding decodes with any codec
+    for bytes in [&v1, &v2] {
+        for codec in [Codec::V1, Codec::V2] {
+            let decoded: ServerMessage = codec.decode(bytes).expect("decode failed");
+            assert!(matches!(
+                decoded,
+                ServerMessage::Ack {
+                    request_id: Some(7),
+                    ..
+                }
+            ));
+        }
+    }
+
+    assert_eq!(Codec::current().version(), PROTOCOL_VERSION);
+    assert_eq!(Codec::oldest().version(), MIN_PROTOCOL_VERSION);
+    assert!(Codec::for_version(PROTOCOL_VERSION + 1).is_err());
+}
+
+#[test]
+fn test_unknown_capabilities_decode() {
+    // What a newer peer might advertise
+    #[derive(serde::Serialize)]
+    #[serde(rename_all = "kebab-case")]
+    enum FutureCapability {
+        Heartbeat,
+        Compression,
+    }
+
+    let bytes = serialize(&vec![
+        FutureCapability::Heartbeat,
+        FutureCapability::Compression,
+    ])
+    .expect("serialize failed");
+    let decoded: Vec<Capability> = deserialize(&bytes).expect("deserialize failed");
+    assert_eq!(decoded, vec![Capability::Heartbeat, Capability::Unknown]);
+}
+
 #[test]
 fn test_serialize_and_frame() {
     let msg = ClientMessage::Input {
@@ -226,6 +372,11 @@ fn test_full_protocol_flow() {
     // Simulate a client-server handshake
     let client_hello = ClientMessage::Hello {
         protocol_version: PROTOCOL_VERSION,
+        token: None,
+        identity: None,
+        capabilities: CAPABILITIES.to_vec(),
+        observe: false,
+        name: None,
     };
 
     // Client serializes and frames
@@ -239,6 +390,7 @@ fn test_full_protocol_flow() {
     // Verify handshake
     if let ClientMessage::Hello {
         protocol_version: client_version,
+        ..
     } = decoded_hello
     {
         check_version_compatibility(client_version, PROTOCOL_VERSION)
@@ -251,6 +403,8 @@ fn test_full_protocol_flow() {
     let server_welcome = ServerMessage::Welcome {
         session_id: uuid::Uuid::new_v4(),
         protocol_version: PROTOCOL_VERSION,
+        resume_token: Some("token".to_string()),
+        capabilities: CAPABILITIES.to_vec(),
     };
 
     let framed_welcome = serialize_and_frame(&server_welcome).expect("serialize failed");
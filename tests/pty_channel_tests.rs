@@ -1,4 +1,4 @@
-use nexus::channel::{ChannelConfig, PtyChannel};
+use nexus::channel::{ChannelConfig, ChannelMode, PtyChannel};
 use tokio::time::{timeout, Duration};
 
 #[tokio::test]
@@ -32,3 +32,134 @@ async fn pty_echoes_output() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn pty_applies_custom_env() -> anyhow::Result<()> {
+    let config = ChannelConfig::new("env-pty")
+        .with_command("/bin/sh -c 'echo value=$NEXUS_TEST_VAR'")
+        .with_env("NEXUS_TEST_VAR", "from-config");
+    let mut channel = PtyChannel::spawn(config).await?;
+    let mut output = channel
+        .take_output_receiver()
+        .expect("output receiver should be available");
+
+    let mut buffer = Vec::new();
+    let needle = b"value=from-config";
+    for _ in 0..10 {
+        match timeout(Duration::from_secs(2), output.recv()).await {
+            Ok(Some(chunk)) => {
+                buffer.extend_from_slice(&chunk);
+                if buffer.windows(needle.len()).any(|w| w == needle) {
+                    break;
+                }
+            }
+            _ => break,
+        }
+    }
+
+    channel.kill().await.ok();
+    assert!(
+        buffer.windows(needle.len()).any(|w| w == needle),
+        "PTY output did not contain env value; got: {:?}",
+        String::from_utf8_lossy(&buffer)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn channels_do_not_hold_blocking_threads() -> anyhow::Result<()> {
+    // With one blocking thread, a channel that parked a reader there would
+    // starve every other channel
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .max_blocking_threads(1)
+        .build()?;
+    let exercise = async {
+        let mut channels = Vec::new();
+        for i in 0..4 {
+            let config = ChannelConfig::new(format!("cat-{}", i)).with_command("cat");
+            channels.push(PtyChannel::spawn(config).await?);
+        }
+
+        for (i, channel) in channels.iter_mut().enumerate() {
+            let mut output = channel
+                .take_output_receiver()
+                .expect("output receiver should be available");
+            let needle = format!("ping {}", i);
+            channel.write(format!("{}\n", needle).as_bytes()).await?;
+
+            let mut buffer = Vec::new();
+            while !buffer.windows(needle.len()).any(|w| w == needle.as_bytes()) {
+                match timeout(Duration::from_secs(2), output.recv()).await {
+                    Ok(Some(chunk)) => buffer.extend_from_slice(&chunk),
+                    _ => panic!(
+                        "channel {} never echoed; got: {:?}",
+                        i,
+                        String::from_utf8_lossy(&buffer)
+                    ),
+                }
+            }
+        }
+
+        for channel in &mut channels {
+            channel.kill().await.ok();
+        }
+        anyhow::Ok(())
+    };
+    runtime.block_on(async {
+        timeout(Duration::from_secs(10), exercise)
+            .await
+            .expect("channels stalled waiting for blocking threads")
+    })
+}
+
+#[tokio::test]
+async fn large_input_does_not_wait_for_a_slow_reader() -> anyhow::Result<()> {
+    // `sleep` never reads its stdin, so the pipe fills after ~64 KiB
+    let config = ChannelConfig::new("stalled")
+        .with_command("sleep 30")
+        .with_mode(ChannelMode::Pipe);
+    let mut channel = PtyChannel::spawn(config).await?;
+
+    let paste = vec![b'x'; 1024 * 1024];
+    timeout(Duration::from_secs(2), channel.write(&paste))
+        .await
+        .expect("write should return without waiting for the reader")?;
+
+    // Past the queue's limit further input is refused outright
+    let flood = vec![b'x'; 8 * 1024 * 1024];
+    let refused = timeout(Duration::from_secs(2), channel.write(&flood))
+        .await
+        .expect("write should return without waiting for the reader");
+    assert!(refused.is_err());
+
+    channel.kill().await.ok();
+    Ok(())
+}
+
+#[tokio::test]
+async fn large_input_arrives_intact() -> anyhow::Result<()> {
+    let config = ChannelConfig::new("wc")
+        .with_command("wc -c")
+        .with_mode(ChannelMode::Pipe);
+    let mut channel = PtyChannel::spawn(config).await?;
+    let mut output = channel
+        .take_output_receiver()
+        .expect("output receiver should be available");
+
+    let paste = vec![b'x'; 512 * 1024 + 7];
+    channel.write(&paste).await?;
+    // Dropping the channel closes stdin once the queue is written out
+    drop(channel);
+
+    let mut buffer = Vec::new();
+    while let Ok(Some(chunk)) = timeout(Duration::from_secs(5), output.recv()).await {
+        buffer.extend_from_slice(&chunk);
+    }
+    assert_eq!(
+        String::from_utf8_lossy(&buffer).trim(),
+        paste.len().to_string()
+    );
+    Ok(())
+}
@@ -1,7 +1,14 @@
 //! Integration tests for the server module
 
-use nexus::protocol::{deserialize, serialize, ClientMessage, ServerMessage, PROTOCOL_VERSION};
-use nexus::server::ServerListener;
+use nexus::channel::ChannelMode;
+use nexus::client::{ClientEvent, EventStream, NexusClient};
+use nexus::config::{Config, ProfileChannel};
+use nexus::protocol::{
+    deserialize, serialize, Capability, ChannelEvent, ClientMessage, Codec, CreateChannelRequest,
+    ServerMessage, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use nexus::server::tls::ClientTls;
+use nexus::server::{ServerListener, SessionHost};
 use std::os::unix::net::UnixListener as StdUnixListener;
 use std::path::Path;
 use std::time::Duration;
@@ -88,9 +95,12 @@ async fn test_server_accepts_connection() {
 
     match welcome {
         ServerMessage::Welcome {
-            protocol_version, ..
+            protocol_version,
+            capabilities,
+            ..
         } => {
             assert_eq!(protocol_version, PROTOCOL_VERSION);
+            assert!(capabilities.contains(&Capability::Heartbeat));
         }
         _ => panic!("Expected Welcome message, got {:?}", welcome),
     }
@@ -127,6 +137,11 @@ async fn test_server_handles_hello() {
     // Send Hello
     let hello = ClientMessage::Hello {
         protocol_version: PROTOCOL_VERSION,
+        token: None,
+        identity: None,
+        capabilities: Vec::new(),
+        observe: false,
+        name: None,
     };
     let hello_bytes = serialize(&hello).unwrap();
     write_message(&mut stream, &hello_bytes).await;
@@ -140,8 +155,12 @@ async fn test_server_handles_hello() {
     let response: ServerMessage = deserialize(&response_bytes).expect("Should deserialize");
 
     match response {
-        ServerMessage::Ack { for_command } => {
+        ServerMessage::Ack {
+            for_command,
+            request_id,
+        } => {
             assert_eq!(for_command, "Hello");
+            assert_eq!(request_id, None);
         }
         _ => panic!("Expected Ack message, got {:?}", response),
     }
@@ -151,6 +170,56 @@ async fn test_server_handles_hello() {
     let _ = timeout(Duration::from_secs(2), server_handle).await;
 }
 
+#[tokio::test]
+async fn test_server_speaks_each_clients_protocol_version() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_server_speaks_each_clients_protocol_version: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("test_versions.sock");
+
+    let server = ServerListener::new("test_versions".to_string(), socket_path.clone());
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+
+    for version in MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION {
+        let codec = Codec::for_version(version).unwrap();
+        let mut stream = wait_for_socket(&socket_path).await;
+        let _ = read_message(&mut stream).await;
+
+        let hello = ClientMessage::Hello {
+            protocol_version: version,
+            token: None,
+            identity: None,
+            capabilities: Vec::new(),
+            observe: false,
+            name: None,
+        };
+        write_message(&mut stream, &codec.encode(&hello).unwrap()).await;
+
+        let ack = timeout(Duration::from_secs(2), read_message(&mut stream))
+            .await
+            .expect("Should receive response")
+            .expect("Response should not be empty");
+        assert!(matches!(
+            deserialize(&ack).expect("Should deserialize"),
+            ServerMessage::Ack { .. }
+        ));
+        // The reply is encoded the way the client's version expects
+        assert_eq!(
+            ack,
+            codec
+                .encode(&deserialize::<ServerMessage>(&ack).unwrap())
+                .unwrap()
+        );
+    }
+
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(2), server_handle).await;
+}
+
 #[tokio::test]
 async fn test_server_handles_list_channels() {
     if !can_create_unix_socket() {
@@ -219,6 +288,11 @@ async fn test_server_rejects_wrong_protocol_version() {
     // Send Hello with wrong version
     let hello = ClientMessage::Hello {
         protocol_version: 999,
+        token: None,
+        identity: None,
+        capabilities: Vec::new(),
+        observe: false,
+        name: None,
     };
     let hello_bytes = serialize(&hello).unwrap();
     write_message(&mut stream, &hello_bytes).await;
@@ -232,7 +306,7 @@ async fn test_server_rejects_wrong_protocol_version() {
     let response: ServerMessage = deserialize(&response_bytes).expect("Should deserialize");
 
     match response {
-        ServerMessage::Error { message } => {
+        ServerMessage::Error { message, .. } => {
             assert!(message.contains("Protocol version mismatch"));
         }
         _ => panic!("Expected Error message, got {:?}", response),
@@ -242,3 +316,1063 @@ async fn test_server_rejects_wrong_protocol_version() {
     let _ = shutdown_tx.send(()).await;
     let _ = timeout(Duration::from_secs(2), server_handle).await;
 }
+
+#[tokio::test]
+async fn test_server_creates_startup_channels() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_server_creates_startup_channels: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("test_startup.sock");
+
+    let mut config = Config::default();
+    for name in ["shell", "build"] {
+        config.startup.channels.push(ProfileChannel {
+            name: name.to_string(),
+            ..Default::default()
+        });
+    }
+    config.startup.focus = Some("build".to_string());
+
+    let server =
+        ServerListener::new("test_startup".to_string(), socket_path.clone()).with_config(config);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+
+    let mut stream = wait_for_socket(&socket_path).await;
+
+    let list_bytes = serialize(&ClientMessage::ListChannels).unwrap();
+    write_message(&mut stream, &list_bytes).await;
+
+    // Skip the welcome and any replayed output until the channel list arrives
+    let mut channels = None;
+    for _ in 0..50 {
+        let bytes = timeout(Duration::from_secs(2), read_message(&mut stream))
+            .await
+            .expect("Should receive message")
+            .expect("Message should not be empty");
+        if let ServerMessage::ChannelList { channels: list } = deserialize(&bytes).unwrap() {
+            channels = Some(list);
+            break;
+        }
+    }
+
+    let mut channels = channels.expect("Should receive ChannelList");
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    let names: Vec<_> = channels.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec!["build", "shell"]);
+    assert!(channels[0].is_active, "focus should make build active");
+
+    drop(stream);
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(2), server_handle).await;
+}
+
+#[tokio::test]
+async fn test_server_runs_script_commands() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_server_runs_script_commands: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("test_scripts.sock");
+    let scripts_dir = temp_dir.path().join("scripts");
+    std::fs::create_dir(&scripts_dir).unwrap();
+    std::fs::write(
+        scripts_dir.join("greet.lua"),
+        r#"nexus.command("greet", function(args) return "hello " .. args[1] end)"#,
+    )
+    .unwrap();
+
+    let server = ServerListener::new("test_scripts".to_string(), socket_path.clone())
+        .with_scripts_dir(scripts_dir);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+
+    let mut stream = wait_for_socket(&socket_path).await;
+    let _ = read_message(&mut stream).await;
+
+    let command = ClientMessage::ScriptCommand {
+        name: "greet".to_string(),
+        args: vec!["nexus".to_string()],
+    };
+    write_message(&mut stream, &serialize(&command).unwrap()).await;
+
+    let response_bytes = timeout(Duration::from_secs(2), read_message(&mut stream))
+        .await
+        .expect("Should receive response")
+        .expect("Response should not be empty");
+    match deserialize(&response_bytes).unwrap() {
+        ServerMessage::ScriptResult { command, message } => {
+            assert_eq!(command, "greet");
+            assert_eq!(message.as_deref(), Some("hello nexus"));
+        }
+        other => panic!("Expected ScriptResult, got {:?}", other),
+    }
+
+    drop(stream);
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(2), server_handle).await;
+}
+
+#[tokio::test]
+async fn test_nexus_client_drives_session() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_nexus_client_drives_session: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("test_client.sock");
+
+    let server = ServerListener::new("test_client".to_string(), socket_path.clone());
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+
+    drop(wait_for_socket(&socket_path).await);
+    let mut client = NexusClient::connect(&socket_path).await.unwrap();
+    let mut events = client.take_events().unwrap();
+    assert!(client.take_events().is_none());
+
+    client
+        .create_channel("greeter", Some("echo hello-from-nexus"))
+        .await
+        .unwrap();
+    let channels = client.list_channels().await.unwrap();
+    assert_eq!(channels.len(), 1);
+    assert_eq!(channels[0].name, "greeter");
+    assert!(channels[0].is_subscribed);
+
+    let mut output = String::new();
+    let _ = timeout(Duration::from_secs(10), async {
+        while let Some(event) = events.next().await {
+            if let ClientEvent::Output { channel, data, .. } = event {
+                assert_eq!(channel, "greeter");
+                output.push_str(&String::from_utf8_lossy(&data));
+                if output.contains("hello-from-nexus") {
+                    break;
+                }
+            }
+        }
+    })
+    .await;
+    assert!(output.contains("hello-from-nexus"), "got {:?}", output);
+
+    let subscribed = client.unsubscribe(&["greeter"]).await.unwrap();
+    assert!(subscribed.is_empty());
+    assert!(client.kill_channel("missing").await.is_err());
+
+    client.ping().await.unwrap();
+    let (info, _uptime) = client.session_info().await.unwrap();
+    assert_eq!(info.name, "test_client");
+    assert_eq!(info.client_count, 1);
+    assert_eq!(info.channel_count, 1);
+    assert_eq!(info.server_version, env!("CARGO_PKG_VERSION"));
+
+    client.detach().await.unwrap();
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(2), server_handle).await;
+}
+
+/// The next client attached or left event
+async fn next_presence(events: &mut EventStream) -> ClientEvent {
+    let next = async {
+        loop {
+            match events.next().await {
+                Some(event @ (ClientEvent::ClientJoined(_) | ClientEvent::ClientLeft(_))) => {
+                    return event
+                }
+                Some(_) => {}
+                None => panic!("connection closed"),
+            }
+        }
+    };
+    timeout(Duration::from_secs(5), next)
+        .await
+        .expect("expected presence event never arrived")
+}
+
+#[tokio::test]
+async fn test_clients_see_each_other_attach_and_detach() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_clients_see_each_other_attach_and_detach: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("test_presence.sock");
+
+    let server = ServerListener::new("test_presence".to_string(), socket_path.clone());
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+
+    drop(wait_for_socket(&socket_path).await);
+    let mut watcher = NexusClient::connect(&socket_path).await.unwrap();
+    let mut events = watcher.take_events().unwrap();
+
+    let mut laptop = UnixStream::connect(&socket_path).await.unwrap();
+    let hello = ClientMessage::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        token: None,
+        identity: None,
+        capabilities: Vec::new(),
+        observe: false,
+        name: Some("laptop".to_string()),
+    };
+    write_message(&mut laptop, &serialize(&hello).unwrap()).await;
+    // Saying Hello again doesn't announce the client twice
+    write_message(&mut laptop, &serialize(&hello).unwrap()).await;
+
+    match next_presence(&mut events).await {
+        ClientEvent::ClientJoined(client) => assert_eq!(client.label(), "laptop"),
+        other => panic!("expected ClientJoined, got {:?}", other),
+    }
+
+    let clients = watcher.clients().await.unwrap();
+    assert_eq!(clients.len(), 2);
+    assert!(clients[0].is_self, "longest attached comes first");
+    assert_eq!(clients[1].name.as_deref(), Some("laptop"));
+
+    drop(laptop);
+    match next_presence(&mut events).await {
+        ClientEvent::ClientLeft(client) => assert_eq!(client.label(), "laptop"),
+        other => panic!("expected ClientLeft, got {:?}", other),
+    }
+
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(2), server_handle).await;
+}
+
+#[tokio::test]
+async fn test_shutdown_message_stops_server() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_shutdown_message_stops_server: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("test_shutdown.sock");
+
+    let server = ServerListener::new("test_shutdown".to_string(), socket_path.clone());
+    let (_shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+
+    drop(wait_for_socket(&socket_path).await);
+    let client = NexusClient::connect(&socket_path).await.unwrap();
+    client
+        .create_channel("sleeper", Some("sleep 30"))
+        .await
+        .unwrap();
+    client.send(ClientMessage::Shutdown).await.unwrap();
+
+    let result = timeout(Duration::from_secs(5), server_handle)
+        .await
+        .expect("server should stop after Shutdown");
+    assert!(result.unwrap().is_ok());
+    assert!(!socket_path.exists());
+}
+
+#[tokio::test]
+async fn test_host_starts_sessions_in_the_same_process() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_host_starts_sessions_in_the_same_process: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let temp_dir = tempdir().unwrap();
+    let mut config = Config::default();
+    config.general.runtime_dir = Some(temp_dir.path().to_path_buf());
+    let first_socket = config.socket_path("first");
+    let second_socket = config.socket_path("second");
+
+    let host = std::sync::Arc::new(SessionHost::new(config));
+    host.spawn(
+        host.listener("first".to_string(), first_socket.clone()),
+        None,
+    );
+
+    drop(wait_for_socket(&first_socket).await);
+    let first = NexusClient::connect(&first_socket).await.unwrap();
+    first.start_session("second").await.unwrap();
+    assert!(first.start_session("bad/name").await.is_err());
+
+    drop(wait_for_socket(&second_socket).await);
+    let second = NexusClient::connect(&second_socket).await.unwrap();
+    second.create_channel("worker", None).await.unwrap();
+    assert!(first.list_channels().await.unwrap().is_empty());
+
+    // Stopping one session leaves the other running
+    second.send(ClientMessage::Shutdown).await.unwrap();
+    timeout(Duration::from_secs(5), async {
+        while second_socket.exists() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("second session should stop");
+    assert!(first.list_channels().await.is_ok());
+
+    host.shutdown_all();
+    timeout(Duration::from_secs(5), host.wait())
+        .await
+        .expect("host should finish once every session has stopped");
+    assert!(!first_socket.exists());
+}
+
+/// Read messages until `stop` matches, returning everything read
+async fn read_until(
+    stream: &mut UnixStream,
+    stop: impl Fn(&ServerMessage) -> bool,
+) -> Vec<ServerMessage> {
+    let mut messages = Vec::new();
+    timeout(Duration::from_secs(5), async {
+        while let Some(bytes) = read_message(stream).await {
+            let msg: ServerMessage = deserialize(&bytes).unwrap();
+            let done = stop(&msg);
+            messages.push(msg);
+            if done {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("expected message never arrived");
+    messages
+}
+
+/// Connect and say Hello as `identity`, returning what arrived up to the ack
+async fn hello_as(socket_path: &Path, identity: &str) -> (UnixStream, Vec<ServerMessage>) {
+    let mut stream = UnixStream::connect(socket_path).await.unwrap();
+    let hello = ClientMessage::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        token: None,
+        identity: Some(identity.to_string()),
+        capabilities: Vec::new(),
+        observe: false,
+        name: None,
+    };
+    write_message(&mut stream, &serialize(&hello).unwrap()).await;
+    let messages = read_until(
+        &mut stream,
+        |msg| matches!(msg, ServerMessage::Ack { for_command, .. } if for_command == "Hello"),
+    )
+    .await;
+    (stream, messages)
+}
+
+#[tokio::test]
+async fn test_server_echoes_request_ids() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_server_echoes_request_ids: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("test_request_ids.sock");
+
+    let server = ServerListener::new("test_request_ids".to_string(), socket_path.clone());
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+
+    drop(wait_for_socket(&socket_path).await);
+    let (mut stream, _) = hello_as(&socket_path, "tester").await;
+    let tagged = |id, message| ClientMessage::Request {
+        id,
+        message: Box::new(message),
+    };
+    let switch = |name: &str| ClientMessage::SwitchChannel {
+        name: name.to_string(),
+    };
+
+    // Both requests fail; the errors say which request they answer
+    write_message(&mut stream, &serialize(&tagged(7, switch("a"))).unwrap()).await;
+    write_message(&mut stream, &serialize(&tagged(8, switch("b"))).unwrap()).await;
+    let messages = read_until(&mut stream, |msg| {
+        matches!(
+            msg,
+            ServerMessage::Error {
+                request_id: Some(8),
+                ..
+            }
+        )
+    })
+    .await;
+    let failed: Vec<_> = messages
+        .iter()
+        .filter_map(|msg| match msg {
+            ServerMessage::Error {
+                message,
+                request_id,
+            } => Some((*request_id, message.contains("'a'"))),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(failed, vec![(Some(7), true), (Some(8), false)]);
+
+    write_message(
+        &mut stream,
+        &serialize(&tagged(9, ClientMessage::Shutdown)).unwrap(),
+    )
+    .await;
+    read_until(&mut stream, |msg| {
+        matches!(
+            msg,
+            ServerMessage::Ack { for_command, request_id: Some(9) } if for_command == "Shutdown"
+        )
+    })
+    .await;
+
+    drop(shutdown_tx);
+    let _ = timeout(Duration::from_secs(5), server_handle).await;
+}
+
+#[tokio::test]
+async fn test_detach_restores_view_on_reattach() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_detach_restores_view_on_reattach: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("test_detach.sock");
+
+    let server = ServerListener::new("test_detach".to_string(), socket_path.clone());
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+
+    drop(wait_for_socket(&socket_path).await);
+    let admin = NexusClient::connect(&socket_path).await.unwrap();
+    admin.create_channel("one", Some("sleep 30")).await.unwrap();
+    admin.create_channel("two", Some("sleep 30")).await.unwrap();
+
+    let (mut stream, _) = hello_as(&socket_path, "alice").await;
+    let subscribe = ClientMessage::Subscribe {
+        channels: vec!["one".to_string(), "two".to_string()],
+    };
+    write_message(&mut stream, &serialize(&subscribe).unwrap()).await;
+    write_message(&mut stream, &serialize(&ClientMessage::Detach).unwrap()).await;
+    read_until(
+        &mut stream,
+        |msg| matches!(msg, ServerMessage::Ack { for_command, .. } if for_command == "Detach"),
+    )
+    .await;
+    let closed = timeout(Duration::from_secs(2), read_message(&mut stream))
+        .await
+        .expect("server should close a detached connection");
+    assert!(closed.is_none());
+
+    // Another identity starts from scratch
+    let (_bob, messages) = hello_as(&socket_path, "bob").await;
+    assert!(!messages.iter().any(|msg| matches!(
+        msg,
+        ServerMessage::Event(ChannelEvent::SubscriptionChanged { .. })
+    )));
+
+    let (_alice, messages) = hello_as(&socket_path, "alice").await;
+    let restored = messages.iter().find_map(|msg| match msg {
+        ServerMessage::Event(ChannelEvent::SubscriptionChanged { subscribed }) => {
+            Some(subscribed.clone())
+        }
+        _ => None,
+    });
+    assert_eq!(restored, Some(vec!["one".to_string(), "two".to_string()]));
+
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(5), server_handle).await;
+}
+
+/// Collect a channel's output events until `done` accepts the text so far
+async fn collect_output(
+    events: &mut nexus::client::EventStream,
+    done: impl Fn(&str) -> bool,
+) -> (String, Vec<u64>) {
+    let mut output = String::new();
+    let mut seqs = Vec::new();
+    let _ = timeout(Duration::from_secs(10), async {
+        while let Some(event) = events.next().await {
+            if let ClientEvent::Output { data, seq, .. } = event {
+                output.push_str(&String::from_utf8_lossy(&data));
+                seqs.push(seq);
+                if done(&output) {
+                    break;
+                }
+            }
+        }
+    })
+    .await;
+    (output, seqs)
+}
+
+#[tokio::test]
+async fn test_resume_replays_only_missed_output() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_resume_replays_only_missed_output: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("test_resume.sock");
+
+    let server = ServerListener::new("test_resume".to_string(), socket_path.clone());
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+
+    drop(wait_for_socket(&socket_path).await);
+    let admin = NexusClient::connect(&socket_path).await.unwrap();
+    // The first channel becomes active, which new connections replay in full
+    admin
+        .create_channel("idle", Some("sleep 30"))
+        .await
+        .unwrap();
+    admin.create_channel("log", Some("cat")).await.unwrap();
+
+    let mut first = NexusClient::connect(&socket_path).await.unwrap();
+    let mut events = first.take_events().unwrap();
+    first.subscribe(&["log"]).await.unwrap();
+    first.send_input("log", "before-drop\n").await.unwrap();
+    let (output, seqs) =
+        collect_output(&mut events, |out| out.matches("before-drop").count() >= 2).await;
+    assert!(output.contains("before-drop"), "got {:?}", output);
+    assert!(
+        seqs.windows(2).all(|pair| pair[0] < pair[1]),
+        "got {:?}",
+        seqs
+    );
+    let last_seen = *seqs.last().unwrap();
+    let token = first.resume_token().unwrap().to_string();
+    drop(first);
+
+    admin.send_input("log", "after-drop\n").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let mut second = NexusClient::connect(&socket_path).await.unwrap();
+    let mut events = second.take_events().unwrap();
+    let since = [("log".to_string(), last_seen)].into_iter().collect();
+    assert!(second.resume(&token, since).await.unwrap());
+    let (output, seqs) = collect_output(&mut events, |out| out.contains("after-drop")).await;
+    assert!(output.contains("after-drop"), "got {:?}", output);
+    assert!(!output.contains("before-drop"), "got {:?}", output);
+    assert!(seqs.iter().all(|&seq| seq > last_seen), "got {:?}", seqs);
+
+    // Tokens are single-use
+    assert!(!second.resume(&token, Default::default()).await.unwrap());
+    assert!(!second.resume("unknown", Default::default()).await.unwrap());
+
+    second.detach().await.unwrap();
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(5), server_handle).await;
+}
+
+#[tokio::test]
+async fn test_embedded_server_runs_without_socket() {
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("unused.sock");
+
+    let mut server = ServerListener::new("embedded".to_string(), socket_path.clone())
+        .start_embedded()
+        .await
+        .unwrap();
+    assert!(!socket_path.exists());
+    let mut events = server.take_events().unwrap();
+
+    server
+        .create_channel("greeter", Some("echo hello-embedded"))
+        .await
+        .unwrap();
+    let channels = server.list_channels().await.unwrap();
+    assert_eq!(channels.len(), 1);
+    assert_eq!(channels[0].name, "greeter");
+
+    let mut output = String::new();
+    let _ = timeout(Duration::from_secs(10), async {
+        while let Some(msg) = events.recv().await {
+            if let ServerMessage::Output { data, .. } = msg {
+                output.push_str(&String::from_utf8_lossy(&data));
+                if output.contains("hello-embedded") {
+                    break;
+                }
+            }
+        }
+    })
+    .await;
+    assert!(output.contains("hello-embedded"), "got {:?}", output);
+
+    assert!(server.send_input("missing", "x").await.is_err());
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_ephemeral_channels_are_removed_after_exit() {
+    let mut server = ServerListener::new("ephemeral".to_string(), Default::default())
+        .start_embedded()
+        .await
+        .unwrap();
+    let mut events = server.take_events().unwrap();
+
+    let reply = server
+        .request(ClientMessage::CreateChannel(CreateChannelRequest {
+            name: "once".to_string(),
+            command: Some("echo done; exit 4".to_string()),
+            mode: Some(ChannelMode::Pipe),
+            ephemeral: true,
+            ..Default::default()
+        }))
+        .await;
+    assert!(matches!(reply, Some(ServerMessage::Ack { .. })));
+
+    // Output must arrive before the exit event
+    let mut output = String::new();
+    let exit_code = timeout(Duration::from_secs(10), async {
+        while let Some(msg) = events.recv().await {
+            match msg {
+                ServerMessage::Output { data, .. } => {
+                    output.push_str(&String::from_utf8_lossy(&data))
+                }
+                ServerMessage::Event(ChannelEvent::Exited { exit_code, .. }) => return exit_code,
+                _ => {}
+            }
+        }
+        None
+    })
+    .await
+    .unwrap();
+    assert_eq!(exit_code, Some(4));
+    assert_eq!(output, "done\n");
+
+    let mut removed = false;
+    for _ in 0..20 {
+        if server.list_channels().await.unwrap().is_empty() {
+            removed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(removed, "ephemeral channel should be removed after exit");
+    server.shutdown().await;
+}
+
+/// Send one HTTP/1.1 request and return (status, body)
+async fn http_request(
+    addr: &str,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: &str,
+) -> (u16, String) {
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let auth = token
+        .map(|t| format!("Authorization: Bearer {}\r\n", t))
+        .unwrap_or_default();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        addr,
+        auth,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, b)| b.to_string())
+        .unwrap_or_default();
+    (status, body)
+}
+
+#[tokio::test]
+async fn test_http_api_controls_channels() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_http_api_controls_channels: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let addr = format!("127.0.0.1:{}", port);
+    let mut config = Config::default();
+    config.http.enabled = true;
+    config.http.bind = addr.clone();
+    config.http.token = Some("secret".to_string());
+
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("test_http.sock");
+    let server =
+        ServerListener::new("test_http".to_string(), socket_path.clone()).with_config(config);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+    drop(wait_for_socket(&socket_path).await);
+
+    let (status, _) = http_request(&addr, "GET", "/channels", None, "").await;
+    assert_eq!(status, 401);
+    let (status, _) = http_request(&addr, "GET", "/channels", Some("wrong"), "").await;
+    assert_eq!(status, 401);
+
+    let (status, body) = http_request(&addr, "GET", "/sessions", Some("secret"), "").await;
+    assert_eq!(status, 200);
+    assert!(body.contains("test_http"), "got {}", body);
+
+    let (status, _) = http_request(
+        &addr,
+        "POST",
+        "/channels",
+        Some("secret"),
+        r#"{"name": "greeter", "command": "echo hello-http"}"#,
+    )
+    .await;
+    assert_eq!(status, 201);
+
+    let (status, body) = http_request(&addr, "GET", "/channels", Some("secret"), "").await;
+    assert_eq!(status, 200);
+    assert!(body.contains("\"greeter\""), "got {}", body);
+
+    let mut output = String::new();
+    for _ in 0..50 {
+        let (status, body) = http_request(
+            &addr,
+            "GET",
+            "/channels/greeter/output?lines=5",
+            Some("secret"),
+            "",
+        )
+        .await;
+        assert_eq!(status, 200);
+        output = body;
+        if output.contains("hello-http") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(output.contains("hello-http"), "got {:?}", output);
+
+    let (status, _) = http_request(
+        &addr,
+        "POST",
+        "/channels/missing/input",
+        Some("secret"),
+        r#"{"data": "ls\n"}"#,
+    )
+    .await;
+    assert_eq!(status, 400);
+    let (status, _) =
+        http_request(&addr, "GET", "/channels/missing/output", Some("secret"), "").await;
+    assert_eq!(status, 404);
+
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(2), server_handle).await;
+}
+
+#[tokio::test]
+async fn test_tcp_clients_must_authenticate() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_tcp_clients_must_authenticate: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let addr = format!("127.0.0.1:{}", port);
+    let mut config = Config::default();
+    config.tcp.enabled = true;
+    config.tcp.bind = addr.clone();
+    config.tcp.token = Some("secret".to_string());
+
+    let temp_dir = tempdir().unwrap();
+    let socket_path = temp_dir.path().join("test_tcp.sock");
+    let server =
+        ServerListener::new("test_tcp".to_string(), socket_path.clone()).with_config(config);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+    drop(wait_for_socket(&socket_path).await);
+
+    let error = NexusClient::connect_tcp(&addr, "wrong")
+        .await
+        .err()
+        .expect("wrong token should be rejected");
+    assert!(error.to_string().contains("invalid token"), "got {}", error);
+
+    // Nothing about the session is sent before authentication
+    let mut stream = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let list = serialize(&ClientMessage::ListChannels).unwrap();
+    stream
+        .write_all(&(list.len() as u32).to_be_bytes())
+        .await
+        .unwrap();
+    stream.write_all(&list).await.unwrap();
+    let mut len_bytes = [0u8; 4];
+    stream.read_exact(&mut len_bytes).await.unwrap();
+    let mut reply = vec![0u8; u32::from_be_bytes(len_bytes) as usize];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert!(matches!(
+        deserialize(&reply).unwrap(),
+        ServerMessage::Error { .. }
+    ));
+
+    let client = NexusClient::connect_tcp(&addr, "secret").await.unwrap();
+    assert!(client.list_channels().await.unwrap().is_empty());
+    client.detach().await.unwrap();
+
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(2), server_handle).await;
+}
+
+/// Write a CA, a `localhost` server cert and a client cert (PEM) into `dir`
+fn write_test_pki(dir: &Path) {
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+    for name in ["server", "client"] {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+        std::fs::write(dir.join(format!("{}.pem", name)), cert.pem()).unwrap();
+        std::fs::write(dir.join(format!("{}.key", name)), key.serialize_pem()).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_tcp_transport_uses_mutual_tls() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_tcp_transport_uses_mutual_tls: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let temp_dir = tempdir().unwrap();
+    let pki = temp_dir.path();
+    write_test_pki(pki);
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let addr = format!("127.0.0.1:{}", port);
+    let mut config = Config::default();
+    config.tcp.enabled = true;
+    config.tcp.bind = addr.clone();
+    config.tcp.token = Some("secret".to_string());
+    config.tcp.cert = Some(pki.join("server.pem"));
+    config.tcp.key = Some(pki.join("server.key"));
+    config.tcp.client_ca = Some(pki.join("ca.pem"));
+
+    let socket_path = temp_dir.path().join("test_tls.sock");
+    let server =
+        ServerListener::new("test_tls".to_string(), socket_path.clone()).with_config(config);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+    drop(wait_for_socket(&socket_path).await);
+
+    let mut tls = ClientTls {
+        ca: pki.join("ca.pem"),
+        cert: None,
+        key: None,
+        server_name: Some("localhost".to_string()),
+    };
+
+    // Plain TCP and TLS without a client certificate are both refused
+    assert!(NexusClient::connect_tcp(&addr, "secret").await.is_err());
+    assert!(NexusClient::connect_tls(&addr, "secret", &tls)
+        .await
+        .is_err());
+
+    tls.cert = Some(pki.join("client.pem"));
+    tls.key = Some(pki.join("client.key"));
+    let client = NexusClient::connect_tls(&addr, "secret", &tls)
+        .await
+        .unwrap();
+    assert!(client.list_channels().await.unwrap().is_empty());
+    client.detach().await.unwrap();
+
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(2), server_handle).await;
+}
+
+/// The `nexus` binary, with its config and runtime directories inside `home`
+/// so it finds the sessions started under `home` and nothing else
+fn nexus_cli(home: &Path) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new(env!("CARGO_BIN_EXE_nexus"));
+    command
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_RUNTIME_DIR", home.join("run"))
+        .env("RUST_LOG", "off")
+        .kill_on_drop(true);
+    command
+}
+
+/// Start session `name` where [`nexus_cli`] looks for it
+async fn start_cli_session(
+    home: &Path,
+    name: &str,
+) -> (
+    NexusClient,
+    mpsc::Sender<()>,
+    tokio::task::JoinHandle<anyhow::Result<()>>,
+) {
+    let socket_path = home
+        .join("run")
+        .join("nexus")
+        .join(format!("{}.sock", name));
+    std::fs::create_dir_all(socket_path.parent().unwrap()).unwrap();
+    let server = ServerListener::new(name.to_string(), socket_path.clone());
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+    let server_handle = tokio::spawn(async move { server.run(shutdown_rx).await });
+    drop(wait_for_socket(&socket_path).await);
+    let client = NexusClient::connect(&socket_path).await.unwrap();
+    (client, shutdown_tx, server_handle)
+}
+
+#[tokio::test]
+async fn test_send_types_into_a_channel() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_send_types_into_a_channel: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let home = tempdir().unwrap();
+    let (mut client, shutdown_tx, server_handle) = start_cli_session(home.path(), "work").await;
+    let mut events = client.take_events().unwrap();
+    client.create_channel("build", Some("cat")).await.unwrap();
+
+    let status = nexus_cli(home.path())
+        .args([
+            "send",
+            "--session",
+            "work",
+            "--channel",
+            "build",
+            "typed-by-send",
+        ])
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+    let (output, _) = collect_output(&mut events, |out| out.contains("typed-by-send")).await;
+    assert!(output.contains("typed-by-send"), "got {:?}", output);
+
+    let missing = nexus_cli(home.path())
+        .args(["send", "--session", "work", "--channel", "missing", "lost"])
+        .output()
+        .await
+        .unwrap();
+    assert!(!missing.status.success());
+    let stderr = String::from_utf8_lossy(&missing.stderr);
+    assert!(
+        stderr.contains("Channel 'missing' not found in session 'work'"),
+        "got {:?}",
+        stderr
+    );
+
+    client.detach().await.unwrap();
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(2), server_handle).await;
+}
+
+#[tokio::test]
+async fn test_wait_exits_with_the_channels_status() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_wait_exits_with_the_channels_status: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let home = tempdir().unwrap();
+    let (client, shutdown_tx, server_handle) = start_cli_session(home.path(), "jobs").await;
+    client
+        .create_channel("job", Some("sleep 1; exit 3"))
+        .await
+        .unwrap();
+
+    let status = timeout(
+        Duration::from_secs(10),
+        nexus_cli(home.path())
+            .args(["wait", "jobs", "job"])
+            .status(),
+    )
+    .await
+    .expect("wait should return once the channel exits")
+    .unwrap();
+    assert_eq!(status.code(), Some(3));
+
+    // An exited channel reports its status straight away
+    let status = nexus_cli(home.path())
+        .args(["wait", "jobs", "job"])
+        .status()
+        .await
+        .unwrap();
+    assert_eq!(status.code(), Some(3));
+
+    client.detach().await.unwrap();
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(2), server_handle).await;
+}
+
+#[tokio::test]
+async fn test_wait_fails_when_the_channel_times_out() {
+    if !can_create_unix_socket() {
+        eprintln!("Skipping test_wait_fails_when_the_channel_times_out: unix sockets not permitted in this environment");
+        return;
+    }
+
+    let home = tempdir().unwrap();
+    let (client, shutdown_tx, server_handle) = start_cli_session(home.path(), "slow").await;
+    client
+        .create_channel_with(ClientMessage::CreateChannel(CreateChannelRequest {
+            name: "hang".to_string(),
+            command: Some("sleep 30".to_string()),
+            timeout_secs: Some(1),
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+
+    let output = timeout(
+        Duration::from_secs(10),
+        nexus_cli(home.path())
+            .args(["wait", "slow", "hang"])
+            .output(),
+    )
+    .await
+    .expect("wait should return once the timeout kills the channel")
+    .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("channel 'hang' was killed"),
+        "got {:?}",
+        stderr
+    );
+
+    let missing = nexus_cli(home.path())
+        .args(["wait", "slow", "missing"])
+        .output()
+        .await
+        .unwrap();
+    assert!(!missing.status.success());
+
+    client.detach().await.unwrap();
+    let _ = shutdown_tx.send(()).await;
+    let _ = timeout(Duration::from_secs(2), server_handle).await;
+}